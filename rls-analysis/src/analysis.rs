@@ -2,7 +2,7 @@ use fst;
 use std::collections::{BTreeMap, HashMap, HashSet};
 use std::iter;
 use std::path::{Path, PathBuf};
-use std::time::SystemTime;
+use std::time::{Duration, SystemTime};
 
 use crate::raw::{CrateId, DefKind};
 use crate::{Id, Span, SymbolQuery};
@@ -31,6 +31,7 @@ pub(crate) struct Analysis {
     pub src_url_base: String,
 }
 
+#[derive(Clone)]
 pub struct PerCrateAnalysis {
     // Map span to id of def (either because it is the span of the def, or of
     // the def for the ref).
@@ -48,17 +49,34 @@ pub struct PerCrateAnalysis {
     pub ref_spans: HashMap<Id, Vec<Span>>,
     pub globs: HashMap<Span, Glob>,
     pub impls: HashMap<Id, Vec<Span>>,
+    // Items of impl blocks, keyed by the id of the self type.
+    pub impl_items: HashMap<Id, Vec<ImplItems>>,
     pub idents: HashMap<PathBuf, IdentsByLine>,
 
     pub root_id: Option<Id>,
     pub timestamp: SystemTime,
     pub path: Option<PathBuf>,
+    /// How long it took to lower the raw data for this crate, or `None` if it
+    /// wasn't lowered again by the last reload.
+    pub lowering_time: Option<Duration>,
+    /// Hashes of the raw data lowered from each file, used to only lower the
+    /// files which changed when the crate is reloaded.
+    pub file_hashes: HashMap<PathBuf, u64>,
     // All definitions in this crate will include the global_crate_num. See
     // lowering::id_from_compiler_id for details of how.
     // global_crate_num is not available until after lowering.
     pub global_crate_num: u32,
 }
 
+/// The items defined in a single impl block.
+#[derive(Debug, Clone)]
+pub struct ImplItems {
+    pub span: Span,
+    /// The implemented trait, if this is a trait impl.
+    pub trait_id: Option<Id>,
+    pub items: Vec<Id>,
+}
+
 #[derive(Debug, Clone)]
 pub enum Ref {
     // The common case - a reference to a single definition.
@@ -100,7 +118,26 @@ pub struct Def {
     pub parent: Option<Id>,
     pub value: String,
     pub docs: String,
-    // pub sig: Option<Signature>,
+    pub sig: Option<Signature>,
+    /// The attributes on the item, without the surrounding `#[` and `]`.
+    pub attributes: Vec<String>,
+}
+
+impl Def {
+    /// Returns the name of the derive macro defined by this def, i.e., of a
+    /// `macro` (e.g., the built-in `Debug`) or a `#[proc_macro_derive]`
+    /// function (e.g., `Serialize` of `serde_derive`), if it defines one.
+    pub fn derive_macro_name(&self) -> Option<&str> {
+        match self.kind {
+            DefKind::Macro if self.name.starts_with(char::is_uppercase) => Some(&self.name),
+            DefKind::Function => self.attributes.iter().find_map(|attribute| {
+                let args = attribute.strip_prefix("proc_macro_derive")?.trim_start();
+                let name = args.strip_prefix('(')?.split(|c| c == ',' || c == ')').next()?;
+                Some(name.trim()).filter(|name| !name.is_empty())
+            }),
+            _ => None,
+        }
+    }
 }
 
 pub type IdentsByLine = BTreeMap<Row<ZeroIndexed>, IdentsByColumn>;
@@ -138,10 +175,7 @@ pub struct Ident {
 
 #[derive(Debug, Clone)]
 pub struct Signature {
-    pub span: Span,
     pub text: String,
-    pub ident_start: u32,
-    pub ident_end: u32,
     pub defs: Vec<SigElement>,
     pub refs: Vec<SigElement>,
 }
@@ -153,9 +187,11 @@ pub struct SigElement {
     pub end: usize,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Glob {
     pub value: String,
+    /// The module (or other item) containing the glob import.
+    pub parent: Option<Id>,
 }
 
 impl PerCrateAnalysis {
@@ -172,10 +208,13 @@ impl PerCrateAnalysis {
             ref_spans: HashMap::new(),
             globs: HashMap::new(),
             impls: HashMap::new(),
+            impl_items: HashMap::new(),
             idents: HashMap::new(),
             root_id: None,
             timestamp,
             path,
+            lowering_time: None,
+            file_hashes: HashMap::new(),
             global_crate_num: 0,
         }
     }
@@ -190,6 +229,87 @@ impl PerCrateAnalysis {
         }
     }
 
+    // Removes everything that was lowered from `files`, so that the data for
+    // these files can be lowered again. Returns the removed definitions, which
+    // should be passed to `remove_stale_defs` once the files are lowered.
+    pub(crate) fn remove_files(&mut self, files: &HashSet<PathBuf>) -> HashMap<Id, Def> {
+        let mut removed = HashMap::new();
+        for file in files {
+            for id in self.defs_per_file.remove(file).unwrap_or_default() {
+                if let Some(def) = self.defs.remove(&id) {
+                    removed.insert(id, def);
+                }
+            }
+            self.idents.remove(file);
+        }
+
+        for (id, def) in &removed {
+            if let Some(ids) = self.def_names.get_mut(&def.name) {
+                ids.retain(|i| i != id);
+                if ids.is_empty() {
+                    self.def_names.remove(&def.name);
+                }
+            }
+            // Children from other files are not going to be lowered again, so
+            // keep them around in case the definition is lowered again.
+            if let Some(children) = self.children.get_mut(id) {
+                children.retain(|c| !removed.contains_key(c));
+            }
+            if self.root_id == Some(*id) {
+                self.root_id = None;
+            }
+        }
+        for ids in &mut self.def_fst_values {
+            ids.retain(|id| !removed.contains_key(id));
+        }
+
+        let in_files = |span: &Span| files.contains(&span.file);
+        self.def_id_for_span.retain(|span, _| !in_files(span));
+        self.globs.retain(|span, _| !in_files(span));
+        for spans in self.ref_spans.values_mut().chain(self.impls.values_mut()) {
+            spans.retain(|span| !in_files(span));
+        }
+        self.ref_spans.retain(|_, spans| !spans.is_empty());
+        self.impls.retain(|_, spans| !spans.is_empty());
+        for impls in self.impl_items.values_mut() {
+            impls.retain(|i| !in_files(&i.span));
+        }
+        self.impl_items.retain(|_, impls| !impls.is_empty());
+
+        removed
+    }
+
+    // Drops the definitions returned by `remove_files` which weren't lowered
+    // again, along with any references to them from the remaining files.
+    pub(crate) fn remove_stale_defs(&mut self, removed: HashMap<Id, Def>) {
+        let stale: HashMap<_, _> =
+            removed.into_iter().filter(|(id, _)| !self.defs.contains_key(id)).collect();
+        if stale.is_empty() {
+            return;
+        }
+
+        for (id, def) in &stale {
+            if let Some(children) = def.parent.and_then(|p| self.children.get_mut(&p)) {
+                children.remove(id);
+            }
+            self.children.remove(id);
+            self.impls.remove(id);
+            self.impl_items.remove(id);
+            for span in self.ref_spans.remove(id).unwrap_or_default() {
+                if let Some(Ref::Id(ref_id)) = self.def_id_for_span.get(&span) {
+                    if ref_id == id {
+                        self.def_id_for_span.remove(&span);
+                    }
+                }
+            }
+        }
+        for def in self.defs.values_mut() {
+            if def.parent.map_or(false, |p| stale.contains_key(&p)) {
+                def.parent = None;
+            }
+        }
+    }
+
     // Returns all identifiers which overlap with `span`. There is no guarantee about
     // the ordering of identifiers in the result, but they will probably be roughly
     // in order of appearance.
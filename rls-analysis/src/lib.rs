@@ -19,16 +19,19 @@ mod test;
 mod util;
 
 use analysis::Analysis;
-pub use analysis::{Def, Ident, IdentKind, Ref};
-pub use loader::{AnalysisLoader, CargoAnalysisLoader, SearchDirectory, Target};
+pub use analysis::{Def, Ident, IdentKind, Ref, SigElement, Signature};
+pub use loader::{
+    compile_target_name, needs_local_std_analysis, AnalysisLoader, CargoAnalysisLoader,
+    SearchDirectory, Target,
+};
 pub use raw::{name_space_for_def_kind, read_analysis_from_files, Crate, CrateId, DefKind};
 pub use symbol_query::SymbolQuery;
 
-use std::collections::HashMap;
+use std::collections::{BTreeSet, HashMap};
 use std::fmt::Debug;
 use std::path::{Path, PathBuf};
 use std::sync::Mutex;
-use std::time::{Instant, SystemTime};
+use std::time::{Duration, Instant, SystemTime};
 use std::u64;
 
 pub struct AnalysisHost<L: AnalysisLoader = CargoAnalysisLoader> {
@@ -105,6 +108,29 @@ impl AnalysisHost<CargoAnalysisLoader> {
             loader: Mutex::new(CargoAnalysisLoader::new(target)),
         }
     }
+
+    /// Configures where the analysis data of a (possibly cross-compiled)
+    /// build is to be found. Any change forces the next reload to be a hard one.
+    pub fn set_build_target(
+        &self,
+        target_dir: Option<&Path>,
+        compile_target: Option<&str>,
+        build_std: bool,
+    ) -> AResult<()> {
+        let mut loader = self.loader.lock()?;
+        let target_dir = target_dir.map(Path::to_owned);
+        let compile_target = compile_target.map(str::to_owned);
+        if loader.target_dir != target_dir
+            || loader.compile_target != compile_target
+            || loader.build_std != build_std
+        {
+            loader.target_dir = target_dir;
+            loader.compile_target = compile_target;
+            loader.build_std = build_std;
+            loader.path_prefix = None;
+        }
+        Ok(())
+    }
 }
 
 impl<L: AnalysisLoader> AnalysisHost<L> {
@@ -156,7 +182,15 @@ impl<L: AnalysisLoader> AnalysisHost<L> {
             return self.hard_reload_with_blacklist(path_prefix, base_dir, blacklist);
         }
 
-        let timestamps = self.analysis.lock()?.as_ref().unwrap().timestamps();
+        let timestamps = {
+            let mut analysis = self.analysis.lock()?;
+            let analysis = analysis.as_mut().unwrap();
+            // Only the crates lowered by this reload report a lowering time.
+            for per_crate in analysis.per_crate.values_mut() {
+                per_crate.lowering_time = None;
+            }
+            analysis.timestamps()
+        };
         let raw_analysis = {
             let loader = self.loader.lock()?;
             read_analysis_from_files(&*loader, timestamps, blacklist)
@@ -277,6 +311,70 @@ impl<L: AnalysisLoader> AnalysisHost<L> {
         })
     }
 
+    /// Returns the names of the derive macros defined in the program, including
+    /// those of its dependencies, sorted and without duplicates.
+    pub fn derive_macros(&self) -> AResult<Vec<String>> {
+        self.with_analysis(|a| {
+            let names: BTreeSet<_> = a
+                .per_crate
+                .values()
+                .flat_map(|c| c.defs.values())
+                .filter_map(|def| def.derive_macro_name())
+                .map(ToOwned::to_owned)
+                .collect();
+            Some(names.into_iter().collect())
+        })
+    }
+
+    /// Returns every glob import in the program, together with the qualified
+    /// name of the module containing it and the names it actually imports.
+    ///
+    /// A glob compiled as part of several crates (e.g., a library and its
+    /// tests) is reported once, with the names used by any of them.
+    pub fn globs(&self) -> AResult<Vec<(Span, String, Vec<String>)>> {
+        self.with_analysis(|a| {
+            let mut globs: HashMap<Span, (String, BTreeSet<String>)> = HashMap::new();
+            for (crate_id, c) in &a.per_crate {
+                for (span, glob) in &c.globs {
+                    let module = glob
+                        .parent
+                        .and_then(|id| c.defs.get(&id))
+                        .map(|def| def.qualname.trim_end_matches(':').to_owned())
+                        .unwrap_or_else(|| crate_id.name.clone());
+                    let entry =
+                        globs.entry(span.clone()).or_insert_with(|| (module, BTreeSet::new()));
+                    entry.1.extend(
+                        glob.value
+                            .split(',')
+                            .map(str::trim)
+                            .filter(|s| !s.is_empty())
+                            .map(String::from),
+                    );
+                }
+            }
+            let mut result: Vec<_> = globs
+                .into_iter()
+                .map(|(span, (module, names))| (span, module, names.into_iter().collect()))
+                .collect();
+            result.sort_by(|a, b| a.0.cmp(&b.0));
+            Some(result)
+        })
+    }
+
+    /// Returns how long the most recent lowering of each loaded crate took.
+    /// Returns how long it took to lower each of the crates lowered by the
+    /// last reload.
+    pub fn lowering_times(&self) -> AResult<Vec<(CrateId, Duration)>> {
+        self.with_analysis(|a| {
+            Some(
+                a.per_crate
+                    .iter()
+                    .filter_map(|(id, c)| Some((id.clone(), c.lowering_time?)))
+                    .collect(),
+            )
+        })
+    }
+
     pub fn id(&self, span: &Span) -> AResult<Id> {
         self.with_analysis(|a| a.def_id_for_span(span))
     }
@@ -421,6 +519,25 @@ impl<L: AnalysisLoader> AnalysisHost<L> {
         self.with_analysis(|a| Some(a.for_all_crates(|c| c.impls.get(&id).cloned())))
     }
 
+    /// Returns the items of every impl block for the type with the given id,
+    /// along with the trait implemented by the block they're in, if any.
+    pub fn impl_items(&self, id: Id) -> AResult<Vec<(Option<Id>, Def)>> {
+        self.with_analysis(|a| {
+            Some(a.for_all_crates(|c| {
+                c.impl_items.get(&id).map(|impls| {
+                    impls
+                        .iter()
+                        .flat_map(|i| {
+                            i.items.iter().filter_map(move |item| {
+                                a.with_defs(*item, |def| (i.trait_id, def.clone()))
+                            })
+                        })
+                        .collect()
+                })
+            }))
+        })
+    }
+
     /// Search for a symbol name, returning a list of def_ids for that name.
     pub fn search_for_id(&self, name: &str) -> AResult<Vec<Id>> {
         self.with_analysis(|a| Some(a.with_def_names(name, Clone::clone)))
@@ -465,6 +582,24 @@ impl<L: AnalysisLoader> AnalysisHost<L> {
         })
     }
 
+    /// Like `doc_url`, but for the def with the given id.
+    pub fn doc_url_by_id(&self, id: Id) -> AResult<String> {
+        self.with_analysis(|a| {
+            a.with_defs_and_then(id, |def| AnalysisHost::<L>::mk_doc_url(def, a))
+        })
+    }
+
+    /// Like `src_url`, but for the def with the given id.
+    pub fn src_url_by_id(&self, id: Id) -> AResult<String> {
+        let path_prefix = self.loader.lock().unwrap().abs_path_prefix();
+
+        self.with_analysis(|a| {
+            a.with_defs_and_then(id, |def| {
+                AnalysisHost::<L>::mk_src_url(def, path_prefix.as_ref(), a)
+            })
+        })
+    }
+
     fn with_analysis<F, T>(&self, f: F) -> AResult<T>
     where
         F: FnOnce(&Analysis) -> Option<T>,
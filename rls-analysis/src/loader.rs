@@ -15,6 +15,15 @@ use crate::AnalysisHost;
 pub struct CargoAnalysisLoader {
     pub path_prefix: Option<PathBuf>,
     pub target: Target,
+    /// The Cargo target directory, defaults to `target/rls` under the path prefix.
+    pub target_dir: Option<PathBuf>,
+    /// Target triple or path to a custom target specification JSON file, if
+    /// not compiling for the host.
+    pub compile_target: Option<String>,
+    /// Whether the standard library is built from source (`-Zbuild-std`), in
+    /// which case its analysis data is found alongside the dependencies rather
+    /// than in the sysroot.
+    pub build_std: bool,
 }
 
 #[derive(Debug, new)]
@@ -29,7 +38,13 @@ pub struct SearchDirectory {
 
 impl CargoAnalysisLoader {
     pub fn new(target: Target) -> CargoAnalysisLoader {
-        CargoAnalysisLoader { path_prefix: None, target }
+        CargoAnalysisLoader {
+            path_prefix: None,
+            target,
+            target_dir: None,
+            compile_target: None,
+            build_std: false,
+        }
     }
 }
 
@@ -52,6 +67,9 @@ impl AnalysisLoader for CargoAnalysisLoader {
     fn fresh_host(&self) -> AnalysisHost<Self> {
         AnalysisHost::new_with_loader(CargoAnalysisLoader {
             path_prefix: self.path_prefix.clone(),
+            target_dir: self.target_dir.clone(),
+            compile_target: self.compile_target.clone(),
+            build_std: self.build_std,
             ..CargoAnalysisLoader::new(self.target)
         })
     }
@@ -68,18 +86,64 @@ impl AnalysisLoader for CargoAnalysisLoader {
         let path_prefix = self.path_prefix.as_ref().unwrap();
         let target = self.target.to_string();
 
-        let deps_path =
-            path_prefix.join("target").join("rls").join(&target).join("deps").join("save-analysis");
+        let mut deps_path =
+            self.target_dir.clone().unwrap_or_else(|| path_prefix.join("target").join("rls"));
+        // Cargo places the output of cross-compiled builds in a per-target directory
+        if let Some(compile_target) = &self.compile_target {
+            deps_path.push(compile_target_name(compile_target));
+        }
+        let deps_path = deps_path.join(&target).join("deps").join("save-analysis");
+        let deps_dir = SearchDirectory::new(deps_path, None);
+
+        // When building the standard library from source, its analysis data is
+        // emitted into the dependency directory like for any other crate.
+        if self.build_std {
+            return vec![deps_dir];
+        }
+
         // FIXME sys_root_path allows to break out of 'sandbox' - is that Ok?
         // FIXME libs_path and src_path both assume the default `libdir = "lib"`.
         let sys_root_path = sys_root_path();
-        let target_triple = extract_target_triple(sys_root_path.as_path());
+        let target_triple = match &self.compile_target {
+            Some(compile_target) => compile_target_name(compile_target).to_owned(),
+            None => extract_target_triple(sys_root_path.as_path()),
+        };
         let libs_path =
             sys_root_path.join("lib").join("rustlib").join(&target_triple).join("analysis");
+        if !libs_path.is_dir() {
+            debug!(
+                "no `rust-analysis` component for target {} in the sysroot, \
+                 std analysis is only available when building it from source",
+                target_triple
+            );
+        }
 
         let src_path = sys_root_path.join("lib").join("rustlib").join("src").join("rust");
 
-        vec![SearchDirectory::new(libs_path, Some(src_path)), SearchDirectory::new(deps_path, None)]
+        vec![SearchDirectory::new(libs_path, Some(src_path)), deps_dir]
+    }
+}
+
+/// Returns whether std analysis data for the given compilation target has to
+/// be produced locally, because the sysroot has no `rust-analysis` component
+/// for it, but has the `rust-src` one to build the standard library from.
+pub fn needs_local_std_analysis(compile_target: &str) -> bool {
+    sysroot_needs_local_std_analysis(&sys_root_path(), compile_target)
+}
+
+fn sysroot_needs_local_std_analysis(sys_root_path: &Path, compile_target: &str) -> bool {
+    let rustlib = sys_root_path.join("lib").join("rustlib");
+    !rustlib.join(compile_target_name(compile_target)).join("analysis").is_dir()
+        && rustlib.join("src").join("rust").is_dir()
+}
+
+/// Returns the name Cargo uses for the given compilation target, which is the
+/// file stem for custom target specification files.
+pub fn compile_target_name(compile_target: &str) -> &str {
+    if compile_target.ends_with(".json") {
+        Path::new(compile_target).file_stem().and_then(OsStr::to_str).unwrap_or(compile_target)
+    } else {
+        compile_target
     }
 }
 
@@ -167,6 +231,49 @@ mod tests {
         assert_eq!(extract_rustup_target_triple(path), String::from("x86_64-unknown-linux-gnu"));
     }
 
+    #[test]
+    fn custom_target_name() {
+        assert_eq!(compile_target_name("thumbv7em-none-eabihf"), "thumbv7em-none-eabihf");
+        assert_eq!(compile_target_name("targets/my-board.json"), "my-board");
+    }
+
+    #[test]
+    fn local_std_analysis() {
+        let sys_root = env::temp_dir().join(format!("rls-analysis-sysroot-{}", std::process::id()));
+        let rustlib = sys_root.join("lib").join("rustlib");
+        std::fs::create_dir_all(rustlib.join("x86_64-unknown-linux-gnu").join("analysis")).unwrap();
+        let needs = |target| sysroot_needs_local_std_analysis(&sys_root, target);
+
+        // Without `rust-src` there's nothing to build std from.
+        assert!(!needs("targets/my-board.json"));
+
+        std::fs::create_dir_all(rustlib.join("src").join("rust")).unwrap();
+        assert!(!needs("x86_64-unknown-linux-gnu"));
+        assert!(needs("thumbv7em-none-eabihf"));
+        assert!(needs("targets/my-board.json"));
+
+        std::fs::create_dir_all(rustlib.join("my-board").join("analysis")).unwrap();
+        assert!(!needs("targets/my-board.json"));
+
+        std::fs::remove_dir_all(&sys_root).unwrap();
+    }
+
+    #[test]
+    fn cross_compiled_deps_directory() {
+        let loader = CargoAnalysisLoader {
+            path_prefix: Some(PathBuf::from("/project")),
+            compile_target: Some("targets/my-board.json".to_owned()),
+            build_std: true,
+            ..CargoAnalysisLoader::new(Target::Debug)
+        };
+        let dirs = loader.search_directories();
+        assert_eq!(dirs.len(), 1);
+        assert_eq!(
+            dirs[0].path,
+            Path::new("/project/target/rls/my-board/debug/deps/save-analysis")
+        );
+    }
+
     #[test]
     fn target_triple() {
         let sys_root_path = sys_root_path();
@@ -1,7 +1,7 @@
 //! For processing the raw save-analysis data from rustc into the rls
 //! in-memory representation.
 
-use crate::analysis::{Def, Glob, PerCrateAnalysis, Ref};
+use crate::analysis::{Def, Glob, ImplItems, PerCrateAnalysis, Ref, SigElement, Signature};
 #[cfg(feature = "idents")]
 use crate::analysis::{IdentBound, IdentKind, IdentsByColumn, IdentsByLine};
 use crate::loader::AnalysisLoader;
@@ -9,14 +9,16 @@ use crate::raw::{self, CrateId, DefKind, RelationKind};
 use crate::util;
 use crate::{AResult, AnalysisHost, Id, Span, NULL};
 
-use std::collections::hash_map::Entry;
+use std::collections::hash_map::{DefaultHasher, Entry};
 use std::collections::{HashMap, HashSet};
+use std::hash::{Hash, Hasher};
 use std::iter::Extend;
+use std::mem;
 use std::path::{Path, PathBuf};
 use std::time::Instant;
 use std::u32;
 
-use fst;
+use fst::{self, Streamer};
 use itertools::Itertools;
 
 // f is a function used to record the lowered crate into analysis.
@@ -40,10 +42,12 @@ where
     for c in raw_analysis {
         let t_start = Instant::now();
 
-        let (per_crate, id) = CrateReader::read_crate(analysis, c, base_dir, &invalidated_crates);
+        let (mut per_crate, id) =
+            CrateReader::read_crate(analysis, c, base_dir, &invalidated_crates);
         invalidated_crates.retain(|elem| *elem != id);
 
         let time = t_start.elapsed();
+        per_crate.lowering_time = Some(time);
         info!(
             "Lowering {} in {:.2}s",
             format!("{} ({:?})", id.name, id.disambiguator),
@@ -68,10 +72,21 @@ where
 }
 
 fn lower_span(raw_span: &raw::SpanData, base_dir: &Path, path_rewrite: &Option<PathBuf>) -> Span {
-    let file_name = &raw_span.file_name;
+    let file_name = lower_file_name(&raw_span.file_name, base_dir, path_rewrite);
 
+    // Rustc uses 1-indexed rows and columns, the RLS uses 0-indexed.
+    span::Span::new(
+        raw_span.line_start.zero_indexed(),
+        raw_span.line_end.zero_indexed(),
+        raw_span.column_start.zero_indexed(),
+        raw_span.column_end.zero_indexed(),
+        file_name,
+    )
+}
+
+fn lower_file_name(file_name: &Path, base_dir: &Path, path_rewrite: &Option<PathBuf>) -> PathBuf {
     // Go from relative to absolute paths.
-    let file_name = if let Some(ref prefix) = *path_rewrite {
+    if let Some(ref prefix) = *path_rewrite {
         // Invariant: !file_name.is_absolute()
         // We don't assert this because better to have an incorrect span than to
         // panic.
@@ -81,16 +96,7 @@ fn lower_span(raw_span: &raw::SpanData, base_dir: &Path, path_rewrite: &Option<P
         file_name.to_owned()
     } else {
         base_dir.join(file_name)
-    };
-
-    // Rustc uses 1-indexed rows and columns, the RLS uses 0-indexed.
-    span::Span::new(
-        raw_span.line_start.zero_indexed(),
-        raw_span.line_end.zero_indexed(),
-        raw_span.column_start.zero_indexed(),
-        raw_span.column_end.zero_indexed(),
-        file_name,
-    )
+    }
 }
 
 /// Responsible for processing the raw `data::Analysis`, including translating
@@ -158,43 +164,203 @@ impl<'a> CrateReader<'a> {
     }
 
     /// Lowers a given `raw::Crate` into `AnalysisHost`.
+    ///
+    /// If the crate was lowered before, only the data for the files which
+    /// changed since then is lowered again and merged with the existing data.
     fn read_crate<L: AnalysisLoader>(
         project_analysis: &AnalysisHost<L>,
-        krate: raw::Crate,
+        mut krate: raw::Crate,
         base_dir: &Path,
         invalidated_crates: &[CrateId],
     ) -> (PerCrateAnalysis, CrateId) {
         let reader = CrateReader::from_prelude(
-            krate.analysis.prelude.unwrap(),
+            krate.analysis.prelude.take().unwrap(),
             &mut project_analysis.master_crate_map.lock().unwrap(),
             base_dir,
-            krate.path_rewrite,
+            krate.path_rewrite.take(),
             invalidated_crates,
         );
 
-        let mut per_crate = PerCrateAnalysis::new(krate.timestamp, krate.path);
-
         let is_distro_crate = krate.analysis.config.distro_crate;
-        reader.read_defs(krate.analysis.defs, &mut per_crate, is_distro_crate, project_analysis);
-        reader.read_imports(krate.analysis.imports, &mut per_crate, project_analysis);
-        reader.read_refs(krate.analysis.refs, &mut per_crate, project_analysis);
-        reader.read_impls(krate.analysis.relations, &mut per_crate, project_analysis);
+        let file_hashes = reader.hash_files(&krate.analysis, is_distro_crate);
+        let lowered_hashes: HashMap<_, _> =
+            file_hashes.values().map(|(file, hash)| (file.clone(), *hash)).collect();
+
+        let mut per_crate = match Self::take_previous(project_analysis, &krate.id, &lowered_hashes)
+        {
+            Some((mut per_crate, changed_files)) => {
+                trace!("re-lowering {} changed files: {:?}", krate.id.name, changed_files);
+                let raw_changed_files: HashSet<_> = file_hashes
+                    .into_iter()
+                    .filter(|(_, (file, _))| changed_files.contains(file))
+                    .map(|(raw_file, _)| raw_file)
+                    .collect();
+                let changed = |span: &raw::SpanData| raw_changed_files.contains(&span.file_name);
+                let analysis = &mut krate.analysis;
+                analysis.defs.retain(|d| changed(&d.span));
+                analysis.imports.retain(|i| changed(&i.span));
+                analysis.refs.retain(|r| changed(&r.span));
+                analysis.relations.retain(|r| changed(&r.span));
+                analysis.impls.retain(|i| changed(&i.span));
+
+                let removed_defs = per_crate.remove_files(&changed_files);
+                reader.read_crate_data(
+                    krate.analysis,
+                    &mut per_crate,
+                    is_distro_crate,
+                    project_analysis,
+                );
+                per_crate.remove_stale_defs(removed_defs);
+                per_crate
+            }
+            None => {
+                let mut per_crate = PerCrateAnalysis::new(krate.timestamp, None);
+                reader.read_crate_data(
+                    krate.analysis,
+                    &mut per_crate,
+                    is_distro_crate,
+                    project_analysis,
+                );
+                per_crate
+            }
+        };
+        per_crate.timestamp = krate.timestamp;
+        per_crate.path = krate.path;
+        per_crate.file_hashes = lowered_hashes;
         per_crate.global_crate_num = reader.crate_map[0];
 
         {
             let analysis = &mut project_analysis.analysis.lock().unwrap();
-            analysis
+            let ids = analysis
                 .as_mut()
                 .unwrap()
                 .crate_names
                 .entry(krate.id.name.clone())
-                .or_insert_with(Vec::new)
-                .push(krate.id.clone());
+                .or_insert_with(Vec::new);
+            if !ids.contains(&krate.id) {
+                ids.push(krate.id.clone());
+            }
         }
 
         (per_crate, krate.id)
     }
 
+    fn read_crate_data<L: AnalysisLoader>(
+        &self,
+        analysis: data::Analysis,
+        per_crate: &mut PerCrateAnalysis,
+        is_distro_crate: bool,
+        project_analysis: &AnalysisHost<L>,
+    ) {
+        let defs_to_index =
+            self.read_defs(analysis.defs, per_crate, is_distro_crate, project_analysis);
+        self.read_imports(analysis.imports, per_crate, project_analysis);
+        self.read_refs(analysis.refs, per_crate, project_analysis);
+        self.read_impls(analysis.relations, analysis.impls, per_crate, project_analysis);
+
+        let (def_fst, def_fst_values) = merge_index(
+            &per_crate.def_fst,
+            mem::take(&mut per_crate.def_fst_values),
+            defs_to_index,
+        );
+        per_crate.def_fst = def_fst;
+        per_crate.def_fst_values = def_fst_values;
+    }
+
+    /// Copies the previously lowered data for the crate out of the host, along
+    /// with the files which have changed since, if any of the files did not
+    /// change (otherwise it's not worth updating the data in place). The host
+    /// keeps serving the previous data until the updated copy is swapped in.
+    fn take_previous<L: AnalysisLoader>(
+        project_analysis: &AnalysisHost<L>,
+        id: &CrateId,
+        file_hashes: &HashMap<PathBuf, u64>,
+    ) -> Option<(PerCrateAnalysis, HashSet<PathBuf>)> {
+        let analysis = project_analysis.analysis.lock().unwrap();
+        let previous = analysis.as_ref().unwrap().per_crate.get(id)?;
+
+        let previous_hashes = &previous.file_hashes;
+        let changed_files: HashSet<_> = file_hashes
+            .iter()
+            .filter(|(file, hash)| previous_hashes.get(*file) != Some(hash))
+            .map(|(file, _)| file)
+            .chain(previous_hashes.keys().filter(|file| !file_hashes.contains_key(*file)))
+            .cloned()
+            .collect();
+        if file_hashes.keys().all(|file| changed_files.contains(file)) {
+            return None;
+        }
+
+        Some((previous.clone(), changed_files))
+    }
+
+    /// Hashes the raw data for every file in the crate, so that we can tell
+    /// which files changed since the crate was last lowered. Returns a map
+    /// from the raw file name to the lowered file name and its hash.
+    fn hash_files(
+        &self,
+        analysis: &data::Analysis,
+        is_distro_crate: bool,
+    ) -> HashMap<PathBuf, (PathBuf, u64)> {
+        // Lowered ids depend on the mapping of crate numbers, so any change to
+        // the latter invalidates every file.
+        let mut seed = DefaultHasher::new();
+        self.crate_map.hash(&mut seed);
+        is_distro_crate.hash(&mut seed);
+
+        let mut hashers: HashMap<&Path, DefaultHasher> = HashMap::new();
+        for d in &analysis.defs {
+            let h = hasher_for(&mut hashers, &seed, &d.span);
+            mem::discriminant(&d.kind).hash(h);
+            (d.id, &d.name, &d.qualname, &d.value, d.parent, &d.children, d.decl_id, &d.docs)
+                .hash(h);
+            if let Some(ref sig) = d.sig {
+                sig.text.hash(h);
+                for e in sig.defs.iter().chain(&sig.refs) {
+                    (e.id, e.start, e.end).hash(h);
+                }
+                (sig.defs.len(), sig.refs.len()).hash(h);
+            }
+            for a in &d.attributes {
+                a.value.hash(h);
+                hash_span(&a.span, h);
+            }
+            d.attributes.len().hash(h);
+        }
+        for i in &analysis.imports {
+            let h = hasher_for(&mut hashers, &seed, &i.span);
+            mem::discriminant(&i.kind).hash(h);
+            (i.ref_id, &i.value).hash(h);
+            if let Some(ref alias_span) = i.alias_span {
+                hash_span(alias_span, h);
+            }
+        }
+        for r in &analysis.refs {
+            let h = hasher_for(&mut hashers, &seed, &r.span);
+            r.ref_id.hash(h);
+        }
+        for r in &analysis.relations {
+            let h = hasher_for(&mut hashers, &seed, &r.span);
+            match r.kind {
+                RelationKind::Impl { id } => Some(id).hash(h),
+                RelationKind::SuperTrait => None::<u32>.hash(h),
+            }
+            (r.from, r.to).hash(h);
+        }
+        for i in &analysis.impls {
+            let h = hasher_for(&mut hashers, &seed, &i.span);
+            (i.id, &i.children).hash(h);
+        }
+
+        hashers
+            .into_iter()
+            .map(|(file, hasher)| {
+                let lowered = lower_file_name(file, &self.base_dir, &self.path_rewrite);
+                (file.to_owned(), (lowered, hasher.finish()))
+            })
+            .collect()
+    }
+
     fn read_imports<L: AnalysisLoader>(
         &self,
         imports: Vec<raw::Import>,
@@ -206,7 +372,8 @@ impl<'a> CrateReader<'a> {
             if !i.value.is_empty() {
                 // A glob import.
                 if !self.has_congruent_glob(&span, project_analysis) {
-                    let glob = Glob { value: i.value };
+                    let parent = i.parent.map(|id| self.id_from_compiler_id(id));
+                    let glob = Glob { value: i.value, parent };
                     trace!("record glob {:?} {:?}", span, glob);
                     analysis.globs.insert(span, glob);
                 }
@@ -315,13 +482,14 @@ impl<'a> CrateReader<'a> {
         homonyms_to_consider.filter_map(|ch| project_analysis.per_crate.get(ch)).any(pred)
     }
 
+    // Returns the lowercased names of the lowered defs, to be indexed for search.
     fn read_defs<L: AnalysisLoader>(
         &self,
         defs: Vec<raw::Def>,
         analysis: &mut PerCrateAnalysis,
         distro_crate: bool,
         project_analysis: &AnalysisHost<L>,
-    ) {
+    ) -> Vec<(String, Id)> {
         let mut defs_to_index = Vec::new();
         for d in defs {
             if bad_span(&d.span, d.kind == DefKind::Mod) {
@@ -391,7 +559,8 @@ impl<'a> CrateReader<'a> {
                     distro_crate,
                     parent,
                     docs: d.docs,
-                    // sig: d.sig.map(|ref s| self.lower_sig(s, &self.base_dir)),
+                    sig: d.sig.as_ref().map(|s| self.lower_sig(s)),
+                    attributes: d.attributes.into_iter().map(|a| a.value).collect(),
                 };
                 trace!(
                     "record def: {:?}/{:?} ({}): {:?}",
@@ -410,10 +579,6 @@ impl<'a> CrateReader<'a> {
             }
         }
 
-        let (def_fst, def_fst_values) = build_index(defs_to_index);
-        analysis.def_fst = def_fst;
-        analysis.def_fst_values = def_fst_values;
-
         // We must now run a pass over the defs setting parents, because
         // save-analysis often omits parent info.
         for (parent, children) in &analysis.children {
@@ -423,6 +588,8 @@ impl<'a> CrateReader<'a> {
                 }
             }
         }
+
+        defs_to_index
     }
 
     fn read_refs<L: AnalysisLoader>(
@@ -444,50 +611,58 @@ impl<'a> CrateReader<'a> {
     fn read_impls<L: AnalysisLoader>(
         &self,
         relations: Vec<raw::Relation>,
+        impls: Vec<raw::Impl>,
         analysis: &mut PerCrateAnalysis,
         project_analysis: &AnalysisHost<L>,
     ) {
+        let mut impl_children: HashMap<_, _> =
+            impls.into_iter().map(|i| (i.id, i.children)).collect();
         for r in relations {
-            match r.kind {
-                RelationKind::Impl { .. } => {}
+            let impl_id = match r.kind {
+                RelationKind::Impl { id } => id,
                 _ => continue,
-            }
+            };
             let self_id = self.id_from_compiler_id(r.from);
             let trait_id = self.id_from_compiler_id(r.to);
             let span = lower_span(&r.span, &self.base_dir, &self.path_rewrite);
+            let trait_id = if trait_id != NULL {
+                abs_ref_id(trait_id, analysis, project_analysis)
+            } else {
+                None
+            };
             if self_id != NULL {
                 if let Some(self_id) = abs_ref_id(self_id, analysis, project_analysis) {
                     trace!("record impl for self type {:?} {}", span, self_id);
                     analysis.impls.entry(self_id).or_insert_with(Vec::new).push(span.clone());
+
+                    let items = impl_children
+                        .remove(&impl_id)
+                        .unwrap_or_default()
+                        .into_iter()
+                        .map(|id| self.id_from_compiler_id(id))
+                        .collect();
+                    let impl_items = ImplItems { span: span.clone(), trait_id, items };
+                    analysis.impl_items.entry(self_id).or_insert_with(Vec::new).push(impl_items);
                 }
             }
-            if trait_id != NULL {
-                if let Some(trait_id) = abs_ref_id(trait_id, analysis, project_analysis) {
-                    trace!("record impl for trait {:?} {}", span, trait_id);
-                    analysis.impls.entry(trait_id).or_insert_with(Vec::new).push(span);
-                }
+            if let Some(trait_id) = trait_id {
+                trace!("record impl for trait {:?} {}", span, trait_id);
+                analysis.impls.entry(trait_id).or_insert_with(Vec::new).push(span);
             }
         }
     }
 
-    // fn lower_sig(&self, raw_sig: &raw::Signature, base_dir: &Path) -> Signature {
-    //     Signature {
-    //         span: lower_span(&raw_sig.span, base_dir, &self.path_rewrite),
-    //         text: raw_sig.text.clone(),
-    //         ident_start: raw_sig.ident_start as u32,
-    //         ident_end: raw_sig.ident_end as u32,
-    //         defs: raw_sig.defs.iter().map(|se| self.lower_sig_element(se)).collect(),
-    //         refs: raw_sig.refs.iter().map(|se| self.lower_sig_element(se)).collect(),
-    //     }
-    // }
-
-    // fn lower_sig_element(&self, raw_se: &raw::SigElement) -> SigElement {
-    //     SigElement {
-    //         id: self.id_from_compiler_id(raw_se.id),
-    //         start: raw_se.start,
-    //         end: raw_se.end,
-    //     }
-    // }
+    fn lower_sig(&self, raw_sig: &raw::Signature) -> Signature {
+        Signature {
+            text: raw_sig.text.clone(),
+            defs: raw_sig.defs.iter().map(|se| self.lower_sig_element(se)).collect(),
+            refs: raw_sig.refs.iter().map(|se| self.lower_sig_element(se)).collect(),
+        }
+    }
+
+    fn lower_sig_element(&self, raw_se: &raw::SigElement) -> SigElement {
+        SigElement { id: self.id_from_compiler_id(raw_se.id), start: raw_se.start, end: raw_se.end }
+    }
 
     /// Recreates resulting crate-local (`u32`, `u32`) id from compiler
     /// to a global `u64` `Id`, mapping from a local to global crate id.
@@ -529,6 +704,52 @@ fn build_index(mut defs: Vec<(String, Id)>) -> (fst::Map<Vec<u8>>, Vec<Vec<Id>>)
     (fst, values)
 }
 
+/// Merges the given defs into an existing index. Names which are no longer
+/// associated with any def are dropped from the index.
+fn merge_index(
+    index: &fst::Map<Vec<u8>>,
+    values: Vec<Vec<Id>>,
+    defs: Vec<(String, Id)>,
+) -> (fst::Map<Vec<u8>>, Vec<Vec<Id>>) {
+    let (new_fst, new_values) = build_index(defs);
+
+    let mut merged_values: Vec<Vec<Id>> = Vec::new();
+    let mut builder = fst::MapBuilder::memory();
+    let mut union = index.op().add(&new_fst).union();
+    while let Some((name, indexed_values)) = union.next() {
+        let ids: Vec<Id> = indexed_values
+            .iter()
+            .flat_map(|v| match v.index {
+                0 => &values[v.value as usize],
+                _ => &new_values[v.value as usize],
+            })
+            .cloned()
+            .collect();
+        if ids.is_empty() {
+            continue;
+        }
+        builder.insert(name, merged_values.len() as u64).expect("union of fsts is sorted");
+        merged_values.push(ids);
+    }
+    (builder.into_map(), merged_values)
+}
+
+// Returns the hasher for the file of `span`, having hashed the span itself.
+fn hasher_for<'m, 'a>(
+    hashers: &'m mut HashMap<&'a Path, DefaultHasher>,
+    seed: &DefaultHasher,
+    span: &'a raw::SpanData,
+) -> &'m mut DefaultHasher {
+    let hasher = hashers.entry(&span.file_name).or_insert_with(|| seed.clone());
+    hash_span(span, hasher);
+    hasher
+}
+
+fn hash_span<H: Hasher>(span: &raw::SpanData, state: &mut H) {
+    (span.byte_start, span.byte_end).hash(state);
+    (span.line_start.0, span.line_end.0, span.column_start.0, span.column_end.0).hash(state);
+}
+
 fn bad_span(span: &raw::SpanData, is_mod: bool) -> bool {
     span.file_name.to_str().map(|s| s.ends_with('>')).unwrap_or(true)
         || (!is_mod && span.byte_start == 0 && span.byte_end == 0)
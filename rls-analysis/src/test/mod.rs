@@ -1,8 +1,8 @@
 use crate::loader::SearchDirectory;
 use crate::raw::DefKind;
-use crate::{AnalysisHost, AnalysisLoader};
+use crate::{read_analysis_from_files, AnalysisHost, AnalysisLoader};
 
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
 use std::path::{Path, PathBuf};
 
 #[derive(Clone, new)]
@@ -343,6 +343,22 @@ fn test_self() {
     assert_eq!(def.unwrap(), spans[0]);
 }
 
+#[test]
+fn test_impl_items() {
+    let host = AnalysisHost::new_with_loader(TestAnalysisLoader::new(
+        Path::new("test_data/exprs/save-analysis").to_owned(),
+    ));
+    host.reload(Path::new("test_data/exprs"), Path::new("test_data/exprs")).unwrap();
+
+    let ids = host.search_for_id("Foo").unwrap();
+    let items = host.impl_items(ids[0]).unwrap();
+    assert_eq!(items.len(), 1);
+    let (trait_id, def) = &items[0];
+    assert_eq!(*trait_id, None);
+    assert_eq!(def.name, "bar");
+    assert_eq!(def.kind, DefKind::Method);
+}
+
 #[test]
 fn test_extern_fn() {
     let host = AnalysisHost::new_with_loader(TestAnalysisLoader::new(
@@ -373,3 +389,123 @@ fn test_all_ref_unique() {
     let refs = host.find_all_refs(&spans[2], true, true);
     assert_eq!(refs.unwrap().len(), 3);
 }
+
+#[test]
+fn test_globs() {
+    let host = AnalysisHost::new_with_loader(TestAnalysisLoader::new(
+        Path::new("test_data/rls-analysis").to_owned(),
+    ));
+    host.reload(Path::new("test_data/rls-analysis"), Path::new("test_data/rls-analysis")).unwrap();
+
+    let globs = host.globs().unwrap();
+    let glob_at = |file: &str, row: u32| {
+        globs
+            .iter()
+            .find(|(span, ..)| span.file.ends_with(file) && span.range.row_start.0 == row)
+            .map(|(_, module, names)| (module.as_str(), names.join(", ")))
+    };
+
+    // The names are sorted and the module is the one containing the import.
+    assert_eq!(
+        glob_at("ryu-0.2.7/src/d2s.rs", 22),
+        Some(("ryu::d2s", "copy_special_str, log10_pow2, log10_pow5, pow5bits".to_owned()))
+    );
+    assert_eq!(
+        glob_at("itertools-0.7.11/src/either_or_both.rs", 0),
+        Some(("itertools::either_or_both", "Both, Left, Right".to_owned()))
+    );
+    // A glob in the crate root is in the module named after the crate.
+    assert_eq!(glob_at("itertools-0.7.11/src/lib.rs", 125).unwrap().0, "itertools");
+    // A glob in a function body is in that function.
+    assert_eq!(glob_at("fst-0.3.3/src/raw/node.rs", 58).unwrap().0, "fst::raw::node::node_new");
+    assert_eq!(glob_at("ryu-0.2.7/src/d2s.rs", 0), None);
+}
+
+#[test]
+fn test_derive_macros() {
+    let host = AnalysisHost::new_with_loader(TestAnalysisLoader::new(
+        Path::new("test_data/rls-analysis").to_owned(),
+    ));
+    host.reload(Path::new("test_data/rls-analysis"), Path::new("test_data/rls-analysis")).unwrap();
+
+    // `derive_new` is a dependency whose `#[proc_macro_derive(new, ..)]`
+    // function defines the `new` derive.
+    let derives = host.derive_macros().unwrap();
+    assert!(derives.contains(&"new".to_owned()));
+    assert!(!derives.contains(&"derive".to_owned()));
+}
+
+#[test]
+fn test_incremental_reload() {
+    let path = Path::new("test_data/rls-analysis");
+    let loader = TestAnalysisLoader::new(path.to_owned());
+    let host = AnalysisHost::new_with_loader(loader.clone());
+    host.reload(path, path).unwrap();
+
+    let sorted_refs = |id| {
+        let mut refs = host.find_all_refs_by_id(id).unwrap();
+        refs.sort();
+        refs
+    };
+
+    let ids = host.search_for_id("DiagnosticSpan").unwrap();
+    assert_eq!(ids.len(), 1);
+    let refs = sorted_refs(ids[0]);
+    let span_refs = || -> HashMap<_, _> {
+        let ids = host.search_for_id("Span").unwrap();
+        ids.into_iter().map(|id| (id, sorted_refs(id))).collect()
+    };
+    let old_span_refs = span_refs();
+
+    // Renaming `DiagnosticSpan` only changes the data for `compiler.rs`, so the
+    // data for the other files of `rls_span` is kept as is.
+    let mut krate = read_analysis_from_files(&loader, HashMap::new(), &[] as &[&str])
+        .into_iter()
+        .find(|c| c.id.name == "rls_span")
+        .unwrap();
+    for def in krate.analysis.defs.iter_mut().filter(|d| d.name == "DiagnosticSpan") {
+        def.name = "RenamedSpan".to_owned();
+    }
+    host.reload_from_analysis(vec![krate.analysis], path, path, &[] as &[&str]).unwrap();
+
+    assert!(host.search_for_id("DiagnosticSpan").unwrap().is_empty());
+    assert_eq!(host.search_for_id("RenamedSpan").unwrap(), ids);
+    assert_eq!(sorted_refs(ids[0]), refs);
+    assert!(host
+        .matching_defs("DiagnosticSpan")
+        .unwrap()
+        .iter()
+        .all(|d| d.name != "DiagnosticSpan"));
+    assert_eq!(host.matching_defs("RenamedSpan").unwrap().len(), 1);
+
+    assert_eq!(span_refs(), old_span_refs);
+}
+
+#[test]
+fn test_incremental_reload_attributes() {
+    let path = Path::new("test_data/rls-analysis");
+    let loader = TestAnalysisLoader::new(path.to_owned());
+    let host = AnalysisHost::new_with_loader(loader.clone());
+    host.reload(path, path).unwrap();
+
+    let ids = host.search_for_id("DiagnosticSpan").unwrap();
+    assert_eq!(ids.len(), 1);
+    let attributes = host.get_def(ids[0]).unwrap().attributes;
+    assert!(!attributes.contains(&"must_use".to_owned()));
+
+    // Only the attributes of `DiagnosticSpan` change, which must still cause
+    // `compiler.rs` to be lowered again.
+    let mut krate = read_analysis_from_files(&loader, HashMap::new(), &[] as &[&str])
+        .into_iter()
+        .find(|c| c.id.name == "rls_span")
+        .unwrap();
+    for def in krate.analysis.defs.iter_mut().filter(|d| d.name == "DiagnosticSpan") {
+        let attribute = data::Attribute { value: "must_use".to_owned(), span: def.span.clone() };
+        def.attributes.push(attribute);
+    }
+    host.reload_from_analysis(vec![krate.analysis], path, path, &[] as &[&str]).unwrap();
+
+    let attributes = host.get_def(ids[0]).unwrap().attributes;
+    assert!(attributes.contains(&"must_use".to_owned()));
+    assert_eq!(host.search_for_id("DiagnosticSpan").unwrap(), ids);
+}
@@ -0,0 +1,413 @@
+//! Completion inside attributes: attribute names, derivable traits, lint
+//! names, `cfg` options and the Cargo features of the package.
+
+// FIXME: switch to something more ergonomic here, once available.
+// (Currently, there is no way to opt into sysroot crates without `extern crate`.)
+#[cfg(feature = "clippy")]
+extern crate rustc_errors;
+extern crate rustc_lint;
+#[cfg(feature = "clippy")]
+extern crate rustc_session;
+extern crate rustc_span;
+
+use std::path::Path;
+
+use lazy_static::lazy_static;
+use regex::Regex;
+use rls_vfs::FileContents;
+
+use crate::actions::codegen;
+use crate::actions::InitActionContext;
+use crate::lsp_data::*;
+
+/// Built-in attributes as (name, snippet, whether the attribute only applies
+/// to the crate, i.e., is an inner attribute of the crate root).
+const BUILTIN_ATTRIBUTES: &[(&str, &str, bool)] = &[
+    ("allow", "allow($0)", false),
+    ("warn", "warn($0)", false),
+    ("deny", "deny($0)", false),
+    ("forbid", "forbid($0)", false),
+    ("cfg", "cfg($0)", false),
+    ("cfg_attr", "cfg_attr($1, $0)", false),
+    ("derive", "derive($0)", false),
+    ("test", "test", false),
+    ("ignore", "ignore", false),
+    ("should_panic", "should_panic", false),
+    ("inline", "inline", false),
+    ("cold", "cold", false),
+    ("track_caller", "track_caller", false),
+    ("target_feature", "target_feature(enable = \"$0\")", false),
+    ("must_use", "must_use", false),
+    ("deprecated", "deprecated", false),
+    ("doc", "doc = \"$0\"", false),
+    ("path", "path = \"$0\"", false),
+    ("macro_use", "macro_use", false),
+    ("macro_export", "macro_export", false),
+    ("proc_macro", "proc_macro", false),
+    ("proc_macro_derive", "proc_macro_derive($0)", false),
+    ("proc_macro_attribute", "proc_macro_attribute", false),
+    ("repr", "repr($0)", false),
+    ("non_exhaustive", "non_exhaustive", false),
+    ("no_mangle", "no_mangle", false),
+    ("export_name", "export_name = \"$0\"", false),
+    ("link", "link(name = \"$0\")", false),
+    ("link_name", "link_name = \"$0\"", false),
+    ("link_section", "link_section = \"$0\"", false),
+    ("used", "used", false),
+    ("global_allocator", "global_allocator", false),
+    ("panic_handler", "panic_handler", false),
+    ("automatically_derived", "automatically_derived", false),
+    ("no_implicit_prelude", "no_implicit_prelude", false),
+    ("no_std", "no_std", true),
+    ("no_main", "no_main", true),
+    ("crate_name", "crate_name = \"$0\"", true),
+    ("crate_type", "crate_type = \"$0\"", true),
+    ("recursion_limit", "recursion_limit = \"$0\"", true),
+    ("type_length_limit", "type_length_limit = \"$0\"", true),
+    ("windows_subsystem", "windows_subsystem = \"$0\"", true),
+    ("feature", "feature($0)", true),
+];
+
+lazy_static! {
+    /// The lints and lint groups of `rustc` and, with the `clippy` feature, of
+    /// Clippy, sorted by name. Those of Clippy start with `clippy::`.
+    static ref LINTS: Vec<String> = registered_lints();
+}
+
+/// Returns the names of the lints and lint groups registered with the lint
+/// store of `rustc`, along with those of Clippy if it's linked.
+fn registered_lints() -> Vec<String> {
+    rustc_span::with_default_session_globals(|| {
+        #[allow(unused_mut)]
+        let mut store = rustc_lint::new_lint_store(false, false);
+        #[cfg(feature = "clippy")]
+        {
+            let sess = rustc_session::build_session(
+                rustc_session::config::Options::default(),
+                None,
+                rustc_errors::registry::Registry::new(&[]),
+                rustc_session::DiagnosticOutput::Default,
+                Default::default(),
+                None,
+                None,
+            );
+            let conf = clippy_lints::read_conf(&sess);
+            clippy_lints::register_plugins(&mut store, &sess, &conf);
+            clippy_lints::register_pre_expansion_lints(&mut store);
+        }
+
+        let lints = store.get_lints().iter().map(|lint| lint.name_lower());
+        let groups = store.get_lint_groups().into_iter().map(|(name, ..)| name.to_owned());
+        // `warnings` isn't registered, it's handled by the lint levels.
+        let mut names: Vec<_> = lints.chain(groups).chain(Some("warnings".to_owned())).collect();
+        names.sort();
+        names.dedup();
+        names
+    })
+}
+
+/// Configuration options as (name, snippet).
+const CFG_OPTIONS: &[(&str, &str)] = &[
+    ("feature", "feature = \"$0\""),
+    ("test", "test"),
+    ("debug_assertions", "debug_assertions"),
+    ("doc", "doc"),
+    ("doctest", "doctest"),
+    ("unix", "unix"),
+    ("windows", "windows"),
+    ("proc_macro", "proc_macro"),
+    ("target_os", "target_os = \"$0\""),
+    ("target_family", "target_family = \"$0\""),
+    ("target_arch", "target_arch = \"$0\""),
+    ("target_env", "target_env = \"$0\""),
+    ("target_vendor", "target_vendor = \"$0\""),
+    ("target_endian", "target_endian = \"$0\""),
+    ("target_pointer_width", "target_pointer_width = \"$0\""),
+    ("target_has_atomic", "target_has_atomic = \"$0\""),
+    ("panic", "panic = \"$0\""),
+    ("any", "any($0)"),
+    ("all", "all($0)"),
+    ("not", "not($0)"),
+];
+
+/// What is being completed in an attribute, see `attribute_context`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum AttributeContext {
+    /// The name of an attribute, which is an inner attribute (`#![..]`) if
+    /// `inner`.
+    Attribute { inner: bool },
+    /// A trait in `derive(..)`, following those already listed.
+    Derive { existing: Vec<String> },
+    /// A lint in `allow(..)`, `warn(..)`, `deny(..)` or `forbid(..)`, whose path
+    /// starts with `clippy::` if `clippy`.
+    Lint { clippy: bool },
+    /// A configuration option in `cfg(..)`, also nested in `any`, `all` or
+    /// `not`.
+    Cfg,
+    /// A Cargo feature in `cfg(feature = "..")`, whose name starts at the given
+    /// offset.
+    Feature { start: usize },
+}
+
+/// A group in an attribute, e.g., the arguments of `derive(..)`.
+struct Group {
+    name: String,
+    /// The offset at which the arguments start.
+    start: usize,
+    /// The index of the argument being parsed.
+    arg: usize,
+    /// The offset at which that argument starts.
+    arg_start: usize,
+}
+
+/// Parses the attribute around `offset`, returning what is being completed
+/// there, or `None` if `offset` isn't in an attribute (or at a position which
+/// can't be completed, e.g., the value of `#[doc = ".."]`).
+pub fn attribute_context(text: &str, offset: usize) -> Option<AttributeContext> {
+    lazy_static! {
+        static ref FEATURE: Regex = Regex::new(r"^\s*feature\s*=\s*$").unwrap();
+        static ref PATH: Regex = Regex::new(r"^\s*(?:[A-Za-z_]\w*\s*::\s*)*\w*$").unwrap();
+    }
+    let before = &text[..offset];
+    let (open, inner) = match (before.rfind("#["), before.rfind("#![")) {
+        (Some(outer), Some(inner)) if inner > outer => (inner + 2, true),
+        (Some(outer), _) => (outer + 1, false),
+        (None, Some(inner)) => (inner + 2, true),
+        (None, None) => return None,
+    };
+
+    let mut groups =
+        vec![Group { name: String::new(), start: open + 1, arg: 0, arg_start: open + 1 }];
+    let mut path_start = open + 1;
+    let mut string_start = None;
+    let mut brackets = 0;
+    let mut escaped = false;
+    for (i, c) in text[open + 1..offset].char_indices().map(|(i, c)| (open + 1 + i, c)) {
+        if string_start.is_some() {
+            match c {
+                _ if escaped => escaped = false,
+                '\\' => escaped = true,
+                '"' => string_start = None,
+                _ => {}
+            }
+            continue;
+        }
+        match c {
+            '"' => string_start = Some(i),
+            '(' => {
+                let name = text[path_start..i].trim().to_owned();
+                groups.push(Group { name, start: i + 1, arg: 0, arg_start: i + 1 });
+            }
+            ')' => {
+                groups.pop();
+                if groups.is_empty() {
+                    return None;
+                }
+            }
+            ',' => {
+                let group = groups.last_mut().unwrap();
+                group.arg += 1;
+                group.arg_start = i + 1;
+            }
+            '[' => brackets += 1,
+            ']' if brackets == 0 => return None,
+            ']' => brackets -= 1,
+            ';' | '{' | '}' => return None,
+            _ => {}
+        }
+        if !(c.is_alphanumeric() || c == '_' || c == ':' || c.is_whitespace()) {
+            path_start = i + 1;
+        }
+    }
+
+    let group = groups.last().unwrap();
+    let in_cfg = groups
+        .iter()
+        .rev()
+        .find(|group| !["any", "all", "not"].contains(&&group.name[..]))
+        .map_or(false, |group| group.name == "cfg" || (group.name == "cfg_attr" && group.arg == 0));
+    if let Some(string_start) = string_start {
+        if in_cfg && FEATURE.is_match(&text[group.arg_start..string_start]) {
+            return Some(AttributeContext::Feature { start: string_start + 1 });
+        }
+        return None;
+    }
+    let arg = &text[group.arg_start..offset];
+    if !PATH.is_match(arg) {
+        return None;
+    }
+
+    match &group.name[..] {
+        _ if in_cfg => Some(AttributeContext::Cfg),
+        "" | "cfg_attr" => Some(AttributeContext::Attribute { inner }),
+        "derive" => {
+            let existing = codegen::split_top_level(&text[group.start..group.arg_start])
+                .into_iter()
+                .map(|derive| derive.trim().rsplit("::").next().unwrap().to_owned())
+                .filter(|derive| !derive.is_empty())
+                .collect();
+            Some(AttributeContext::Derive { existing })
+        }
+        "allow" | "warn" | "deny" | "forbid" => {
+            Some(AttributeContext::Lint { clippy: arg.trim_start().starts_with("clippy") })
+        }
+        _ => None,
+    }
+}
+
+/// Returns whether the item following the attribute at `offset` is an enum.
+fn precedes_enum(text: &str, offset: usize) -> bool {
+    lazy_static! {
+        static ref ITEM: Regex = Regex::new(r"\b(struct|enum|union)\b").unwrap();
+    }
+    ITEM.captures(&text[offset..]).map_or(false, |captures| &captures[1] == "enum")
+}
+
+/// Completes the attribute at `position`, or returns `None` if `position`
+/// isn't in an attribute.
+pub fn complete(
+    ctx: &InitActionContext,
+    file_path: &Path,
+    position: Position,
+) -> Option<Vec<CompletionItem>> {
+    let text = match ctx.vfs.load_file(file_path) {
+        Ok(FileContents::Text(text)) => text,
+        _ => return None,
+    };
+    let offset = codegen::position_to_offset(&text, position)?;
+    let context = attribute_context(&text, offset)?;
+    let snippets = ctx.client_capabilities.code_completion_has_snippet_support;
+    let item = |label: &str, kind, snippet: Option<&str>| {
+        let snippet = snippet.filter(|snippet| snippets && *snippet != label);
+        CompletionItem {
+            label: label.to_owned(),
+            kind: Some(kind),
+            insert_text: snippet.map(ToOwned::to_owned),
+            insert_text_format: snippet.map(|_| InsertTextFormat::Snippet),
+            ..CompletionItem::default()
+        }
+    };
+
+    let items = match context {
+        AttributeContext::Attribute { inner } => BUILTIN_ATTRIBUTES
+            .iter()
+            .filter(|(_, _, crate_level)| inner || !crate_level)
+            .map(|(name, snippet, _)| item(name, CompletionItemKind::Keyword, Some(snippet)))
+            .collect(),
+        AttributeContext::Derive { existing } => {
+            let is_enum = precedes_enum(&text, offset);
+            let mut items: Vec<_> = codegen::derivable_traits(&existing, is_enum)
+                .into_iter()
+                .map(|name| item(name, CompletionItemKind::Interface, None))
+                .collect();
+            // Derive macros of the program and its dependencies, e.g.,
+            // `Serialize`.
+            let macros = ctx.analysis.derive_macros().unwrap_or_default();
+            items.extend(
+                macros
+                    .iter()
+                    .filter(|name| !existing.contains(name))
+                    .map(|name| item(name, CompletionItemKind::Interface, None)),
+            );
+            items
+        }
+        AttributeContext::Lint { clippy } => {
+            // Clippy lints are also offered before `clippy::` is typed.
+            let lints: Vec<&str> = if clippy {
+                LINTS.iter().filter_map(|lint| lint.strip_prefix("clippy::")).collect()
+            } else {
+                LINTS.iter().map(String::as_str).collect()
+            };
+            lints.into_iter().map(|lint| item(lint, CompletionItemKind::Value, None)).collect()
+        }
+        AttributeContext::Cfg => CFG_OPTIONS
+            .iter()
+            .map(|(name, snippet)| item(name, CompletionItemKind::Keyword, Some(snippet)))
+            .collect(),
+        AttributeContext::Feature { start } => {
+            let project = ctx.project_model().ok()?;
+            let package = project.package_for_path(file_path)?;
+            let range = Range::new(codegen::offset_to_position(&text, start), position);
+            package
+                .features(&project)
+                .iter()
+                .map(|feature| CompletionItem {
+                    label: feature.clone(),
+                    kind: Some(CompletionItemKind::Value),
+                    detail: Some("feature".to_owned()),
+                    // Feature names may contain `-`, so replace the whole name.
+                    text_edit: Some(TextEdit::new(range, feature.clone())),
+                    ..CompletionItem::default()
+                })
+                .collect()
+        }
+    };
+    Some(items)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn context(text: &str) -> Option<AttributeContext> {
+        attribute_context(&text.replace('|', ""), text.find('|').unwrap())
+    }
+
+    #[test]
+    fn parses_attribute_context() {
+        assert_eq!(
+            context("#[te|]\nfn f() {}"),
+            Some(AttributeContext::Attribute { inner: false })
+        );
+        assert_eq!(context("#![no|]"), Some(AttributeContext::Attribute { inner: true }));
+        assert_eq!(
+            context("#[cfg_attr(test, al|)]"),
+            Some(AttributeContext::Attribute { inner: false })
+        );
+        assert_eq!(
+            context("#[derive(Clone, Debug, P|)]"),
+            Some(AttributeContext::Derive { existing: vec!["Clone".into(), "Debug".into()] })
+        );
+        assert_eq!(
+            context("#[cfg_attr(test, derive(serde::Serialize, |))]"),
+            Some(AttributeContext::Derive { existing: vec!["Serialize".into()] })
+        );
+        assert_eq!(context("#[allow(dead_|)]"), Some(AttributeContext::Lint { clippy: false }));
+        assert_eq!(
+            context("#![warn(unused, clippy::ne|)]"),
+            Some(AttributeContext::Lint { clippy: true })
+        );
+        assert_eq!(context("#[cfg(|)]"), Some(AttributeContext::Cfg));
+        assert_eq!(context("#[cfg(all(unix, not(te|)))]"), Some(AttributeContext::Cfg));
+        assert_eq!(context("#[cfg_attr(fe|, derive(Debug))]"), Some(AttributeContext::Cfg));
+        assert_eq!(
+            context("#[cfg(feature = \"ser|\")]"),
+            Some(AttributeContext::Feature { start: 17 })
+        );
+        assert_eq!(
+            context("#[cfg(any(unix, feature = \"|"),
+            Some(AttributeContext::Feature { start: 27 })
+        );
+
+        assert_eq!(context("#[cfg(target_os = \"|\")]"), None);
+        assert_eq!(context("#[doc = \"te|\"]"), None);
+        assert_eq!(context("#[test]\nfn f() { |}"), None);
+        assert_eq!(context("#[derive(Debug)]\nstruct S;\n|"), None);
+        assert_eq!(context("let v = x[|];"), None);
+        assert_eq!(context("#[repr(|)]"), None);
+    }
+
+    #[test]
+    fn lists_registered_lints() {
+        for lint in &["warnings", "unused", "unused_imports", "rust_2018_idioms"] {
+            assert!(LINTS.iter().any(|l| l == lint), "missing lint `{}`", lint);
+        }
+        assert_eq!(LINTS.iter().any(|l| l == "clippy::all"), cfg!(feature = "clippy"));
+    }
+
+    #[test]
+    fn finds_attributed_item_kind() {
+        assert!(precedes_enum("#[derive()]\npub enum E { A }", 9));
+        assert!(!precedes_enum("#[derive()]\n#[allow(x)]\nstruct S { e: enum_t }", 9));
+    }
+}
@@ -0,0 +1,192 @@
+//! Baselines of the warnings of a project, so that only new warnings are
+//! surfaced.
+//!
+//! A baseline records each warning by its lint, its file and a hash of the
+//! source it points at, rather than by its position, so that it still matches
+//! the warning once the code around it has moved.
+
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use lsp_types::{DiagnosticSeverity, NumberOrString};
+use serde_derive::{Deserialize, Serialize};
+
+use crate::actions::post_build::BuildResults;
+use crate::lsp_data::RichDiagnostic;
+
+/// The version of the baseline file format.
+const VERSION: u32 = 1;
+
+/// A warning recorded in a baseline.
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub struct BaselineEntry {
+    /// The lint of the warning, e.g. `clippy::needless_return`.
+    pub lint: String,
+    /// The file of the warning, relative to the workspace root and with `/`
+    /// as separator.
+    pub file: String,
+    /// The hash of the whitespace-normalized source the warning points at.
+    pub hash: String,
+}
+
+/// The warnings of a project at some point, which aren't new.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Baseline {
+    pub version: u32,
+    pub warnings: Vec<BaselineEntry>,
+}
+
+impl Baseline {
+    /// Creates a baseline of the warnings of the build `results`, in the
+    /// files of the project at `project_path`.
+    pub fn from_results(results: &BuildResults, project_path: &Path) -> Baseline {
+        let mut warnings: Vec<_> = results
+            .iter()
+            .filter(|(path, _)| path.starts_with(project_path))
+            .flat_map(|(path, diagnostics)| {
+                let file = relative_file(project_path, path);
+                diagnostics.iter().filter_map(move |(diagnostic, _)| {
+                    let (lint, hash) = key(diagnostic)?;
+                    Some(BaselineEntry { lint, file: file.clone(), hash })
+                })
+            })
+            .collect();
+        warnings.sort();
+        Baseline { version: VERSION, warnings }
+    }
+
+    pub fn load(path: &Path) -> io::Result<Baseline> {
+        let json = fs::read_to_string(path)?;
+        serde_json::from_str(&json).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        let json = serde_json::to_string_pretty(self)?;
+        fs::write(path, json + "\n")
+    }
+
+    /// Returns, for each of the `diagnostics` of the file at `path`, whether
+    /// it's a warning recorded in the baseline. If a warning occurs more often
+    /// than it was recorded, its last occurrences are new.
+    pub fn baselined<'a>(
+        &self,
+        project_path: &Path,
+        path: &Path,
+        diagnostics: impl IntoIterator<Item = &'a RichDiagnostic>,
+    ) -> Vec<bool> {
+        let file = relative_file(project_path, path);
+        let mut remaining: HashMap<(String, String), usize> = HashMap::new();
+        for entry in self.warnings.iter().filter(|entry| entry.file == file) {
+            *remaining.entry((entry.lint.clone(), entry.hash.clone())).or_default() += 1;
+        }
+
+        diagnostics
+            .into_iter()
+            .map(|diagnostic| match key(diagnostic).and_then(|key| remaining.get_mut(&key)) {
+                Some(count) if *count > 0 => {
+                    *count -= 1;
+                    true
+                }
+                _ => false,
+            })
+            .collect()
+    }
+}
+
+/// Returns the lint and the snippet hash identifying the `diagnostic` in a
+/// baseline, if it's a warning.
+fn key(diagnostic: &RichDiagnostic) -> Option<(String, String)> {
+    if diagnostic.severity != Some(DiagnosticSeverity::Warning) {
+        return None;
+    }
+    let lint = match &diagnostic.code {
+        Some(NumberOrString::String(code)) => code.clone(),
+        Some(NumberOrString::Number(code)) => code.to_string(),
+        None => String::new(),
+    };
+    let snippet = diagnostic.snippet.as_deref().unwrap_or_default();
+    Some((lint, format!("{:016x}", snippet_hash(snippet))))
+}
+
+fn relative_file(project_path: &Path, path: &Path) -> String {
+    let relative = path.strip_prefix(project_path).unwrap_or(path);
+    relative.components().map(|c| c.as_os_str().to_string_lossy()).collect::<Vec<_>>().join("/")
+}
+
+/// The 64-bit FNV-1a hash of `snippet`. Unlike the std hashers, it's stable
+/// across Rust versions and platforms, so that it can be stored in a baseline.
+fn snippet_hash(snippet: &str) -> u64 {
+    snippet.bytes().fold(0xcbf2_9ce4_8422_2325, |hash, byte| {
+        (hash ^ u64::from(byte)).wrapping_mul(0x0100_0000_01b3)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use lsp_types::Diagnostic;
+    use std::path::PathBuf;
+
+    fn warning(lint: &str, line: u64, snippet: &str) -> RichDiagnostic {
+        let mut diagnostic = RichDiagnostic::from(Diagnostic {
+            range: lsp_types::Range::new(
+                lsp_types::Position::new(line, 0),
+                lsp_types::Position::new(line, 4),
+            ),
+            severity: Some(DiagnosticSeverity::Warning),
+            code: Some(NumberOrString::String(lint.to_owned())),
+            message: "warning".to_owned(),
+            ..Diagnostic::default()
+        });
+        diagnostic.snippet = Some(snippet.to_owned());
+        diagnostic
+    }
+
+    #[test]
+    fn baselined_warnings() {
+        let project = PathBuf::from("/project");
+        let file = project.join("src").join("lib.rs");
+
+        let mut results = BuildResults::new();
+        results.insert(
+            file.clone(),
+            vec![
+                (warning("dead_code", 1, "fn foo()"), vec![]),
+                (warning("clippy::identity_op", 3, "1 / 1"), vec![]),
+            ],
+        );
+        results.insert(
+            PathBuf::from("/elsewhere/lib.rs"),
+            vec![(warning("dead_code", 1, "fn bar()"), vec![])],
+        );
+        let baseline = Baseline::from_results(&results, &project);
+        assert_eq!(baseline.warnings.len(), 2);
+        assert_eq!(baseline.warnings[0].lint, "clippy::identity_op");
+        assert_eq!(baseline.warnings[0].file, "src/lib.rs");
+        assert_eq!(baseline.warnings[0].hash, format!("{:016x}", snippet_hash("1 / 1")));
+
+        // The warnings moved, and a new one was added alongside them.
+        let diagnostics = vec![
+            warning("dead_code", 10, "fn foo()"),
+            warning("clippy::identity_op", 12, "1 / 1"),
+            warning("clippy::identity_op", 13, "1 / 1"),
+            warning("dead_code", 14, "fn baz()"),
+        ];
+        assert_eq!(
+            baseline.baselined(&project, &file, &diagnostics),
+            vec![true, true, false, false]
+        );
+        assert_eq!(
+            baseline.baselined(&project, &project.join("src/main.rs"), &diagnostics[..1]),
+            vec![false]
+        );
+    }
+
+    #[test]
+    fn stable_snippet_hash() {
+        assert_eq!(snippet_hash(""), 0xcbf2_9ce4_8422_2325);
+        assert_eq!(snippet_hash("a"), 0xaf63_dc4c_8601_ec8c);
+    }
+}
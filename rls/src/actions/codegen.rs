@@ -0,0 +1,2036 @@
+//! Text-level helpers for the code actions which generate code.
+//!
+//! Like `imports`, these work on the source text rather than on a syntax tree:
+//! they only need to understand enough of an item's shape (delimiters, generic
+//! parameter lists, signatures) to know where the new code goes. Postfix
+//! expressions are the exception, as they're parsed to find their start.
+
+// FIXME: switch to something more ergonomic here, once available.
+// (Currently, there is no way to opt into sysroot crates without `extern crate`.)
+extern crate rustc_ast;
+extern crate rustc_parse;
+extern crate rustc_session;
+extern crate rustc_span;
+
+use lazy_static::lazy_static;
+use regex::Regex;
+use rls_analysis::DefKind;
+
+use self::rustc_ast::visit::{self, Visitor};
+use self::rustc_ast::{token, Expr, ExprKind, MacCall};
+use self::rustc_session::parse::ParseSess;
+use self::rustc_span::FileName;
+
+use crate::actions::imports;
+use crate::lsp_data::{Position, Range, TextEdit};
+
+/// Converts a byte offset in `text` into a `Position`, whose character is
+/// counted in UTF-16 code units like LSP does.
+pub fn offset_to_position(text: &str, offset: usize) -> Position {
+    let before = &text[..offset];
+    let line = before.matches('\n').count();
+    let line_start = before.rfind('\n').map(|i| i + 1).unwrap_or(0);
+    let character = before[line_start..].encode_utf16().count();
+    Position::new(line as u64, character as u64)
+}
+
+/// Converts a `Position` (counted in UTF-16 code units) into a byte offset in
+/// `text`, if it's in bounds and on a character boundary.
+pub fn position_to_offset(text: &str, position: Position) -> Option<usize> {
+    let mut line_start = 0;
+    for _ in 0..position.line {
+        line_start += text[line_start..].find('\n')? + 1;
+    }
+    let line = text[line_start..].split('\n').next().unwrap_or("");
+    let character = position.character as usize;
+    let mut units = 0;
+    for (i, c) in line.char_indices().chain(Some((line.len(), '\n'))) {
+        if units >= character {
+            return Some(line_start + i).filter(|_| units == character);
+        }
+        units += c.len_utf16();
+    }
+    None
+}
+
+/// Returns the leading whitespace of the line containing `offset`.
+pub fn indent_at(text: &str, offset: usize) -> &str {
+    let line_start = text[..offset].rfind('\n').map(|i| i + 1).unwrap_or(0);
+    let line = &text[line_start..];
+    let len = line.len() - line.trim_start_matches(&[' ', '\t'][..]).len();
+    &line[..len]
+}
+
+/// Scans `text` from `start`, calling `f` with the offset and character of
+/// everything outside string and character literals and comments. Stops as
+/// soon as `f` returns `Some`.
+fn scan_code<T>(
+    text: &str,
+    start: usize,
+    mut f: impl FnMut(usize, char) -> Option<T>,
+) -> Option<T> {
+    let mut chars = text[start..].char_indices().map(|(i, c)| (i + start, c));
+    while let Some((i, c)) = chars.next() {
+        let rest = &text[i..];
+        if rest.starts_with("//") {
+            chars.by_ref().find(|&(_, c)| c == '\n');
+        } else if rest.starts_with("/*") {
+            chars.next();
+            let mut prev = ' ';
+            chars.by_ref().find(|&(_, c)| {
+                let done = prev == '*' && c == '/';
+                prev = c;
+                done
+            });
+        } else if c == '"' {
+            let mut escaped = false;
+            chars.by_ref().find(|&(_, c)| {
+                let done = !escaped && c == '"';
+                escaped = !escaped && c == '\\';
+                done
+            });
+        } else if c == '\'' && (rest.starts_with("'\\") || rest.chars().nth(2) == Some('\'')) {
+            // A character literal rather than a lifetime.
+            let mut escaped = false;
+            chars.by_ref().find(|&(_, c)| {
+                let done = !escaped && c == '\'';
+                escaped = !escaped && c == '\\';
+                done
+            });
+        } else if let Some(t) = f(i, c) {
+            return Some(t);
+        }
+    }
+    None
+}
+
+/// Returns the offset of the delimiter closing the one at `open`.
+///
+/// Angle brackets are matched too, skipping the `>` of `->` and `=>`.
+pub fn matching_delimiter(text: &str, open: usize) -> Option<usize> {
+    let (open_char, close_char) = match text[open..].chars().next()? {
+        '(' => ('(', ')'),
+        '[' => ('[', ']'),
+        '{' => ('{', '}'),
+        '<' => ('<', '>'),
+        _ => return None,
+    };
+    let mut depth = 0;
+    scan_code(text, open, |i, c| {
+        if c == close_char && !(c == '>' && (text[..i].ends_with('-') || text[..i].ends_with('=')))
+        {
+            depth -= 1;
+            if depth == 0 {
+                return Some(i);
+            }
+        } else if c == open_char {
+            depth += 1;
+        }
+        None
+    })
+}
+
+/// Splits a comma-separated list on its top-level commas, e.g., the contents
+/// of a generic parameter list.
+pub fn split_top_level(list: &str) -> Vec<&str> {
+    let mut result = vec![];
+    let mut depth = 0i32;
+    let mut start = 0;
+    let _ = scan_code(list, 0, |i, c| {
+        match c {
+            '(' | '[' | '{' | '<' => depth += 1,
+            '>' if list[..i].ends_with('-') || list[..i].ends_with('=') => {}
+            ')' | ']' | '}' | '>' => depth -= 1,
+            ',' if depth == 0 => {
+                result.push(list[start..i].trim());
+                start = i + 1;
+            }
+            _ => {}
+        }
+        None::<()>
+    });
+    result.push(list[start..].trim());
+    result.retain(|s| !s.is_empty());
+    result
+}
+
+/// Returns the offset of the first `c` in `text` outside of any delimiters.
+pub fn find_top_level(text: &str, c: char) -> Option<usize> {
+    let mut depth = 0i32;
+    scan_code(text, 0, |i, ch| {
+        match ch {
+            '(' | '[' | '{' | '<' => depth += 1,
+            '>' if text[..i].ends_with('-') || text[..i].ends_with('=') => {}
+            ')' | ']' | '}' | '>' => depth -= 1,
+            _ if ch == c && depth == 0 => return Some(i),
+            _ => {}
+        }
+        None
+    })
+}
+
+/// A parameter of a generic parameter list, e.g. `Rhs = Self` or `'a`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct GenericParam {
+    pub name: String,
+    pub default: Option<String>,
+}
+
+/// Parses the contents of a generic parameter list (without the brackets).
+pub fn parse_generic_params(list: &str) -> Vec<GenericParam> {
+    split_top_level(list)
+        .into_iter()
+        .map(|param| {
+            let param = param.trim_start_matches("const ").trim();
+            let name_len = param
+                .find(|c: char| c == ':' || c == '=' || c.is_whitespace())
+                .unwrap_or(param.len());
+            let default = find_top_level(param, '=').map(|i| param[i + 1..].trim().to_owned());
+            GenericParam { name: param[..name_len].to_owned(), default }
+        })
+        .collect()
+}
+
+/// Returns the contents of the generic list directly following the first
+/// occurrence of the word `name` in `text`, e.g., `T, U` for `Foo` in
+/// `trait Foo<T, U>: Bar`.
+pub fn generic_list_after<'t>(text: &'t str, name: &str) -> Option<&'t str> {
+    let mut search_from = 0;
+    while let Some(i) = text[search_from..].find(name) {
+        let start = search_from + i;
+        let end = start + name.len();
+        search_from = end;
+        let is_ident = |c: char| c.is_alphanumeric() || c == '_';
+        if text[..start].ends_with(is_ident) || text[end..].starts_with(is_ident) {
+            continue;
+        }
+        let open = end + (text[end..].len() - text[end..].trim_start().len());
+        if !text[open..].starts_with('<') {
+            return None;
+        }
+        let close = matching_delimiter(text, open)?;
+        return Some(&text[open + 1..close]);
+    }
+    None
+}
+
+/// Replaces each of `params` in `text` with the corresponding argument from
+/// `args` (or its default if there are fewer arguments than parameters).
+pub fn substitute_generics(text: &str, params: &[GenericParam], args: &[String]) -> String {
+    let substitutions: Vec<(&str, &str)> = params
+        .iter()
+        .enumerate()
+        .filter_map(|(i, param)| {
+            let arg = args.get(i).or(param.default.as_ref())?;
+            Some((param.name.as_str(), arg.as_str()))
+        })
+        .collect();
+    substitute_names(text, &substitutions)
+}
+
+/// Returns, for each byte of `text`, whether it's part of the code proper
+/// rather than of a literal or comment.
+fn code_mask(text: &str) -> Vec<bool> {
+    let mut mask = vec![false; text.len()];
+    let _ = scan_code(text, 0, |i, c| {
+        mask[i..i + c.len_utf8()].iter_mut().for_each(|b| *b = true);
+        None::<()>
+    });
+    mask
+}
+
+/// Returns the ranges of the names (identifiers or lifetimes) in `text` that
+/// may refer to variables or items in scope. Names following a `.` or `::`
+/// are skipped, as they're fields or associated items, as are field names in
+/// struct expressions and anything in literals or comments.
+fn bare_names(text: &str) -> Vec<(usize, usize)> {
+    lazy_static! {
+        static ref NAME: Regex = Regex::new(r"(::\s*|\.\s*)?('?[A-Za-z_][A-Za-z0-9_]*)").unwrap();
+    }
+
+    let mask = code_mask(text);
+    NAME.captures_iter(text)
+        .filter_map(|caps| {
+            let name = caps.get(2).unwrap();
+            let rest = text[name.end()..].trim_start();
+            let is_field = rest.starts_with(':')
+                && !rest.starts_with("::")
+                && text[..name.start()].trim_end().ends_with(&['{', ','][..]);
+            if caps.get(1).is_none() && !is_field && mask[name.start()] {
+                Some((name.start(), name.end()))
+            } else {
+                None
+            }
+        })
+        .collect()
+}
+
+/// Replaces the names (identifiers or lifetimes) in `text` according to
+/// `substitutions`. Names following a `.` or `::` are left alone, as they're
+/// fields or associated items, as are field names in struct expressions.
+pub fn substitute_names(text: &str, substitutions: &[(&str, &str)]) -> String {
+    let mut result = String::with_capacity(text.len());
+    let mut last = 0;
+    for (start, end) in bare_names(text) {
+        if let Some((_, arg)) = substitutions.iter().find(|(param, _)| *param == &text[start..end])
+        {
+            result.push_str(&text[last..start]);
+            result.push_str(arg);
+            last = end;
+        }
+    }
+    result.push_str(&text[last..]);
+    result
+}
+
+/// Returns whether `text` uses `name` as a variable or an item in scope, see
+/// `substitute_names`.
+pub fn refers_to(text: &str, name: &str) -> bool {
+    bare_names(text).into_iter().any(|(start, end)| &text[start..end] == name)
+}
+
+/// The parts of an `impl Trait for Type { .. }` block needed to add items to it.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ImplBlock {
+    /// The last segment of the path of the implemented trait.
+    pub trait_name: String,
+    /// The offset of `trait_name` in the text.
+    pub trait_name_offset: usize,
+    /// The generic arguments passed to the trait.
+    pub trait_args: Vec<String>,
+    /// The offsets of the braces delimiting the body.
+    pub open: usize,
+    pub close: usize,
+}
+
+/// Parses the trait impl block starting at `start`.
+pub fn parse_impl_block(text: &str, start: usize) -> Option<ImplBlock> {
+    lazy_static! {
+        static ref IMPL: Regex = Regex::new(r"^(?:(?:unsafe|default)\s+)*impl\b\s*").unwrap();
+        static ref FOR: Regex = Regex::new(r"\sfor\s").unwrap();
+    }
+
+    let mut offset = start + IMPL.find(&text[start..])?.end();
+    if text[offset..].starts_with('<') {
+        offset = matching_delimiter(text, offset)? + 1;
+        offset += text[offset..].len() - text[offset..].trim_start().len();
+    }
+    let open = scan_code(text, offset, |i, c| if c == '{' { Some(i) } else { None })?;
+    let header = &text[offset..open];
+
+    // The trait path ends at the first ` for ` outside of its generic arguments.
+    let mut trait_end = None;
+    for m in FOR.find_iter(header) {
+        let prefix = &header[..m.start()];
+        if prefix.matches('<').count() <= prefix.matches('>').count() - prefix.matches("->").count()
+        {
+            trait_end = Some(m.start());
+            break;
+        }
+    }
+    let trait_path = header[..trait_end?].trim_end();
+    let (path, trait_args) = match trait_path.find('<') {
+        Some(i) => {
+            let close = matching_delimiter(trait_path, i)?;
+            let args = split_top_level(&trait_path[i + 1..close]);
+            (&trait_path[..i], args.into_iter().map(str::to_owned).collect())
+        }
+        None => (trait_path, vec![]),
+    };
+    let path = path.trim_end();
+    let name_start = path.rfind("::").map(|i| i + 2).unwrap_or(0);
+
+    Some(ImplBlock {
+        trait_name: path[name_start..].to_owned(),
+        trait_name_offset: offset + name_start,
+        trait_args,
+        open,
+        close: matching_delimiter(text, open)?,
+    })
+}
+
+/// Returns the declaration of the item whose name is at `offset`, up to (but
+/// excluding) its body or terminating `;`. Continuation lines are dedented
+/// relative to the first line.
+pub fn item_declaration(text: &str, offset: usize) -> Option<String> {
+    let indent = indent_at(text, offset);
+    let line_start = text[..offset].rfind('\n').map(|i| i + 1).unwrap_or(0) + indent.len();
+
+    let mut depth = 0;
+    let end = scan_code(text, line_start, |i, c| match c {
+        '(' | '[' => {
+            depth += 1;
+            None
+        }
+        ')' | ']' => {
+            depth -= 1;
+            None
+        }
+        ';' | '{' if depth == 0 => Some(i),
+        _ => None,
+    })?;
+
+    let declaration = text[line_start..end].trim_end();
+    Some(
+        declaration
+            .lines()
+            .map(|line| line.strip_prefix(indent).unwrap_or(line))
+            .collect::<Vec<_>>()
+            .join("\n"),
+    )
+}
+
+/// Returns a stub implementation of the trait item `name` declared by `sig`,
+/// or `None` if the kind of item can't be implemented in a trait impl.
+///
+/// Associated types get `()` as a placeholder to fill in, as no type is known
+/// to meet their bounds, and consts get `todo!()` like functions do.
+pub fn trait_item_stub(kind: DefKind, name: &str, sig: &str) -> Option<String> {
+    // Save-analysis signatures may end with an empty body or a semicolon.
+    let sig = sig.trim().trim_end_matches("{}").trim_end().trim_end_matches(';').trim_end();
+    match kind {
+        DefKind::Function | DefKind::Method => Some(format!("{} {{\n    todo!()\n}}", sig)),
+        DefKind::Const => {
+            let sig = find_top_level(sig, '=').map_or(sig, |i| sig[..i].trim_end());
+            Some(format!("{} = todo!();", sig))
+        }
+        DefKind::Type => Some(format!("type {} = (); // TODO", name)),
+        _ => None,
+    }
+}
+
+/// Returns the edit adding `items` at the end of the block delimited by the
+/// braces at `open` and `close`.
+pub fn add_items_edit(text: &str, (open, close): (usize, usize), items: &[String]) -> TextEdit {
+    let indent = indent_at(text, open);
+    let item_indent = format!("{}    ", indent);
+    let items = items
+        .iter()
+        .map(|item| {
+            item.lines()
+                .map(|line| {
+                    if line.is_empty() {
+                        String::new()
+                    } else {
+                        format!("{}{}", item_indent, line)
+                    }
+                })
+                .collect::<Vec<_>>()
+                .join("\n")
+        })
+        .collect::<Vec<_>>()
+        .join("\n\n");
+
+    let body = &text[open + 1..close];
+    let existing = body.trim_end();
+    let start = open + 1 + existing.len();
+    let separator = if existing.trim().is_empty() { "\n" } else { "\n\n" };
+    TextEdit {
+        range: Range::new(offset_to_position(text, start), offset_to_position(text, close)),
+        new_text: format!("{}{}\n{}", separator, items, indent),
+    }
+}
+
+/// The parts of a struct or enum definition needed to generate code for it.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TypeDefinition {
+    /// The offset of the first attribute or doc comment of the item.
+    pub attrs_start: usize,
+    /// The offset of the item proper, following its attributes.
+    pub start: usize,
+    /// The offset just past the item's closing `}` or `;`.
+    pub end: usize,
+    /// The visibility of the item followed by a space (e.g., `pub `), if any.
+    pub visibility: String,
+    /// The generic parameters, with their bounds but without defaults.
+    pub generics: Vec<String>,
+    /// The `where` clause, if any.
+    pub where_clause: Option<String>,
+    /// The traits listed in the item's `#[derive(..)]` attributes.
+    pub derives: Vec<String>,
+    /// The offset of the `)` closing the last `#[derive(..)]` attribute.
+    pub derive_close: Option<usize>,
+}
+
+impl TypeDefinition {
+    /// Returns the header of an impl block for the type `name`, implementing
+    /// `trait_name` if given, e.g., `impl<T: Clone> Default for Foo<T>`.
+    pub fn impl_header(&self, name: &str, trait_name: Option<&str>) -> String {
+        let mut header = "impl".to_owned();
+        if !self.generics.is_empty() {
+            header.push_str(&format!("<{}>", self.generics.join(", ")));
+        }
+        if let Some(trait_name) = trait_name {
+            header.push_str(&format!(" {} for", trait_name));
+        }
+        header.push_str(&format!(" {}", name));
+        if !self.generics.is_empty() {
+            let args = parse_generic_params(&self.generics.join(", "));
+            let args: Vec<_> = args.into_iter().map(|param| param.name).collect();
+            header.push_str(&format!("<{}>", args.join(", ")));
+        }
+        if let Some(ref where_clause) = self.where_clause {
+            header.push_str(&format!(" {}", where_clause));
+        }
+        header
+    }
+
+    /// Returns the header of an impl of `trait_name` for the type `name`
+    /// which, like a derive, requires its type parameters to implement the
+    /// trait too, e.g., `impl<T: Clone + Default> Default for Foo<T>`.
+    pub fn bounded_impl_header(&self, name: &str, trait_name: &str) -> String {
+        let generics = self
+            .generics
+            .iter()
+            .map(|param| {
+                if param.starts_with('\'') || param.starts_with("const ") {
+                    param.clone()
+                } else if find_top_level(param, ':').is_some() {
+                    format!("{} + {}", param, trait_name)
+                } else {
+                    format!("{}: {}", param, trait_name)
+                }
+            })
+            .collect();
+        TypeDefinition { generics, ..self.clone() }.impl_header(name, Some(trait_name))
+    }
+}
+
+/// Parses the definition of the struct or enum whose name is at `name_offset`.
+pub fn parse_type_definition(text: &str, name_offset: usize) -> Option<TypeDefinition> {
+    lazy_static! {
+        static ref HEAD: Regex =
+            Regex::new(r"^(pub(?:\s*\([^)]*\))?\s+)?(?:struct|enum|union)\s+$").unwrap();
+        static ref DERIVE: Regex = Regex::new(r"#\[\s*derive\s*\(([^)]*)\)\s*\]").unwrap();
+        static ref WHERE: Regex = Regex::new(r"\bwhere\b").unwrap();
+    }
+
+    let line_start = text[..name_offset].rfind('\n').map(|i| i + 1).unwrap_or(0);
+    let start = line_start + indent_at(text, name_offset).len();
+    let visibility = HEAD
+        .captures(&text[start..name_offset])?
+        .get(1)
+        .map_or(String::new(), |m| format!("{} ", m.as_str().trim()));
+
+    // Attributes and doc comments are on the lines preceding the item.
+    let mut attrs_start = start;
+    for line in text[..line_start].lines().rev() {
+        let line = line.trim();
+        if !(line.starts_with("#[") || line.starts_with("///") || line.starts_with("//!")) {
+            break;
+        }
+        attrs_start = text[..attrs_start].trim_end().rfind('\n').map(|i| i + 1).unwrap_or(0);
+        attrs_start += indent_at(text, attrs_start).len();
+    }
+    let mut derives = vec![];
+    let mut derive_close = None;
+    for caps in DERIVE.captures_iter(&text[attrs_start..start]) {
+        let list = caps.get(1).unwrap();
+        derives.extend(
+            list.as_str()
+                .split(',')
+                .map(str::trim)
+                .filter(|d| !d.is_empty())
+                .map(|derive| derive.rsplit("::").next().unwrap_or(derive).to_owned()),
+        );
+        derive_close = Some(attrs_start + list.end());
+    }
+
+    let name_end =
+        name_offset + text[name_offset..].find(|c: char| !c.is_alphanumeric() && c != '_')?;
+    let mut generics_end = name_end;
+    let mut generics = vec![];
+    if text[name_end..].starts_with('<') {
+        let close = matching_delimiter(text, name_end)?;
+        generics = split_top_level(&text[name_end + 1..close])
+            .into_iter()
+            .map(|param| {
+                find_top_level(param, '=').map_or(param, |i| param[..i].trim_end()).to_owned()
+            })
+            .collect();
+        generics_end = close + 1;
+    }
+
+    let mut depth = 0;
+    let (body, end) = scan_code(text, generics_end, |i, c| match c {
+        '(' | '[' => {
+            depth += 1;
+            None
+        }
+        ')' | ']' => {
+            depth -= 1;
+            None
+        }
+        '{' if depth == 0 => Some((i, matching_delimiter(text, i).map(|close| close + 1))),
+        ';' if depth == 0 => Some((i, Some(i + 1))),
+        _ => None,
+    })?;
+    let rest = &text[generics_end..body];
+    // A tuple struct's fields precede its `where` clause.
+    let where_clause = WHERE
+        .find(rest)
+        .map(|m| rest[m.start()..].trim().trim_end_matches(',').trim_end().to_owned());
+
+    Some(TypeDefinition {
+        attrs_start,
+        start,
+        end: end?,
+        visibility,
+        generics,
+        where_clause,
+        derives,
+        derive_close,
+    })
+}
+
+/// Returns the offsets of the braces delimiting the body of the first inherent
+/// impl of the type `name` in `text`.
+pub fn inherent_impl_body(text: &str, name: &str) -> Option<(usize, usize)> {
+    lazy_static! {
+        static ref IMPL: Regex = Regex::new(r"(?m)^[ \t]*impl\b").unwrap();
+    }
+
+    let mask = code_mask(text);
+    IMPL.find_iter(text).filter(|m| mask[m.end() - 1]).find_map(|m| {
+        let mut offset = m.end();
+        offset += text[offset..].len() - text[offset..].trim_start().len();
+        if text[offset..].starts_with('<') {
+            offset = matching_delimiter(text, offset)? + 1;
+        }
+        let open = scan_code(text, offset, |i, c| match c {
+            '{' => Some(Some(i)),
+            ';' => Some(None),
+            _ => None,
+        })??;
+        if !is_inherent_impl_header(&text[m.start()..open]) {
+            return None;
+        }
+        let path =
+            text[offset..open].trim().split(|c: char| c == '<' || c.is_whitespace()).next()?;
+        if path.rsplit("::").next() == Some(name) {
+            Some((open, matching_delimiter(text, open)?))
+        } else {
+            None
+        }
+    })
+}
+
+/// Returns the traits which can be added to the `derives` of a struct (or an
+/// enum), i.e., the standard derivable traits whose supertraits are derived.
+pub fn derivable_traits(derives: &[String], is_enum: bool) -> Vec<&'static str> {
+    const DERIVABLE: &[(&str, &[&str])] = &[
+        ("Debug", &[]),
+        ("Clone", &[]),
+        ("Copy", &["Clone"]),
+        ("PartialEq", &[]),
+        ("Eq", &["PartialEq"]),
+        ("PartialOrd", &["PartialEq"]),
+        ("Ord", &["Eq", "PartialOrd"]),
+        ("Hash", &[]),
+        ("Default", &[]),
+    ];
+    let derived = |name: &str| derives.iter().any(|derive| derive == name);
+    DERIVABLE
+        .iter()
+        .filter(|(name, _)| !derived(name) && (!is_enum || *name != "Default"))
+        .filter(|(_, supertraits)| supertraits.iter().all(|name| derived(name)))
+        .map(|(name, _)| *name)
+        .collect()
+}
+
+/// Returns the edit deriving `trait_name` for the type defined by `def`,
+/// extending its last `#[derive(..)]` attribute if it has one.
+pub fn derive_edit(text: &str, def: &TypeDefinition, trait_name: &str) -> TextEdit {
+    let (offset, new_text) = match def.derive_close {
+        Some(close) if text[..close].trim_end().ends_with('(') => (close, trait_name.to_owned()),
+        Some(close) => {
+            let end = text[..close].trim_end().trim_end_matches(',').len();
+            (end, format!(", {}", trait_name))
+        }
+        None => (def.start, format!("#[derive({})]\n{}", trait_name, indent_at(text, def.start))),
+    };
+    let position = offset_to_position(text, offset);
+    TextEdit { range: Range::new(position, position), new_text }
+}
+
+/// Returns the edit adding `item` (unindented) after the type defined by `def`.
+pub fn add_item_after_edit(text: &str, def: &TypeDefinition, item: &str) -> TextEdit {
+    let indent = indent_at(text, def.start);
+    let position = offset_to_position(text, def.end);
+    TextEdit {
+        range: Range::new(position, position),
+        new_text: format!("\n\n{}{}", indent, reindent(item, "", indent).trim_start()),
+    }
+}
+
+/// Returns a `new` function initializing every one of `fields` (pairs of
+/// names and types) from a parameter of the same name.
+pub fn constructor(visibility: &str, fields: &[(String, String)]) -> String {
+    let params: Vec<_> =
+        fields.iter().map(|(name, ty)| format!("{}: {}", accessor_name(name), ty)).collect();
+    let names: Vec<_> = fields.iter().map(|(name, _)| accessor_name(name)).collect();
+    let value = if fields.iter().all(|(name, _)| is_positional(name)) {
+        format!("Self({})", names.join(", "))
+    } else {
+        format!("Self {{ {} }}", names.join(", "))
+    };
+    format!("{}fn new({}) -> Self {{\n    {}\n}}", visibility, params.join(", "), value)
+}
+
+/// Returns whether `name` is the name of a field of a tuple struct.
+fn is_positional(name: &str) -> bool {
+    name.chars().all(|c| c.is_ascii_digit())
+}
+
+/// Returns the name of the getter of the field `name`, which is also used for
+/// variables holding its value, e.g. `field_0` for the field `0`.
+pub fn accessor_name(name: &str) -> String {
+    if is_positional(name) {
+        format!("field_{}", name)
+    } else {
+        name.to_owned()
+    }
+}
+
+/// Returns a getter for the field `name` of type `ty`, which returns copyable
+/// types by value and anything else by reference.
+pub fn getter(visibility: &str, name: &str, ty: &str) -> String {
+    let (ty, value) =
+        if is_copy_type(ty) { (ty.to_owned(), "") } else { (format!("&{}", ty), "&") };
+    format!(
+        "{}fn {}(&self) -> {} {{\n    {}self.{}\n}}",
+        visibility,
+        accessor_name(name),
+        ty,
+        value,
+        name
+    )
+}
+
+/// Returns a setter for the field `name` of type `ty`.
+pub fn setter(visibility: &str, name: &str, ty: &str) -> String {
+    let param = accessor_name(name);
+    format!(
+        "{}fn set_{}(&mut self, {}: {}) {{\n    self.{} = {};\n}}",
+        visibility,
+        param.trim_start_matches("r#"),
+        param,
+        ty,
+        name,
+        param
+    )
+}
+
+/// Returns an impl of `Default` (with the given `header`) initializing every
+/// one of `fields` to its default value.
+pub fn default_impl(header: &str, fields: &[String]) -> String {
+    let value = if fields.iter().all(|name| is_positional(name)) {
+        let values = vec!["Default::default()"; fields.len()];
+        format!("Self({})", values.join(", "))
+    } else {
+        let fields: Vec<_> = fields
+            .iter()
+            .map(|name| format!("            {}: Default::default(),\n", name))
+            .collect();
+        format!("Self {{\n{}        }}", fields.concat())
+    };
+    format!("{} {{\n    fn default() -> Self {{\n        {}\n    }}\n}}", header, value)
+}
+
+/// Re-indents `snippet`, stripping `old_indent` from all but its first line
+/// and prefixing every non-empty line with `new_indent`.
+pub fn reindent(snippet: &str, old_indent: &str, new_indent: &str) -> String {
+    snippet
+        .lines()
+        .enumerate()
+        .map(|(i, line)| {
+            let line = if i == 0 { line } else { line.strip_prefix(old_indent).unwrap_or(line) };
+            if line.trim().is_empty() {
+                String::new()
+            } else {
+                format!("{}{}", new_indent, line)
+            }
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// Returns whether the delimiters of `snippet` are balanced.
+pub fn is_balanced(snippet: &str) -> bool {
+    let mut stack = vec![];
+    let mismatch = scan_code(snippet, 0, |_, c| match c {
+        '(' | '[' | '{' => {
+            stack.push(c);
+            None
+        }
+        ')' | ']' | '}' => {
+            let open = match c {
+                ')' => '(',
+                ']' => '[',
+                _ => '{',
+            };
+            if stack.pop() == Some(open) {
+                None
+            } else {
+                Some(())
+            }
+        }
+        _ => None,
+    });
+    mismatch.is_none() && stack.is_empty()
+}
+
+/// Returns the offsets of the opening braces of the blocks containing
+/// `offset`, innermost first.
+pub fn enclosing_blocks(text: &str, offset: usize) -> Vec<usize> {
+    let mut stack = vec![];
+    let _ = scan_code(text, 0, |i, c| {
+        if i >= offset {
+            return Some(());
+        }
+        match c {
+            '{' => stack.push(i),
+            '}' => {
+                stack.pop();
+            }
+            _ => {}
+        }
+        None
+    });
+    stack.reverse();
+    stack
+}
+
+/// Returns the header of the block opened at `open`, e.g., `impl Foo` or
+/// `fn foo(&self) -> u32`, including any preceding attributes.
+pub fn block_header(text: &str, open: usize) -> &str {
+    let start = text[..open].rfind(&[';', '{', '}'][..]).map_or(0, |i| i + 1);
+    text[start..open].trim()
+}
+
+/// Returns whether `header` is the header of a function.
+pub fn is_fn_header(header: &str) -> bool {
+    lazy_static! {
+        static ref FN: Regex = Regex::new(r"\bfn\s+[A-Za-z_]").unwrap();
+    }
+    FN.is_match(header)
+}
+
+/// Returns whether `header` is the header of an inherent (not trait) impl.
+pub fn is_inherent_impl_header(header: &str) -> bool {
+    lazy_static! {
+        static ref IMPL: Regex = Regex::new(r"(?m)^\s*(?:unsafe\s+)?impl\b").unwrap();
+        static ref FOR: Regex = Regex::new(r"\sfor\s").unwrap();
+    }
+    IMPL.is_match(header) && !FOR.is_match(header)
+}
+
+/// Returns whether `header` is the header of an inline module.
+pub fn is_mod_header(header: &str) -> bool {
+    lazy_static! {
+        static ref MOD: Regex =
+            Regex::new(r"(?m)^\s*(?:pub(?:\([^)]*\))?\s+)?mod\s+[A-Za-z_]\w*$").unwrap();
+    }
+    MOD.is_match(header)
+}
+
+/// The name of the field appended to the expression in front of a `.` by
+/// `postfix_expression_start`, to find that expression in the syntax tree.
+const RECEIVER_FIELD: &str = "__rls_receiver";
+
+/// Returns the offset at which the postfix expression ending at `end` starts,
+/// e.g., `foo.bar(x)?` or `v[0]` in front of a `.`, or `None` if there is no
+/// expression there. Prefix operators (e.g., `&x`) aren't included.
+///
+/// The code up to `end` is parsed, with a field access appended to the
+/// expression, and the receiver of that field access is returned. Unclosed
+/// delimiters are closed by the parser; the statement containing the
+/// expression may need to be completed.
+pub fn postfix_expression_start(text: &str, end: usize) -> Option<usize> {
+    ["", ";", " {}"]
+        .iter()
+        .find_map(|suffix| receiver_start(format!("{}.{}{}", &text[..end], RECEIVER_FIELD, suffix)))
+}
+
+/// Parses `source` and returns the offset of the receiver of the
+/// `RECEIVER_FIELD` field access, if it parses.
+fn receiver_start(source: String) -> Option<usize> {
+    rustc_span::with_default_session_globals(|| {
+        let sess = ParseSess::with_silent_emitter();
+        let name = FileName::Custom("postfix".to_owned());
+        let mut parser = rustc_parse::maybe_new_parser_from_source_str(&sess, name, source).ok()?;
+        let (_, items, _) = parser.parse_mod(&token::Eof).map_err(|mut err| err.cancel()).ok()?;
+        let mut finder = ReceiverFinder { sess: &sess, receiver: None };
+        for item in &items {
+            finder.visit_item(item);
+        }
+        let receiver = finder.receiver?;
+        Some(sess.source_map().lookup_byte_offset(receiver.lo()).pos.0 as usize)
+    })
+}
+
+/// Finds the receiver of the `RECEIVER_FIELD` field access, see
+/// `receiver_start`.
+struct ReceiverFinder<'a> {
+    sess: &'a ParseSess,
+    receiver: Option<rustc_span::Span>,
+}
+
+impl<'ast> Visitor<'ast> for ReceiverFinder<'_> {
+    fn visit_expr(&mut self, expr: &'ast Expr) {
+        match &expr.kind {
+            ExprKind::Field(receiver, field) if field.name.as_str() == RECEIVER_FIELD => {
+                self.receiver = Some(receiver.span);
+            }
+            _ => visit::walk_expr(self, expr),
+        }
+    }
+
+    /// Macro arguments aren't parsed, so they are parsed here as the
+    /// comma-separated expressions most macros (e.g., `println!`) take.
+    fn visit_mac_call(&mut self, mac: &'ast MacCall) {
+        let mut parser = rustc_parse::stream_to_parser(self.sess, mac.args.inner_tokens(), None);
+        while parser.token != token::Eof {
+            match parser.parse_expr() {
+                Ok(expr) => self.visit_expr(&expr),
+                Err(mut err) => {
+                    err.cancel();
+                    parser.bump();
+                }
+            }
+            parser.eat(&token::Comma);
+        }
+    }
+}
+
+/// Returns the offset at which the statement containing `offset` starts.
+pub fn statement_start(text: &str, offset: usize) -> usize {
+    let block = enclosing_blocks(text, offset).first().map_or(0, |&open| open + 1);
+    let mut boundary = block;
+    let mut depth = 0;
+    let _ = scan_code(text, block, |i, c| {
+        if i >= offset {
+            return Some(());
+        }
+        match c {
+            '(' | '[' | '{' => depth += 1,
+            ')' | ']' => depth -= 1,
+            '}' => {
+                depth -= 1;
+                // A block ends the statement unless the expression goes on,
+                // e.g., `match x { .. }.unwrap()` or `if a { .. } else { .. }`.
+                let rest = text[i + 1..].trim_start();
+                let continues = rest.starts_with("else")
+                    || rest.starts_with(|c: char| ".?;,)]=+-*/%&|^<>".contains(c));
+                if depth == 0 && !continues {
+                    boundary = i + 1;
+                }
+            }
+            ';' if depth == 0 => boundary = i + 1,
+            _ => {}
+        }
+        None
+    });
+    let rest = &text[boundary..offset];
+    boundary + (rest.len() - rest.trim_start().len())
+}
+
+/// Converts a `CamelCase` name to `snake_case`.
+pub fn to_snake_case(name: &str) -> String {
+    let mut result = String::new();
+    let mut prev_lower = false;
+    for c in name.chars() {
+        if c.is_uppercase() && prev_lower {
+            result.push('_');
+        }
+        prev_lower = c.is_lowercase() || c.is_numeric();
+        result.extend(c.to_lowercase());
+    }
+    result
+}
+
+/// Suggests a name for a variable holding the value of `expr`, based on the
+/// method, function or field it ends with.
+pub fn variable_name(expr: &str) -> String {
+    lazy_static! {
+        static ref NAME: Regex = Regex::new(r"([A-Za-z_][A-Za-z0-9_]*)(?:::<.*>)?\s*$").unwrap();
+    }
+
+    let mut expr = expr.trim().trim_end_matches('?');
+    if expr.ends_with(')') {
+        let close = expr.len() - 1;
+        let mut depth = 0;
+        let open = expr.char_indices().rev().find(|&(_, c)| {
+            match c {
+                ')' => depth += 1,
+                '(' => depth -= 1,
+                _ => {}
+            }
+            depth == 0
+        });
+        match open {
+            Some((open, _)) if open < close => expr = &expr[..open],
+            _ => return "value".to_owned(),
+        }
+    }
+    let name = match NAME.captures(expr) {
+        Some(caps) => caps.get(1).unwrap().as_str(),
+        None => return "value".to_owned(),
+    };
+    // `Foo::new(..)` is best named after `Foo`.
+    let name = if ["new", "default", "from"].contains(&name) {
+        let path = expr[..expr.len() - name.len()].trim_end().trim_end_matches("::");
+        NAME.captures(path).map_or(name, |caps| caps.get(1).unwrap().as_str())
+    } else {
+        name
+    };
+    let name = ["get_", "to_", "into_", "as_"]
+        .iter()
+        .find_map(|prefix| name.strip_prefix(prefix))
+        .unwrap_or(name);
+    let name = to_snake_case(name);
+    if name.is_empty() || name.starts_with(char::is_numeric) || is_keyword(&name) {
+        "value".to_owned()
+    } else {
+        name
+    }
+}
+
+/// Returns whether `name` is a (strict or reserved) keyword.
+pub fn is_keyword(name: &str) -> bool {
+    const KEYWORDS: &[&str] = &[
+        "Self", "abstract", "as", "async", "await", "become", "box", "break", "const", "continue",
+        "crate", "do", "dyn", "else", "enum", "extern", "false", "final", "fn", "for", "if",
+        "impl", "in", "let", "loop", "macro", "match", "mod", "move", "mut", "override", "priv",
+        "pub", "ref", "return", "self", "static", "struct", "super", "trait", "true", "try",
+        "type", "typeof", "unsafe", "unsized", "use", "virtual", "where", "while", "yield",
+    ];
+    KEYWORDS.contains(&name)
+}
+
+/// Returns whether `name` can be used to name a variable or an item, i.e.,
+/// it's an identifier and, unless it's a raw identifier, not a keyword.
+pub fn is_valid_identifier(name: &str) -> bool {
+    lazy_static! {
+        static ref IDENT: Regex =
+            Regex::new(r"^(r#)?([A-Za-z][A-Za-z0-9_]*|_[A-Za-z0-9_]+)$").unwrap();
+    }
+    match IDENT.captures(name) {
+        // `self`, `super`, `crate` and `Self` can't be raw identifiers.
+        Some(caps) if caps.get(1).is_some() => {
+            !["self", "super", "crate", "Self"].contains(&&caps[2])
+        }
+        Some(_) => !is_keyword(name),
+        None => false,
+    }
+}
+
+/// Returns whether `snippet` assigns to, or mutably borrows, the variable `name`.
+pub fn mutates(snippet: &str, name: &str) -> bool {
+    let assign = format!(r"(?:^|[^.\w]){}\s*(?:[-+*/%&|^]|<<|>>)?=(?:[^=]|$)", regex::escape(name));
+    let borrow = format!(r"&\s*mut\s+{}\b", regex::escape(name));
+    Regex::new(&assign).unwrap().is_match(snippet) || Regex::new(&borrow).unwrap().is_match(snippet)
+}
+
+/// Returns the receiver a function needs to run `snippet`: `None` if it
+/// doesn't use `self`, `&mut self` if it assigns to (a field of) `self` or
+/// borrows it mutably, and `&self` otherwise.
+pub fn receiver_for(snippet: &str) -> Option<&'static str> {
+    lazy_static! {
+        static ref SELF: Regex = Regex::new(r"\bself\b").unwrap();
+        static ref SELF_MUT: Regex =
+            Regex::new(r"\bself(?:\.\w+)*\s*(?:[-+*/%&|^]|<<|>>)?=(?:[^=]|$)|&\s*mut\s+self\b")
+                .unwrap();
+    }
+    if SELF_MUT.is_match(snippet) {
+        Some("&mut self")
+    } else if SELF.is_match(snippet) {
+        Some("&self")
+    } else {
+        None
+    }
+}
+
+/// How a local is passed to an extracted function.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PassBy {
+    /// By value, declared `mut` if `mutable`.
+    Value {
+        mutable: bool,
+    },
+    Ref,
+    RefMut,
+}
+
+/// A local passed to or returned from an extracted function.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ExtractedLocal {
+    pub name: String,
+    pub ty: String,
+    pub pass_by: PassBy,
+}
+
+/// The signature of a function extracted from a sequence of statements.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ExtractedFunction {
+    pub name: String,
+    /// `&self` or `&mut self` if the statements use `self`.
+    pub receiver: Option<&'static str>,
+    pub params: Vec<ExtractedLocal>,
+    /// Locals defined by the statements and used after them. Only their
+    /// mutability is relevant for `pass_by`.
+    pub returns: Vec<ExtractedLocal>,
+    /// The generic parameters of the enclosing function the statements use,
+    /// with their bounds.
+    pub generics: Vec<String>,
+    /// The `where` predicates on `generics`.
+    pub where_predicates: Vec<String>,
+}
+
+impl ExtractedFunction {
+    fn tuple(items: impl Iterator<Item = String>) -> String {
+        let items: Vec<_> = items.collect();
+        match items.len() {
+            1 => items[0].clone(),
+            _ => format!("({})", items.join(", ")),
+        }
+    }
+
+    /// Returns the statement calling the function through `callee` (e.g.,
+    /// `self.foo` or `Self::foo`).
+    pub fn call(&self, callee: &str) -> String {
+        let args = self.params.iter().map(|param| match param.pass_by {
+            PassBy::Value { .. } => param.name.clone(),
+            PassBy::Ref => format!("&{}", param.name),
+            PassBy::RefMut => format!("&mut {}", param.name),
+        });
+        let call = format!("{}({})", callee, args.collect::<Vec<_>>().join(", "));
+        if self.returns.is_empty() {
+            return format!("{};", call);
+        }
+        let pattern = Self::tuple(self.returns.iter().map(|local| match local.pass_by {
+            PassBy::Value { mutable: true } => format!("mut {}", local.name),
+            _ => local.name.clone(),
+        }));
+        format!("let {} = {};", pattern, call)
+    }
+
+    /// Returns the definition of the function, with `body` (dedented
+    /// statements) as its body.
+    pub fn definition(&self, body: &str) -> String {
+        let mut body = body.to_owned();
+        let mut params: Vec<String> = self.receiver.iter().map(|r| (*r).to_owned()).collect();
+        for param in &self.params {
+            params.push(match param.pass_by {
+                PassBy::Value { mutable: true } => format!("mut {}: {}", param.name, param.ty),
+                PassBy::Value { mutable: false } => format!("{}: {}", param.name, param.ty),
+                PassBy::Ref => format!("{}: &{}", param.name, param.ty),
+                PassBy::RefMut => {
+                    // The parameter is a reference now, so assign through it.
+                    let name = regex::escape(&param.name);
+                    let assign = Regex::new(&format!(
+                        r"(^|[^.\w])({}\s*(?:[-+*/%&|^]|<<|>>)?=(?:[^=]|$))",
+                        name
+                    ))
+                    .unwrap();
+                    body = assign.replace_all(&body, "$1*$2").into_owned();
+                    let borrow = Regex::new(&format!(r"&\s*mut\s+({})\b", name)).unwrap();
+                    body = borrow.replace_all(&body, "$1").into_owned();
+                    format!("{}: &mut {}", param.name, param.ty)
+                }
+            });
+        }
+
+        let mut signature = format!("fn {}", self.name);
+        if !self.generics.is_empty() {
+            signature.push_str(&format!("<{}>", self.generics.join(", ")));
+        }
+        signature.push_str(&format!("({})", params.join(", ")));
+        if !self.returns.is_empty() {
+            let ty = Self::tuple(self.returns.iter().map(|local| local.ty.clone()));
+            signature.push_str(&format!(" -> {}", ty));
+            let value = Self::tuple(self.returns.iter().map(|local| local.name.clone()));
+            body.push_str(&format!("\n{}", value));
+        }
+        if !self.where_predicates.is_empty() {
+            signature.push_str(&format!(" where {}", self.where_predicates.join(", ")));
+        }
+        format!("{} {{\n{}\n}}", signature, reindent(&body, "", "    "))
+    }
+}
+
+/// Returns the generic parameters and `where` predicates of the function with
+/// the header `header` which `text` depends on, directly or through the
+/// bounds of the parameters it uses.
+pub fn used_generics(header: &str, text: &str) -> (Vec<String>, Vec<String>) {
+    lazy_static! {
+        static ref FN_NAME: Regex = Regex::new(r"\bfn\s+([A-Za-z_]\w*)").unwrap();
+        static ref WHERE: Regex = Regex::new(r"\bwhere\b").unwrap();
+    }
+
+    let caps = match FN_NAME.captures(header) {
+        Some(caps) => caps,
+        None => return (vec![], vec![]),
+    };
+    let signature = &header[caps.get(0).unwrap().start()..];
+    let params = generic_list_after(signature, &caps[1]).map_or(vec![], split_top_level);
+    let predicates = WHERE
+        .find(signature)
+        .map_or(vec![], |m| split_top_level(signature[m.end()..].trim_end_matches(',')));
+    let names: Vec<_> =
+        params.iter().map(|param| parse_generic_params(param)[0].name.clone()).collect();
+
+    let mut used: Vec<bool> = names.iter().map(|name| refers_to(text, name)).collect();
+    let bounds_on = |predicate: &str, used: &[bool]| {
+        let bounded = find_top_level(predicate, ':').map_or(predicate, |i| &predicate[..i]);
+        names.iter().zip(used).any(|(name, &used)| used && refers_to(bounded, name))
+    };
+    // The bounds of the used parameters may refer to other parameters.
+    loop {
+        let bounds: Vec<&str> = params
+            .iter()
+            .zip(&used)
+            .filter(|(_, &used)| used)
+            .map(|(param, _)| *param)
+            .chain(predicates.iter().copied().filter(|predicate| bounds_on(predicate, &used)))
+            .collect();
+        let mut changed = false;
+        for (name, used) in names.iter().zip(&mut used) {
+            if !*used && bounds.iter().any(|bound| refers_to(bound, name)) {
+                *used = true;
+                changed = true;
+            }
+        }
+        if !changed {
+            break;
+        }
+    }
+
+    let generics = params.iter().zip(&used).filter(|(_, &used)| used).map(|(p, _)| p.to_string());
+    let predicates = predicates.iter().filter(|predicate| bounds_on(predicate, &used));
+    (generics.collect(), predicates.map(|p| p.to_string()).collect())
+}
+
+/// Returns the range of the identifier at (or just before) `offset`.
+pub fn identifier_at(text: &str, offset: usize) -> Option<(usize, usize)> {
+    let is_ident = |c: char| c.is_alphanumeric() || c == '_';
+    let start = text[..offset]
+        .char_indices()
+        .rev()
+        .find(|&(_, c)| !is_ident(c))
+        .map_or(0, |(i, c)| i + c.len_utf8());
+    let end = offset + text[offset..].find(|c| !is_ident(c)).unwrap_or(text.len() - offset);
+    if start == end || text[start..].starts_with(char::is_numeric) {
+        None
+    } else {
+        Some((start, end))
+    }
+}
+
+/// Returns whether evaluating `expr` may have side effects (or be expensive),
+/// i.e., it calls a function or macro, awaits, uses `?` or assigns.
+pub fn has_side_effects(expr: &str) -> bool {
+    lazy_static! {
+        static ref EFFECTS: Regex =
+            Regex::new(r"[\w>)\]]\s*\(|\w!\s*[(\[{]|\.await\b|\?|[^=!<>]=[^=>]").unwrap();
+    }
+    let mask = code_mask(expr);
+    let code: String = expr.char_indices().map(|(i, c)| if mask[i] { c } else { ' ' }).collect();
+    EFFECTS.is_match(&code)
+}
+
+/// Returns whether `expr` has to be parenthesized when substituted for a
+/// name, i.e., it contains an operator or whitespace outside of delimiters.
+pub fn needs_parens(expr: &str) -> bool {
+    let mut depth = 0i32;
+    scan_code(expr, 0, |i, c| {
+        match c {
+            '(' | '[' | '{' => depth += 1,
+            '<' if expr[..i].ends_with("::") => depth += 1,
+            ')' | ']' | '}' => depth -= 1,
+            '>' if depth > 0 && !expr[..i].ends_with('-') => depth -= 1,
+            // Method calls, field accesses and `?` bind tightly enough.
+            _ if depth == 0 && i > 0 && (c.is_whitespace() || "+-*/%&|^<>=!".contains(c)) => {
+                return Some(());
+            }
+            _ if depth == 0 && i == 0 && c == '|' => return Some(()),
+            _ => {}
+        }
+        None
+    })
+    .is_some()
+}
+
+/// Returns whether an expression substituted for `text[start..end]` can do
+/// without parentheses, i.e., it's delimited by `=`, `(`, `,`, `;` and the like.
+pub fn is_standalone_position(text: &str, start: usize, end: usize) -> bool {
+    let before = text[..start].trim_end();
+    let after = text[end..].trim_start();
+    let open = before.ends_with(|c| "=({[,".contains(c))
+        && !before.ends_with("==")
+        && !before.ends_with("!=")
+        && !before.ends_with("<=")
+        && !before.ends_with(">=");
+    open && after.starts_with(|c| ";,)]}".contains(c))
+}
+
+/// Returns whether values of the type `ty` are (certainly) `Copy`.
+pub fn is_copy_type(ty: &str) -> bool {
+    const COPY: &[&str] = &[
+        "bool", "char", "i8", "i16", "i32", "i64", "i128", "isize", "u8", "u16", "u32", "u64",
+        "u128", "usize", "f32", "f64", "()",
+    ];
+    let ty = ty.trim();
+    COPY.contains(&ty) || (ty.starts_with('&') && !ty.starts_with("&mut"))
+}
+
+/// Extends the range `start..end` over the whole lines it's on, if it's the
+/// only thing on them, so that removing it doesn't leave blank lines.
+pub fn line_range(text: &str, start: usize, end: usize) -> (usize, usize) {
+    let line_start = text[..start].rfind('\n').map_or(0, |i| i + 1);
+    let line_end = text[end..].find('\n').map_or(text.len(), |i| end + i + 1);
+    if text[line_start..start].trim().is_empty() && text[end..line_end].trim().is_empty() {
+        (line_start, line_end)
+    } else {
+        (start, end)
+    }
+}
+
+/// Combines non-overlapping replacements of ranges of `text` into a single
+/// replacement of the range spanning all of them.
+pub fn combine_edits(
+    text: &str,
+    mut edits: Vec<(usize, usize, String)>,
+) -> Option<(usize, usize, String)> {
+    edits.sort_by_key(|(start, _, _)| *start);
+    let start = edits.first()?.0;
+    let end = edits.iter().map(|(_, end, _)| *end).max()?;
+    let mut result = String::new();
+    let mut offset = start;
+    for (edit_start, edit_end, new_text) in edits {
+        if edit_start < offset {
+            return None;
+        }
+        result.push_str(&text[offset..edit_start]);
+        result.push_str(&new_text);
+        offset = edit_end;
+    }
+    result.push_str(&text[offset..end]);
+    Some((start, end, result))
+}
+
+/// A `let` statement binding a single name.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct LetBinding {
+    /// The range of the statement, including the `;`.
+    pub start: usize,
+    pub end: usize,
+    pub mutable: bool,
+    /// The type annotation, if any.
+    pub ty: Option<String>,
+    pub init: String,
+}
+
+/// Parses the `let` statement binding the name at `offset`, e.g.,
+/// `let x: u32 = 1;`. Fails for other patterns and declarations without an
+/// initializer.
+pub fn parse_let(text: &str, offset: usize) -> Option<LetBinding> {
+    lazy_static! {
+        static ref LET: Regex = Regex::new(r"let\s+(mut\s+)?$").unwrap();
+    }
+
+    let start = statement_start(text, offset);
+    let caps = LET.captures(&text[start..offset])?;
+    if caps.get(0)?.start() != 0 {
+        return None;
+    }
+    let (_, name_end) = identifier_at(text, offset)?;
+    let semi = name_end + find_top_level(&text[name_end..], ';')?;
+    let eq = name_end + find_top_level(&text[name_end..semi], '=')?;
+    let ty = text[name_end..eq].trim().strip_prefix(':').map(|ty| ty.trim().to_owned());
+    Some(LetBinding {
+        start,
+        end: semi + 1,
+        mutable: caps.get(1).is_some(),
+        ty,
+        init: text[eq + 1..semi].trim().to_owned(),
+    })
+}
+
+/// The parts of a function definition needed to inline it.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct FnDefinition {
+    /// The names of the parameters, which have to be plain identifiers, and
+    /// whether they're declared `mut`.
+    pub params: Vec<(String, bool)>,
+    /// Whether the function has generic parameters.
+    pub generic: bool,
+    /// The statements of the body, dedented.
+    pub statements: String,
+    /// The tail expression of the body, if any.
+    pub tail: Option<String>,
+}
+
+/// Parses the function whose name is at `offset`. Fails for methods taking
+/// `self` and for parameters which are patterns.
+pub fn parse_fn(text: &str, offset: usize) -> Option<FnDefinition> {
+    let (_, name_end) = identifier_at(text, offset)?;
+    let mut open = name_end + (text[name_end..].len() - text[name_end..].trim_start().len());
+    let generic = text[open..].starts_with('<');
+    if generic {
+        open = matching_delimiter(text, open)? + 1;
+        open += text[open..].len() - text[open..].trim_start().len();
+    }
+    if !text[open..].starts_with('(') {
+        return None;
+    }
+    let close = matching_delimiter(text, open)?;
+    let params = split_top_level(&text[open + 1..close])
+        .into_iter()
+        .map(|param| {
+            let pattern = param[..find_top_level(param, ':')?].trim();
+            let name = pattern.trim_start_matches("mut ").trim();
+            if name == "self" || !name.chars().all(|c| c.is_alphanumeric() || c == '_') {
+                None
+            } else {
+                Some((name.to_owned(), name.len() < pattern.len()))
+            }
+        })
+        .collect::<Option<Vec<_>>>()?;
+
+    let body_open = scan_code(text, close, |i, c| match c {
+        '{' => Some(Some(i)),
+        ';' => Some(None),
+        _ => None,
+    })??;
+    let body_close = matching_delimiter(text, body_open)?;
+    let body = text[body_open + 1..body_close].trim();
+    let indent = text[body_open + 1..body_close]
+        .lines()
+        .find(|line| !line.trim().is_empty())
+        .map_or("", |line| &line[..line.len() - line.trim_start().len()]);
+
+    // Everything after the last top-level statement is the tail expression.
+    let mut depth = 0;
+    let mut split = 0;
+    let _ = scan_code(body, 0, |i, c| {
+        match c {
+            '(' | '[' | '{' => depth += 1,
+            ')' | ']' => depth -= 1,
+            '}' => {
+                depth -= 1;
+                if depth == 0 {
+                    split = i + 1;
+                }
+            }
+            ';' if depth == 0 => split = i + 1,
+            _ => {}
+        }
+        None::<()>
+    });
+    let statements = reindent(body[..split].trim(), indent, "");
+    let tail = Some(body[split..].trim()).filter(|tail| !tail.is_empty());
+    Some(FnDefinition {
+        params,
+        generic,
+        statements,
+        tail: tail.map(|tail| reindent(tail, indent, "")),
+    })
+}
+
+impl FnDefinition {
+    /// Returns whether the body means the same when pasted in place of a call
+    /// in the same module, i.e., it doesn't return early or break out of
+    /// loops (which would affect the caller's control flow instead), and it
+    /// doesn't use generic parameters, imports or paths relative to the
+    /// function's module or impl.
+    pub fn is_inlinable(&self) -> bool {
+        lazy_static! {
+            static ref BLOCKER: Regex = Regex::new(
+                r"\b(?:return|break|continue|use|Self|self\s*::|super\s*::)\b|'\w+\s*:|\?"
+            )
+            .unwrap();
+        }
+        let bodies = [&self.statements[..], self.tail.as_deref().unwrap_or("")];
+        !self.generic
+            && bodies.iter().all(|body| {
+                let mask = code_mask(body);
+                let code: String =
+                    body.char_indices().map(|(i, c)| if mask[i] { c } else { ' ' }).collect();
+                !BLOCKER.is_match(&code)
+            })
+    }
+}
+
+/// The `match` expression whose scrutinee starts at `offset`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct MatchBlock {
+    /// The offsets of the braces delimiting the arms.
+    pub open: usize,
+    pub close: usize,
+    /// The patterns of the arms without guards, which cover all of their
+    /// variants.
+    pub patterns: Vec<String>,
+    /// Whether the last arm needs a comma before another arm can follow it.
+    pub needs_comma: bool,
+}
+
+/// Parses the arms of the `match` expression whose scrutinee (or `match`
+/// keyword) starts at `offset`.
+pub fn parse_match_block(text: &str, offset: usize) -> Option<MatchBlock> {
+    lazy_static! {
+        static ref GUARD: Regex = Regex::new(r"\bif\b").unwrap();
+        static ref BLOCK_LIKE: Regex =
+            Regex::new(r"^(?:\{|(?:if|match|loop|while|for|unsafe)\b)").unwrap();
+    }
+
+    let mut depth = 0;
+    let open = scan_code(text, offset, |i, c| match c {
+        '(' | '[' => {
+            depth += 1;
+            None
+        }
+        ')' | ']' => {
+            depth -= 1;
+            None
+        }
+        '{' if depth == 0 => Some(i),
+        _ => None,
+    })?;
+    let close = matching_delimiter(text, open)?;
+
+    let mut patterns = vec![];
+    let mut arm_start = open + 1;
+    // The start of the current arm's body, and whether it's block-like.
+    let mut body: Option<(usize, bool)> = None;
+    let mut depth = 0;
+    let _ = scan_code(text, open + 1, |i, c| {
+        if i >= close {
+            return Some(());
+        }
+        match c {
+            '(' | '[' | '{' => depth += 1,
+            ')' | ']' => depth -= 1,
+            '}' => {
+                depth -= 1;
+                let rest = text[i + 1..].trim_start();
+                let continues =
+                    rest.starts_with("else") || rest.starts_with(|c: char| ".?,".contains(c));
+                if depth == 0 && body.map_or(false, |(_, block_like)| block_like) && !continues {
+                    body = None;
+                    arm_start = i + 1;
+                }
+            }
+            '=' if depth == 0 && body.is_none() && text[i + 1..].starts_with('>') => {
+                let pattern = text[arm_start..i].trim();
+                if !GUARD.is_match(pattern) {
+                    patterns.push(pattern.to_owned());
+                }
+                let rest = text[i + 2..].trim_start();
+                body = Some((i + 2, BLOCK_LIKE.is_match(rest)));
+            }
+            ',' if depth == 0 && body.is_some() => {
+                body = None;
+                arm_start = i + 1;
+            }
+            _ => {}
+        }
+        None
+    });
+    let needs_comma = body.map_or(false, |(start, _)| !text[start..close].trim().is_empty());
+
+    Some(MatchBlock { open, close, patterns, needs_comma })
+}
+
+/// Returns whether one of `patterns` matches the variant `name` as a whole.
+/// Only the top-level alternatives of each pattern are considered, so that
+/// e.g. `E::B(E::C)` doesn't count as covering `C`.
+pub fn covers_variant(patterns: &[String], name: &str) -> bool {
+    patterns.iter().any(|pattern| {
+        let mut rest = pattern.as_str();
+        loop {
+            let (alternative, next) = match find_top_level(rest, '|') {
+                Some(i) => (&rest[..i], Some(&rest[i + 1..])),
+                None => (rest, None),
+            };
+            // Skip any binding, e.g. `x @ E::A`, and references.
+            let alternative = find_top_level(alternative, '@')
+                .map_or(alternative, |i| &alternative[i + 1..])
+                .trim()
+                .trim_start_matches('&')
+                .trim_start();
+            let path_end = alternative
+                .find(|c: char| c == '(' || c == '{' || c.is_whitespace())
+                .unwrap_or(alternative.len());
+            if alternative[..path_end].rsplit("::").next() == Some(name) {
+                return true;
+            }
+            match next {
+                Some(next) => rest = next,
+                None => return false,
+            }
+        }
+    })
+}
+
+/// Returns a pattern matching any value of the enum variant `name` of the
+/// given `kind`, whose save-analysis value (e.g., `Foo::Bar(u8, u16)`) is
+/// `value`.
+pub fn variant_pattern(kind: DefKind, prefix: &str, name: &str, value: &str) -> String {
+    match kind {
+        DefKind::StructVariant => format!("{}{} {{ .. }}", prefix, name),
+        _ => match value.find('(') {
+            Some(open) => {
+                let fields = matching_delimiter(value, open)
+                    .map_or(1, |close| split_top_level(&value[open + 1..close]).len());
+                format!("{}{}({})", prefix, name, vec!["_"; fields].join(", "))
+            }
+            None => format!("{}{}", prefix, name),
+        },
+    }
+}
+
+/// Returns the prefix with which to qualify the variants of the enum
+/// `enum_name` in `text`, given the `patterns` of a match on it: the prefix
+/// the patterns already use, nothing if the variants are imported, or the name
+/// the enum is in scope as.
+pub fn variant_prefix(
+    text: &str,
+    enum_name: &str,
+    variants: &[String],
+    patterns: &[String],
+) -> String {
+    lazy_static! {
+        static ref QUALIFIED: Regex =
+            Regex::new(r"((?:[A-Za-z_][A-Za-z0-9_]*\s*::\s*)+)([A-Za-z_][A-Za-z0-9_]*)").unwrap();
+    }
+
+    let used_prefix = patterns
+        .iter()
+        .flat_map(|pattern| QUALIFIED.captures_iter(pattern))
+        .find(|caps| variants.iter().any(|variant| *variant == caps[2]));
+    if let Some(caps) = used_prefix {
+        return caps[1].split_whitespace().collect();
+    }
+    let is_bare_variant = |pattern: &String| {
+        let first = pattern.split(|c: char| !c.is_alphanumeric() && c != '_').next();
+        variants.iter().any(|variant| Some(variant.as_str()) == first)
+    };
+    if patterns.iter().any(is_bare_variant) {
+        return String::new();
+    }
+
+    let items = imports::parse_use_items(text);
+    let paths: Vec<_> = items.iter().flat_map(|item| &item.paths).collect();
+    let imports_variants = paths.iter().any(|path| {
+        path.parent().last().map(String::as_str) == Some(enum_name)
+            && (path.segments.last().map(String::as_str) == Some("*")
+                || path.binding().map_or(false, |binding| variants.iter().any(|v| v == binding)))
+    });
+    if imports_variants {
+        return String::new();
+    }
+    let alias = paths
+        .iter()
+        .find(|path| path.segments.last().map(String::as_str) == Some(enum_name))
+        .and_then(|path| path.binding());
+    format!("{}::", alias.unwrap_or(enum_name))
+}
+
+/// Returns the edit adding arms with the given `patterns` (each with a
+/// `todo!()` body) to the end of `block`.
+pub fn add_match_arms_edit(text: &str, block: &MatchBlock, patterns: &[String]) -> TextEdit {
+    let body = &text[block.open + 1..block.close];
+    let existing = body.trim_end();
+    let start = block.open + 1 + existing.len();
+    let indent = indent_at(text, block.open);
+    let first_arm = block.open + 1 + (body.len() - body.trim_start().len());
+    let arm_indent = if text[block.open..first_arm].contains('\n') && first_arm < block.close {
+        indent_at(text, first_arm).to_owned()
+    } else {
+        format!("{}    ", indent)
+    };
+    let arms: String =
+        patterns.iter().map(|pattern| format!("\n{}{} => todo!(),", arm_indent, pattern)).collect();
+    let comma = if block.needs_comma { "," } else { "" };
+    TextEdit {
+        range: Range::new(offset_to_position(text, start), offset_to_position(text, block.close)),
+        new_text: format!("{}{}\n{}", comma, arms, indent),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn converts_utf16_positions() {
+        let text = "ab\n\u{e9}\u{1f600}x\n";
+        let x = text.find('x').unwrap();
+        assert_eq!(offset_to_position(text, x), Position::new(1, 3));
+        assert_eq!(position_to_offset(text, Position::new(1, 3)), Some(x));
+        assert_eq!(position_to_offset(text, Position::new(1, 4)), Some(x + 1));
+        // In the middle of the surrogate pair of the emoji.
+        assert_eq!(position_to_offset(text, Position::new(1, 2)), None);
+        assert_eq!(position_to_offset(text, Position::new(1, 5)), None);
+        assert_eq!(position_to_offset(text, Position::new(2, 0)), Some(text.len()));
+    }
+
+    #[test]
+    fn parses_generic_params() {
+        assert_eq!(
+            parse_generic_params("'a, T: Iterator<Item = u8>, Rhs = Self, const N: usize"),
+            vec![
+                GenericParam { name: "'a".to_owned(), default: None },
+                GenericParam { name: "T".to_owned(), default: None },
+                GenericParam { name: "Rhs".to_owned(), default: Some("Self".to_owned()) },
+                GenericParam { name: "N".to_owned(), default: None },
+            ]
+        );
+        assert_eq!(
+            generic_list_after("trait Foo<T, F: Fn() -> T>: Bar", "Foo"),
+            Some("T, F: Fn() -> T")
+        );
+        assert_eq!(generic_list_after("trait FooBar<T>", "Foo"), None);
+    }
+
+    #[test]
+    fn substitutes_generics() {
+        let params = parse_generic_params("T, Rhs = Self");
+        assert_eq!(
+            substitute_generics(
+                "fn f(&self, t: T, r: Rhs) -> Self::T",
+                &params,
+                &["u32".to_owned()]
+            ),
+            "fn f(&self, t: u32, r: Self) -> Self::T"
+        );
+    }
+
+    #[test]
+    fn adds_missing_trait_items() {
+        let text = "mod m {\n    impl<T> ops::Add<Vec<T>> for Foo where T: Clone {\n        type Output = Foo;\n    }\n}\n";
+        let block = parse_impl_block(text, text.find("impl").unwrap()).unwrap();
+        assert_eq!(block.trait_name, "Add");
+        assert_eq!(&text[block.trait_name_offset..block.trait_name_offset + 3], "Add");
+        assert_eq!(block.trait_args, vec!["Vec<T>".to_owned()]);
+
+        let trait_text =
+            "trait Add<Rhs = Self> {\n    fn add(self,\n           rhs: Rhs) -> Self::Output;\n}";
+        let sig = item_declaration(trait_text, trait_text.find("add(").unwrap()).unwrap();
+        assert_eq!(sig, "fn add(self,\n       rhs: Rhs) -> Self::Output");
+        let params = parse_generic_params(generic_list_after(trait_text, "Add").unwrap());
+        let sig = substitute_generics(&sig, &params, &block.trait_args);
+        let stub = trait_item_stub(DefKind::Method, "add", &sig).unwrap();
+
+        let edit = add_items_edit(text, (block.open, block.close), &[stub]);
+        assert_eq!(edit.range.start, Position::new(2, 26));
+        assert_eq!(edit.range.end, Position::new(3, 4));
+        assert_eq!(
+            edit.new_text,
+            "\n\n        fn add(self,\n               rhs: Vec<T>) -> Self::Output {\n            todo!()\n        }\n    "
+        );
+
+        let text = "impl Default for Foo {}";
+        let block = parse_impl_block(text, 0).unwrap();
+        let stub =
+            trait_item_stub(DefKind::Function, "default", "fn default() -> Self {}").unwrap();
+        let edit = add_items_edit(text, (block.open, block.close), &[stub]);
+        assert_eq!(edit.new_text, "\n    fn default() -> Self {\n        todo!()\n    }\n");
+
+        assert_eq!(
+            trait_item_stub(DefKind::Type, "Item", "type Item: Clone + Send;").unwrap(),
+            "type Item = (); // TODO"
+        );
+        assert_eq!(
+            trait_item_stub(DefKind::Const, "MAX", "const MAX: u32 = 1;").unwrap(),
+            "const MAX: u32 = todo!();"
+        );
+    }
+
+    #[test]
+    fn finds_statement_start() {
+        let text = "fn main() {\n    let a = foo(1);\n    let b = match a {\n        _ => 2,\n    }\n    .max(bar(a));\n}\n";
+        let offset = text.find("bar").unwrap();
+        assert_eq!(
+            &text[statement_start(text, offset)..offset],
+            "let b = match a {\n        _ => 2,\n    }\n    .max("
+        );
+        let offset = text.find("foo").unwrap();
+        assert_eq!(&text[statement_start(text, offset)..offset], "let a = ");
+        assert!(is_fn_header(block_header(text, text.find('{').unwrap())));
+        assert!(is_balanced("foo(a, [b]) { c }"));
+        assert!(!is_balanced("foo(a, [b) }"));
+    }
+
+    #[test]
+    fn suggests_variable_names() {
+        assert_eq!(variable_name("self.items.len()"), "len");
+        assert_eq!(variable_name("foo.get_bar(1, (2, 3))?"), "bar");
+        assert_eq!(variable_name("HashMap::<u32, u32>::new()"), "hash_map");
+        assert_eq!(variable_name("point.x"), "x");
+        assert_eq!(variable_name("a + b"), "b");
+        assert_eq!(variable_name("(a, b)"), "value");
+        assert_eq!(variable_name("1"), "value");
+    }
+
+    #[test]
+    fn extracts_function() {
+        let local = |name: &str, ty: &str, pass_by| ExtractedLocal {
+            name: name.to_owned(),
+            ty: ty.to_owned(),
+            pass_by,
+        };
+        let function = ExtractedFunction {
+            name: "fun_name".to_owned(),
+            receiver: None,
+            params: vec![
+                local("total", "u32", PassBy::RefMut),
+                local("items", "Vec<u32>", PassBy::Ref),
+            ],
+            returns: vec![local("count", "usize", PassBy::Value { mutable: true })],
+            generics: vec![],
+            where_predicates: vec![],
+        };
+        assert_eq!(function.call("fun_name"), "let mut count = fun_name(&mut total, &items);");
+        assert!(mutates("total += 1;", "total"));
+        assert!(!mutates("total == 1", "total"));
+        assert_eq!(receiver_for("self.count += 1;"), Some("&mut self"));
+        assert_eq!(receiver_for("let a = self.count;"), Some("&self"));
+        assert_eq!(receiver_for("let a = itself;"), None);
+        assert_eq!(
+            function.definition("let count = items.len();\ntotal += count as u32;"),
+            "fn fun_name(total: &mut u32, items: &Vec<u32>) -> usize {\n    \
+             let count = items.len();\n    *total += count as u32;\n    count\n}"
+        );
+
+        let header = "#[inline]\npub fn f<'a, T: Into<U>, U, V: Clone>(t: &'a T, v: V) -> U\n\
+                      where\n    U: Default,\n    V: Copy,";
+        let (generics, predicates) = used_generics(header, "t: &T\nlet u = t.into();");
+        assert_eq!(generics, vec!["T: Into<U>", "U"]);
+        assert_eq!(predicates, vec!["U: Default"]);
+        let function = ExtractedFunction {
+            name: "g".to_owned(),
+            receiver: None,
+            params: vec![local("t", "T", PassBy::Value { mutable: false })],
+            returns: vec![],
+            generics,
+            where_predicates: predicates,
+        };
+        assert_eq!(
+            function.definition("drop(t.into());"),
+            "fn g<T: Into<U>, U>(t: T) where U: Default {\n    drop(t.into());\n}"
+        );
+        assert_eq!(used_generics("fn f(a: u32)", "a"), (vec![], vec![]));
+    }
+
+    #[test]
+    fn finds_identifiers() {
+        let text = "// see→foo";
+        let offset = text.find("foo").unwrap();
+        assert_eq!(identifier_at(text, offset + 1), Some((offset, text.len())));
+        assert_eq!(identifier_at("aéb c", 3), Some((0, 4)));
+        assert_eq!(identifier_at("1 + 2", 1), None);
+    }
+
+    #[test]
+    fn parses_let_bindings() {
+        let text = "fn f() {\n    let mut x: Vec<u8> = vec![1, 2];\n    x.push(3);\n}";
+        let binding = parse_let(text, text.find("x:").unwrap()).unwrap();
+        assert_eq!(&text[binding.start..binding.end], "let mut x: Vec<u8> = vec![1, 2];");
+        assert!(binding.mutable);
+        assert_eq!(binding.ty.as_deref(), Some("Vec<u8>"));
+        assert_eq!(binding.init, "vec![1, 2]");
+        assert_eq!(parse_let("let x = 1;", 4).unwrap().ty, None);
+        assert!(parse_let("let (a, b) = (1, 2);", 5).is_none());
+
+        assert!(has_side_effects("vec![1, 2]"));
+        assert!(has_side_effects("foo.bar()"));
+        assert!(!has_side_effects("a.b + \"f(x)\".len"));
+        assert!(!has_side_effects("x == 1"));
+        assert!(needs_parens("a + b"));
+        assert!(needs_parens("|x| x"));
+        assert!(!needs_parens("foo::<u8>(a + b).bar?"));
+        assert!(!needs_parens("&x"));
+    }
+
+    #[test]
+    fn parses_fn_definitions() {
+        let text = "fn add<T>(mut a: T, b: T) -> T {\n    let c = a;\n    if c { 1 } else { 2 }\n    c + b\n}";
+        let def = parse_fn(text, 3).unwrap();
+        assert_eq!(def.params, vec![("a".to_owned(), true), ("b".to_owned(), false)]);
+        assert_eq!(def.statements, "let c = a;\nif c { 1 } else { 2 }");
+        assert_eq!(def.tail.as_deref(), Some("c + b"));
+        assert!(parse_fn("fn f(&self) {}", 3).is_none());
+        assert!(parse_fn("fn f((a, b): (u8, u8)) {}", 3).is_none());
+
+        assert!(def.generic);
+        assert!(!def.is_inlinable());
+        let inlinable = |body: &str| parse_fn(&format!("fn f(a: u8) {{ {} }}", body), 3).unwrap();
+        assert!(inlinable("let b = a; b + 1").is_inlinable());
+        assert!(inlinable("\"return?\"; /* break */").is_inlinable());
+        assert!(!inlinable("if a > 1 { return 1; } a").is_inlinable());
+        assert!(!inlinable("g(a)?;").is_inlinable());
+        assert!(!inlinable("h(a).ok()?").is_inlinable());
+        assert!(!inlinable("loop { break; }").is_inlinable());
+        assert!(!inlinable("for _ in 0..a { continue; }").is_inlinable());
+        assert!(!inlinable("'outer: loop {}").is_inlinable());
+        assert!(!inlinable("use std::mem; mem::drop(a);").is_inlinable());
+        assert!(!inlinable("self::g(a)").is_inlinable());
+        assert!(!inlinable("super::g(a)").is_inlinable());
+        assert!(!inlinable("Self::g(a)").is_inlinable());
+
+        assert_eq!(
+            substitute_names("let c = a.a + S { a: a };", &[("a", "x")]),
+            "let c = x.a + S { a: x };"
+        );
+        assert_eq!(
+            combine_edits("0123456789", vec![(6, 8, "x".to_owned()), (1, 2, String::new())]),
+            Some((1, 8, "2345x".to_owned()))
+        );
+    }
+
+    #[test]
+    fn validates_identifiers() {
+        assert!(is_valid_identifier("foo_bar2"));
+        assert!(is_valid_identifier("_foo"));
+        assert!(is_valid_identifier("r#match"));
+        assert!(!is_valid_identifier("_"));
+        assert!(!is_valid_identifier("match"));
+        assert!(!is_valid_identifier("r#self"));
+        assert!(!is_valid_identifier("2foo"));
+        assert!(!is_valid_identifier("foo-bar"));
+
+        assert!(refers_to("let x = foo(y);", "foo"));
+        assert!(!refers_to("let x = a.foo + Foo::foo + S { foo: 1 }; // foo", "foo"));
+    }
+
+    #[test]
+    fn generates_type_boilerplate() {
+        let text = "/// Docs.\n#[derive(Debug)]\npub(crate) struct Foo<'a, T: Clone = u8>\nwhere\n    T: Send,\n{\n    a: &'a T,\n}\n\nimpl<'a, T: Clone> Foo<'a, T> {\n}\n";
+        let def = parse_type_definition(text, text.find("Foo").unwrap()).unwrap();
+        assert_eq!(def.attrs_start, 0);
+        assert_eq!(&text[def.start..def.start + 3], "pub");
+        assert_eq!(&text[def.end - 1..def.end], "}");
+        assert_eq!(def.visibility, "pub(crate) ");
+        assert_eq!(def.derives, vec!["Debug".to_owned()]);
+        assert_eq!(
+            def.impl_header("Foo", Some("Default")),
+            "impl<'a, T: Clone> Default for Foo<'a, T> where\n    T: Send"
+        );
+        assert_eq!(derivable_traits(&def.derives, true), vec!["Clone", "PartialEq", "Hash"]);
+        assert_eq!(derive_edit(text, &def, "Clone").new_text, ", Clone");
+        assert_eq!(
+            derive_edit(text, &def, "Clone").range.start,
+            offset_to_position(text, text.find(")]").unwrap())
+        );
+
+        let body = inherent_impl_body(text, "Foo").unwrap();
+        assert_eq!(&text[body.0..=body.1], "{\n}");
+        assert!(inherent_impl_body(text, "Bar").is_none());
+
+        let text = "    struct Unit;";
+        let def = parse_type_definition(text, 11).unwrap();
+        assert_eq!(def.end, text.len());
+        assert_eq!(derive_edit(text, &def, "Debug").new_text, "#[derive(Debug)]\n    ");
+
+        let fields =
+            vec![("a".to_owned(), "u32".to_owned()), ("b".to_owned(), "String".to_owned())];
+        assert_eq!(
+            constructor("pub ", &fields),
+            "pub fn new(a: u32, b: String) -> Self {\n    Self { a, b }\n}"
+        );
+        assert_eq!(getter("", "b", "String"), "fn b(&self) -> &String {\n    &self.b\n}");
+        assert_eq!(getter("", "a", "u32"), "fn a(&self) -> u32 {\n    self.a\n}");
+        assert_eq!(
+            setter("", "r#type", "u8"),
+            "fn set_type(&mut self, r#type: u8) {\n    self.r#type = r#type;\n}"
+        );
+        assert_eq!(
+            default_impl("impl Default for Foo", &["a".to_owned()]),
+            "impl Default for Foo {\n    fn default() -> Self {\n        Self {\n            \
+             a: Default::default(),\n        }\n    }\n}"
+        );
+
+        let text = "struct Pair<'a, T, U: Clone, const N: usize>(&'a T, [U; N]);";
+        let def = parse_type_definition(text, text.find("Pair").unwrap()).unwrap();
+        assert_eq!(
+            def.bounded_impl_header("Pair", "Default"),
+            "impl<'a, T: Default, U: Clone + Default, const N: usize> Default for Pair<'a, T, U, N>"
+        );
+        let fields =
+            vec![("0".to_owned(), "u32".to_owned()), ("1".to_owned(), "Vec<u8>".to_owned())];
+        assert_eq!(
+            constructor("", &fields),
+            "fn new(field_0: u32, field_1: Vec<u8>) -> Self {\n    Self(field_0, field_1)\n}"
+        );
+        assert_eq!(getter("", "1", "Vec<u8>"), "fn field_1(&self) -> &Vec<u8> {\n    &self.1\n}");
+        assert_eq!(
+            setter("", "0", "u32"),
+            "fn set_field_0(&mut self, field_0: u32) {\n    self.0 = field_0;\n}"
+        );
+        assert_eq!(
+            default_impl("impl Default for Pair", &["0".to_owned(), "1".to_owned()]),
+            "impl Default for Pair {\n    fn default() -> Self {\n        \
+             Self(Default::default(), Default::default())\n    }\n}"
+        );
+    }
+
+    #[test]
+    fn adds_missing_match_arms() {
+        let text = "fn f(x: E) {\n    match x {\n        E::A => 1,\n        E::B(y) if y > 0 => {}\n        E::C { .. } => 2\n    }\n}";
+        let block = parse_match_block(text, text.find("x {").unwrap()).unwrap();
+        assert_eq!(block.patterns, vec!["E::A".to_owned(), "E::C { .. }".to_owned()]);
+        assert!(block.needs_comma);
+        assert!(covers_variant(&block.patterns, "C"));
+        assert!(!covers_variant(&block.patterns, "B"));
+        let patterns = vec!["E::B(E::C)".to_owned(), "x @ E::A | &E::D { .. }".to_owned()];
+        assert!(!covers_variant(&patterns, "C"));
+        assert!(covers_variant(&patterns, "A"));
+        assert!(covers_variant(&patterns, "B"));
+        assert!(covers_variant(&patterns, "D"));
+
+        let variants = vec!["A".to_owned(), "B".to_owned(), "C".to_owned()];
+        let prefix = variant_prefix(text, "E", &variants, &block.patterns);
+        assert_eq!(prefix, "E::");
+        assert_eq!(
+            variant_pattern(DefKind::TupleVariant, &prefix, "B", "E::B(u8, (u8, u8))"),
+            "E::B(_, _)"
+        );
+        assert_eq!(variant_pattern(DefKind::StructVariant, "", "C", "E::C { a }"), "C { .. }");
+        assert_eq!(variant_pattern(DefKind::TupleVariant, "", "D", "E::D"), "D");
+
+        let edit = add_match_arms_edit(text, &block, &["E::B(_)".to_owned()]);
+        assert_eq!(edit.new_text, ",\n        E::B(_) => todo!(),\n    ");
+
+        let text = "use self::E::*;\nfn f(x: E) { match x {} }";
+        let block = parse_match_block(text, text.find("x {").unwrap()).unwrap();
+        assert!(block.patterns.is_empty());
+        assert!(!block.needs_comma);
+        assert_eq!(variant_prefix(text, "E", &variants, &block.patterns), "");
+        let text = "use crate::e::E as Alias;\n";
+        assert_eq!(variant_prefix(text, "E", &variants, &[]), "Alias::");
+    }
+
+    #[test]
+    fn finds_postfix_expressions() {
+        /// The expression in front of the last `.` of `text`.
+        fn expression(text: &str) -> Option<&str> {
+            let end = text.rfind('.').unwrap();
+            postfix_expression_start(text, end).map(|start| &text[start..end])
+        }
+        assert_eq!(expression("fn f() { x."), Some("x"));
+        assert_eq!(expression("fn f() { let y = &foo.bar(a, b)?."), Some("foo.bar(a, b)?"));
+        assert_eq!(expression("fn f() { v[0]\n        .iter()."), Some("v[0]\n        .iter()"));
+        assert_eq!(expression("fn f() { std::env::args()."), Some("std::env::args()"));
+        assert_eq!(expression("fn f() { vec![1, 2]."), Some("vec![1, 2]"));
+        assert_eq!(expression("fn f() { let y = (a + b)."), Some("(a + b)"));
+        assert_eq!(expression("fn f() { Foo { x: 1 }."), Some("Foo { x: 1 }"));
+        assert_eq!(expression("fn f() { \"a.b\".len()."), Some("\"a.b\".len()"));
+        assert_eq!(expression("fn f() { x.0."), Some("x.0"));
+        assert_eq!(expression("fn f() { foo(a, b."), Some("b"));
+        assert_eq!(expression("fn f() { println!(\"{}\", x + y."), Some("y"));
+        assert_eq!(expression("fn f() { if a * b."), Some("b"));
+        assert_eq!(expression("fn f() { let x = ."), None);
+    }
+}
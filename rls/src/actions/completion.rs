@@ -0,0 +1,1031 @@
+//! Code completion using the save-analysis data.
+//!
+//! Names are completed from the data of the last build, so code which hasn't
+//! been analyzed yet (e.g., a local variable that was only just typed) can't
+//! be completed here. In that case no items are returned and the caller may
+//! fall back to Racer.
+
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::path::{Path, PathBuf};
+
+use itertools::Itertools;
+
+use lazy_static::lazy_static;
+use regex::Regex;
+use rls_analysis::{Def, DefKind, Id};
+use rls_span as span;
+use rls_vfs::FileContents;
+
+use crate::actions::codegen;
+use crate::actions::hover;
+use crate::actions::imports::{self, UseItem};
+use crate::actions::InitActionContext;
+use crate::lsp_data::*;
+
+/// Traits from the standard prelude, whose methods are always in scope.
+const PRELUDE_TRAITS: &[&str] = &[
+    "AsMut",
+    "AsRef",
+    "Clone",
+    "Default",
+    "DoubleEndedIterator",
+    "Drop",
+    "Eq",
+    "ExactSizeIterator",
+    "Extend",
+    "Fn",
+    "FnMut",
+    "FnOnce",
+    "From",
+    "Into",
+    "IntoIterator",
+    "Iterator",
+    "Ord",
+    "PartialEq",
+    "PartialOrd",
+    "ToOwned",
+    "ToString",
+];
+
+/// Definition kinds which name a type that can have fields or impls.
+const TYPE_KINDS: &[DefKind] = &[
+    DefKind::Struct,
+    DefKind::Tuple,
+    DefKind::Enum,
+    DefKind::Union,
+    DefKind::Trait,
+    DefKind::Type,
+];
+
+/// Smart pointers which dereference to the type of their first generic
+/// argument, so a receiver of that type also has the pointee's methods.
+const SMART_POINTERS: &[&str] = &["Arc", "Box", "MutexGuard", "Rc", "Ref", "RefMut"];
+
+/// Number of recently accepted completions remembered for ranking.
+const RECENT_COMPLETIONS: usize = 32;
+
+/// Score bonuses and penalties used by `rank`, added to the quality of the
+/// fuzzy match of the typed name.
+const SAME_FILE_SCORE: i64 = 6;
+const SAME_WORKSPACE_SCORE: i64 = 3;
+const RELEVANT_KIND_SCORE: i64 = 8;
+const MAX_RECENT_SCORE: i64 = 10;
+const DEPRECATED_SCORE: i64 = -15;
+
+/// Where the definition completed by an item is, relative to the completed
+/// code. Closer definitions are ranked first.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Locality {
+    SameFile,
+    SameWorkspace,
+    External,
+}
+
+impl Locality {
+    pub fn of(ctx: &InitActionContext, file_path: &Path, def_path: &Path) -> Locality {
+        if def_path == file_path {
+            Locality::SameFile
+        } else if def_path.starts_with(&ctx.current_project) {
+            Locality::SameWorkspace
+        } else {
+            Locality::External
+        }
+    }
+}
+
+/// The keys (see `item_key`) of the completion items most recently accepted by
+/// the user, most recent last.
+#[derive(Debug, Default)]
+pub struct RecentCompletions {
+    keys: VecDeque<String>,
+}
+
+impl RecentCompletions {
+    pub fn record(&mut self, key: &str) {
+        self.keys.retain(|k| k != key);
+        if self.keys.len() == RECENT_COMPLETIONS {
+            self.keys.pop_front();
+        }
+        self.keys.push_back(key.to_owned());
+    }
+
+    /// Returns how many other items were accepted since the one with `key`
+    /// was, if it was accepted recently.
+    fn age(&self, key: &str) -> Option<usize> {
+        self.keys.iter().rev().position(|k| k == key)
+    }
+}
+
+/// Identifies `item` when ranking: its label and kind, along with the path of
+/// its definition (stored in its `data`) or else its detail, so that distinct
+/// items of the same name (e.g. `fmt::Result` and `io::Result`) are told
+/// apart, while the same item re-exported through several globs is not.
+pub fn item_key(item: &CompletionItem) -> String {
+    let path = item.data.as_ref().and_then(|data| data.as_str());
+    format!(
+        "{}\0{:?}\0{}",
+        item.label,
+        item.kind,
+        path.or(item.detail.as_deref()).unwrap_or_default()
+    )
+}
+
+/// What is being completed, see `completion_context`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum CompletionContext {
+    /// A field or method of the receiver identifier at the given offsets, e.g.
+    /// `foo.`.
+    Member { receiver: (usize, usize) },
+    /// An item of the path whose segments are at the given offsets, e.g.
+    /// `foo::bar::`.
+    Path { segments: Vec<(usize, usize)> },
+    /// A name in scope.
+    Scope,
+}
+
+/// A completion item, along with the definition it completes, if any.
+struct Candidate {
+    item: CompletionItem,
+    def: Option<Def>,
+    /// The locality of items without a definition.
+    locality: Locality,
+}
+
+impl From<Def> for Candidate {
+    fn from(def: Def) -> Candidate {
+        let detail = match def.kind {
+            DefKind::Mod => def.qualname.trim_start_matches("::").to_owned(),
+            _ => def.value.clone(),
+        };
+        let documentation = if def.docs.is_empty() {
+            None
+        } else {
+            Some(Documentation::MarkupContent(MarkupContent {
+                kind: MarkupKind::Markdown,
+                value: hover::process_docs(&def.docs),
+            }))
+        };
+        let item = CompletionItem {
+            label: def.name.clone(),
+            kind: Some(completion_kind_from_def_kind(def.kind)),
+            detail: Some(detail).filter(|detail| !detail.is_empty()),
+            documentation,
+            data: Some(def.qualname.clone().into()),
+            ..CompletionItem::default()
+        };
+        Candidate { item, def: Some(def), locality: Locality::External }
+    }
+}
+
+/// Completes the name at `position` from the analysis data, returning each
+/// item along with the locality of its definition. Also returns whether the
+/// position is in a `use` declaration.
+///
+/// Returns no items if the code there can't be completed, e.g. because it
+/// hasn't been analyzed yet.
+pub fn complete(
+    ctx: &InitActionContext,
+    file_path: &Path,
+    position: Position,
+) -> (Vec<(CompletionItem, Locality)>, bool) {
+    let text = match ctx.vfs.load_file(file_path) {
+        Ok(FileContents::Text(text)) => text,
+        _ => return (vec![], false),
+    };
+    let offset = match codegen::position_to_offset(&text, position) {
+        Some(offset) => offset,
+        None => return (vec![], false),
+    };
+    let is_use_stmt = in_use_statement(&text, offset);
+    let (context, start) = match completion_context(&text, offset) {
+        Some(context) => context,
+        None => return (vec![], is_use_stmt),
+    };
+    let candidates = match context {
+        CompletionContext::Member { receiver } => member_items(ctx, &text, file_path, receiver),
+        CompletionContext::Path { segments } => path_items(ctx, &text, file_path, &segments),
+        CompletionContext::Scope => scope_items(ctx, &text, file_path, start),
+    };
+
+    let prefix = &text[start..offset];
+    let snippets = ctx.client_capabilities.code_completion_has_snippet_support && !is_use_stmt;
+    let mut sources = HashMap::new();
+    let items = candidates
+        .into_iter()
+        .filter(|c| fuzzy_score(prefix, &c.item.label).is_some())
+        .map(|Candidate { mut item, def, locality }| {
+            let def = match def {
+                Some(def) => def,
+                None => return (item, locality),
+            };
+            // Only look at the source of the definition while the span of it
+            // is still current.
+            let def_source = Some(&def.span.file)
+                .filter(|file| ctx.build_results_current(file))
+                .and_then(|file| source(ctx, &mut sources, file));
+            let def_offset = def_source.and_then(|source| {
+                let start = ls_util::rls_to_range(def.span.range).start;
+                Some((source, codegen::position_to_offset(source, start)?))
+            });
+            if let Some((source, offset)) = def_offset {
+                if has_deprecated_attribute(source, offset) {
+                    item.deprecated = Some(true);
+                }
+            }
+            if def.kind == DefKind::Function || def.kind == DefKind::Method {
+                // Multi-line declarations are too long to be useful as details.
+                let declaration = def_offset
+                    .and_then(|(source, offset)| codegen::item_declaration(source, offset))
+                    .filter(|declaration| !declaration.contains('\n'));
+                if let Some(declaration) = declaration {
+                    item.detail = Some(declaration);
+                }
+                if is_use_stmt {
+                    item.insert_text = Some(def.name.clone());
+                } else if snippets {
+                    item.insert_text = Some(call_snippet(&def.name, &def.value));
+                    item.insert_text_format = Some(InsertTextFormat::Snippet);
+                }
+            }
+            (item, Locality::of(ctx, file_path, &def.span.file))
+        })
+        .collect();
+    (items, is_use_stmt)
+}
+
+/// Returns whether `offset` is in a `use` declaration.
+pub fn in_use_statement(text: &str, offset: usize) -> bool {
+    lazy_static! {
+        static ref USE: Regex = Regex::new(r"^(?:pub(?:\([^)]*\))?\s+)?use\b").unwrap();
+    }
+    let start = codegen::statement_start(text, offset);
+    USE.is_match(text[start..offset].trim_start())
+}
+
+/// Returns the text of `file`, caching it in `sources`.
+fn source<'s>(
+    ctx: &InitActionContext,
+    sources: &'s mut HashMap<PathBuf, Option<String>>,
+    file: &Path,
+) -> Option<&'s str> {
+    let text = sources.entry(file.to_owned()).or_insert_with(|| match ctx.vfs.load_file(file) {
+        Ok(FileContents::Text(text)) => Some(text),
+        _ => None,
+    });
+    text.as_ref().map(String::as_str)
+}
+
+/// Returns whether the item whose name is at `offset` has a `#[deprecated]`
+/// attribute.
+pub fn has_deprecated_attribute(text: &str, offset: usize) -> bool {
+    let line_start = text[..offset].rfind('\n').map_or(0, |i| i + 1);
+    text[..line_start]
+        .lines()
+        .rev()
+        .map(str::trim)
+        .take_while(|line| line.starts_with('#') || line.starts_with("//"))
+        .any(|line| line.starts_with("#[deprecated"))
+}
+
+/// Returns a snippet calling the function `name` with signature `value`
+/// (e.g. `fn (&self, x: u32) -> u32`), with a placeholder for each argument
+/// other than `self`.
+pub fn call_snippet(name: &str, value: &str) -> String {
+    let params = value
+        .find('(')
+        .and_then(|open| Some((open, codegen::matching_delimiter(value, open)?)))
+        .map_or(vec![], |(open, close)| codegen::split_top_level(&value[open + 1..close]));
+    let args = params
+        .into_iter()
+        .filter(|param| !takes_self(&format!("({}", param)))
+        .enumerate()
+        .map(|(i, param)| {
+            let pattern =
+                codegen::find_top_level(param, ':').map_or(param, |colon| &param[..colon]);
+            format!("${{{}:{}}}", i + 1, pattern.trim())
+        })
+        .join(", ");
+    format!("{}({})", name, args)
+}
+
+/// Parses the code before `offset`, returning what is being completed there
+/// and the offset at which the partially typed name starts. Returns `None` if
+/// the code can't be completed from the analysis data, e.g. the fields of the
+/// result of a method call.
+pub fn completion_context(text: &str, offset: usize) -> Option<(CompletionContext, usize)> {
+    let start = identifier_start(text, offset);
+    let before = text[..start].trim_end();
+
+    if before.ends_with('.') && !before.ends_with("..") {
+        let end = before[..before.len() - 1].trim_end().len();
+        let receiver_start = identifier_start(text, end);
+        if receiver_start == end || text[receiver_start..].starts_with(|c: char| c.is_ascii_digit())
+        {
+            return None;
+        }
+        return Some((CompletionContext::Member { receiver: (receiver_start, end) }, start));
+    }
+
+    if before.ends_with("::") {
+        let mut segments = vec![];
+        let mut end = before.len() - 2;
+        loop {
+            end = text[..end].trim_end().len();
+            let segment_start = identifier_start(text, end);
+            if segment_start == end {
+                // An absolute path or a qualified path, e.g. `<T as Trait>::`,
+                // whose last segments can still be resolved.
+                if segments.is_empty() {
+                    return None;
+                }
+                break;
+            }
+            segments.push((segment_start, end));
+            let before = text[..segment_start].trim_end();
+            if !before.ends_with("::") {
+                break;
+            }
+            end = before.len() - 2;
+        }
+        segments.reverse();
+        return Some((CompletionContext::Path { segments }, start));
+    }
+
+    Some((CompletionContext::Scope, start))
+}
+
+/// Returns the offset at which the identifier ending at `end` starts.
+pub fn identifier_start(text: &str, end: usize) -> usize {
+    text[..end]
+        .char_indices()
+        .rev()
+        .take_while(|&(_, c)| c.is_alphanumeric() || c == '_')
+        .last()
+        .map_or(end, |(i, _)| i)
+}
+
+/// Returns the paths of the types whose members a receiver of type `ty` has,
+/// e.g. `std::rc::Rc` and `Foo` for `&Rc<Foo>`. References, pointers and
+/// generic arguments are ignored.
+pub fn receiver_types(ty: &str) -> Vec<&str> {
+    lazy_static! {
+        static ref TYPE: Regex = Regex::new(
+            r"^(?:&\s*(?:'\w+\s+)?|\*\s*(?:const|mut)\s+|mut\s+|dyn\s+|impl\s+)*((?:::)?[A-Za-z_]\w*(?:::[A-Za-z_]\w*)*)"
+        )
+        .unwrap();
+    }
+    let ty = ty.trim();
+    let path = match TYPE.captures(ty) {
+        Some(captures) => captures.get(1).unwrap(),
+        None => return vec![],
+    };
+    let mut types = vec![path.as_str()];
+
+    let name = path.as_str().rsplit("::").next().unwrap_or_default();
+    let rest = &ty[path.end()..];
+    if SMART_POINTERS.contains(&name) && rest.starts_with('<') && rest.ends_with('>') {
+        let args = codegen::split_top_level(&rest[1..rest.len() - 1]);
+        if let Some(arg) = args.iter().find(|arg| !arg.trim_start().starts_with('\'')) {
+            types.extend(receiver_types(arg));
+        }
+    }
+    types
+}
+
+/// Returns whether the function with signature `value` (e.g. `fn (&self) -> u32`)
+/// takes `self`, i.e. whether it can be called with the method call syntax.
+pub fn takes_self(value: &str) -> bool {
+    lazy_static! {
+        static ref SELF: Regex =
+            Regex::new(r"^[^(]*\(\s*(?:&\s*(?:'\w+\s+)?)?(?:mut\s+)?self\b").unwrap();
+    }
+    SELF.is_match(value)
+}
+
+/// Returns the span of the text between the `start` and `end` offsets.
+pub fn text_span(text: &str, file_path: &Path, start: usize, end: usize) -> span::Span {
+    let range = Range::new(
+        codegen::offset_to_position(text, start),
+        codegen::offset_to_position(text, end),
+    );
+    span::Span::from_range(ls_util::range_to_rls(range), file_path.to_owned())
+}
+
+/// Completes the fields and methods of the identifier at `receiver`.
+fn member_items(
+    ctx: &InitActionContext,
+    text: &str,
+    file_path: &Path,
+    (start, end): (usize, usize),
+) -> Vec<Candidate> {
+    let ty = match ctx.analysis.show_type(&text_span(text, file_path, start, end)) {
+        Ok(ty) => ty,
+        Err(_) => return vec![],
+    };
+    let use_items = imports::parse_use_items(text);
+
+    let mut items = vec![];
+    for path in receiver_types(&ty) {
+        let type_id = match resolve_type(ctx, file_path, path) {
+            Some(id) => id,
+            None => continue,
+        };
+        let children = ctx.analysis.for_each_child_def(type_id, |_, def| def.clone());
+        items.extend(
+            children
+                .unwrap_or_default()
+                .into_iter()
+                .filter(|def| match def.kind {
+                    DefKind::Field => true,
+                    DefKind::Method => takes_self(&def.value),
+                    _ => false,
+                })
+                .map(Candidate::from),
+        );
+
+        let mut traits = vec![];
+        for (trait_id, def) in ctx.analysis.impl_items(type_id).unwrap_or_default() {
+            if def.kind != DefKind::Method || !takes_self(&def.value) {
+                continue;
+            }
+            if let Some(trait_id) = trait_id {
+                if !trait_in_scope(ctx, file_path, &use_items, trait_id) {
+                    continue;
+                }
+                if !traits.contains(&trait_id) {
+                    traits.push(trait_id);
+                }
+            }
+            items.push(def.into());
+        }
+
+        // Provided methods, which aren't part of the impls unless overridden.
+        for trait_id in traits {
+            let methods = ctx.analysis.for_each_child_def(trait_id, |_, def| def.clone());
+            items.extend(
+                methods
+                    .unwrap_or_default()
+                    .into_iter()
+                    .filter(|def| def.kind == DefKind::Method && takes_self(&def.value))
+                    .map(Candidate::from),
+            );
+        }
+    }
+    items
+}
+
+/// Finds the definition of the type at `path`, as shown by `show_type`.
+pub fn resolve_type(ctx: &InitActionContext, file_path: &Path, path: &str) -> Option<Id> {
+    let name = path.rsplit("::").next()?;
+    let defs: Vec<_> = ctx
+        .analysis
+        .search_for_id(name)
+        .unwrap_or_default()
+        .into_iter()
+        .filter_map(|id| Some((id, ctx.analysis.get_def(id).ok()?)))
+        .filter(|(_, def)| TYPE_KINDS.contains(&def.kind))
+        .collect();
+
+    // Prefer the definition matching the whole path, then one in this file.
+    let suffix = format!("::{}", path.trim_start_matches("::"));
+    let (id, def) = defs
+        .iter()
+        .find(|(_, def)| path.contains("::") && def.qualname.ends_with(&suffix))
+        .or_else(|| defs.iter().find(|(_, def)| def.span.file == file_path))
+        .or_else(|| defs.first())?;
+
+    if def.kind == DefKind::Type {
+        // Look through type aliases, but only once so that a recursive alias
+        // can't send us into a loop.
+        let aliased = receiver_types(&def.value).into_iter().next()?;
+        return ctx
+            .analysis
+            .search_for_id(aliased.rsplit("::").next()?)
+            .unwrap_or_default()
+            .into_iter()
+            .find(|id| {
+                ctx.analysis.get_def(*id).map_or(false, |def| {
+                    def.kind != DefKind::Type && TYPE_KINDS.contains(&def.kind)
+                })
+            });
+    }
+    Some(*id)
+}
+
+/// Returns whether the methods of the trait `trait_id` can be called in
+/// `file_path`, i.e. whether it's defined there, imported or in the prelude.
+fn trait_in_scope(
+    ctx: &InitActionContext,
+    file_path: &Path,
+    use_items: &[UseItem],
+    trait_id: Id,
+) -> bool {
+    let def = match ctx.analysis.get_def(trait_id) {
+        Ok(def) => def,
+        Err(_) => return false,
+    };
+    def.span.file == file_path
+        || PRELUDE_TRAITS.contains(&def.name.as_str())
+        || use_items.iter().flat_map(|item| &item.paths).any(|path| {
+            // Glob imports might bring the trait into scope, so assume they do.
+            path.segments.last().map_or(false, |name| *name == def.name || name == "*")
+        })
+}
+
+/// Completes the items of the module, type or trait at the end of the path
+/// whose segments are at `segments`.
+fn path_items(
+    ctx: &InitActionContext,
+    text: &str,
+    file_path: &Path,
+    segments: &[(usize, usize)],
+) -> Vec<Candidate> {
+    let id = match resolve_path(ctx, text, file_path, segments) {
+        Some(id) => id,
+        None => return vec![],
+    };
+    let kind = match ctx.analysis.get_def(id) {
+        Ok(def) => def.kind,
+        Err(_) => return vec![],
+    };
+
+    let children = || ctx.analysis.for_each_child_def(id, |_, def| def.clone()).unwrap_or_default();
+    let impl_items = || {
+        let use_items = imports::parse_use_items(text);
+        ctx.analysis
+            .impl_items(id)
+            .unwrap_or_default()
+            .into_iter()
+            .filter(|(trait_id, _)| {
+                trait_id.map_or(true, |t| trait_in_scope(ctx, file_path, &use_items, t))
+            })
+            .map(|(_, def)| def)
+            .collect::<Vec<_>>()
+    };
+    let defs = match kind {
+        DefKind::Mod => children()
+            .into_iter()
+            .filter(|def| match def.kind {
+                DefKind::Local | DefKind::Field | DefKind::Method => false,
+                _ => true,
+            })
+            .collect(),
+        DefKind::Enum => children().into_iter().chain(impl_items()).collect(),
+        DefKind::Trait => children(),
+        DefKind::Struct | DefKind::Tuple | DefKind::Union | DefKind::Type => impl_items(),
+        _ => vec![],
+    };
+    defs.into_iter().map(Candidate::from).collect()
+}
+
+/// Resolves the path whose segments are at `segments`, from the analysis data
+/// for its last segment if possible, otherwise one segment at a time.
+fn resolve_path(
+    ctx: &InitActionContext,
+    text: &str,
+    file_path: &Path,
+    segments: &[(usize, usize)],
+) -> Option<Id> {
+    let &(start, end) = segments.last()?;
+    if let Ok(id) = ctx.analysis.id(&text_span(text, file_path, start, end)) {
+        return Some(id);
+    }
+
+    let mut names = segments.iter().map(|&(start, end)| &text[start..end]);
+    let module = module_at(ctx, text, file_path, start);
+    let parents = || module.and_then(|module| ctx.analysis.def_parents(module).ok());
+    let mut id = match names.next()? {
+        "crate" => parents()?.first().map(|&(id, _)| id).or(module)?,
+        "self" => module?,
+        "super" => parents()?.last()?.0,
+        name => module.and_then(|module| child_named(ctx, module, name)).or_else(|| {
+            let roots = ctx.analysis.def_roots().ok()?;
+            roots.into_iter().find(|(_, root)| root == name).map(|(id, _)| id)
+        })?,
+    };
+    for name in names {
+        id = child_named(ctx, id, name)?;
+    }
+    Some(id)
+}
+
+fn child_named(ctx: &InitActionContext, parent: Id, name: &str) -> Option<Id> {
+    let children = ctx.analysis.for_each_child_def(parent, |id, def| (id, def.name.clone()));
+    children.ok()?.into_iter().find(|(_, child)| child == name).map(|(id, _)| id)
+}
+
+/// Returns the id of the module containing `offset`.
+pub fn module_at(
+    ctx: &InitActionContext,
+    text: &str,
+    file_path: &Path,
+    offset: usize,
+) -> Option<Id> {
+    let symbols = ctx.analysis.symbols(file_path).ok()?;
+
+    let inline = codegen::enclosing_blocks(text, offset)
+        .into_iter()
+        .map(|open| codegen::block_header(text, open))
+        .find(|header| codegen::is_mod_header(header));
+    if let Some(header) = inline {
+        let name = header.rsplit(char::is_whitespace).next()?;
+        return symbols.iter().find(|s| s.kind == DefKind::Mod && s.name == name).map(|s| s.id);
+    }
+
+    // Otherwise this is the module of the file, i.e. the parent of the items
+    // declared at the top level of it.
+    symbols
+        .iter()
+        .filter(|s| {
+            let start = ls_util::rls_to_range(s.span.range).start;
+            codegen::position_to_offset(text, start)
+                .map_or(false, |start| codegen::enclosing_blocks(text, start).is_empty())
+        })
+        .find_map(|s| s.parent)
+}
+
+/// Completes the names in scope at `offset`: locals and items declared in the
+/// enclosing function before it, the items of the enclosing module, imported
+/// names and crates.
+fn scope_items(
+    ctx: &InitActionContext,
+    text: &str,
+    file_path: &Path,
+    offset: usize,
+) -> Vec<Candidate> {
+    let mut items = vec![];
+
+    let fn_body = codegen::enclosing_blocks(text, offset)
+        .into_iter()
+        .rev()
+        .find(|&open| codegen::is_fn_header(codegen::block_header(text, open)));
+    // The spans of the analysis data can't be mapped onto the text once it
+    // has changed since the last build, so only complete locals from the
+    // data while it's current.
+    let fn_body = fn_body.filter(|_| ctx.build_results_current(file_path));
+    if let Some(open) = fn_body {
+        // Include the parameters, which are declared in the header.
+        let fn_start = text[..open].rfind(&[';', '{', '}'][..]).map_or(0, |i| i + 1);
+        let symbols = ctx.analysis.symbols(file_path).unwrap_or_default();
+        let mut locals: Vec<_> = symbols
+            .into_iter()
+            .filter(|s| match s.kind {
+                DefKind::Field | DefKind::Method => false,
+                _ => true,
+            })
+            .filter_map(|s| {
+                let start = ls_util::rls_to_range(s.span.range).start;
+                let start = codegen::position_to_offset(text, start)?;
+                Some((start, s.id)).filter(|&(start, _)| fn_start <= start && start < offset)
+            })
+            .filter(|&(start, _)| visible_from(text, start, offset))
+            .collect();
+        // Closest first, so that shadowing locals win.
+        locals.sort_by(|a, b| b.0.cmp(&a.0));
+        items.extend(
+            locals
+                .into_iter()
+                .filter_map(|(_, id)| ctx.analysis.get_def(id).ok())
+                .map(Candidate::from),
+        );
+    }
+
+    if let Some(module) = module_at(ctx, text, file_path, offset) {
+        let children = ctx.analysis.for_each_child_def(module, |_, def| def.clone());
+        items.extend(
+            children
+                .unwrap_or_default()
+                .into_iter()
+                .filter(|def| match def.kind {
+                    DefKind::Local | DefKind::Field | DefKind::Method => false,
+                    _ => true,
+                })
+                .map(Candidate::from),
+        );
+    }
+
+    for path in imports::parse_use_items(text).iter().flat_map(|item| &item.paths) {
+        let name = match path.binding() {
+            Some(name) => name,
+            None => continue,
+        };
+        let segments =
+            path.segments.iter().filter(|s| !["crate", "self", "super"].contains(&&s[..]));
+        let suffix = format!("::{}", segments.join("::"));
+        let kind = ctx
+            .analysis
+            .search_for_id(path.segments.last().map_or(name, String::as_str))
+            .unwrap_or_default()
+            .into_iter()
+            .filter_map(|id| ctx.analysis.get_def(id).ok())
+            .find(|def| def.qualname.ends_with(&suffix))
+            .map_or(CompletionItemKind::Module, |def| completion_kind_from_def_kind(def.kind));
+        let item = CompletionItem {
+            label: name.to_owned(),
+            kind: Some(kind),
+            detail: Some(path.segments.join("::")),
+            ..CompletionItem::default()
+        };
+        items.push(Candidate { item, def: None, locality: Locality::SameFile });
+    }
+
+    for (_, name) in ctx.analysis.def_roots().unwrap_or_default() {
+        if !name.is_empty() {
+            let item = CompletionItem {
+                label: name,
+                kind: Some(CompletionItemKind::Module),
+                ..CompletionItem::default()
+            };
+            items.push(Candidate { item, def: None, locality: Locality::External });
+        }
+    }
+    items
+}
+
+/// Returns whether a local declared at `decl` is visible at `offset` (after
+/// it), i.e. whether the block containing the declaration also contains
+/// `offset`, rather than being a sibling block which was closed before it.
+fn visible_from(text: &str, decl: usize, offset: usize) -> bool {
+    match codegen::enclosing_blocks(text, decl).first() {
+        Some(block) => codegen::enclosing_blocks(text, offset).contains(block),
+        None => true,
+    }
+}
+
+/// Sorts `items` by relevance to the code at `position`, dropping those which
+/// don't match the typed name and merging duplicates (e.g. the same item
+/// re-exported through several globs). The order is preserved by the clients
+/// through the `sort_text` of the items.
+///
+/// Items are scored by the quality of the (fuzzy) match of the typed name, the
+/// locality of their definition, their kind (e.g. methods after a `.`, types
+/// in type position), deprecation and how recently they were accepted. Each
+/// item carries a command, which clients run once it's accepted, to record
+/// it.
+pub fn rank(
+    ctx: &InitActionContext,
+    file_path: &Path,
+    position: Position,
+    items: Vec<(CompletionItem, Locality)>,
+) -> Vec<CompletionItem> {
+    let text = match ctx.vfs.load_file(file_path) {
+        Ok(FileContents::Text(text)) => text,
+        _ => String::new(),
+    };
+    let offset = codegen::position_to_offset(&text, position).unwrap_or(0);
+    let start = identifier_start(&text, offset);
+    let prefix = &text[start..offset];
+    let relevant_kinds: &[CompletionItemKind] = match completion_context(&text, offset) {
+        Some((CompletionContext::Member { .. }, _)) => {
+            &[CompletionItemKind::Field, CompletionItemKind::Method]
+        }
+        _ if is_type_position(&text, start) => &[
+            CompletionItemKind::Struct,
+            CompletionItemKind::Enum,
+            CompletionItemKind::Interface,
+            CompletionItemKind::TypeParameter,
+            CompletionItemKind::Module,
+        ],
+        _ => &[],
+    };
+    let recent = ctx.recent_completions.lock().unwrap();
+
+    let mut scored: Vec<_> = items
+        .into_iter()
+        .filter_map(|(item, locality)| {
+            let mut score = fuzzy_score(prefix, &item.label)?;
+            score += match locality {
+                Locality::SameFile => SAME_FILE_SCORE,
+                Locality::SameWorkspace => SAME_WORKSPACE_SCORE,
+                Locality::External => 0,
+            };
+            if item.kind.map_or(false, |kind| relevant_kinds.contains(&kind)) {
+                score += RELEVANT_KIND_SCORE;
+            }
+            if item.deprecated == Some(true) {
+                score += DEPRECATED_SCORE;
+            }
+            if let Some(age) = recent.age(&item_key(&item)) {
+                score += MAX_RECENT_SCORE * (RECENT_COMPLETIONS - age) as i64
+                    / RECENT_COMPLETIONS as i64;
+            }
+            Some((score, item))
+        })
+        .collect();
+    // A stable sort, so that equally scored items keep their order.
+    scored.sort_by(|a, b| b.0.cmp(&a.0));
+
+    let mut seen = HashSet::new();
+    scored
+        .into_iter()
+        .map(|(_, item)| (item_key(&item), item))
+        .filter(|(key, _)| seen.insert(key.clone()))
+        .enumerate()
+        .map(|(i, (key, mut item))| {
+            item.sort_text = Some(format!("{:04}", i));
+            if item.filter_text.is_none() {
+                item.filter_text = Some(item.label.clone());
+            }
+            item.command = Some(Command {
+                title: "Accept completion".to_owned(),
+                command: format!("rls.acceptCompletion-{}", ctx.pid()),
+                arguments: Some(vec![key.into()]),
+            });
+            item
+        })
+        .collect()
+}
+
+/// Scores how well `pattern` fuzzily matches `name`, i.e. all characters of
+/// `pattern` appear in `name` in order, ignoring case. Matching the start of
+/// `name` or of a word in it, consecutive and same-case matches score higher,
+/// as do shorter names. Returns `None` if `pattern` doesn't match.
+pub fn fuzzy_score(pattern: &str, name: &str) -> Option<i64> {
+    let name: Vec<char> = name.chars().collect();
+    let mut score = 0;
+    let mut next = 0;
+    let mut previous: Option<usize> = None;
+    for p in pattern.chars() {
+        let i = next + name[next..].iter().position(|c| c.to_lowercase().eq(p.to_lowercase()))?;
+        score += 1;
+        if i == 0 {
+            score += 8;
+        } else if name[i - 1] == '_' || (name[i - 1].is_lowercase() && name[i].is_uppercase()) {
+            score += 4;
+        }
+        if previous.map_or(false, |previous| previous + 1 == i) {
+            score += 3;
+        }
+        if name[i] == p {
+            score += 1;
+        }
+        previous = Some(i);
+        next = i + 1;
+    }
+    Some(score - (name.len() - pattern.chars().count()) as i64 / 4)
+}
+
+/// Returns whether a type is expected at `offset`, e.g. after `x: ` or `-> `.
+fn is_type_position(text: &str, offset: usize) -> bool {
+    lazy_static! {
+        static ref TYPE_PREFIX: Regex =
+            Regex::new(r"(?:[^:]:|->|\b(?:as|dyn|impl)\b)\s*(?:&\s*(?:'\w+\s+)?(?:mut\s+)?)?$")
+                .unwrap();
+    }
+    let line_start = text[..offset].rfind('\n').map_or(0, |i| i + 1);
+    TYPE_PREFIX.is_match(&text[line_start..offset])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Parses the context at `|` in `text`, returning its kind, the names of
+    /// the receiver or path segments and the partially typed name.
+    fn context(text: &str) -> Option<(&'static str, Vec<String>, String)> {
+        let offset = text.find('|').unwrap();
+        let text = text.replace('|', "");
+        let (context, start) = completion_context(&text, offset)?;
+        let names = |offsets: &[(usize, usize)]| {
+            offsets.iter().map(|&(start, end)| text[start..end].to_owned()).collect()
+        };
+        let (kind, names) = match context {
+            CompletionContext::Member { receiver } => ("member", names(&[receiver])),
+            CompletionContext::Path { segments } => ("path", names(&segments)),
+            CompletionContext::Scope => ("scope", vec![]),
+        };
+        Some((kind, names, text[start..offset].to_owned()))
+    }
+
+    fn names(names: &[&str]) -> Vec<String> {
+        names.iter().map(|name| name.to_string()).collect()
+    }
+
+    #[test]
+    fn parses_completion_context() {
+        assert_eq!(context("    foo.ba|"), Some(("member", names(&["foo"]), "ba".to_owned())));
+        assert_eq!(context("    self.|"), Some(("member", names(&["self"]), "".to_owned())));
+        assert_eq!(
+            context("    foo\n        .ba|"),
+            Some(("member", names(&["foo"]), "ba".to_owned()))
+        );
+        assert_eq!(context("    foo().ba|"), None);
+        assert_eq!(context("    1.|"), None);
+        assert_eq!(context("    for i in 0..|"), Some(("scope", vec![], "".to_owned())));
+
+        assert_eq!(
+            context("    std::io::Wr|"),
+            Some(("path", names(&["std", "io"]), "Wr".to_owned()))
+        );
+        assert_eq!(context("    ::std::|"), Some(("path", names(&["std"]), "".to_owned())));
+        assert_eq!(context("    <T as Trait>::|"), None);
+
+        assert_eq!(context("    let x = fo|"), Some(("scope", vec![], "fo".to_owned())));
+    }
+
+    #[test]
+    fn finds_receiver_types() {
+        assert_eq!(receiver_types("Foo"), vec!["Foo"]);
+        assert_eq!(receiver_types("&mut Vec<u8>"), vec!["Vec"]);
+        assert_eq!(receiver_types("&'a std::rc::Rc<Foo>"), vec!["std::rc::Rc", "Foo"]);
+        assert_eq!(receiver_types("Box<dyn Trait + Send>"), vec!["Box", "Trait"]);
+        assert_eq!(receiver_types("*const Foo"), vec!["Foo"]);
+        assert_eq!(receiver_types("(u8, u8)"), Vec::<&str>::new());
+    }
+
+    #[test]
+    fn creates_call_snippets() {
+        assert_eq!(call_snippet("foo", "fn () -> ()"), "foo()");
+        assert_eq!(
+            call_snippet("foo", "fn (x: u32, (a, b): (u8, u8))"),
+            "foo(${1:x}, ${2:(a, b)})"
+        );
+        assert_eq!(call_snippet("bar", "fn (&mut self, f: impl Fn(u8) -> u8)"), "bar(${1:f})");
+    }
+
+    #[test]
+    fn scores_fuzzy_matches() {
+        assert_eq!(fuzzy_score("", "foo"), Some(0));
+        assert_eq!(fuzzy_score("xyz", "foo"), None);
+        assert_eq!(fuzzy_score("of", "foo"), None);
+        // Prefixes beat word starts, which beat other matches.
+        assert!(fuzzy_score("len", "len") > fuzzy_score("len", "max_len"));
+        assert!(fuzzy_score("ml", "max_len") > fuzzy_score("ml", "small"));
+        assert!(fuzzy_score("hm", "HashMap") > fuzzy_score("hm", "theme"));
+        // Shorter names win.
+        assert!(fuzzy_score("it", "iter") > fuzzy_score("it", "iter_mut"));
+    }
+
+    #[test]
+    fn detects_type_positions() {
+        let at_end = |text: &str| is_type_position(text, text.len());
+        assert!(at_end("fn foo(x: "));
+        assert!(at_end("fn foo() -> "));
+        assert!(at_end("let x = y as "));
+        assert!(at_end("fn foo(x: &mut "));
+        assert!(!at_end("let x = "));
+        assert!(!at_end("let x = foo::"));
+        assert!(!at_end("foo("));
+        assert!(!at_end("let y = &"));
+    }
+
+    #[test]
+    fn detects_deprecated_attributes() {
+        let text = "#[deprecated(since = \"1.0\")]\n/// Docs.\npub fn foo() {}\n\nfn bar() {}\n";
+        assert!(has_deprecated_attribute(text, text.find("foo").unwrap()));
+        assert!(!has_deprecated_attribute(text, text.find("bar").unwrap()));
+    }
+
+    #[test]
+    fn keys_items_by_path() {
+        let item = |path: &str, detail: &str| CompletionItem {
+            label: "Result".to_owned(),
+            kind: Some(CompletionItemKind::Enum),
+            detail: Some(detail.to_owned()),
+            data: Some(path).filter(|path| !path.is_empty()).map(Into::into),
+            ..CompletionItem::default()
+        };
+        assert_eq!(
+            item_key(&item("std::fmt::Result", "a")),
+            item_key(&item("std::fmt::Result", "b"))
+        );
+        assert_ne!(
+            item_key(&item("std::fmt::Result", "a")),
+            item_key(&item("std::io::Result", "a"))
+        );
+        assert_ne!(item_key(&item("", "std::fmt")), item_key(&item("", "std::io")));
+
+        let mut recent = RecentCompletions::default();
+        recent.record(&item_key(&item("std::fmt::Result", "")));
+        recent.record(&item_key(&item("std::io::Result", "")));
+        assert_eq!(recent.age(&item_key(&item("std::fmt::Result", ""))), Some(1));
+        assert_eq!(recent.age(&item_key(&item("std::io::Result", ""))), Some(0));
+        assert_eq!(recent.age(&item_key(&item("core::result::Result", ""))), None);
+    }
+
+    #[test]
+    fn scopes_locals_to_their_blocks() {
+        let text = "fn f(a: u8) {\n    { let b = 1; }\n    let c = 2;\n    if a > c { let d = 3; }\n    \n}";
+        let visible = |name: &str| {
+            let end = text.rfind('\n').unwrap();
+            visible_from(text, text.find(name).unwrap(), end)
+        };
+        assert!(visible("a:"));
+        assert!(!visible("b ="));
+        assert!(visible("c ="));
+        assert!(!visible("d ="));
+
+        let inner = text.find("= 3").unwrap();
+        assert!(visible_from(text, text.find("c =").unwrap(), inner));
+        assert!(visible_from(text, text.find("d =").unwrap(), inner));
+    }
+
+    #[test]
+    fn detects_self_parameters() {
+        assert!(takes_self("fn (&self) -> ()"));
+        assert!(takes_self("fn (&'a mut self, x: u32) -> u32"));
+        assert!(takes_self("fn (self: Box<Self>)"));
+        assert!(!takes_self("fn () -> Foo"));
+        assert!(!takes_self("fn (selfish: u32)"));
+    }
+}
@@ -8,27 +8,28 @@ use std::collections::HashMap;
 use std::iter;
 use std::path::{Path, PathBuf};
 
-use crate::lsp_data::ls_util;
+use crate::lsp_data::{ls_util, CodeDescription, DiagnosticData, DiagnosticTag, RichDiagnostic};
 use log::debug;
 use lsp_types::{
-    DiagnosticRelatedInformation, DiagnosticSeverity, Location, NumberOrString, Range,
+    Diagnostic, DiagnosticRelatedInformation, DiagnosticSeverity, Location, NumberOrString, Range,
 };
 use rls_span::compiler::DiagnosticSpan;
 use serde_derive::Deserialize;
 use url::Url;
 
-pub use lsp_types::Diagnostic;
-
 #[derive(Debug)]
 pub struct Suggestion {
     pub range: Range,
     pub new_text: String,
     pub label: String,
+    /// Whether rustc is confident that applying the suggestion is correct, so
+    /// that it can be applied without the user looking at it.
+    pub machine_applicable: bool,
 }
 
 #[derive(Debug)]
 pub struct ParsedDiagnostics {
-    pub diagnostics: HashMap<PathBuf, Vec<(Diagnostic, Vec<Suggestion>)>>,
+    pub diagnostics: HashMap<PathBuf, Vec<(RichDiagnostic, Vec<Suggestion>)>>,
 }
 
 /// Deserialized JSON diagnostic that was emitted by rustc.
@@ -39,6 +40,8 @@ struct CompilerMessage {
     level: String,
     spans: Vec<DiagnosticSpan>,
     children: Vec<AssociatedMessage>,
+    /// The message as rustc renders it in a terminal.
+    rendered: Option<String>,
 }
 
 /// Represents an emitted subdiagnostic for a certain message. Rustc also emits
@@ -48,7 +51,24 @@ struct CompilerMessage {
 struct AssociatedMessage {
     message: String,
     level: String,
-    spans: Vec<DiagnosticSpan>,
+    spans: Vec<SuggestionSpan>,
+}
+
+/// A span of a subdiagnostic, which may carry a suggested replacement.
+#[derive(Debug, Deserialize)]
+struct SuggestionSpan {
+    #[serde(flatten)]
+    span: DiagnosticSpan,
+    suggestion_applicability: Option<Applicability>,
+}
+
+/// How confident rustc is that a suggested replacement is correct.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize)]
+enum Applicability {
+    MachineApplicable,
+    HasPlaceholders,
+    MaybeIncorrect,
+    Unspecified,
 }
 
 #[derive(Debug, Deserialize)]
@@ -93,9 +113,16 @@ pub fn parse_diagnostics(
 
     for (path, diagnostic) in diagnostic_spans.iter().map(|span| {
         let children = || message.children.iter().flat_map(|msg| &msg.spans);
-        let all_spans = || iter::once(span).chain(&secondaries).chain(children());
-
-        let suggestions = make_suggestions(span, all_spans());
+        let all_spans =
+            || iter::once(span).chain(&secondaries).chain(children().map(|child| &child.span));
+
+        let suggestions = make_suggestions(
+            span,
+            iter::once(span)
+                .chain(&secondaries)
+                .map(|span| (span, None))
+                .chain(children().map(|child| (&child.span, child.suggestion_applicability))),
+        );
         let related_information = if related_information_support {
             Some(make_related_information(all_spans(), cwd))
         } else {
@@ -119,28 +146,33 @@ pub fn parse_diagnostics(
         // the further information link to the rust-clippy project.
         let source = if diagnostic_message.contains("rust-clippy") { "clippy" } else { "rustc" };
 
-        let rls_span = {
+        let source_span = {
             let mut span = span;
             // If span points to a macro, search through the expansions
             // for a more useful source location.
             while span.file_name.ends_with(" macros>") && span.expansion.is_some() {
                 span = &span.expansion.as_ref().unwrap().span;
             }
-            span.rls_span().zero_indexed()
+            span
         };
+        let rls_span = source_span.rls_span().zero_indexed();
 
         let file_path = cwd.join(&rls_span.file);
-
-        let diagnostic = Diagnostic {
-            range: ls_util::rls_to_range(rls_span.range),
-            severity: Some(severity(&message.level, span.is_primary)),
-            code: Some(NumberOrString::String(match message.code {
-                Some(ref c) => c.code.clone(),
-                None => String::new(),
-            })),
-            source: Some(source.to_owned()),
-            message: diagnostic_message,
-            related_information,
+        let code = message.code.as_ref().map_or("", |c| c.code.as_str());
+
+        let diagnostic = RichDiagnostic {
+            diagnostic: Diagnostic {
+                range: ls_util::rls_to_range(rls_span.range),
+                severity: Some(severity(&message.level, span.is_primary)),
+                code: Some(NumberOrString::String(code.to_owned())),
+                source: Some(source.to_owned()),
+                message: diagnostic_message,
+                related_information,
+            },
+            tags: if span.is_primary { tags(code) } else { vec![] },
+            code_description: code_description(code, source),
+            data: message.rendered.clone().map(|rendered| DiagnosticData { rendered }),
+            snippet: snippet(source_span),
         };
 
         (file_path, (diagnostic, suggestions))
@@ -172,9 +204,9 @@ fn format_notes(children: &[AssociatedMessage], primary: &DiagnosticSpan) -> Opt
 
         if spans.is_empty() {
             add_message_to_notes!(message);
-        } else if spans.len() == 1 && spans[0].is_within(primary) {
+        } else if spans.len() == 1 && spans[0].span.is_within(primary) {
             add_message_to_notes!(message);
-            if let Some(ref suggested) = spans[0].suggested_replacement {
+            if let Some(ref suggested) = spans[0].span.suggested_replacement {
                 notes.push_str(&format!(": `{}`", suggested));
             }
         }
@@ -187,6 +219,80 @@ fn format_notes(children: &[AssociatedMessage], primary: &DiagnosticSpan) -> Opt
     }
 }
 
+/// Returns the tags of the diagnostics of the lint or error `code`.
+fn tags(code: &str) -> Vec<DiagnosticTag> {
+    let lint = code.strip_prefix("clippy::").unwrap_or(code);
+    // Lints about code which can be removed, unlike e.g. `unused_must_use`
+    // and `unused_results`, which are about code missing.
+    match lint {
+        "dead_code"
+        | "unreachable_code"
+        | "unreachable_patterns"
+        | "redundant_semicolons"
+        | "unused_imports"
+        | "unused_extern_crates"
+        | "unused_variables"
+        | "unused_assignments"
+        | "unused_mut"
+        | "unused_macros"
+        | "unused_labels"
+        | "unused_lifetimes"
+        | "unused_parens"
+        | "unused_braces"
+        | "unused_unsafe"
+        | "unused_qualifications"
+        | "unused_attributes"
+        | "unused_doc_comments"
+        | "unused_self"
+        | "unused_unit" => vec![DiagnosticTag::Unnecessary],
+        "deprecated" => vec![DiagnosticTag::Deprecated],
+        _ => vec![],
+    }
+}
+
+/// Returns the link to the explanation of the error `code` in the rustc error
+/// index, or to the docs of the clippy lint `code`.
+fn code_description(code: &str, source: &str) -> Option<CodeDescription> {
+    let href = if source == "clippy" && !code.is_empty() {
+        let lint = code.strip_prefix("clippy::").unwrap_or(code);
+        format!("https://rust-lang.github.io/rust-clippy/master/index.html#{}", lint)
+    } else if code.len() == 5
+        && code.starts_with('E')
+        && code[1..].chars().all(|c| c.is_ascii_digit())
+    {
+        format!("https://doc.rust-lang.org/error-index.html#{}", code)
+    } else {
+        return None;
+    };
+    Some(CodeDescription { href: Url::parse(&href).ok()? })
+}
+
+/// Returns the source highlighted by `span`, or the lines it's on if it's
+/// empty, with runs of whitespace collapsed into a single space.
+fn snippet(span: &DiagnosticSpan) -> Option<String> {
+    let highlighted = span
+        .text
+        .iter()
+        .map(|line| {
+            let len = line.highlight_end.saturating_sub(line.highlight_start);
+            line.text.chars().skip(line.highlight_start.saturating_sub(1)).take(len).collect()
+        })
+        .collect::<Vec<String>>()
+        .join("\n");
+    let text = if highlighted.trim().is_empty() {
+        span.text.iter().map(|line| line.text.as_str()).collect::<Vec<_>>().join("\n")
+    } else {
+        highlighted
+    };
+
+    let snippet = text.split_whitespace().collect::<Vec<_>>().join(" ");
+    if snippet.is_empty() {
+        None
+    } else {
+        Some(snippet)
+    }
+}
+
 fn severity(level: &str, is_primary_span: bool) -> DiagnosticSeverity {
     match (level, is_primary_span) {
         (_, false) => DiagnosticSeverity::Information,
@@ -220,15 +326,18 @@ fn make_related_information<'a>(
 
 fn make_suggestions<'a>(
     primary: &DiagnosticSpan,
-    spans: impl Iterator<Item = &'a DiagnosticSpan>,
+    spans: impl Iterator<Item = (&'a DiagnosticSpan, Option<Applicability>)>,
 ) -> Vec<Suggestion> {
     let primary_range = ls_util::rls_to_range(primary.rls_span().zero_indexed().range);
 
     let mut suggestions: Vec<Suggestion> = spans
-        .filter_map(|span| {
+        .filter_map(|(span, applicability)| {
             span.suggested_replacement
                 .as_ref()
-                .map(|suggested| span_suggestion(span, suggested))
+                .map(|suggested| Suggestion {
+                    machine_applicable: applicability == Some(Applicability::MachineApplicable),
+                    ..span_suggestion(span, suggested)
+                })
                 .or_else(|| span.label.as_ref().and_then(|label| label_suggestion(span, label)))
         })
         .collect();
@@ -251,7 +360,7 @@ fn span_suggestion(span: &DiagnosticSpan, suggested: &str) -> Suggestion {
     let range = ls_util::rls_to_range(rls_span.range);
     let action = if range.start == range.end { "Add" } else { "Change to" };
     let label = format!("{} `{}`", action, suggested);
-    Suggestion { new_text: suggested.to_string(), range, label }
+    Suggestion { new_text: suggested.to_string(), range, label, machine_applicable: false }
 }
 
 fn label_suggestion(span: &DiagnosticSpan, label: &str) -> Option<Suggestion> {
@@ -321,7 +430,7 @@ mod diagnostic_message_test {
     }
 
     pub(super) trait FileDiagnosticTestExt {
-        fn single_file_results(&self) -> &Vec<(Diagnostic, Vec<Suggestion>)>;
+        fn single_file_results(&self) -> &Vec<(RichDiagnostic, Vec<Suggestion>)>;
         /// Returns `(primary message, secondary messages)`.
         fn to_messages(&self) -> Vec<(String, Vec<String>)>;
         fn to_primary_messages(&self) -> Vec<String>;
@@ -329,7 +438,7 @@ mod diagnostic_message_test {
     }
 
     impl FileDiagnosticTestExt for ParsedDiagnostics {
-        fn single_file_results(&self) -> &Vec<(Diagnostic, Vec<Suggestion>)> {
+        fn single_file_results(&self) -> &Vec<(RichDiagnostic, Vec<Suggestion>)> {
             self.diagnostics.values().nth(0).unwrap()
         }
 
@@ -548,6 +657,43 @@ help: consider borrowing here: `&string`"#,
         }
     }
 
+    #[test]
+    fn message_metadata() {
+        let diag = parse_compiler_message(&read_fixture("compiler_message/unused-use.json"), true);
+        let (diagnostic, _) = &diag.single_file_results()[0];
+        assert_eq!(diagnostic.tags, vec![DiagnosticTag::Unnecessary]);
+        assert_eq!(diagnostic.code_description, None);
+        let rendered = &diagnostic.data.as_ref().unwrap().rendered;
+        assert!(rendered.starts_with("warning: unused imports"), "{}", rendered);
+
+        let diag =
+            parse_compiler_message(&read_fixture("compiler_message/mismatched-types.json"), true);
+        let (diagnostic, _) = &diag.single_file_results()[0];
+        assert!(diagnostic.tags.is_empty());
+        assert_eq!(
+            diagnostic.code_description.as_ref().unwrap().href.as_str(),
+            "https://doc.rust-lang.org/error-index.html#E0308"
+        );
+
+        let diag =
+            parse_compiler_message(&read_fixture("compiler_message/clippy-identity-op.json"), true);
+        let (diagnostic, _) = &diag.single_file_results()[0];
+        assert_eq!(
+            diagnostic.code_description.as_ref().unwrap().href.as_str(),
+            "https://rust-lang.github.io/rust-clippy/master/index.html#identity_op"
+        );
+        assert_eq!(diagnostic.snippet.as_deref(), Some("1 / 1"));
+
+        assert_eq!(tags("dead_code"), vec![DiagnosticTag::Unnecessary]);
+        assert_eq!(tags("clippy::unused_self"), vec![DiagnosticTag::Unnecessary]);
+        assert_eq!(tags("unused_mut"), vec![DiagnosticTag::Unnecessary]);
+        assert_eq!(tags("unused_must_use"), vec![]);
+        assert_eq!(tags("unused_results"), vec![]);
+        assert_eq!(tags("clippy::unused_io_amount"), vec![]);
+        assert_eq!(tags("deprecated"), vec![DiagnosticTag::Deprecated]);
+        assert_eq!(code_description("E03", "rustc"), None);
+    }
+
     #[test]
     fn message_cannot_find_type() {
         let messages =
@@ -761,4 +907,38 @@ mod diagnostic_suggestion_test {
             Range { start: Position::new(0, 0), end: Position::new(0, 0) }
         );
     }
+
+    /// ```
+    /// pub fn main() {
+    ///     let mut x = 1;
+    ///     println!("{}", x);
+    /// }
+    /// ```
+    #[test]
+    fn suggest_machine_applicable_unused_mut() {
+        let diag = parse_compiler_message(&read_fixture("compiler_message/unused-mut.json"), true);
+        let diagnostics = diag.diagnostics.values().nth(0).unwrap();
+
+        let remove_mut = diagnostics
+            .iter()
+            .flat_map(|(_, suggestions)| suggestions)
+            .find(|s| s.new_text == "")
+            .expect("removal of `mut` not found");
+
+        assert!(remove_mut.machine_applicable);
+        assert_eq!(
+            remove_mut.range,
+            Range { start: Position::new(1, 8), end: Position::new(1, 12) }
+        );
+
+        let diag = parse_compiler_message(
+            &read_fixture("compiler_message/macro-error-no-trait.json"),
+            true,
+        );
+        let diagnostics = diag.diagnostics.values().nth(0).unwrap();
+        assert!(diagnostics
+            .iter()
+            .flat_map(|(_, suggestions)| suggestions)
+            .all(|s| !s.machine_applicable));
+    }
 }
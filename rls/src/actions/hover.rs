@@ -6,7 +6,10 @@ use rls_span::{Column, Range, Row, Span, ZeroIndexed};
 use rls_vfs::{self as vfs, Vfs};
 use rustfmt_nightly::NewlineStyle;
 use serde_derive::{Deserialize, Serialize};
+use url::Url;
 
+use crate::actions::codegen;
+use crate::actions::completion;
 use crate::actions::format::Rustfmt;
 use crate::actions::requests;
 use crate::actions::InitActionContext;
@@ -22,7 +25,8 @@ pub struct Tooltip {
 
 /// Cleanup documentation code blocks. The `docs` are expected to have
 /// the preceding `///` or `//!` prefixes already trimmed away. Rust code
-/// blocks will ignore lines beginning with `#`. Code block annotations
+/// blocks will ignore lines hidden by rustdoc, i.e., those beginning with
+/// `# ` or consisting of a single `#`. Code block annotations
 /// that are common to Rust will be converted to `rust` allow for markdown
 /// syntax coloring.
 pub fn process_docs(docs: &str) -> String {
@@ -47,6 +51,9 @@ pub fn process_docs(docs: &str) -> String {
         }
         let line = if in_rust_codeblock && trimmed.starts_with("```") {
             "```rust".into()
+        } else if in_rust_codeblock && trimmed.starts_with("##") {
+            // A leading `##` escapes a line which would otherwise be hidden.
+            line.replacen("##", "#", 1)
         } else {
             line.to_string()
         };
@@ -54,9 +61,7 @@ pub fn process_docs(docs: &str) -> String {
         // Racer sometimes pulls out comment block headers from the standard library.
         let ignore_slashes = line.starts_with("////");
 
-        let maybe_attribute = trimmed.starts_with("#[") || trimmed.starts_with("#![");
-        let is_attribute = maybe_attribute && in_rust_codeblock;
-        let is_hidden = trimmed.starts_with('#') && in_rust_codeblock && !is_attribute;
+        let is_hidden = in_rust_codeblock && (trimmed == "#" || trimmed.starts_with("# "));
 
         let ignore_whitespace = last_line_ignored && trimmed.is_empty();
         let ignore_line = ignore_slashes || ignore_whitespace || is_hidden;
@@ -283,7 +288,7 @@ fn tooltip_type(ctx: &InitActionContext, def: &Def, doc_url: Option<String>) ->
 
     let the_type = || def.value.trim().into();
     let the_type = def_decl(def, &vfs, the_type);
-    let docs = def_docs(def, &vfs);
+    let docs = def_docs(ctx, def);
     let context = None;
 
     create_tooltip(the_type, doc_url, context, docs)
@@ -296,10 +301,8 @@ fn tooltip_field_or_variant(
 ) -> Vec<MarkedString> {
     debug!("tooltip_field_or_variant: {}", def.name);
 
-    let vfs = &ctx.vfs;
-
     let the_type = def.value.trim().into();
-    let docs = def_docs(def, &vfs);
+    let docs = def_docs(ctx, def);
     let context = None;
 
     create_tooltip(the_type, doc_url, context, docs)
@@ -329,7 +332,7 @@ fn tooltip_struct_enum_union_trait(
     let decl = def_decl(def, &vfs, the_type);
 
     let the_type = format_object(fmt, &fmt_config, decl);
-    let docs = def_docs(def, &vfs);
+    let docs = def_docs(ctx, def);
     let context = None;
 
     create_tooltip(the_type, doc_url, context, docs)
@@ -338,8 +341,6 @@ fn tooltip_struct_enum_union_trait(
 fn tooltip_mod(ctx: &InitActionContext, def: &Def, doc_url: Option<String>) -> Vec<MarkedString> {
     debug!("tooltip_mod: name: {}", def.name);
 
-    let vfs = &ctx.vfs;
-
     let the_type = def.value.trim();
     let the_type = the_type.replace("\\\\", "/");
     let the_type = the_type.replace("\\", "/");
@@ -354,7 +355,7 @@ fn tooltip_mod(ctx: &InitActionContext, def: &Def, doc_url: Option<String>) -> V
         the_type
     };
 
-    let docs = def_docs(def, &vfs);
+    let docs = def_docs(ctx, def);
     let context = None;
 
     create_tooltip(mod_path, doc_url, context, docs)
@@ -383,7 +384,7 @@ fn tooltip_function_method(
     let decl = def_decl(def, &vfs, the_type);
 
     let the_type = format_method(fmt, &fmt_config, decl);
-    let docs = def_docs(def, &vfs);
+    let docs = def_docs(ctx, def);
     let context = None;
 
     create_tooltip(the_type, doc_url, context, docs)
@@ -441,7 +442,7 @@ fn tooltip_static_const_decl(
     let vfs = &ctx.vfs;
 
     let the_type = def_decl(def, &vfs, || def.value.trim().into());
-    let docs = def_docs(def, &vfs);
+    let docs = def_docs(ctx, def);
     let context = None;
 
     create_tooltip(the_type, doc_url, context, docs)
@@ -455,12 +456,296 @@ fn empty_to_none(s: String) -> Option<String> {
     }
 }
 
-/// Extracts and processes source documentation for the give `def`.
-fn def_docs(def: &Def, vfs: &Vfs) -> Option<String> {
+/// Extracts and processes source documentation for the give `def`, with its
+/// intra-doc links resolved.
+fn def_docs(ctx: &InitActionContext, def: &Def) -> Option<String> {
     let save_analysis_docs = || empty_to_none(def.docs.trim().into());
-    extract_and_process_docs(&vfs, def.span.file.as_ref(), def.span.range.row_start)
+    extract_and_process_docs(&ctx.vfs, def.span.file.as_ref(), def.span.range.row_start)
         .or_else(save_analysis_docs)
         .filter(|docs| !docs.trim().is_empty())
+        .map(|docs| rewrite_doc_links(&docs, |path| resolve_doc_link(ctx, def, path)))
+}
+
+/// Returns the path an intra-doc link refers to, without the backticks,
+/// disambiguators (e.g., `struct@`) and `()` or `!` suffixes. Returns `None`
+/// if `target` isn't a path, e.g., if it's a URL.
+fn doc_link_path(target: &str) -> Option<&str> {
+    let path = target.trim().trim_matches('`');
+    let path = match path.find('@') {
+        Some(at) => &path[at + 1..],
+        None => path,
+    };
+    let path = path.trim_end_matches("()").trim_end_matches('!');
+    let is_path = path.split("::").all(|segment| {
+        segment.chars().next().map_or(false, |c| c.is_alphabetic() || c == '_')
+            && segment.chars().all(|c| c.is_alphanumeric() || c == '_')
+    });
+    if is_path {
+        Some(path)
+    } else {
+        None
+    }
+}
+
+/// Returns the index of the `close` bracket matching the `open` one at the
+/// start of `text`, skipping over inline code.
+fn matching_bracket(text: &str, open: char, close: char) -> Option<usize> {
+    let mut depth = 0;
+    let mut in_code = false;
+    for (i, c) in text.char_indices() {
+        match c {
+            '`' => in_code = !in_code,
+            _ if in_code => {}
+            c if c == open => depth += 1,
+            c if c == close => {
+                depth -= 1;
+                if depth == 0 {
+                    return Some(i);
+                }
+            }
+            _ => {}
+        }
+    }
+    None
+}
+
+/// Rewrites the intra-doc links in the markdown `docs` to point at the URL
+/// returned by `resolve` for their path. Handles inline (`[label](path)`),
+/// reference (`[label][path]`, `[label]: path`) and shortcut (`[path]`)
+/// links outside of code. Links which don't resolve are left as they are.
+fn rewrite_doc_links<F>(docs: &str, mut resolve: F) -> String
+where
+    F: FnMut(&str) -> Option<String>,
+{
+    // Links to the reference definitions must not be resolved as paths.
+    let mut definitions = vec![];
+    let mut in_codeblock = false;
+    for line in docs.lines() {
+        let trimmed = line.trim();
+        if trimmed.starts_with("```") {
+            in_codeblock = !in_codeblock;
+        } else if !in_codeblock && trimmed.starts_with('[') {
+            if let Some(end) = trimmed.find("]:") {
+                definitions.push(trimmed[1..end].to_lowercase());
+            }
+        }
+    }
+    let is_defined = |label: &str| definitions.contains(&label.to_lowercase());
+
+    let mut in_codeblock = false;
+    let mut lines = vec![];
+    for line in docs.lines() {
+        let trimmed = line.trim();
+        if trimmed.starts_with("```") {
+            in_codeblock = !in_codeblock;
+        }
+        if in_codeblock || trimmed.starts_with("```") {
+            lines.push(line.to_owned());
+            continue;
+        }
+
+        if trimmed.starts_with('[') {
+            if let Some(end) = trimmed.find("]:") {
+                let target = trimmed[end + 2..].trim();
+                if let Some(url) = doc_link_path(target).and_then(&mut resolve) {
+                    let indent = &line[..line.len() - line.trim_start().len()];
+                    lines.push(format!("{}{}: {}", indent, &trimmed[..=end], url));
+                    continue;
+                }
+                lines.push(line.to_owned());
+                continue;
+            }
+        }
+
+        let mut rewritten = String::new();
+        let mut rest = line;
+        while let Some(start) = rest.find(&['[', '`'][..]) {
+            rewritten.push_str(&rest[..start]);
+            rest = &rest[start..];
+            if rest.starts_with('`') {
+                // Skip over inline code.
+                let end = rest[1..].find('`').map_or(rest.len(), |end| end + 2);
+                rewritten.push_str(&rest[..end]);
+                rest = &rest[end..];
+                continue;
+            }
+            let label_end = match matching_bracket(rest, '[', ']') {
+                Some(end) => end,
+                None => break,
+            };
+            let label = &rest[1..label_end];
+            let after = &rest[label_end + 1..];
+            let (path, link_end) = if after.starts_with('(') {
+                match matching_bracket(after, '(', ')') {
+                    Some(end) => (Some(&after[1..end]), label_end + end + 2),
+                    None => (None, label_end + 1),
+                }
+            } else if after.starts_with('[') {
+                match after.find(']') {
+                    Some(end) => {
+                        let reference = if end == 1 { label } else { &after[1..end] };
+                        let path = if is_defined(reference) { None } else { Some(reference) };
+                        (path, label_end + end + 2)
+                    }
+                    None => (None, label_end + 1),
+                }
+            } else if is_defined(label) {
+                (None, label_end + 1)
+            } else {
+                (Some(label), label_end + 1)
+            };
+
+            match path.and_then(doc_link_path).and_then(&mut resolve) {
+                Some(url) => rewritten.push_str(&format!("[{}]({})", label, url)),
+                None => rewritten.push_str(&rest[..link_end]),
+            }
+            rest = &rest[link_end..];
+        }
+        rewritten.push_str(rest);
+        lines.push(rewritten);
+    }
+    lines.join("\n")
+}
+
+/// Returns `qualname` with the `<Type as Trait>` of items in impls replaced
+/// by the path of the type, so it can be compared with the paths in docs.
+fn normalize_qualname(qualname: &str) -> String {
+    let open = match qualname.find('<') {
+        Some(open) => open,
+        None => return qualname.to_owned(),
+    };
+    let mut depth = 0;
+    let close = qualname[open..].find(|c| {
+        match c {
+            '<' => depth += 1,
+            '>' => depth -= 1,
+            _ => {}
+        }
+        depth == 0
+    });
+    let close = match close {
+        Some(close) => open + close,
+        None => return qualname.to_owned(),
+    };
+    let ty = qualname[open + 1..close].split(" as ").next().unwrap();
+    // Generic arguments are not part of the paths in docs.
+    let ty = ty.split('<').next().unwrap().trim_start_matches(':');
+    let krate = qualname[..open].trim_end_matches(':');
+    let rest = qualname[close + 1..].trim_start_matches(':');
+    [krate, ty, rest].iter().filter(|part| !part.is_empty()).cloned().collect::<Vec<_>>().join("::")
+}
+
+/// Returns the path of the parent of the item at `path`.
+fn parent_path(path: &str) -> Option<&str> {
+    path.rfind("::").map(|end| &path[..end])
+}
+
+/// Returns the path of the module the `def` with the (normalized) `qualname`
+/// is declared in.
+fn def_module<'a>(def: &Def, qualname: &'a str) -> Option<&'a str> {
+    match def.kind {
+        DefKind::Mod => Some(qualname),
+        DefKind::Method | DefKind::Field | DefKind::TupleVariant | DefKind::StructVariant => {
+            parent_path(qualname).and_then(parent_path)
+        }
+        _ => parent_path(qualname),
+    }
+}
+
+/// Qualifies a `crate::`, `self::`, `super::` or `Self::` intra-doc link
+/// `path` in the docs of `def`. Returns `None` for relative paths.
+fn qualify_doc_link(def: &Def, qualname: &str, path: &str) -> Option<String> {
+    let (first, rest) = path.split_once("::")?;
+    let base = match first {
+        "crate" => Some(qualname.split("::").next().unwrap()),
+        "self" => def_module(def, qualname),
+        "super" => {
+            let mut base = def_module(def, qualname).and_then(parent_path);
+            let mut rest = rest;
+            while let Some(path) = rest.strip_prefix("super::") {
+                base = base.and_then(parent_path);
+                rest = path;
+            }
+            return base.map(|base| format!("{}::{}", base, rest));
+        }
+        "Self" => match def.kind {
+            DefKind::Method | DefKind::Field | DefKind::TupleVariant | DefKind::StructVariant => {
+                parent_path(qualname)
+            }
+            _ => Some(qualname),
+        },
+        _ => return None,
+    };
+    base.map(|base| format!("{}::{}", base, rest))
+}
+
+/// Resolves the intra-doc link `path` in the docs of `def` through the
+/// analysis index, returning the URL of the docs of the item it refers to
+/// if it's in the standard library, or of its source otherwise.
+fn resolve_doc_link(ctx: &InitActionContext, def: &Def, path: &str) -> Option<String> {
+    let analysis = &ctx.analysis;
+    let qualname = normalize_qualname(&def.qualname);
+    let qualified = qualify_doc_link(def, &qualname, path);
+    let name = qualified.as_ref().map_or(path, String::as_str).rsplit("::").next()?;
+    let module = def_module(def, &qualname).unwrap_or("");
+    let krate = qualname.split("::").next().unwrap();
+
+    let (id, target) = analysis
+        .search_for_id(name)
+        .ok()?
+        .into_iter()
+        .filter_map(|id| analysis.get_def(id).ok().map(|target| (id, target)))
+        .filter(|(_, target)| target.kind != DefKind::Local)
+        .filter(|(_, target)| {
+            let target = normalize_qualname(&target.qualname);
+            match qualified {
+                Some(ref qualified) => target == *qualified,
+                None => target == path || target.ends_with(&format!("::{}", path)),
+            }
+        })
+        .min_by_key(|(_, target)| {
+            let in_module = !module.is_empty() && target.qualname.starts_with(module);
+            let in_crate = target.qualname.starts_with(krate);
+            (!in_module, !in_crate, target.qualname.len())
+        })?;
+
+    analysis.doc_url_by_id(id).or_else(|_| analysis.src_url_by_id(id)).ok().or_else(|| {
+        let url = Url::from_file_path(&target.span.file).ok()?;
+        Some(format!("{}#L{}", url, target.span.range.row_start.one_indexed().0))
+    })
+}
+
+/// The attributes shown in hovers.
+const SHOWN_ATTRIBUTES: &[&str] = &["deprecated", "must_use", "cfg"];
+
+/// Returns the attributes of `def` which are shown in its hover.
+fn shown_attributes(def: &Def) -> Vec<String> {
+    def.attributes
+        .iter()
+        .filter(|attr| {
+            let name = attr.split(|c: char| c == '(' || c == '=' || c.is_whitespace()).next();
+            name.map_or(false, |name| SHOWN_ATTRIBUTES.contains(&name))
+        })
+        .map(|attr| format!("#[{}]", attr))
+        .collect()
+}
+
+/// Adds the path of the crate or module declaring `def` to the start of its
+/// `tooltip`, and its shown attributes to the declaration.
+fn add_declaration_info(def: &Def, tooltip: &mut Vec<MarkedString>) {
+    if tooltip.is_empty() {
+        return;
+    }
+    let attributes = shown_attributes(def);
+    if !attributes.is_empty() {
+        if let MarkedString::LanguageString(ref mut decl) = tooltip[0] {
+            decl.value = format!("{}\n{}", attributes.join("\n"), decl.value);
+        }
+    }
+    let qualname = normalize_qualname(&def.qualname);
+    if let Some(path) = parent_path(&qualname) {
+        tooltip.insert(0, MarkedString::from_language_code("rust".into(), path.to_owned()));
+    }
 }
 
 /// Returns the type or function declaration from source. If source
@@ -628,6 +913,8 @@ fn racer_match_to_def(ctx: &InitActionContext, m: &racer::Match) -> Option<Def>
             distro_crate: false,
             parent: None,
             docs,
+            sig: None,
+            attributes: vec![],
         };
         trace!(
             "racer_match_to_def: Def {{ kind: {:?}, span: {:?}, name: {:?}, \
@@ -686,6 +973,48 @@ fn racer_def(ctx: &InitActionContext, span: &Span<ZeroIndexed>) -> Option<Def> {
     results.unwrap_or(None)
 }
 
+/// Returns the method which runs when the trait method `def` is called at
+/// `span`, i.e., the method of the impl of the trait for the type of the
+/// receiver, if it overrides the trait's.
+pub fn impl_method(ctx: &InitActionContext, span: &Span<ZeroIndexed>, def: &Def) -> Option<Def> {
+    if def.kind != DefKind::Method {
+        return None;
+    }
+    let trait_id = def.parent?;
+    if ctx.analysis.get_def(trait_id).ok()?.kind != DefKind::Trait {
+        return None;
+    }
+
+    let text = match ctx.vfs.load_file(&span.file) {
+        Ok(vfs::FileContents::Text(text)) => text,
+        _ => return None,
+    };
+    let position = Position::new(span.range.row_start.0.into(), span.range.col_start.0.into());
+    let start = codegen::position_to_offset(&text, position)?;
+    // Only method calls on an identifier, e.g., `x.clone()`, have a receiver
+    // whose type we can look up.
+    let receiver_end = text[..start].trim_end().strip_suffix('.')?.trim_end().len();
+    let receiver_start = completion::identifier_start(&text, receiver_end);
+    if receiver_start == receiver_end {
+        return None;
+    }
+    let receiver = completion::text_span(&text, &span.file, receiver_start, receiver_end);
+    let ty = ctx.analysis.show_type(&receiver).ok()?;
+    trace!("impl_method: receiver type: {:?}", ty);
+
+    completion::receiver_types(&ty).into_iter().find_map(|path| {
+        let type_id = completion::resolve_type(ctx, &span.file, path)?;
+        ctx.analysis.impl_items(type_id).ok()?.into_iter().find_map(|(impl_trait, item)| {
+            if impl_trait == Some(trait_id) && item.kind == DefKind::Method && item.name == def.name
+            {
+                Some(item)
+            } else {
+                None
+            }
+        })
+    })
+}
+
 /// Formats a struct, enum, union, or trait. The original type is returned
 /// in the event of an error.
 fn format_object(rustfmt: Rustfmt, fmt_config: &FmtConfig, the_type: String) -> String {
@@ -796,9 +1125,9 @@ fn format_method(rustfmt: Rustfmt, fmt_config: &FmtConfig, the_type: String) ->
     result.trim().into()
 }
 
-/// Builds a hover tooltip composed of the function signature or type declaration, doc URL
-/// (if available in the save-analysis), source extracted documentation, and code context
-/// for local variables.
+/// Builds a hover tooltip composed of the declaring module path, the function signature
+/// or type declaration, doc URL (if available in the save-analysis), source extracted
+/// documentation with resolved intra-doc links, and code context for local variables.
 pub fn tooltip(
     ctx: &InitActionContext,
     params: &TextDocumentPositionParams,
@@ -848,7 +1177,7 @@ pub fn tooltip(
         } else if def.kind == DefKind::Local && def.span == hover_span {
             tooltip_function_signature_arg(&ctx, &def, doc_url)
         } else {
-            match def.kind {
+            let mut contents = match def.kind {
                 DefKind::TupleVariant | DefKind::StructVariant | DefKind::Field => {
                     tooltip_field_or_variant(&ctx, &def, doc_url)
                 }
@@ -876,7 +1205,16 @@ pub fn tooltip(
 
                     Vec::default()
                 }
+            };
+            add_declaration_info(&def, &mut contents);
+            // Show the impl which is called before the trait method.
+            if let Some(impl_def) = impl_method(&ctx, &hover_span, &def) {
+                let mut impl_contents = tooltip_function_method(&ctx, &impl_def, None);
+                add_declaration_info(&impl_def, &mut impl_contents);
+                impl_contents.extend(contents);
+                contents = impl_contents;
             }
+            contents
         }
     } else {
         debug!("tooltip: def is empty");
@@ -1120,6 +1458,138 @@ pub mod test {
         assert_eq!(expected, actual);
     }
 
+    #[test]
+    fn test_process_docs_hidden_lines() {
+        let docs = noindent(
+            "
+            ```
+            # fn main() {
+            #
+            #[derive(Debug)]
+            ## not hidden
+            #foo
+            # }
+            ```
+        ",
+        );
+        let expected = noindent(
+            "
+            ```rust
+            #[derive(Debug)]
+            # not hidden
+            #foo
+            ```
+        ",
+        );
+
+        let actual = process_docs(&docs);
+        assert_eq!(expected, actual);
+    }
+
+    #[test]
+    fn test_doc_link_path() {
+        assert_eq!(doc_link_path("Foo"), Some("Foo"));
+        assert_eq!(doc_link_path("`crate::bar`"), Some("crate::bar"));
+        assert_eq!(doc_link_path("struct@Foo"), Some("Foo"));
+        assert_eq!(doc_link_path("foo()"), Some("foo"));
+        assert_eq!(doc_link_path("vec!"), Some("vec"));
+        assert_eq!(doc_link_path("https://rust-lang.org"), None);
+        assert_eq!(doc_link_path("0, 1"), None);
+        assert_eq!(doc_link_path("^1"), None);
+    }
+
+    #[test]
+    fn test_rewrite_doc_links() {
+        let resolve = |path: &str| match path {
+            "Foo" | "crate::Foo" => Some("foo.html".to_owned()),
+            _ => None,
+        };
+        let docs = noindent(
+            "
+            Returns a [`Foo`], see [the docs](crate::Foo) and [Bar].
+            Also [a][Foo], [`x[0]`](https://x.org) and `[Foo]`.
+
+            ```
+            let x = [Foo];
+            ```
+
+            [Bar]: Foo
+        ",
+        );
+        let expected = noindent(
+            "
+            Returns a [`Foo`](foo.html), see [the docs](foo.html) and [Bar].
+            Also [a](foo.html), [`x[0]`](https://x.org) and `[Foo]`.
+
+            ```
+            let x = [Foo];
+            ```
+
+            [Bar]: foo.html
+        ",
+        );
+
+        assert_eq!(expected, rewrite_doc_links(&docs, resolve));
+    }
+
+    fn def(kind: DefKind, qualname: &str) -> Def {
+        let row = Row::new_zero_indexed(0);
+        let col = Column::new_zero_indexed(0);
+        Def {
+            kind,
+            span: Span::new(row, row, col, col, PathBuf::from("lib.rs")),
+            name: String::new(),
+            qualname: qualname.to_owned(),
+            distro_crate: false,
+            parent: None,
+            value: String::new(),
+            docs: String::new(),
+            sig: None,
+            attributes: vec![],
+        }
+    }
+
+    #[test]
+    fn test_qualify_doc_link() {
+        assert_eq!(normalize_qualname("foo::bar::Baz"), "foo::bar::Baz");
+        assert_eq!(normalize_qualname("foo<bar::Baz as Qux>::f"), "foo::bar::Baz::f");
+        assert_eq!(normalize_qualname("foo<Bar<T>>::new"), "foo::Bar::new");
+        assert_eq!(normalize_qualname("foo<Bar<T, U> as Qux<T>>::f"), "foo::Bar::f");
+
+        let method = def(DefKind::Method, "foo::bar::Baz::f");
+        let qualname = &method.qualname;
+        assert_eq!(qualify_doc_link(&method, qualname, "crate::Qux").unwrap(), "foo::Qux");
+        assert_eq!(qualify_doc_link(&method, qualname, "self::Qux").unwrap(), "foo::bar::Qux");
+        assert_eq!(qualify_doc_link(&method, qualname, "super::Qux").unwrap(), "foo::Qux");
+        assert_eq!(qualify_doc_link(&method, qualname, "Self::g").unwrap(), "foo::bar::Baz::g");
+        assert_eq!(qualify_doc_link(&method, qualname, "bar::Qux"), None);
+
+        let module = def(DefKind::Mod, "foo::bar");
+        let qualname = &module.qualname;
+        assert_eq!(qualify_doc_link(&module, qualname, "self::Qux").unwrap(), "foo::bar::Qux");
+    }
+
+    #[test]
+    fn test_add_declaration_info() {
+        let mut def = def(DefKind::Function, "foo::bar::f");
+        def.attributes = vec![
+            "inline".to_owned(),
+            "must_use".to_owned(),
+            "deprecated(note = \"use g\")".to_owned(),
+        ];
+        let mut tooltip = vec![MarkedString::from_language_code("rust".into(), "fn f()".into())];
+        add_declaration_info(&def, &mut tooltip);
+
+        let expected = vec![
+            MarkedString::from_language_code("rust".into(), "foo::bar".into()),
+            MarkedString::from_language_code(
+                "rust".into(),
+                "#[must_use]\n#[deprecated(note = \"use g\")]\nfn f()".into(),
+            ),
+        ];
+        assert_eq!(expected, tooltip);
+    }
+
     #[test]
     fn test_format_method() {
         let fmt = Rustfmt::Internal;
@@ -0,0 +1,825 @@
+//! Parsing and editing of the `use` declarations of a file.
+//!
+//! This is a line-based approximation which only considers the top-level (i.e.
+//! non-indented) declarations of a file. That is enough for the import-related
+//! code actions and spares us reparsing the whole file.
+
+use std::cmp::Ordering;
+use std::fmt;
+
+use itertools::Itertools;
+use lazy_static::lazy_static;
+use regex::Regex;
+
+use crate::actions::codegen;
+use crate::lsp_data::{Position, Range, TextEdit};
+
+/// Conventional groups of imports, in the order in which they're laid out.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum ImportGroup {
+    /// `std`, `core`, `alloc` and the other crates shipped with the compiler.
+    Std,
+    /// Any other external crate.
+    External,
+    /// Paths starting with `crate`, `self` or `super`.
+    Local,
+}
+
+impl ImportGroup {
+    /// Returns the group of a path starting with `first_segment`.
+    pub fn of(first_segment: &str) -> ImportGroup {
+        match first_segment {
+            "std" | "core" | "alloc" | "proc_macro" | "test" => ImportGroup::Std,
+            "crate" | "self" | "super" => ImportGroup::Local,
+            _ => ImportGroup::External,
+        }
+    }
+}
+
+/// The visibility of an item.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Visibility {
+    /// `pub`
+    Public,
+    /// `pub(crate)`
+    Crate,
+    /// `pub(super)`, `pub(self)` or `pub(in path)`
+    Restricted,
+    Private,
+}
+
+/// Returns the visibility of an item declared by `header`, its source up to
+/// the name of the item (e.g. `#[inline] pub(crate) fn `).
+pub fn declared_visibility(header: &str) -> Visibility {
+    let mut rest = header.trim_start();
+    // Skip the attributes of the item.
+    while rest.starts_with('#') {
+        let mut depth = 0;
+        let end = rest.char_indices().find_map(|(i, c)| {
+            match c {
+                '[' => depth += 1,
+                ']' if depth == 1 => return Some(i + 1),
+                ']' => depth -= 1,
+                _ => {}
+            }
+            None
+        });
+        match end {
+            Some(end) => rest = rest[end..].trim_start(),
+            None => return Visibility::Private,
+        }
+    }
+
+    let rest = match rest.strip_prefix("pub") {
+        Some(rest) if !rest.starts_with(|c: char| c.is_alphanumeric() || c == '_') => {
+            rest.trim_start()
+        }
+        _ => return Visibility::Private,
+    };
+    match rest.strip_prefix('(') {
+        Some(restriction) if restriction.trim_start().starts_with("crate") => Visibility::Crate,
+        Some(_) => Visibility::Restricted,
+        None => Visibility::Public,
+    }
+}
+
+/// A single imported path, e.g. `std::io::Write as _`.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct ImportPath {
+    pub segments: Vec<String>,
+    pub alias: Option<String>,
+}
+
+impl ImportPath {
+    pub fn new(path: &str) -> ImportPath {
+        ImportPath { segments: path.split("::").map(str::to_owned).collect(), alias: None }
+    }
+
+    pub fn group(&self) -> ImportGroup {
+        ImportGroup::of(&self.segments[0])
+    }
+
+    /// The path of the module this path is imported from.
+    pub fn parent(&self) -> &[String] {
+        &self.segments[..self.segments.len() - 1]
+    }
+
+    /// The name brought into scope by this import, if any.
+    pub fn binding(&self) -> Option<&str> {
+        if let Some(alias) = &self.alias {
+            return Some(alias).filter(|alias| *alias != "_").map(String::as_str);
+        }
+        match self.segments.last().map(String::as_str) {
+            Some("*") => None,
+            Some("self") => self.parent().last().map(String::as_str),
+            name => name,
+        }
+    }
+}
+
+impl fmt::Display for ImportPath {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.segments.join("::"))?;
+        if let Some(alias) = &self.alias {
+            write!(f, " as {}", alias)?;
+        }
+        Ok(())
+    }
+}
+
+/// A top-level `use` declaration.
+#[derive(Clone, Debug)]
+pub struct UseItem {
+    /// Zero-indexed line of the first attribute of the declaration, if any,
+    /// otherwise the same as `start_line`.
+    pub first_line: usize,
+    /// Zero-indexed line on which the `use` keyword is.
+    pub start_line: usize,
+    /// Zero-indexed line on which the declaration ends.
+    pub end_line: usize,
+    /// Visibility of the declaration, e.g. `pub(crate)`.
+    pub vis: Option<String>,
+    /// Whether the declaration has attributes (e.g. `#[cfg(test)]`), in which
+    /// case it can't be merged with other declarations.
+    pub has_attrs: bool,
+    pub paths: Vec<ImportPath>,
+    /// Position of the last segment of each of `paths`.
+    pub leaves: Vec<Position>,
+}
+
+impl UseItem {
+    pub fn group(&self) -> ImportGroup {
+        self.paths.first().map_or(ImportGroup::Local, ImportPath::group)
+    }
+
+    /// The range of the declaration, without its attributes.
+    pub fn range(&self, lines: &[&str]) -> Range {
+        let end_col = lines
+            .get(self.end_line)
+            .map_or(0, |line| codegen::offset_to_position(line, line.len()).character);
+        Range::new(
+            Position::new(self.start_line as u64, 0),
+            Position::new(self.end_line as u64, end_col),
+        )
+    }
+}
+
+lazy_static! {
+    static ref USE_ITEM: Regex = Regex::new(r"^(pub(?:\([^)]*\))?\s+)?use\s+(.*)$").unwrap();
+}
+
+/// Finds and parses the top-level `use` declarations of `text`. Declarations
+/// which can't be parsed (e.g. because they contain comments) are skipped.
+pub fn parse_use_items(text: &str) -> Vec<UseItem> {
+    let lines: Vec<_> = text.lines().collect();
+    let mut items = vec![];
+
+    let mut line = 0;
+    while line < lines.len() {
+        let caps = match USE_ITEM.captures(lines[line]) {
+            Some(caps) => caps,
+            None => {
+                line += 1;
+                continue;
+            }
+        };
+
+        let start_line = line;
+        let mut tree = caps[2].to_owned();
+        while !tree.contains(';') && line + 1 < lines.len() {
+            line += 1;
+            tree.push('\n');
+            tree.push_str(lines[line]);
+        }
+        line += 1;
+
+        let tree = match tree.find(';') {
+            Some(end) => &tree[..end],
+            None => continue,
+        };
+        let mut first_line = start_line;
+        while first_line > 0 && is_outer_attribute(lines[first_line - 1]) {
+            first_line -= 1;
+        }
+
+        if let Some(paths) = parse_use_tree_with_offsets(tree) {
+            let tree_col = caps.get(2).unwrap().start();
+            let position = |offset: usize| {
+                let before = format!("{}{}", &lines[start_line][..tree_col], &tree[..offset]);
+                let position = codegen::offset_to_position(&before, before.len());
+                Position::new(start_line as u64 + position.line, position.character)
+            };
+            items.push(UseItem {
+                first_line,
+                start_line,
+                end_line: line - 1,
+                vis: caps.get(1).map(|vis| vis.as_str().trim().to_owned()),
+                has_attrs: first_line != start_line,
+                leaves: paths.iter().map(|(_, offset)| position(*offset)).collect(),
+                paths: paths.into_iter().map(|(path, _)| path).collect(),
+            });
+        }
+    }
+
+    items
+}
+
+fn is_outer_attribute(line: &str) -> bool {
+    line.starts_with("#[")
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Token<'a> {
+    Ident(&'a str),
+    PathSep,
+    Open,
+    Close,
+    Comma,
+    Star,
+}
+
+/// Splits a use tree into tokens, along with their byte offsets into `tree`.
+fn tokenize(tree: &str) -> Option<Vec<(Token<'_>, usize)>> {
+    let mut tokens = vec![];
+    let mut offset = tree.len() - tree.trim_start().len();
+    while offset < tree.len() {
+        let rest = &tree[offset..];
+        let (token, len) = if rest.starts_with("::") {
+            (Token::PathSep, 2)
+        } else if rest.starts_with('{') {
+            (Token::Open, 1)
+        } else if rest.starts_with('}') {
+            (Token::Close, 1)
+        } else if rest.starts_with(',') {
+            (Token::Comma, 1)
+        } else if rest.starts_with('*') {
+            (Token::Star, 1)
+        } else {
+            let len = rest
+                .char_indices()
+                .find(|&(i, c)| !(c.is_alphanumeric() || c == '_' || (i == 1 && c == '#')))
+                .map_or(rest.len(), |(i, _)| i);
+            if len == 0 {
+                return None;
+            }
+            (Token::Ident(&rest[..len]), len)
+        };
+        tokens.push((token, offset));
+        let rest = &rest[len..];
+        offset += len + (rest.len() - rest.trim_start().len());
+    }
+    Some(tokens)
+}
+
+/// Parses a use tree (the part of a `use` declaration between `use` and `;`)
+/// into the flat list of paths it imports.
+pub fn parse_use_tree(tree: &str) -> Option<Vec<ImportPath>> {
+    parse_use_tree_with_offsets(tree).map(|paths| paths.into_iter().map(|(p, _)| p).collect())
+}
+
+/// Like `parse_use_tree`, but also returns the byte offset of the last segment
+/// of every path.
+fn parse_use_tree_with_offsets(tree: &str) -> Option<Vec<(ImportPath, usize)>> {
+    let mut parser = TreeParser { tokens: tokenize(tree)?, pos: 0 };
+    let mut paths = vec![];
+    parser.tree(vec![], &mut paths)?;
+    if parser.pos != parser.tokens.len() {
+        return None;
+    }
+    Some(paths)
+}
+
+struct TreeParser<'a> {
+    tokens: Vec<(Token<'a>, usize)>,
+    pos: usize,
+}
+
+impl<'a> TreeParser<'a> {
+    fn peek(&self) -> Option<Token<'a>> {
+        self.tokens.get(self.pos).map(|(token, _)| *token)
+    }
+
+    fn bump(&mut self) -> Option<(Token<'a>, usize)> {
+        let token = self.tokens.get(self.pos).cloned();
+        self.pos += 1;
+        token
+    }
+
+    fn tree(
+        &mut self,
+        mut prefix: Vec<String>,
+        paths: &mut Vec<(ImportPath, usize)>,
+    ) -> Option<()> {
+        loop {
+            match self.bump()? {
+                (Token::Ident(segment), offset) => {
+                    prefix.push(segment.to_owned());
+                    match self.peek() {
+                        Some(Token::PathSep) => {
+                            self.pos += 1;
+                        }
+                        Some(Token::Ident("as")) => {
+                            self.pos += 1;
+                            let alias = match self.bump()? {
+                                (Token::Ident(alias), _) => alias.to_owned(),
+                                _ => return None,
+                            };
+                            let path = ImportPath { segments: prefix, alias: Some(alias) };
+                            paths.push((path, offset));
+                            return Some(());
+                        }
+                        _ => {
+                            paths.push((ImportPath { segments: prefix, alias: None }, offset));
+                            return Some(());
+                        }
+                    }
+                }
+                // Paths with a leading `::`
+                (Token::PathSep, _) if prefix.is_empty() => prefix.push(String::new()),
+                (Token::Star, offset) => {
+                    prefix.push("*".to_owned());
+                    paths.push((ImportPath { segments: prefix, alias: None }, offset));
+                    return Some(());
+                }
+                (Token::Open, _) => {
+                    while self.peek()? != Token::Close {
+                        self.tree(prefix.clone(), paths)?;
+                        match self.peek()? {
+                            Token::Comma => self.pos += 1,
+                            Token::Close => {}
+                            _ => return None,
+                        }
+                    }
+                    self.pos += 1;
+                    return Some(());
+                }
+                _ => return None,
+            }
+        }
+    }
+}
+
+/// Orders path segments the way rustfmt does: `self` first, then
+/// snake_case < CamelCase < UPPER_SNAKE_CASE, with globs last.
+pub fn compare_segments(a: &str, b: &str) -> Ordering {
+    // Algorithm taken from rustfmt (`rustfmt/src/imports.rs`).
+    let rank = |s: &str| match s {
+        "self" => 0,
+        "*" => 2,
+        _ => 1,
+    };
+    if rank(a) != rank(b) {
+        return rank(a).cmp(&rank(b));
+    }
+
+    let is_upper_snake_case =
+        |s: &str| s.chars().all(|c| c.is_uppercase() || c == '_' || c.is_numeric());
+
+    // snake_case < CamelCase < UPPER_SNAKE_CASE
+    if a.starts_with(char::is_uppercase) && b.starts_with(char::is_lowercase) {
+        return Ordering::Greater;
+    }
+    if a.starts_with(char::is_lowercase) && b.starts_with(char::is_uppercase) {
+        return Ordering::Less;
+    }
+    if is_upper_snake_case(a) && !is_upper_snake_case(b) {
+        return Ordering::Greater;
+    }
+    if !is_upper_snake_case(a) && is_upper_snake_case(b) {
+        return Ordering::Less;
+    }
+    a.cmp(b)
+}
+
+/// Compares two paths segment by segment, using `compare_segments`.
+pub fn compare_paths(a: &ImportPath, b: &ImportPath) -> Ordering {
+    a.segments
+        .iter()
+        .zip(&b.segments)
+        .map(|(a, b)| compare_segments(a, b))
+        .find(|ord| *ord != Ordering::Equal)
+        .unwrap_or_else(|| a.segments.len().cmp(&b.segments.len()))
+        .then_with(|| a.alias.cmp(&b.alias))
+}
+
+#[derive(Default)]
+struct Node {
+    children: Vec<(String, Node)>,
+    /// Aliases of the paths ending at this node (`None` for no alias).
+    ends: Vec<Option<String>>,
+}
+
+impl Node {
+    fn insert(&mut self, segments: &[String], alias: &Option<String>) {
+        match segments.split_first() {
+            None => {
+                if !self.ends.contains(alias) {
+                    self.ends.push(alias.clone());
+                }
+            }
+            Some((first, rest)) => {
+                let index = match self.children.iter().position(|(name, _)| name == first) {
+                    Some(index) => index,
+                    None => {
+                        self.children.push((first.clone(), Node::default()));
+                        self.children.len() - 1
+                    }
+                };
+                self.children[index].1.insert(rest, alias);
+            }
+        }
+    }
+
+    /// Renders the entries this node contributes to its parent's tree.
+    fn render(&mut self, name: &str) -> Vec<String> {
+        self.ends.sort();
+        let mut entries: Vec<_> = self
+            .ends
+            .iter()
+            .map(|alias| match alias {
+                Some(alias) => format!("{} as {}", name, alias),
+                None => name.to_owned(),
+            })
+            .collect();
+
+        let children = render_children(&mut self.children);
+        match children.len() {
+            0 => {}
+            // `io::{self}` is the same as `io`, and `io::{self as a}` as `io as a`.
+            1 if children[0] == "self" || children[0].starts_with("self as ") => {
+                let entry = format!("{}{}", name, &children[0]["self".len()..]);
+                if !entries.contains(&entry) {
+                    entries.push(entry);
+                }
+            }
+            1 => entries.push(format!("{}::{}", name, children[0])),
+            _ => entries.push(format!("{}::{{{}}}", name, children.join(", "))),
+        }
+        entries
+    }
+}
+
+fn render_children(children: &mut [(String, Node)]) -> Vec<String> {
+    children.sort_by(|(a, _), (b, _)| compare_segments(a, b));
+    children.iter_mut().flat_map(|(name, child)| child.render(name)).collect()
+}
+
+/// Renders `paths` as a single, nested `use` tree (without `use` and `;`).
+pub fn render_use_tree(paths: &[ImportPath]) -> String {
+    let mut root = Node::default();
+    for path in paths {
+        root.insert(&path.segments, &path.alias);
+    }
+    let entries = render_children(&mut root.children);
+    if entries.len() == 1 {
+        entries.into_iter().next().unwrap()
+    } else {
+        format!("{{{}}}", entries.join(", "))
+    }
+}
+
+/// Renders a whole `use` declaration importing `paths`.
+pub fn render_use(vis: Option<&str>, paths: &[ImportPath]) -> String {
+    match vis {
+        Some(vis) => format!("{} use {};", vis, render_use_tree(paths)),
+        None => format!("use {};", render_use_tree(paths)),
+    }
+}
+
+/// Returns whether `path` (or another import bringing the same name into
+/// scope) is already imported by one of `items`.
+pub fn is_imported(items: &[UseItem], path: &ImportPath) -> bool {
+    items.iter().flat_map(|item| &item.paths).any(|imported| {
+        imported == path || (path.binding().is_some() && imported.binding() == path.binding())
+    })
+}
+
+/// Returns an edit adding an import of `path` to a file with the contents
+/// `text`, or `None` if the name is already imported.
+///
+/// The path is merged into an existing declaration which imports from the same
+/// module if there's one, otherwise a new declaration is added to the import
+/// group the path belongs to (std, external crates, or the current crate).
+pub fn add_import(text: &str, path: &ImportPath) -> Option<TextEdit> {
+    let items = parse_use_items(text);
+    if is_imported(&items, path) {
+        return None;
+    }
+    let lines: Vec<_> = text.lines().collect();
+
+    let mergeable = items.iter().find(|item| {
+        !item.has_attrs
+            && item.vis.is_none()
+            && item.paths.iter().all(|p| p.segments[0] == path.segments[0])
+            && item.paths.iter().any(|p| p.parent() == path.parent())
+    });
+    if let Some(item) = mergeable {
+        let mut paths = item.paths.clone();
+        paths.push(path.clone());
+        return Some(TextEdit::new(item.range(&lines), render_use(None, &paths)));
+    }
+
+    let new_item = render_use(None, std::slice::from_ref(path));
+    let insert_at = |line: usize, new_text: String| {
+        let position = Position::new(line as u64, 0);
+        Some(TextEdit::new(Range::new(position, position), new_text))
+    };
+
+    let group = path.group();
+    let same_group: Vec<_> = items.iter().filter(|item| item.group() == group).collect();
+    if !same_group.is_empty() {
+        // Keep the group sorted, assuming it already is.
+        let next = same_group.iter().find(|item| {
+            !item.has_attrs && compare_paths(&item.paths[0], path) == Ordering::Greater
+        });
+        return match next {
+            Some(next) => insert_at(next.first_line, format!("{}\n", new_item)),
+            None => insert_at(same_group.last().unwrap().end_line + 1, format!("{}\n", new_item)),
+        };
+    }
+
+    if let Some(previous) = items.iter().rev().find(|item| item.group() < group) {
+        return insert_at(previous.end_line + 1, format!("\n{}\n", new_item));
+    }
+    if let Some(next) = items.iter().find(|item| item.group() > group) {
+        return insert_at(next.first_line, format!("{}\n\n", new_item));
+    }
+
+    // No imports at all yet, so put it after the inner attributes and docs.
+    let header_end = lines
+        .iter()
+        .take_while(|line| {
+            let line = line.trim();
+            line.is_empty() || line.starts_with("//") || line.starts_with("#![")
+        })
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty())
+        .map(|(index, _)| index)
+        .last();
+    match header_end {
+        Some(line) => insert_at(line + 1, format!("\n{}\n", new_item)),
+        None => insert_at(0, format!("{}\n\n", new_item)),
+    }
+}
+
+/// How imports are merged when organizing them, mirroring rustfmt's
+/// `imports_granularity` option.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ImportGranularity {
+    /// Keep the declarations as they are.
+    Preserve,
+    /// Merge all imports from the same crate into a single declaration.
+    Crate,
+    /// Merge imports from the same module into a single declaration.
+    Module,
+    /// Import every item with a separate declaration.
+    Item,
+}
+
+impl ImportGranularity {
+    /// Converts the value of rustfmt's `imports_granularity` option.
+    pub fn from_rustfmt(value: &str) -> ImportGranularity {
+        match value {
+            "Crate" => ImportGranularity::Crate,
+            "Module" => ImportGranularity::Module,
+            "Item" => ImportGranularity::Item,
+            _ => ImportGranularity::Preserve,
+        }
+    }
+}
+
+/// Returns the leading block of `use` declarations, i.e. the first declaration
+/// and those following it separated only by blank lines.
+fn import_block<'i>(items: &'i [UseItem], lines: &[&str]) -> &'i [UseItem] {
+    let len = items
+        .windows(2)
+        .take_while(|pair| {
+            lines[pair[0].end_line + 1..pair[1].first_line].iter().all(|l| l.trim().is_empty())
+        })
+        .count();
+    &items[..items.len().min(len + 1)]
+}
+
+/// Returns the range of the leading block of `use` declarations of `text`,
+/// which `organize_imports` replaces, if there is one.
+pub fn import_block_range(text: &str) -> Option<Range> {
+    let items = parse_use_items(text);
+    let lines: Vec<_> = text.lines().collect();
+    let block = import_block(&items, &lines);
+    let (first, last) = (block.first()?, block.last()?);
+    Some(Range::new(Position::new(first.first_line as u64, 0), last.range(&lines).end))
+}
+
+/// The visibility of a `use` declaration and the path prefix it shares.
+type DeclKey = (Option<String>, Vec<String>);
+
+/// Organizes the leading block of `use` declarations of `text`:
+/// * imports whose last segment lies within one of the `unused` ranges are removed,
+/// * the remaining ones are merged according to `granularity`,
+/// * declarations are sorted and split into std, external and local groups.
+///
+/// Declarations with attributes are kept apart, after the groups. Returns an
+/// edit replacing the whole block, or `None` if the file has no imports.
+pub fn organize_imports(
+    text: &str,
+    unused: &[Range],
+    granularity: ImportGranularity,
+) -> Option<TextEdit> {
+    let items = parse_use_items(text);
+    let lines: Vec<_> = text.lines().collect();
+    let block = import_block(&items, &lines);
+    let (first, last) = (block.first()?, block.last()?);
+
+    let is_unused = |leaf: &Position| unused.iter().any(|r| r.start <= *leaf && *leaf <= r.end);
+    let used_paths = |item: &UseItem| -> Vec<ImportPath> {
+        item.paths
+            .iter()
+            .zip(&item.leaves)
+            .filter(|(_, leaf)| !is_unused(leaf))
+            .map(|(path, _)| path.clone())
+            .collect()
+    };
+
+    // Declarations, keyed by their visibility and the path prefix they share.
+    let mut decls: Vec<(DeclKey, Vec<ImportPath>)> = vec![];
+    let mut add =
+        |key: DeclKey, paths: Vec<ImportPath>| match decls.iter_mut().find(|(k, _)| *k == key) {
+            Some((_, existing)) => existing.extend(paths),
+            None => decls.push((key, paths)),
+        };
+    for (index, item) in block.iter().filter(|item| !item.has_attrs).enumerate() {
+        for path in used_paths(item) {
+            let prefix = match granularity {
+                // Use the index of the declaration to keep it apart.
+                ImportGranularity::Preserve => vec![index.to_string()],
+                ImportGranularity::Crate => path.segments[..1].to_vec(),
+                ImportGranularity::Module => path.parent().to_vec(),
+                ImportGranularity::Item => path.segments.clone(),
+            };
+            add((item.vis.clone(), prefix), vec![path]);
+        }
+    }
+
+    let mut decls: Vec<_> = decls
+        .into_iter()
+        .map(|((vis, _), mut paths)| {
+            paths.sort_by(compare_paths);
+            paths.dedup();
+            (vis, paths)
+        })
+        .collect();
+    decls.sort_by(|(a_vis, a), (b_vis, b)| {
+        a[0].group()
+            .cmp(&b[0].group())
+            .then_with(|| compare_paths(&a[0], &b[0]))
+            .then_with(|| a_vis.cmp(b_vis))
+    });
+
+    let mut sections = vec![];
+    for (_, group) in &decls.iter().group_by(|(_, paths)| paths[0].group()) {
+        let group: Vec<_> = group.map(|(vis, paths)| render_use(vis.as_deref(), paths)).collect();
+        sections.push(group.join("\n"));
+    }
+
+    let attributed: Vec<_> = block
+        .iter()
+        .filter(|item| item.has_attrs)
+        .filter_map(|item| {
+            let paths = used_paths(item);
+            if paths.is_empty() {
+                return None;
+            }
+            let attrs = lines[item.first_line..item.start_line].join("\n");
+            Some(if paths.len() == item.paths.len() {
+                lines[item.first_line..=item.end_line].join("\n")
+            } else {
+                format!("{}\n{}", attrs, render_use(item.vis.as_deref(), &paths))
+            })
+        })
+        .collect();
+    if !attributed.is_empty() {
+        sections.push(attributed.join("\n"));
+    }
+
+    let range = Range::new(Position::new(first.first_line as u64, 0), last.range(&lines).end);
+    Some(TextEdit::new(range, sections.join("\n\n")))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn paths(tree: &str) -> Vec<String> {
+        parse_use_tree(tree).unwrap().iter().map(ToString::to_string).collect()
+    }
+
+    fn apply(text: &str, edit: TextEdit) -> String {
+        let lines: Vec<_> = text.lines().collect();
+        let offset = |pos: Position| {
+            lines[..pos.line as usize].iter().map(|l| l.len() + 1).sum::<usize>()
+                + pos.character as usize
+        };
+        let (start, end) = (offset(edit.range.start), offset(edit.range.end));
+        format!("{}{}{}", &text[..start], edit.new_text, &text[end..])
+    }
+
+    #[test]
+    fn parse_nested_trees() {
+        assert_eq!(paths("std::io"), vec!["std::io"]);
+        assert_eq!(
+            paths("std::{io::{self, Write as _}, fmt, collections::*}"),
+            vec!["std::io::self", "std::io::Write as _", "std::fmt", "std::collections::*"]
+        );
+        assert_eq!(paths("::foo::{\n    Bar,\n    Baz,\n}"), vec!["::foo::Bar", "::foo::Baz"]);
+        assert!(parse_use_tree("foo::{Bar /* comment */}").is_none());
+    }
+
+    #[test]
+    fn visibility_of_declarations() {
+        assert_eq!(declared_visibility("pub struct "), Visibility::Public);
+        assert_eq!(declared_visibility("    pub(crate) fn "), Visibility::Crate);
+        assert_eq!(declared_visibility("pub ( in crate::foo ) fn "), Visibility::Restricted);
+        assert_eq!(declared_visibility("pub(super) const "), Visibility::Restricted);
+        assert_eq!(declared_visibility("#[derive(Debug)] pub enum "), Visibility::Public);
+        assert_eq!(
+            declared_visibility("#[cfg(all(test, feature = \"x\"))] fn "),
+            Visibility::Private
+        );
+        assert_eq!(declared_visibility("struct "), Visibility::Private);
+        assert_eq!(declared_visibility("pubx "), Visibility::Private);
+    }
+
+    #[test]
+    fn render_merges_common_prefixes() {
+        let paths: Vec<_> = ["std::io::Write", "std::fmt", "std::io::self", "std::io::Read"]
+            .iter()
+            .map(|p| ImportPath::new(p))
+            .collect();
+        assert_eq!(render_use(None, &paths), "use std::{fmt, io::{self, Read, Write}};");
+    }
+
+    #[test]
+    fn add_import_merges_into_existing_item() {
+        let text = "use std::fmt;\n\nuse crate::foo::{Bar, Qux};\n\nfn main() {}\n";
+        let edit = add_import(text, &ImportPath::new("crate::foo::Baz")).unwrap();
+        assert_eq!(
+            apply(text, edit),
+            "use std::fmt;\n\nuse crate::foo::{Bar, Baz, Qux};\n\nfn main() {}\n"
+        );
+    }
+
+    #[test]
+    fn add_import_to_group() {
+        let text = "use std::fmt;\n\nuse crate::foo::Bar;\n\nfn main() {}\n";
+
+        let edit = add_import(text, &ImportPath::new("serde::Serialize")).unwrap();
+        assert_eq!(
+            apply(text, edit),
+            "use std::fmt;\n\nuse serde::Serialize;\n\nuse crate::foo::Bar;\n\nfn main() {}\n"
+        );
+
+        let edit = add_import(text, &ImportPath::new("std::collections::HashMap")).unwrap();
+        assert_eq!(
+            apply(text, edit),
+            "use std::collections::HashMap;\nuse std::fmt;\n\nuse crate::foo::Bar;\n\n\
+             fn main() {}\n"
+        );
+
+        assert!(add_import(text, &ImportPath::new("crate::foo::Bar")).is_none());
+    }
+
+    #[test]
+    fn organize_removes_unused_and_groups() {
+        let text = "use crate::foo::Bar;\nuse std::fmt;\nuse serde::{Deserialize, Serialize};\n\
+                    use std::io::{self, Read};\nuse crate::foo::Baz;\n\n\
+                    #[cfg(test)]\nuse std::mem;\n\nfn main() {}\n";
+        // `Deserialize` and `Read` are unused.
+        let unused = [
+            Range::new(Position::new(2, 12), Position::new(2, 23)),
+            Range::new(Position::new(3, 20), Position::new(3, 24)),
+        ];
+
+        let edit = organize_imports(text, &unused, ImportGranularity::Preserve).unwrap();
+        assert_eq!(import_block_range(text), Some(edit.range));
+        assert_eq!(
+            apply(text, edit),
+            "use std::fmt;\nuse std::io;\n\nuse serde::Serialize;\n\n\
+             use crate::foo::Bar;\nuse crate::foo::Baz;\n\n#[cfg(test)]\nuse std::mem;\n\n\
+             fn main() {}\n"
+        );
+
+        let edit = organize_imports(text, &unused, ImportGranularity::Crate).unwrap();
+        assert_eq!(
+            apply(text, edit),
+            "use std::{fmt, io};\n\nuse serde::Serialize;\n\nuse crate::foo::{Bar, Baz};\n\n\
+             #[cfg(test)]\nuse std::mem;\n\nfn main() {}\n"
+        );
+    }
+
+    #[test]
+    fn add_first_import() {
+        let text = "//! Docs\n\nfn main() {}\n";
+        let edit = add_import(text, &ImportPath::new("crate::foo::Bar")).unwrap();
+        assert_eq!(apply(text, edit), "//! Docs\n\nuse crate::foo::Bar;\n\nfn main() {}\n");
+    }
+}
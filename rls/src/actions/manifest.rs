@@ -0,0 +1,1252 @@
+//! Language support for Cargo manifests (`Cargo.toml`): completion of keys,
+//! dependencies, versions and features, hover showing the locked versions of
+//! a dependency, diagnostics and going to the manifest of a `path`
+//! dependency.
+//!
+//! Like `codegen`, this works on the text rather than on a syntax tree: the
+//! manifest is split into its table headers and `key = value` entries, which
+//! is enough to know what is at a position even while the manifest is being
+//! edited and isn't valid TOML.
+
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use lazy_static::lazy_static;
+use regex::Regex;
+use rls_vfs::FileContents;
+use serde_derive::Deserialize;
+use url::Url;
+
+use crate::actions::codegen;
+use crate::actions::InitActionContext;
+use crate::lsp_data::*;
+use crate::server::Output;
+
+/// The maximum number of dependency names offered by a completion.
+const MAX_CRATE_NAMES: usize = 100;
+
+/// The tables at the top level of a manifest.
+const TABLES: &[&str] = &[
+    "package",
+    "lib",
+    "bin",
+    "example",
+    "test",
+    "bench",
+    "dependencies",
+    "dev-dependencies",
+    "build-dependencies",
+    "dev_dependencies",
+    "build_dependencies",
+    "target",
+    "features",
+    "workspace",
+    "patch",
+    "replace",
+    "profile",
+    "badges",
+    "project",
+];
+
+const PACKAGE_KEYS: &[&str] = &[
+    "name",
+    "version",
+    "authors",
+    "edition",
+    "rust-version",
+    "description",
+    "documentation",
+    "readme",
+    "homepage",
+    "repository",
+    "license",
+    "license-file",
+    "keywords",
+    "categories",
+    "workspace",
+    "build",
+    "links",
+    "exclude",
+    "include",
+    "publish",
+    "metadata",
+    "default-run",
+    "autobins",
+    "autoexamples",
+    "autotests",
+    "autobenches",
+    "resolver",
+    "im-a-teapot",
+];
+
+/// The keys of the `lib`, `bin`, `example`, `test` and `bench` targets.
+const TARGET_KEYS: &[&str] = &[
+    "name",
+    "path",
+    "test",
+    "doctest",
+    "bench",
+    "doc",
+    "plugin",
+    "proc-macro",
+    "proc_macro",
+    "harness",
+    "edition",
+    "crate-type",
+    "crate_type",
+    "required-features",
+];
+
+const DEPENDENCY_KEYS: &[&str] = &[
+    "version",
+    "path",
+    "git",
+    "branch",
+    "tag",
+    "rev",
+    "features",
+    "optional",
+    "default-features",
+    "default_features",
+    "package",
+    "registry",
+    "registry-index",
+];
+
+const WORKSPACE_KEYS: &[&str] =
+    &["members", "exclude", "default-members", "resolver", "metadata", "dependencies", "package"];
+
+const PROFILE_KEYS: &[&str] = &[
+    "opt-level",
+    "debug",
+    "split-debuginfo",
+    "debug-assertions",
+    "overflow-checks",
+    "lto",
+    "panic",
+    "incremental",
+    "codegen-units",
+    "rpath",
+    "strip",
+    "inherits",
+    "build-override",
+    "package",
+];
+
+/// The kinds of dependency tables, including the aliases with underscores.
+const DEPENDENCY_TABLES: &[&str] = &[
+    "dependencies",
+    "dev-dependencies",
+    "build-dependencies",
+    "dev_dependencies",
+    "build_dependencies",
+];
+
+/// Returns whether the dependency table `kind` is for dev-dependencies.
+fn is_dev_table(kind: &str) -> bool {
+    kind == "dev-dependencies" || kind == "dev_dependencies"
+}
+
+/// Returns whether `path` is a Cargo manifest.
+pub fn is_manifest(path: &Path) -> bool {
+    path.file_name().map_or(false, |name| name == "Cargo.toml")
+}
+
+/// A table header of a manifest, e.g., `[dependencies.serde]`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Header {
+    /// The path of the table, e.g., `["target", "cfg(unix)", "dependencies"]`.
+    pub table: Vec<String>,
+    /// The offsets of the header.
+    pub range: (usize, usize),
+}
+
+/// A `key = value` entry of a manifest.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Entry {
+    /// The path of the table the entry is in, including the leading segments
+    /// of a dotted key.
+    pub table: Vec<String>,
+    pub key: String,
+    /// The offsets of the key and of the value.
+    pub key_range: (usize, usize),
+    pub value_range: (usize, usize),
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Manifest {
+    pub headers: Vec<Header>,
+    pub entries: Vec<Entry>,
+}
+
+impl Manifest {
+    /// Returns the path of the table (as given by the last header) at `offset`.
+    pub fn table_at(&self, offset: usize) -> &[String] {
+        self.headers.iter().rev().find(|h| h.range.0 < offset).map_or(&[], |h| &h.table)
+    }
+}
+
+/// Splits a (possibly dotted) key into its unquoted segments.
+fn split_key(key: &str) -> Vec<String> {
+    let mut segments = vec![];
+    let mut segment = String::new();
+    let mut quote = None;
+    for c in key.chars() {
+        match (quote, c) {
+            (Some(q), c) if c == q => quote = None,
+            (Some(_), c) => segment.push(c),
+            (None, '"') | (None, '\'') => quote = Some(c),
+            (None, '.') => segments.push(std::mem::take(&mut segment)),
+            (None, c) if !c.is_whitespace() => segment.push(c),
+            _ => {}
+        }
+    }
+    segments.push(segment);
+    segments
+}
+
+/// Returns the offset at which the value starting at `start` ends, excluding
+/// any trailing comment. Arrays may span several lines; a line which looks
+/// like a table header or an entry ends an unclosed one.
+fn value_end(text: &str, start: usize) -> usize {
+    lazy_static! {
+        static ref NEXT_ITEM: Regex =
+            Regex::new(r#"^\s*(?:\[\[?\s*[A-Za-z_']|[A-Za-z0-9_\-"'.]+\s*=)"#).unwrap();
+    }
+    let mut end = start;
+    let mut depth = 0;
+    let mut quote = None;
+    let mut escaped = false;
+    let mut comment = false;
+    for (i, c) in text[start..].char_indices().map(|(i, c)| (start + i, c)) {
+        if c == '\n' {
+            quote = None;
+            comment = false;
+            if depth == 0 || NEXT_ITEM.is_match(&text[i + 1..]) {
+                break;
+            }
+            continue;
+        }
+        if comment {
+            continue;
+        }
+        if let Some(q) = quote {
+            match c {
+                _ if escaped => escaped = false,
+                '\\' if q == '"' => escaped = true,
+                _ if c == q => quote = None,
+                _ => {}
+            }
+            end = i + c.len_utf8();
+            continue;
+        }
+        match c {
+            '#' => {
+                comment = true;
+                continue;
+            }
+            '"' | '\'' => quote = Some(c),
+            '[' | '{' => depth += 1,
+            ']' | '}' => depth -= 1,
+            _ => {}
+        }
+        if !c.is_whitespace() {
+            end = i + c.len_utf8();
+        }
+    }
+    end
+}
+
+/// Finds the offset of the first `=` in `line` which isn't quoted.
+fn find_equals(line: &str) -> Option<usize> {
+    let mut quote = None;
+    for (i, c) in line.char_indices() {
+        match (quote, c) {
+            (Some(q), c) if c == q => quote = None,
+            (Some(_), _) => {}
+            (None, '"') | (None, '\'') => quote = Some(c),
+            (None, '=') => return Some(i),
+            (None, '#') => return None,
+            _ => {}
+        }
+    }
+    None
+}
+
+/// Parses the headers and entries of the manifest `text`.
+pub fn parse_manifest(text: &str) -> Manifest {
+    let mut manifest = Manifest::default();
+    let mut table = vec![];
+    let mut line_start = 0;
+    while line_start < text.len() {
+        let line_end = text[line_start..].find('\n').map_or(text.len(), |i| line_start + i);
+        let line = &text[line_start..line_end];
+        let start = line_start + (line.len() - line.trim_start().len());
+        let rest = &text[start..line_end];
+        let mut next = line_end + 1;
+
+        if rest.starts_with('[') {
+            let name = rest.trim_start_matches('[');
+            let name = &name[..name.find(']').unwrap_or(name.len())];
+            table = split_key(name);
+            let end = start + rest.find(']').map_or(rest.trim_end().len(), |i| i + 1);
+            let end = end + text[end..line_end].find(|c| c != ']').unwrap_or(line_end - end);
+            manifest.headers.push(Header { table: table.clone(), range: (start, end) });
+        } else if let Some(eq) = find_equals(rest).filter(|_| !rest.starts_with('#')) {
+            let key = rest[..eq].trim_end();
+            let mut segments = split_key(key);
+            let value_start = start + eq + 1;
+            let value_start = value_start
+                + (text[value_start..line_end].len()
+                    - text[value_start..line_end].trim_start().len());
+            let value_end = value_end(text, value_start);
+            let mut entry_table = table.clone();
+            let key_name = segments.pop().unwrap_or_default();
+            entry_table.extend(segments);
+            manifest.entries.push(Entry {
+                table: entry_table,
+                key: key_name,
+                key_range: (start, start + key.len()),
+                value_range: (value_start, value_end),
+            });
+            next = text[value_end..].find('\n').map_or(text.len(), |i| value_end + i + 1);
+        }
+        line_start = next;
+    }
+    manifest
+}
+
+/// Returns whether `table` is a dependency table, e.g., `[dependencies]` or
+/// `[target.'cfg(unix)'.dev-dependencies]`.
+pub fn is_dependency_table(table: &[String]) -> bool {
+    match table {
+        [kind] => DEPENDENCY_TABLES.contains(&&kind[..]),
+        [target, _, kind] => target == "target" && DEPENDENCY_TABLES.contains(&&kind[..]),
+        [parent, kind] => parent == "patch" || (parent == "workspace" && kind == "dependencies"),
+        _ => false,
+    }
+}
+
+/// Parses the TOML `value`.
+fn parse_value(value: &str) -> Option<toml::Value> {
+    let mut table: toml::value::Table = toml::from_str(&format!("v = {}", value)).ok()?;
+    table.remove("v")
+}
+
+/// A dependency declared in a manifest.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Dependency {
+    /// The name the dependency is declared as.
+    pub name: String,
+    /// The name of the package, which differs from `name` if the dependency is
+    /// renamed.
+    pub package: String,
+    pub path: Option<String>,
+    pub optional: bool,
+    /// Whether the dependency is a dev-dependency.
+    pub dev: bool,
+    /// The table the dependency is declared in.
+    pub table: Vec<String>,
+    /// The offsets of the declaration, i.e., of the entry or of the header
+    /// and its entries.
+    pub range: (usize, usize),
+    /// The offsets of the name.
+    pub name_range: (usize, usize),
+}
+
+/// Returns the dependencies declared in `manifest`.
+pub fn dependencies(text: &str, manifest: &Manifest) -> Vec<Dependency> {
+    let mut dependencies = vec![];
+    for entry in manifest.entries.iter().filter(|e| is_dependency_table(&e.table)) {
+        let value = parse_value(&text[entry.value_range.0..entry.value_range.1]);
+        let field = |key: &str| match &value {
+            Some(toml::Value::Table(table)) => table.get(key).cloned(),
+            _ => None,
+        };
+        dependencies.push(Dependency {
+            name: entry.key.clone(),
+            package: field("package")
+                .and_then(|v| v.as_str().map(ToOwned::to_owned))
+                .unwrap_or_else(|| entry.key.clone()),
+            path: field("path").and_then(|v| v.as_str().map(ToOwned::to_owned)),
+            optional: field("optional").and_then(|v| v.as_bool()).unwrap_or(false),
+            dev: entry.table.last().map_or(false, |kind| is_dev_table(kind)),
+            table: entry.table.clone(),
+            range: (entry.key_range.0, entry.value_range.1),
+            name_range: entry.key_range,
+        });
+    }
+    for header in &manifest.headers {
+        let (name, table) = match header.table.split_last() {
+            Some((name, table)) if is_dependency_table(table) => (name, table),
+            _ => continue,
+        };
+        let entries: Vec<_> = manifest.entries.iter().filter(|e| e.table == header.table).collect();
+        let field = |key: &str| {
+            let entry = entries.iter().find(|e| e.key == key)?;
+            parse_value(&text[entry.value_range.0..entry.value_range.1])
+        };
+        let name_start = text[header.range.0..header.range.1]
+            .rfind(&name[..])
+            .map_or(header.range.0, |i| header.range.0 + i);
+        dependencies.push(Dependency {
+            name: name.clone(),
+            package: field("package")
+                .and_then(|v| v.as_str().map(ToOwned::to_owned))
+                .unwrap_or_else(|| name.clone()),
+            path: field("path").and_then(|v| v.as_str().map(ToOwned::to_owned)),
+            optional: field("optional").and_then(|v| v.as_bool()).unwrap_or(false),
+            dev: table.last().map_or(false, |kind| is_dev_table(kind)),
+            table: table.to_vec(),
+            range: (header.range.0, entries.last().map_or(header.range.1, |e| e.value_range.1)),
+            name_range: (name_start, name_start + name.len()),
+        });
+    }
+    dependencies
+}
+
+/// What is being completed in a manifest, see `manifest_context`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ManifestContext {
+    /// The name of a table in a header.
+    Table,
+    /// A key of the given table, which includes the keys of the inline tables
+    /// the position is in (e.g., `["dependencies", "serde"]` in
+    /// `serde = { ve`), starting at the given offset.
+    Key { table: Vec<String>, start: usize },
+    /// A string in the value of the given key, starting at the given offset.
+    Value { table: Vec<String>, key: String, start: usize },
+}
+
+/// Parses the manifest around `offset`, returning what is being completed
+/// there, if anything.
+pub fn manifest_context(text: &str, manifest: &Manifest, offset: usize) -> Option<ManifestContext> {
+    let is_key_char = |c: char| c.is_alphanumeric() || c == '_' || c == '-';
+    let key_start = |end: usize| {
+        text[..end]
+            .char_indices()
+            .rev()
+            .take_while(|&(_, c)| is_key_char(c))
+            .last()
+            .map_or(end, |(i, _)| i)
+    };
+
+    let entry = manifest
+        .entries
+        .iter()
+        .find(|e| e.value_range.0 <= offset && offset <= e.value_range.1.max(e.value_range.0));
+    if let Some(entry) = entry {
+        // The keys of the inline tables the position is in, along with whether
+        // their values (as opposed to the next key) are being parsed.
+        let mut tables: Vec<(Option<String>, usize)> = vec![];
+        let mut arrays = 0;
+        let mut string_start = None;
+        let mut segment_start = entry.value_range.0;
+        let mut escaped = false;
+        for (i, c) in text[entry.value_range.0..offset].char_indices() {
+            let i = entry.value_range.0 + i;
+            if string_start.is_some() {
+                match c {
+                    _ if escaped => escaped = false,
+                    '\\' => escaped = true,
+                    '"' | '\'' => string_start = None,
+                    _ => {}
+                }
+                continue;
+            }
+            match c {
+                '"' | '\'' => string_start = Some(i + 1),
+                '{' => {
+                    tables.push((None, i + 1));
+                    segment_start = i + 1;
+                }
+                '}' => {
+                    tables.pop();
+                }
+                '[' => arrays += 1,
+                ']' => arrays -= 1,
+                ',' => {
+                    if let Some(table) = tables.last_mut().filter(|_| arrays == 0) {
+                        table.0 = None;
+                        segment_start = i + 1;
+                    }
+                }
+                '=' => {
+                    if let Some(table) = tables.last_mut() {
+                        table.0 = Some(text[segment_start..i].trim().to_owned());
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        let mut table = entry.table.clone();
+        table.push(entry.key.clone());
+        let mut keys = tables.iter().map(|(key, _)| key.clone());
+        let last = keys.next_back();
+        if keys.any(|key| key.is_none()) {
+            return None;
+        }
+        table.extend(tables.iter().rev().skip(1).rev().filter_map(|(key, _)| key.clone()));
+        return match (last, string_start) {
+            // At a key of an inline table.
+            (Some(None), None) => Some(ManifestContext::Key { table, start: key_start(offset) }),
+            (Some(None), Some(_)) => None,
+            (Some(Some(key)), Some(start)) => Some(ManifestContext::Value { table, key, start }),
+            (None, Some(start)) => {
+                let key = table.pop().unwrap();
+                Some(ManifestContext::Value { table, key, start })
+            }
+            (_, None) => None,
+        };
+    }
+
+    let line_start = text[..offset].rfind('\n').map_or(0, |i| i + 1);
+    let line = text[line_start..offset].trim_start();
+    if line.starts_with('#') || line.contains('=') {
+        return None;
+    }
+    if line.starts_with('[') {
+        return Some(ManifestContext::Table).filter(|_| !line.contains(']'));
+    }
+    let mut table = manifest.table_at(line_start).to_vec();
+    let mut segments = split_key(line);
+    segments.pop();
+    table.extend(segments);
+    Some(ManifestContext::Key { table, start: key_start(offset) })
+}
+
+/// A published version of a crate, from the registry index.
+#[derive(Clone, Debug, Deserialize)]
+pub struct IndexVersion {
+    pub vers: String,
+    #[serde(default)]
+    pub features: BTreeMap<String, Vec<String>>,
+    #[serde(default)]
+    pub features2: BTreeMap<String, Vec<String>>,
+    #[serde(default)]
+    pub yanked: bool,
+}
+
+/// Returns the directories of the local caches of the registry indices, e.g.,
+/// `~/.cargo/registry/index/github.com-1ecc6299db9ec823/.cache`.
+fn index_caches() -> Vec<PathBuf> {
+    let index = match home::cargo_home() {
+        Ok(home) => home.join("registry").join("index"),
+        Err(_) => return vec![],
+    };
+    fs::read_dir(index)
+        .map(|dirs| {
+            dirs.filter_map(Result::ok)
+                .map(|dir| dir.path().join(".cache"))
+                .filter(|cache| cache.is_dir())
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+/// Returns the path of the index file of the crate `name`, relative to the
+/// root of the index.
+pub fn index_path(name: &str) -> Option<PathBuf> {
+    if name.is_empty() || !name.is_ascii() {
+        return None;
+    }
+    let name = name.to_lowercase();
+    Some(match name.len() {
+        1 => Path::new("1").join(&name),
+        2 => Path::new("2").join(&name),
+        3 => Path::new("3").join(&name[..1]).join(&name),
+        _ => Path::new(&name[..2]).join(&name[2..4]).join(&name),
+    })
+}
+
+/// Parses a file of the index cache, which holds the NUL-separated version
+/// numbers and JSON descriptions of the versions of a crate (after a header).
+pub fn parse_index_cache(data: &[u8]) -> Vec<IndexVersion> {
+    data.split(|&b| b == 0)
+        .filter(|chunk| chunk.starts_with(b"{"))
+        .filter_map(|chunk| serde_json::from_slice(chunk).ok())
+        .collect()
+}
+
+/// Orders version numbers, ignoring build metadata and treating pre-releases
+/// as older than the release.
+fn version_key(version: &str) -> (Vec<u64>, bool) {
+    let version = version.split('+').next().unwrap_or(version);
+    let (release, pre) = match version.find('-') {
+        Some(i) => (&version[..i], true),
+        None => (version, false),
+    };
+    (release.split('.').map(|n| n.parse().unwrap_or(0)).collect(), !pre)
+}
+
+/// Returns the versions of the crate `name` known to the local registry index
+/// cache, newest first, skipping yanked versions.
+fn crate_versions(name: &str) -> Vec<IndexVersion> {
+    let path = match index_path(name) {
+        Some(path) => path,
+        None => return vec![],
+    };
+    let mut versions = index_caches()
+        .into_iter()
+        .filter_map(|cache| fs::read(cache.join(&path)).ok())
+        .map(|data| parse_index_cache(&data))
+        .find(|versions| !versions.is_empty())
+        .unwrap_or_default();
+    versions.retain(|v| !v.yanked);
+    versions.sort_by_key(|v| Reverse(version_key(&v.vers)));
+    versions
+}
+
+/// Crate names start with the same characters as their directory in the
+/// index, so they're compared after lowercasing and replacing `_` with `-`.
+fn normalize_crate_name(name: &str) -> String {
+    name.to_lowercase().replace('_', "-")
+}
+
+/// Returns the names of the crates in the local registry index cache which
+/// start with `prefix` (treating `-` and `_` alike).
+fn crate_names(prefix: &str) -> Vec<String> {
+    lazy_static! {
+        /// The names of the crates in each directory of the index caches.
+        static ref NAMES: Mutex<HashMap<PathBuf, Vec<String>>> = Mutex::default();
+    }
+    let prefix = normalize_crate_name(prefix);
+    if prefix.is_empty() || !prefix.is_ascii() {
+        return vec![];
+    }
+    let mut cached = NAMES.lock().unwrap();
+    let mut names: Vec<_> = index_caches()
+        .into_iter()
+        .flat_map(|cache| index_dirs(&cache, &prefix))
+        .flat_map(|dir| {
+            let names = cached.entry(dir).or_insert_with_key(|dir| {
+                fs::read_dir(dir)
+                    .map(|entries| {
+                        entries
+                            .filter_map(Result::ok)
+                            .filter_map(|entry| entry.file_name().into_string().ok())
+                            .collect()
+                    })
+                    .unwrap_or_default()
+            });
+            names
+                .iter()
+                .filter(|name| normalize_crate_name(name).starts_with(&prefix))
+                .cloned()
+                .collect::<Vec<_>>()
+        })
+        .collect();
+    names.sort();
+    names.dedup();
+    names.truncate(MAX_CRATE_NAMES);
+    names
+}
+
+/// Returns the directories of the `index` which may hold the crates whose
+/// (normalized, non-empty and ASCII) names start with `prefix`, see
+/// `index_path`: `1`, `2` and `3/x` for the short names and the `ab/cd`
+/// directories matching the first four characters of the longer ones.
+fn index_dirs(index: &Path, prefix: &str) -> Vec<PathBuf> {
+    // Lists the two-character subdirectories of `dir` starting with `prefix`.
+    let subdirs = |dir: &Path, prefix: &str| -> Vec<PathBuf> {
+        fs::read_dir(dir)
+            .map(|entries| {
+                entries
+                    .filter_map(Result::ok)
+                    .filter(|entry| {
+                        let name = entry.file_name().to_string_lossy().into_owned();
+                        name.len() == 2 && normalize_crate_name(&name).starts_with(prefix)
+                    })
+                    .map(|entry| entry.path())
+                    .collect()
+            })
+            .unwrap_or_default()
+    };
+
+    let len = prefix.len();
+    let mut dirs = vec![];
+    if len <= 1 {
+        dirs.push(index.join("1"));
+    }
+    if len <= 2 {
+        dirs.push(index.join("2"));
+    }
+    if len <= 3 {
+        dirs.push(index.join("3").join(&prefix[..1]));
+    }
+    for dir in subdirs(index, &prefix[..len.min(2)]) {
+        dirs.extend(subdirs(&dir, &prefix[len.min(2)..len.min(4)]));
+    }
+    dirs
+}
+
+/// Returns the items completing a string or key starting at `start`, sorted
+/// as given.
+fn items<I: IntoIterator<Item = String>>(
+    text: &str,
+    start: usize,
+    offset: usize,
+    kind: CompletionItemKind,
+    labels: I,
+) -> Vec<CompletionItem> {
+    let range = Range::new(
+        codegen::offset_to_position(text, start),
+        codegen::offset_to_position(text, offset),
+    );
+    let prefix = text[start..offset].to_lowercase();
+    labels
+        .into_iter()
+        .filter(|label| label.to_lowercase().starts_with(&prefix))
+        .enumerate()
+        .map(|(i, label)| CompletionItem {
+            kind: Some(kind),
+            sort_text: Some(format!("{:04}", i)),
+            text_edit: Some(TextEdit::new(range, label.clone())),
+            label,
+            ..CompletionItem::default()
+        })
+        .collect()
+}
+
+fn keys(keys: &[&str]) -> Vec<String> {
+    keys.iter().map(|&key| key.to_owned()).collect()
+}
+
+/// Completes the manifest at `position`.
+pub fn complete(
+    ctx: &InitActionContext,
+    file_path: &Path,
+    position: Position,
+) -> Vec<CompletionItem> {
+    let text = match ctx.vfs.load_file(file_path) {
+        Ok(FileContents::Text(text)) => text,
+        _ => return vec![],
+    };
+    let offset = match codegen::position_to_offset(&text, position) {
+        Some(offset) => offset,
+        None => return vec![],
+    };
+    let manifest = parse_manifest(&text);
+    let context = match manifest_context(&text, &manifest, offset) {
+        Some(context) => context,
+        None => return vec![],
+    };
+    let property = CompletionItemKind::Property;
+    let value = CompletionItemKind::Value;
+
+    match context {
+        ManifestContext::Table => {
+            let start = text[..offset].rfind('[').map_or(offset, |i| i + 1);
+            items(&text, start, offset, CompletionItemKind::Module, keys(TABLES))
+        }
+        ManifestContext::Key { table, start } => {
+            let labels = match &table[..] {
+                [] => keys(&["cargo-features"]),
+                [kind] if kind == "package" || kind == "project" => keys(PACKAGE_KEYS),
+                [kind] if ["lib", "bin", "example", "test", "bench"].contains(&&kind[..]) => {
+                    keys(TARGET_KEYS)
+                }
+                [kind] if kind == "workspace" => keys(WORKSPACE_KEYS),
+                [kind, _] if kind == "profile" => keys(PROFILE_KEYS),
+                [kind] if kind == "features" => keys(&["default"]),
+                table if is_dependency_table(table) => {
+                    let names = crate_names(&text[start..offset]);
+                    return items(&text, start, offset, CompletionItemKind::Module, names);
+                }
+                [table @ .., _] if is_dependency_table(table) => keys(DEPENDENCY_KEYS),
+                _ => vec![],
+            };
+            items(&text, start, offset, property, labels)
+        }
+        ManifestContext::Value { table, key, start } => {
+            let labels = match (&table[..], &key[..]) {
+                ([kind], "edition") if kind == "package" => keys(&["2015", "2018", "2021"]),
+                ([_], "resolver") => keys(&["1", "2"]),
+                (table, name) if is_dependency_table(table) => {
+                    crate_versions(name).into_iter().map(|v| v.vers).collect()
+                }
+                ([table @ .., name], "version") if is_dependency_table(table) => {
+                    let package = dependencies(&text, &manifest)
+                        .into_iter()
+                        .find(|dep| &dep.name == name && dep.table == table)
+                        .map_or(name.clone(), |dep| dep.package);
+                    crate_versions(&package).into_iter().map(|v| v.vers).collect()
+                }
+                ([table @ .., name], "features") if is_dependency_table(table) => {
+                    let package = dependencies(&text, &manifest)
+                        .into_iter()
+                        .find(|dep| &dep.name == name && dep.table == table)
+                        .map_or(name.clone(), |dep| dep.package);
+                    crate_versions(&package)
+                        .into_iter()
+                        .next()
+                        .map(|latest| {
+                            latest
+                                .features
+                                .keys()
+                                .chain(latest.features2.keys())
+                                .filter(|feature| !feature.starts_with("dep:"))
+                                .cloned()
+                                .collect()
+                        })
+                        .unwrap_or_default()
+                }
+                ([kind], feature) if kind == "features" => {
+                    let features = manifest
+                        .entries
+                        .iter()
+                        .filter(|e| e.table == table && e.key != feature)
+                        .map(|e| e.key.clone());
+                    let dependencies: Vec<_> =
+                        dependencies(&text, &manifest).into_iter().filter(|dep| !dep.dev).collect();
+                    let optional = dependencies
+                        .iter()
+                        .filter(|dep| dep.optional)
+                        .flat_map(|dep| vec![dep.name.clone(), format!("dep:{}", dep.name)]);
+                    let features_of =
+                        dependencies.iter().map(|dep| format!("{}/", dep.name)).collect::<Vec<_>>();
+                    features.chain(optional).chain(features_of).collect()
+                }
+                _ => vec![],
+            };
+            items(&text, start, offset, value, labels)
+        }
+    }
+}
+
+/// A package in `Cargo.lock`.
+#[derive(Clone, Debug, Deserialize)]
+pub struct LockedPackage {
+    pub name: String,
+    pub version: String,
+    pub source: Option<String>,
+}
+
+/// Parses the packages of the lock file `text`.
+pub fn parse_lockfile(text: &str) -> Vec<LockedPackage> {
+    #[derive(Deserialize)]
+    struct Lockfile {
+        #[serde(default)]
+        package: Vec<LockedPackage>,
+    }
+    toml::from_str::<Lockfile>(text).map(|lockfile| lockfile.package).unwrap_or_default()
+}
+
+/// Describes where a locked package comes from.
+fn describe_source(source: Option<&str>) -> String {
+    match source {
+        None => "local".to_owned(),
+        Some("registry+https://github.com/rust-lang/crates.io-index") => "crates.io".to_owned(),
+        Some(source) => source.split_once('+').map_or(source, |(_, url)| url).to_owned(),
+    }
+}
+
+/// Shows the versions of the dependency at `position` locked in `Cargo.lock`.
+pub fn hover(ctx: &InitActionContext, file_path: &Path, position: Position) -> Option<Hover> {
+    let text = match ctx.vfs.load_file(file_path) {
+        Ok(FileContents::Text(text)) => text,
+        _ => return None,
+    };
+    let offset = codegen::position_to_offset(&text, position)?;
+    let manifest = parse_manifest(&text);
+    let dependency = dependencies(&text, &manifest)
+        .into_iter()
+        .find(|dep| dep.name_range.0 <= offset && offset <= dep.name_range.1)?;
+
+    let lockfile =
+        file_path.ancestors().skip(1).map(|dir| dir.join("Cargo.lock")).find_map(|path| match ctx
+            .vfs
+            .load_file(&path)
+        {
+            Ok(FileContents::Text(text)) => Some(text),
+            _ => None,
+        });
+    let locked: Vec<_> = lockfile
+        .as_ref()
+        .map(|lockfile| parse_lockfile(lockfile))
+        .unwrap_or_default()
+        .into_iter()
+        .filter(|package| package.name == dependency.package)
+        .map(|package| {
+            format!(
+                "`{} {}` ({})",
+                package.name,
+                package.version,
+                describe_source(package.source.as_ref().map(String::as_str))
+            )
+        })
+        .collect();
+    let value = if locked.is_empty() {
+        format!("`{}` isn't locked in `Cargo.lock`", dependency.package)
+    } else {
+        format!("Locked: {}", locked.join(", "))
+    };
+
+    Some(Hover {
+        contents: HoverContents::Array(vec![MarkedString::String(value)]),
+        range: Some(Range::new(
+            codegen::offset_to_position(&text, dependency.name_range.0),
+            codegen::offset_to_position(&text, dependency.name_range.1),
+        )),
+    })
+}
+
+/// Goes from the `path` dependency at `position` to its manifest.
+pub fn definition(
+    ctx: &InitActionContext,
+    file_path: &Path,
+    position: Position,
+) -> Option<Location> {
+    let text = match ctx.vfs.load_file(file_path) {
+        Ok(FileContents::Text(text)) => text,
+        _ => return None,
+    };
+    let offset = codegen::position_to_offset(&text, position)?;
+    let manifest = parse_manifest(&text);
+    let path = dependencies(&text, &manifest)
+        .into_iter()
+        .find(|dep| dep.range.0 <= offset && offset <= dep.range.1)?
+        .path?;
+    let target = file_path.parent()?.join(path).join("Cargo.toml");
+    if !target.is_file() {
+        return None;
+    }
+    Some(Location::new(Url::from_file_path(target).ok()?, Range::default()))
+}
+
+/// Checks the manifest `text`, reporting syntax errors, unknown keys and
+/// features which refer to nonexistent dependencies or features.
+pub fn diagnostics(text: &str) -> Vec<Diagnostic> {
+    lazy_static! {
+        static ref INLINE_KEY: Regex = Regex::new(r#"[{,]\s*([A-Za-z0-9_\-"']+)\s*="#).unwrap();
+        static ref STRING: Regex = Regex::new(r#""([^"\n]*)""#).unwrap();
+    }
+    let range = |(start, end): (usize, usize)| {
+        Range::new(codegen::offset_to_position(text, start), codegen::offset_to_position(text, end))
+    };
+    let diagnostic = |severity, range, message| Diagnostic {
+        range,
+        severity: Some(severity),
+        source: Some("cargo".to_owned()),
+        message,
+        ..Diagnostic::default()
+    };
+
+    if let Err(e) = toml::from_str::<toml::Value>(text) {
+        let (line, col) = e.line_col().unwrap_or((0, 0));
+        let start = Position::new(line as u64, col as u64);
+        let end = Position::new(line as u64, col as u64 + 1);
+        return vec![diagnostic(DiagnosticSeverity::Error, Range::new(start, end), e.to_string())];
+    }
+
+    let manifest = parse_manifest(text);
+    let mut diagnostics = vec![];
+    let unused = |key: &str, key_range| {
+        diagnostic(
+            DiagnosticSeverity::Warning,
+            range(key_range),
+            format!("unused manifest key: {}", key),
+        )
+    };
+
+    for header in &manifest.headers {
+        if let Some(name) = header.table.first().filter(|name| !TABLES.contains(&&name[..])) {
+            diagnostics.push(unused(name, header.range));
+        }
+    }
+    for entry in &manifest.entries {
+        let known: &[&str] = match &entry.table[..] {
+            [] => &["cargo-features"],
+            [kind] if kind == "package" || kind == "project" => PACKAGE_KEYS,
+            [kind] if ["lib", "bin", "example", "test", "bench"].contains(&&kind[..]) => {
+                TARGET_KEYS
+            }
+            [kind] if kind == "workspace" => WORKSPACE_KEYS,
+            [kind, _] if kind == "profile" => PROFILE_KEYS,
+            [table @ .., _] if is_dependency_table(table) => DEPENDENCY_KEYS,
+            _ => &[],
+        };
+        let known_table = entry.table.is_empty() && TABLES.contains(&&entry.key[..]);
+        if !known.is_empty() && !known.contains(&&entry.key[..]) && !known_table {
+            let key = entry.table.iter().chain(Some(&entry.key)).cloned().collect::<Vec<_>>();
+            diagnostics.push(unused(&key.join("."), entry.key_range));
+        }
+
+        if is_dependency_table(&entry.table) {
+            let value = &text[entry.value_range.0..entry.value_range.1];
+            if value.starts_with('{') {
+                for key in INLINE_KEY.captures_iter(value).filter_map(|c| c.get(1)) {
+                    let name = key.as_str().trim_matches(|c| c == '"' || c == '\'');
+                    if !DEPENDENCY_KEYS.contains(&name) {
+                        let start = entry.value_range.0 + key.start();
+                        let key_name = format!("{}.{}.{}", entry.table.join("."), entry.key, name);
+                        diagnostics.push(unused(&key_name, (start, start + key.as_str().len())));
+                    }
+                }
+            }
+        }
+    }
+
+    let features: HashSet<_> = manifest
+        .entries
+        .iter()
+        .filter(|e| e.table == ["features"])
+        .map(|e| e.key.as_str())
+        .collect();
+    let dependencies: Vec<_> =
+        dependencies(text, &manifest).into_iter().filter(|dep| !dep.dev).collect();
+    let dependency = |name: &str| dependencies.iter().find(|dep| dep.name == name);
+    for entry in manifest.entries.iter().filter(|e| e.table == ["features"]) {
+        let value = &text[entry.value_range.0..entry.value_range.1];
+        for string in STRING.captures_iter(value).filter_map(|c| c.get(1)) {
+            let included = string.as_str();
+            let message = if let Some(name) = included.strip_prefix("dep:") {
+                match dependency(name) {
+                    None => Some(format!("`{}` is not listed as a dependency", name)),
+                    Some(dep) if !dep.optional => {
+                        Some(format!("`{}` is not an optional dependency", name))
+                    }
+                    Some(_) => None,
+                }
+            } else if let Some(slash) = included.find('/') {
+                let name = included[..slash].trim_end_matches('?');
+                Some(format!("`{}` is not a dependency", name))
+                    .filter(|_| dependency(name).is_none())
+            } else if features.contains(included) {
+                None
+            } else {
+                match dependency(included) {
+                    None => {
+                        Some(format!("`{}` is neither a dependency nor another feature", included))
+                    }
+                    Some(dep) if !dep.optional => {
+                        Some(format!("`{}` is not an optional dependency", included))
+                    }
+                    Some(_) => None,
+                }
+            };
+            if let Some(message) = message {
+                let start = entry.value_range.0 + string.start();
+                diagnostics.push(diagnostic(
+                    DiagnosticSeverity::Error,
+                    range((start, start + included.len())),
+                    format!("feature `{}` includes `{}`, but {}", entry.key, included, message),
+                ));
+            }
+        }
+    }
+    diagnostics
+}
+
+/// Checks the manifest at `file_path` (as in the VFS) and publishes the
+/// diagnostics, along with those of the last build for the manifest.
+pub fn publish_diagnostics<O: Output>(ctx: &InitActionContext, file_path: &Path, out: &O) {
+    let text = match ctx.vfs.load_file(file_path) {
+        Ok(FileContents::Text(text)) => text,
+        _ => return,
+    };
+    ctx.manifest_diagnostics.lock().unwrap().insert(file_path.to_owned(), diagnostics(&text));
+    ctx.publish_diagnostics(file_path, out);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MANIFEST: &str = r#"[package]
+name = "foo"
+version = "0.1.0"
+edition = "2018"
+
+[dependencies]
+serde = { version = "1.0", features = ["derive"] }
+log = "0.4" # logging
+bar = { path = "../bar", optional = true }
+
+[target.'cfg(unix)'.dependencies.libc]
+version = "0.2"
+package = "libc"
+
+[features]
+default = [
+    "bar",
+    "serde/std",
+]
+"#;
+
+    #[test]
+    fn parses_manifest() {
+        let manifest = parse_manifest(MANIFEST);
+        let tables: Vec<_> = manifest.headers.iter().map(|h| h.table.join(".")).collect();
+        assert_eq!(
+            tables,
+            ["package", "dependencies", "target.cfg(unix).dependencies.libc", "features"]
+        );
+        let entry = |key: &str| manifest.entries.iter().find(|e| e.key == key).unwrap();
+        let value = |key: &str| {
+            let range = entry(key).value_range;
+            &MANIFEST[range.0..range.1]
+        };
+        assert_eq!(value("log"), "\"0.4\"");
+        assert_eq!(value("serde"), "{ version = \"1.0\", features = [\"derive\"] }");
+        assert_eq!(value("default"), "[\n    \"bar\",\n    \"serde/std\",\n]");
+        assert_eq!(entry("version").table, ["package"]);
+        assert_eq!(manifest.table_at(MANIFEST.find("log").unwrap()), ["dependencies"]);
+
+        // An unclosed array ends at the next entry.
+        let manifest = parse_manifest("a = [\n  \"x\",\nb = 1\n");
+        assert_eq!(manifest.entries.len(), 2);
+        assert_eq!(
+            split_key("target.'cfg(unix)'.dependencies"),
+            ["target", "cfg(unix)", "dependencies"]
+        );
+    }
+
+    #[test]
+    fn finds_dependencies() {
+        let manifest = parse_manifest(MANIFEST);
+        let deps = dependencies(MANIFEST, &manifest);
+        let names: Vec<_> = deps.iter().map(|dep| dep.name.as_str()).collect();
+        assert_eq!(names, ["serde", "log", "bar", "libc"]);
+        assert_eq!(deps[2].path.as_ref().unwrap(), "../bar");
+        assert!(deps[2].optional && !deps[0].optional);
+        assert_eq!(deps[3].table, ["target", "cfg(unix)", "dependencies"]);
+        let (start, end) = deps[3].name_range;
+        assert_eq!(&MANIFEST[start..end], "libc");
+    }
+
+    #[test]
+    fn parses_manifest_context() {
+        let context = |text: &str| {
+            let offset = text.find('|').unwrap();
+            let text = text.replace('|', "");
+            manifest_context(&text, &parse_manifest(&text), offset)
+        };
+        let key = |table: &[&str], start| ManifestContext::Key {
+            table: table.iter().map(|s| s.to_string()).collect(),
+            start,
+        };
+        let value = |table: &[&str], key: &str, start| ManifestContext::Value {
+            table: table.iter().map(|s| s.to_string()).collect(),
+            key: key.to_owned(),
+            start,
+        };
+
+        assert_eq!(context("[dep|"), Some(ManifestContext::Table));
+        assert_eq!(context("[package]\ned|"), Some(key(&["package"], 10)));
+        assert_eq!(context("[dependencies]\nser|"), Some(key(&["dependencies"], 15)));
+        assert_eq!(
+            context("[dependencies]\nserde = \"1.|\""),
+            Some(value(&["dependencies"], "serde", 24))
+        );
+        assert_eq!(
+            context("[dependencies]\nserde = { ver| }"),
+            Some(key(&["dependencies", "serde"], 25))
+        );
+        assert_eq!(
+            context("[dependencies]\nserde = { version = \"1\", features = [\"de|\"] }"),
+            Some(value(&["dependencies", "serde"], "features", 53))
+        );
+        assert_eq!(
+            context("[dependencies.serde]\nversion = \"|\""),
+            Some(value(&["dependencies", "serde"], "version", 32))
+        );
+        assert_eq!(
+            context("[features]\nfull = [\n  \"b|\",\n]"),
+            Some(value(&["features"], "full", 23))
+        );
+        assert_eq!(context("[package]\nname = fo|"), None);
+        assert_eq!(context("# [dep|"), None);
+    }
+
+    #[test]
+    fn reads_index_cache() {
+        let mut data = vec![3, 2, 0, 0, 0];
+        data.extend_from_slice(b"etag\0");
+        data.extend_from_slice(
+            b"1.0.0\0{\"name\":\"foo\",\"vers\":\"1.0.0\",\"features\":{\"std\":[]}}\0",
+        );
+        data.extend_from_slice(b"1.1.0\0{\"name\":\"foo\",\"vers\":\"1.1.0\",\"yanked\":true}\0");
+        let versions = parse_index_cache(&data);
+        assert_eq!(versions.len(), 2);
+        assert_eq!(versions[0].features.keys().collect::<Vec<_>>(), ["std"]);
+        assert!(versions[1].yanked);
+
+        assert_eq!(index_path("a").unwrap(), Path::new("1/a"));
+        assert_eq!(index_path("cc").unwrap(), Path::new("2/cc"));
+        assert_eq!(index_path("syn").unwrap(), Path::new("3/s/syn"));
+        assert_eq!(index_path("Serde").unwrap(), Path::new("se/rd/serde"));
+
+        let mut versions = vec!["1.0.0", "1.10.0", "1.2.0", "1.10.0-beta.1", "0.9.9"];
+        versions.sort_by_key(|v| Reverse(version_key(v)));
+        assert_eq!(versions, ["1.10.0", "1.10.0-beta.1", "1.2.0", "1.0.0", "0.9.9"]);
+    }
+
+    #[test]
+    fn finds_index_dirs() {
+        let index = tempfile::tempdir().unwrap();
+        for dir in &["1", "2", "3/s", "se/rd", "se/rv", "sy/n-", "ab/cd"] {
+            fs::create_dir_all(index.path().join(dir)).unwrap();
+        }
+        let dirs = |prefix: &str| {
+            let mut dirs: Vec<_> = index_dirs(index.path(), prefix)
+                .iter()
+                .map(|dir| dir.strip_prefix(index.path()).unwrap().to_string_lossy().into_owned())
+                .collect();
+            dirs.sort();
+            dirs
+        };
+        assert_eq!(dirs("s"), ["1", "2", "3/s", "se/rd", "se/rv", "sy/n-"]);
+        assert_eq!(dirs("se"), ["2", "3/s", "se/rd", "se/rv"]);
+        assert_eq!(dirs("ser"), ["3/s", "se/rd", "se/rv"]);
+        assert_eq!(dirs("serd"), ["se/rd"]);
+        assert_eq!(dirs("serde-json"), ["se/rd"]);
+        assert_eq!(dirs("syn-"), ["sy/n-"]);
+    }
+
+    #[test]
+    fn reports_manifest_diagnostics() {
+        let messages = |text: &str| -> Vec<String> {
+            diagnostics(text).into_iter().map(|d| d.message).collect()
+        };
+        assert!(messages(MANIFEST).is_empty());
+
+        let text = "[package]\nname = \"foo\"\nauthor = \"me\"\n\n[dependencies]\n\
+                    serde = { version = \"1\", feature = [\"derive\"] }\n\n[bar]\n";
+        assert_eq!(
+            messages(text),
+            [
+                "unused manifest key: bar",
+                "unused manifest key: package.author",
+                "unused manifest key: dependencies.serde.feature",
+            ]
+        );
+
+        let text = "[dependencies]\nlog = \"0.4\"\nbar = { version = \"1\", optional = true }\n\n\
+                    [features]\na = [\"b\", \"log\", \"dep:baz\", \"serde/std\", \"bar\", \"dep:bar\"]\n\
+                    b = []\n";
+        assert_eq!(
+            messages(text),
+            [
+                "feature `a` includes `log`, but `log` is not an optional dependency",
+                "feature `a` includes `dep:baz`, but `baz` is not listed as a dependency",
+                "feature `a` includes `serde/std`, but `serde` is not a dependency",
+            ]
+        );
+
+        let text = "[dev_dependencies]\nfoo = { version = \"1\", registry-index = \"https://x\" }\n\n\
+                    [build_dependencies]\nbar = { version = \"1\", registry_index = \"https://x\" }\n";
+        assert_eq!(messages(text), ["unused manifest key: build_dependencies.bar.registry_index"]);
+        let manifest = parse_manifest(text);
+        let dev: Vec<_> = dependencies(text, &manifest).into_iter().map(|dep| dep.dev).collect();
+        assert_eq!(dev, [true, false]);
+
+        let diagnostics = diagnostics("[package]\nname = \n");
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].severity, Some(DiagnosticSeverity::Error));
+    }
+}
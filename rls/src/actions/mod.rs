@@ -12,15 +12,18 @@ use serde_json::{self, json};
 use url::Url;
 use walkdir::WalkDir;
 
+use crate::actions::completion::RecentCompletions;
 use crate::actions::format::Rustfmt;
-use crate::actions::post_build::{AnalysisQueue, BuildResults, PostBuildHandler};
+use crate::actions::post_build::{
+    AnalysisQueue, BuildResults, DiagnosticFilter, ManifestDiagnostics, PostBuildHandler,
+};
 use crate::actions::progress::{BuildDiagnosticsNotifier, BuildProgressNotifier};
 use crate::build::*;
 use crate::concurrency::{ConcurrentJob, Jobs};
 use crate::lsp_data;
 use crate::lsp_data::*;
 use crate::project_model::{ProjectModel, RacerFallbackModel, RacerProjectModel};
-use crate::server::Output;
+use crate::server::{Notification, Output};
 
 use std::collections::{HashMap, HashSet};
 use std::convert::TryFrom;
@@ -48,14 +51,21 @@ macro_rules! parse_file_path {
     };
 }
 
+pub mod attributes;
+pub mod baseline;
+pub mod codegen;
+pub mod completion;
 pub mod diagnostics;
 pub mod format;
 pub mod hover;
+pub mod imports;
+pub mod manifest;
 pub mod notifications;
 pub mod post_build;
 pub mod progress;
 pub mod requests;
 pub mod run;
+pub mod snippets;
 pub mod work_pool;
 
 /// Persistent context shared across all requests and notifications.
@@ -133,6 +143,11 @@ pub struct InitActionContext {
     project_model: Arc<Mutex<Option<Arc<ProjectModel>>>>,
 
     previous_build_results: Arc<Mutex<BuildResults>>,
+    // The lints of the manifests open in the editor, published along with the
+    // diagnostics of the build for the same file.
+    manifest_diagnostics: Arc<Mutex<ManifestDiagnostics>>,
+    // Timings of the most recent successful build.
+    last_build_report: Arc<Mutex<Option<BuildReport>>>,
     build_queue: BuildQueue,
     file_to_crates: Arc<Mutex<HashMap<PathBuf, HashSet<Crate>>>>,
     // Keep a record of builds/post-build tasks currently in flight so that
@@ -147,6 +162,12 @@ pub struct InitActionContext {
     pub quiescent: Arc<AtomicBool>,
 
     prev_changes: Arc<Mutex<HashMap<PathBuf, u64>>>,
+    // The versions of the changed files the results of the last build are for.
+    build_versions: Arc<Mutex<HashMap<PathBuf, u64>>>,
+    // Files open in the editor.
+    open_files: Arc<Mutex<HashSet<PathBuf>>>,
+    // Completions recently accepted by the user, used to rank completions.
+    recent_completions: Arc<Mutex<RecentCompletions>>,
 
     config: Arc<Mutex<Config>>,
     jobs: Arc<Mutex<Jobs>>,
@@ -201,12 +222,17 @@ impl InitActionContext {
             current_project,
             project_model: Arc::default(),
             previous_build_results: Arc::default(),
+            manifest_diagnostics: Arc::default(),
+            last_build_report: Arc::default(),
             build_queue,
             file_to_crates: Arc::default(),
             active_build_count: Arc::new(AtomicUsize::new(0)),
             shown_cargo_error: Arc::new(AtomicBool::new(false)),
             quiescent: Arc::new(AtomicBool::new(false)),
             prev_changes: Arc::default(),
+            build_versions: Arc::default(),
+            open_files: Arc::default(),
+            recent_completions: Arc::default(),
             client_capabilities: Arc::new(client_capabilities),
             client_supports_cmd_run,
             client_use_change_watched: false,
@@ -225,7 +251,12 @@ impl InitActionContext {
             Some(pm) => Ok(pm),
             None => {
                 info!("loading cargo project model");
-                let pm = ProjectModel::load(&self.current_project.join("Cargo.toml"), &self.vfs)?;
+                let resolve_mode = self.config.lock().unwrap().resolve_mode();
+                let pm = ProjectModel::load(
+                    &self.current_project.join("Cargo.toml"),
+                    &self.vfs,
+                    resolve_mode,
+                )?;
                 let pm = Arc::new(pm);
                 *self.project_model.lock().unwrap() = Some(Arc::clone(&pm));
                 Ok(pm)
@@ -335,19 +366,36 @@ impl InitActionContext {
 
         let pbh = {
             let config = self.config.lock().unwrap();
+            let target_dir = config.target_dir.as_ref().as_deref();
+            let build_std = build_std_crates(&config).is_some();
+            if let Err(e) =
+                self.analysis.set_build_target(target_dir, config.target.as_deref(), build_std)
+            {
+                debug!("failed to configure the analysis build target: {:?}", e);
+            }
             PostBuildHandler {
                 analysis: Arc::clone(&self.analysis),
                 analysis_queue: Arc::clone(&self.analysis_queue),
                 previous_build_results: Arc::clone(&self.previous_build_results),
+                manifest_diagnostics: Arc::clone(&self.manifest_diagnostics),
+                file_versions: self.prev_changes.lock().unwrap().clone(),
+                build_versions: Arc::clone(&self.build_versions),
                 file_to_crates: Arc::clone(&self.file_to_crates),
                 project_path: project_path.to_owned(),
-                show_warnings: config.show_warnings,
+                diagnostic_filter: DiagnosticFilter::new(&config, project_path, &self.open_files),
                 related_information_support: self.client_capabilities.related_information_support,
                 shown_cargo_error: Arc::clone(&self.shown_cargo_error),
                 active_build_count: Arc::clone(&self.active_build_count),
                 crate_blacklist: config.crate_blacklist.as_ref().clone(),
                 notifier: Box::new(BuildDiagnosticsNotifier::new(out.clone())),
                 blocked_threads: vec![],
+                build_report: None,
+                last_build_report: Arc::clone(&self.last_build_report),
+                timings_report_dir: if config.build_timings_report {
+                    config.target_dir.as_ref().clone()
+                } else {
+                    None
+                },
                 _token: token,
             }
         };
@@ -411,6 +459,48 @@ impl InitActionContext {
         VersionOrdering::Ok
     }
 
+    /// Returns whether the file at `file_path` hasn't changed since the last
+    /// build, so that the positions in its results still match the text.
+    pub fn build_results_current(&self, file_path: &Path) -> bool {
+        let version = self.prev_changes.lock().unwrap().get(file_path).copied();
+        version == self.build_versions.lock().unwrap().get(file_path).copied()
+    }
+
+    /// Records whether the file at `file_path` is open in the editor. If only
+    /// the diagnostics of open files are shown, those of the last build for
+    /// the file are published again, or cleared.
+    fn set_file_open<O: Output>(&self, file_path: &Path, open: bool, out: &O) {
+        let changed = if open {
+            self.open_files.lock().unwrap().insert(file_path.to_owned())
+        } else {
+            self.open_files.lock().unwrap().remove(file_path)
+        };
+        if changed && self.config.lock().unwrap().diagnostics_open_files_only {
+            self.publish_diagnostics(file_path, out);
+        }
+    }
+
+    /// Publishes the diagnostics of the file at `file_path`: those of the last
+    /// build along with, for a manifest, its lints. Both are published together
+    /// as each publication replaces the diagnostics of the file.
+    fn publish_diagnostics<O: Output>(&self, file_path: &Path, out: &O) {
+        let uri = match Url::from_file_path(file_path) {
+            Ok(uri) => uri,
+            Err(_) => return,
+        };
+        let mut diagnostics = {
+            let config = self.config.lock().unwrap();
+            let filter = DiagnosticFilter::new(&config, &self.current_project, &self.open_files);
+            let results = self.previous_build_results.lock().unwrap();
+            results.get(file_path).map_or_else(Vec::new, |results| filter.apply(file_path, results))
+        };
+        if let Some(lints) = self.manifest_diagnostics.lock().unwrap().get(file_path) {
+            diagnostics.extend(lints.iter().cloned().map(RichDiagnostic::from));
+        }
+        let params = RichPublishDiagnosticsParams { uri, diagnostics };
+        out.notify(Notification::<RichPublishDiagnostics>::new(params));
+    }
+
     fn reset_change_version(&self, file_path: &Path) {
         let file_path = file_path.to_owned();
         let mut prev_changes = self.prev_changes.lock().unwrap();
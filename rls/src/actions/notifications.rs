@@ -1,5 +1,6 @@
 //! One-way notifications that the RLS receives from the client.
 
+use crate::actions::manifest;
 use crate::actions::{FileWatch, InitActionContext, VersionOrdering};
 use crate::Span;
 use log::{debug, trace, warn};
@@ -15,7 +16,7 @@ use lsp_types::notification::ShowMessage;
 
 pub use crate::lsp_data::notification::{
     Cancel, DidChangeConfiguration, DidChangeTextDocument, DidChangeWatchedFiles,
-    DidOpenTextDocument, DidSaveTextDocument, Initialized,
+    DidCloseTextDocument, DidOpenTextDocument, DidSaveTextDocument, Initialized,
 };
 
 use crate::server::{BlockingNotificationAction, Notification, Output};
@@ -51,12 +52,29 @@ impl BlockingNotificationAction for DidOpenTextDocument {
     fn handle<O: Output>(
         params: Self::Params,
         ctx: &mut InitActionContext,
-        _out: O,
+        out: O,
     ) -> Result<(), ()> {
         trace!("on_open: {:?}", params.text_document.uri);
         let file_path = parse_file_path!(&params.text_document.uri, "on_open")?;
         ctx.reset_change_version(&file_path);
         ctx.vfs.set_file(&file_path, &params.text_document.text);
+        ctx.set_file_open(&file_path, true, &out);
+        if manifest::is_manifest(&file_path) {
+            manifest::publish_diagnostics(ctx, &file_path, &out);
+        }
+        Ok(())
+    }
+}
+
+impl BlockingNotificationAction for DidCloseTextDocument {
+    fn handle<O: Output>(
+        params: Self::Params,
+        ctx: &mut InitActionContext,
+        out: O,
+    ) -> Result<(), ()> {
+        trace!("on_close: {:?}", params.text_document.uri);
+        let file_path = parse_file_path!(&params.text_document.uri, "on_close")?;
+        ctx.set_file_open(&file_path, false, &out);
         Ok(())
     }
 }
@@ -109,6 +127,9 @@ impl BlockingNotificationAction for DidChangeTextDocument {
             })
             .collect();
         ctx.vfs.on_changes(&changes).expect("error committing to VFS");
+        if manifest::is_manifest(&file_path) {
+            manifest::publish_diagnostics(ctx, &file_path, &out);
+        }
 
         ctx.build_queue.mark_file_dirty(file_path, version_num);
 
@@ -5,6 +5,7 @@
 use std::collections::hash_map::DefaultHasher;
 use std::collections::{HashMap, HashSet};
 use std::hash::{Hash, Hasher};
+use std::io;
 use std::ops::Deref;
 use std::panic::RefUnwindSafe;
 use std::path::{Path, PathBuf};
@@ -12,13 +13,17 @@ use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
 use std::sync::{Arc, Mutex};
 use std::thread::{self, Thread};
 
-use crate::actions::diagnostics::{parse_diagnostics, Diagnostic, ParsedDiagnostics, Suggestion};
+use crate::actions::baseline::Baseline;
+use crate::actions::diagnostics::{parse_diagnostics, ParsedDiagnostics, Suggestion};
 use crate::actions::progress::DiagnosticsNotifier;
-use crate::build::{BuildResult, Crate};
+use crate::build::{BuildReport, BuildResult, Crate};
 use crate::concurrency::JobToken;
-use crate::config::CrateBlacklist;
-use crate::lsp_data::{PublishDiagnosticsParams, Range};
+use crate::config::{BaselineMode, Config, CrateBlacklist, DiagnosticLevel};
+use crate::lsp_data::{
+    Diagnostic, NumberOrString, Range, RichDiagnostic, RichPublishDiagnosticsParams,
+};
 
+use glob::Pattern;
 use itertools::Itertools;
 use log::{trace, warn};
 use lsp_types::DiagnosticSeverity;
@@ -26,21 +31,169 @@ use rls_analysis::AnalysisHost;
 use rls_data::Analysis;
 use url::Url;
 
-pub type BuildResults = HashMap<PathBuf, Vec<(Diagnostic, Vec<Suggestion>)>>;
+pub type BuildResults = HashMap<PathBuf, Vec<(RichDiagnostic, Vec<Suggestion>)>>;
+/// The lints of manifests, which aren't produced by the build.
+pub type ManifestDiagnostics = HashMap<PathBuf, Vec<Diagnostic>>;
+
+/// The rules from the config deciding which diagnostics of a build are
+/// published, and with which severity.
+#[derive(Clone, Debug)]
+pub struct DiagnosticFilter {
+    project_path: PathBuf,
+    show_warnings: bool,
+    ignore: Vec<String>,
+    exclude: Vec<Pattern>,
+    severity: HashMap<String, DiagnosticLevel>,
+    /// The baseline of the warnings which are demoted or hidden.
+    baseline: Option<(BaselineMode, Arc<Baseline>)>,
+    /// The files open in the editor, if only their diagnostics are shown.
+    open_files: Option<Arc<Mutex<HashSet<PathBuf>>>>,
+}
+
+impl DiagnosticFilter {
+    pub fn new(
+        config: &Config,
+        project_path: &Path,
+        open_files: &Arc<Mutex<HashSet<PathBuf>>>,
+    ) -> DiagnosticFilter {
+        let exclude = config
+            .diagnostics_exclude
+            .iter()
+            .filter_map(|pattern| match Pattern::new(pattern) {
+                Ok(pattern) => Some(pattern),
+                Err(e) => {
+                    warn!("Invalid `diagnostics_exclude` pattern {:?}: {}", pattern, e);
+                    None
+                }
+            })
+            .collect();
+
+        let baseline = match config.diagnostics_baseline {
+            BaselineMode::Off => None,
+            mode => {
+                let path = project_path.join(&config.diagnostics_baseline_file);
+                match Baseline::load(&path) {
+                    Ok(baseline) => Some((mode, Arc::new(baseline))),
+                    Err(ref e) if e.kind() == io::ErrorKind::NotFound => None,
+                    Err(e) => {
+                        warn!("Invalid diagnostics baseline {:?}: {}", path, e);
+                        None
+                    }
+                }
+            }
+        };
+
+        DiagnosticFilter {
+            project_path: project_path.to_owned(),
+            show_warnings: config.show_warnings,
+            ignore: config.diagnostics_ignore.clone(),
+            exclude,
+            severity: config.diagnostics_severity.clone(),
+            baseline,
+            open_files: if config.diagnostics_open_files_only {
+                Some(Arc::clone(open_files))
+            } else {
+                None
+            },
+        }
+    }
+
+    /// Returns whether the diagnostics of the file at `path` are published.
+    fn shows_file(&self, path: &Path) -> bool {
+        if let Some(open_files) = &self.open_files {
+            if !open_files.lock().unwrap().contains(path) {
+                return false;
+            }
+        }
+        let relative = path.strip_prefix(&self.project_path).unwrap_or(path);
+        !self
+            .exclude
+            .iter()
+            .any(|pattern| pattern.matches_path(relative) || pattern.matches_path(path))
+    }
+
+    /// Returns the diagnostics of the file at `path` to publish, out of those
+    /// produced by the build.
+    pub fn apply(
+        &self,
+        path: &Path,
+        diagnostics: &[(RichDiagnostic, Vec<Suggestion>)],
+    ) -> Vec<RichDiagnostic> {
+        if !self.shows_file(path) {
+            return vec![];
+        }
+
+        let baselined = match &self.baseline {
+            Some((_, baseline)) => {
+                baseline.baselined(&self.project_path, path, diagnostics.iter().map(|(d, _)| d))
+            }
+            None => vec![false; diagnostics.len()],
+        };
+
+        diagnostics
+            .iter()
+            .zip(baselined)
+            .filter_map(|((diag, _), baselined)| {
+                let code = match &diag.code {
+                    Some(NumberOrString::String(code)) => code.as_str(),
+                    _ => "",
+                };
+                if self.ignore.iter().any(|ignored| ignored == code) {
+                    return None;
+                }
+
+                let mut diag = diag.clone();
+                if baselined {
+                    match self.baseline {
+                        Some((BaselineMode::Hide, _)) => return None,
+                        _ => diag.severity = Some(DiagnosticSeverity::Hint),
+                    }
+                } else if diag.severity != Some(DiagnosticSeverity::Information) {
+                    // Only the primary diagnostics are shown as errors or warnings.
+                    if let Some(level) = self.severity.get(code) {
+                        diag.severity = Some(match level {
+                            DiagnosticLevel::Error => DiagnosticSeverity::Error,
+                            DiagnosticLevel::Warning => DiagnosticSeverity::Warning,
+                            DiagnosticLevel::Information => DiagnosticSeverity::Information,
+                            DiagnosticLevel::Hint => DiagnosticSeverity::Hint,
+                        });
+                    }
+                }
+
+                if !self.show_warnings && diag.severity == Some(DiagnosticSeverity::Warning) {
+                    return None;
+                }
+                Some(diag)
+            })
+            .collect()
+    }
+}
 
 pub struct PostBuildHandler {
     pub analysis: Arc<AnalysisHost>,
     pub analysis_queue: Arc<AnalysisQueue>,
     pub previous_build_results: Arc<Mutex<BuildResults>>,
+    /// The lints of the manifests, published along with the diagnostics of
+    /// the build for the same file.
+    pub manifest_diagnostics: Arc<Mutex<ManifestDiagnostics>>,
+    /// The versions of the changed files this build is for.
+    pub file_versions: HashMap<PathBuf, u64>,
+    /// The versions of the changed files `previous_build_results` are for.
+    pub build_versions: Arc<Mutex<HashMap<PathBuf, u64>>>,
     pub file_to_crates: Arc<Mutex<HashMap<PathBuf, HashSet<Crate>>>>,
     pub project_path: PathBuf,
-    pub show_warnings: bool,
+    pub diagnostic_filter: DiagnosticFilter,
     pub crate_blacklist: CrateBlacklist,
     pub related_information_support: bool,
     pub shown_cargo_error: Arc<AtomicBool>,
     pub active_build_count: Arc<AtomicUsize>,
     pub notifier: Box<dyn DiagnosticsNotifier>,
     pub blocked_threads: Vec<thread::Thread>,
+    /// Timings of the build, filled in by the build queue once it's done.
+    pub build_report: Option<BuildReport>,
+    pub last_build_report: Arc<Mutex<Option<BuildReport>>>,
+    /// If set, the timing report is also written to this directory.
+    pub timings_report_dir: Option<PathBuf>,
     pub _token: JobToken,
 }
 
@@ -118,6 +271,7 @@ impl PostBuildHandler {
         // errors for those files.
         let mut results = self.previous_build_results.lock().unwrap();
         results.values_mut().for_each(Vec::clear);
+        *self.build_versions.lock().unwrap() = self.file_versions.clone();
 
         // cover whole manifest if we haven't any better idea.
         let range = manifest_error_range
@@ -139,7 +293,8 @@ impl PostBuildHandler {
                     message,
                     severity: Some(DiagnosticSeverity::Error),
                     ..Diagnostic::default()
-                },
+                }
+                .into(),
                 vec![],
             )],
         );
@@ -157,6 +312,7 @@ impl PostBuildHandler {
         for values in &mut results.values_mut() {
             values.clear();
         }
+        *self.build_versions.lock().unwrap() = self.file_versions.clone();
 
         let file_diagnostics = messages
             .iter()
@@ -183,6 +339,24 @@ impl PostBuildHandler {
             .unwrap();
     }
 
+    fn store_build_report(&mut self) {
+        let mut report = match self.build_report.take() {
+            Some(report) => report,
+            None => return,
+        };
+        if let Ok(lowering_times) = self.analysis.lowering_times() {
+            report.add_lowering_times(&lowering_times);
+        }
+
+        if let Some(dir) = &self.timings_report_dir {
+            if let Err(e) = report.write_to(dir) {
+                warn!("Failed to write build timings report to {:?}: {}", dir, e);
+            }
+        }
+
+        *self.last_build_report.lock().unwrap() = Some(report);
+    }
+
     fn finalize(mut self) {
         // the end message must be dispatched before waking up
         // the blocked threads, or we might see "done":true message
@@ -199,17 +373,15 @@ impl PostBuildHandler {
     }
 
     fn emit_notifications(&self, build_results: &BuildResults) {
+        let manifest_diagnostics = self.manifest_diagnostics.lock().unwrap();
         for (path, diagnostics) in build_results {
-            let params = PublishDiagnosticsParams {
+            let mut diagnostics = self.diagnostic_filter.apply(path, diagnostics);
+            if let Some(lints) = manifest_diagnostics.get(path) {
+                diagnostics.extend(lints.iter().cloned().map(RichDiagnostic::from));
+            }
+            let params = RichPublishDiagnosticsParams {
                 uri: Url::from_file_path(path).unwrap(),
-                diagnostics: diagnostics
-                    .iter()
-                    .map(|(diag, _)| diag)
-                    .filter(|diag| {
-                        self.show_warnings || diag.severity != Some(DiagnosticSeverity::Warning)
-                    })
-                    .cloned()
-                    .collect(),
+                diagnostics,
             };
 
             self.notifier.notify_publish_diagnostics(params);
@@ -336,7 +508,7 @@ impl Job {
         Job { handler, analysis, cwd, hash }
     }
 
-    fn process(self) {
+    fn process(mut self) {
         // Reload the analysis data.
         trace!(
             "reload analysis: {:?} {:?} {}",
@@ -352,6 +524,93 @@ impl Job {
             self.handler.reload_analysis_from_memory(&self.cwd, self.analysis);
         }
 
+        self.handler.store_build_report();
         self.handler.finalize();
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn filters_diagnostics() {
+        let diagnostic = |code: &str, severity| {
+            let diagnostic = Diagnostic {
+                code: Some(NumberOrString::String(code.to_owned())),
+                severity: Some(severity),
+                ..Diagnostic::default()
+            };
+            (RichDiagnostic::from(diagnostic), vec![])
+        };
+        let diagnostics = [
+            diagnostic("dead_code", DiagnosticSeverity::Warning),
+            diagnostic("missing_docs", DiagnosticSeverity::Warning),
+            diagnostic("E0308", DiagnosticSeverity::Error),
+        ];
+        let codes = |diagnostics: Vec<RichDiagnostic>| -> Vec<(String, DiagnosticSeverity)> {
+            diagnostics
+                .into_iter()
+                .map(|diag| match (&diag.code, diag.severity) {
+                    (Some(NumberOrString::String(code)), Some(severity)) => {
+                        (code.clone(), severity)
+                    }
+                    _ => unreachable!(),
+                })
+                .collect()
+        };
+
+        let project = Path::new("/project");
+        let open_files = Arc::default();
+        let mut config = Config::default();
+        config.diagnostics_ignore = vec!["dead_code".to_owned()];
+        config.diagnostics_exclude = vec!["src/generated/**".to_owned()];
+        config.diagnostics_severity.insert("missing_docs".to_owned(), DiagnosticLevel::Hint);
+        let filter = DiagnosticFilter::new(&config, project, &open_files);
+
+        assert_eq!(
+            codes(filter.apply(&project.join("src/lib.rs"), &diagnostics)),
+            [
+                ("missing_docs".to_owned(), DiagnosticSeverity::Hint),
+                ("E0308".to_owned(), DiagnosticSeverity::Error),
+            ]
+        );
+        assert!(filter.apply(&project.join("src/generated/a.rs"), &diagnostics).is_empty());
+
+        config.diagnostics_open_files_only = true;
+        config.show_warnings = false;
+        let filter = DiagnosticFilter::new(&config, project, &open_files);
+        assert!(filter.apply(&project.join("src/lib.rs"), &diagnostics).is_empty());
+        open_files.lock().unwrap().insert(project.join("src/lib.rs"));
+        assert_eq!(filter.apply(&project.join("src/lib.rs"), &diagnostics).len(), 2);
+
+        let mut results = BuildResults::new();
+        results.insert(
+            project.join("src/lib.rs"),
+            vec![
+                diagnostic("dead_code", DiagnosticSeverity::Warning),
+                diagnostic("missing_docs", DiagnosticSeverity::Warning),
+            ],
+        );
+        let baseline = Arc::new(Baseline::from_results(&results, project));
+        let mut filter = DiagnosticFilter::new(&Config::default(), project, &open_files);
+        filter.severity.insert("dead_code".to_owned(), DiagnosticLevel::Error);
+        filter.baseline = Some((BaselineMode::Hint, Arc::clone(&baseline)));
+        // The second `dead_code` warning is new.
+        let diagnostics = [
+            diagnostic("dead_code", DiagnosticSeverity::Warning),
+            diagnostic("dead_code", DiagnosticSeverity::Warning),
+            diagnostic("E0308", DiagnosticSeverity::Error),
+        ];
+        assert_eq!(
+            codes(filter.apply(&project.join("src/lib.rs"), &diagnostics)),
+            [
+                ("dead_code".to_owned(), DiagnosticSeverity::Hint),
+                ("dead_code".to_owned(), DiagnosticSeverity::Error),
+                ("E0308".to_owned(), DiagnosticSeverity::Error),
+            ]
+        );
+        filter.baseline = Some((BaselineMode::Hide, baseline));
+        assert_eq!(filter.apply(&project.join("src/lib.rs"), &diagnostics).len(), 2);
+    }
+}
@@ -1,9 +1,10 @@
 use std::sync::atomic::{AtomicUsize, Ordering};
 
+use crate::lsp_data::{RichPublishDiagnostics, RichPublishDiagnosticsParams};
 use crate::server::{Notification, Output};
 use lazy_static::lazy_static;
-use lsp_types::notification::{Progress, PublishDiagnostics, ShowMessage};
-use lsp_types::{MessageType, ProgressParams, PublishDiagnosticsParams, ShowMessageParams};
+use lsp_types::notification::{Progress, ShowMessage};
+use lsp_types::{MessageType, ProgressParams, ShowMessageParams};
 
 /// Communication of build progress back to the client.
 pub trait ProgressNotifier: Send {
@@ -24,7 +25,7 @@ pub enum ProgressUpdate {
 // is not object-safe).
 pub trait DiagnosticsNotifier: Send {
     fn notify_begin_diagnostics(&self);
-    fn notify_publish_diagnostics(&self, _: PublishDiagnosticsParams);
+    fn notify_publish_diagnostics(&self, _: RichPublishDiagnosticsParams);
     fn notify_error_diagnostics(&self, msg: String);
     fn notify_end_diagnostics(&self);
 }
@@ -105,8 +106,8 @@ impl<O: Output> DiagnosticsNotifier for BuildDiagnosticsNotifier<O> {
         let params = self.progress_params.clone();
         self.out.notify(Notification::<Progress>::new(params));
     }
-    fn notify_publish_diagnostics(&self, params: PublishDiagnosticsParams) {
-        self.out.notify(Notification::<PublishDiagnostics>::new(params));
+    fn notify_publish_diagnostics(&self, params: RichPublishDiagnosticsParams) {
+        self.out.notify(Notification::<RichPublishDiagnostics>::new(params));
     }
     fn notify_error_diagnostics(&self, message: String) {
         self.out.notify(Notification::<ShowMessage>::new(ShowMessageParams {
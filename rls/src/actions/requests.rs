@@ -1,36 +1,52 @@
 //! Requests that the RLS can respond to.
 
-use std::collections::HashMap;
-use std::path::Path;
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
 use std::sync::atomic::Ordering;
 
 use itertools::Itertools;
 use jsonrpc_core::types::ErrorCode;
+use lazy_static::lazy_static;
 use log::{debug, trace, warn};
-use rls_analysis::SymbolQuery;
+use regex::Regex;
+use rls_analysis::{Def, DefKind, SymbolQuery};
 use rls_data as data;
 use rls_span as span;
 use rls_vfs::FileContents;
-use rustfmt_nightly::{Edition as RustfmtEdition, FileLines, FileName, Range as RustfmtRange};
+use rustfmt_nightly::{
+    Edition as RustfmtEdition, FileLines, FileName, NewlineStyle, Range as RustfmtRange,
+};
 use serde_derive::{Deserialize, Serialize};
 use url::Url;
 
+use crate::actions::attributes;
+use crate::actions::baseline::Baseline;
+use crate::actions::codegen::{self, ExtractedFunction, ExtractedLocal, GenericParam, PassBy};
+use crate::actions::completion::{self, Locality};
+use crate::actions::diagnostics::Suggestion;
 use crate::actions::hover;
+use crate::actions::imports::{self, ImportGranularity, ImportPath, Visibility};
+use crate::actions::manifest;
 use crate::actions::run::collect_run_actions;
+use crate::actions::snippets;
 use crate::actions::InitActionContext;
-use crate::build::Edition;
+use crate::build::{BuildReport, Edition};
 use crate::lsp_data;
 use crate::lsp_data::request::ApplyWorkspaceEdit;
 pub use crate::lsp_data::request::{
     CodeActionRequest as CodeAction, CodeLensRequest, Completion,
     DocumentHighlightRequest as DocumentHighlight, DocumentSymbolRequest as Symbols,
     ExecuteCommand, Formatting, GotoDefinition as Definition, GotoImplementation as Implementation,
-    HoverRequest as Hover, RangeFormatting, References, Rename,
-    ResolveCompletionItem as ResolveCompletion, WorkspaceSymbol,
+    HoverRequest as Hover, PrepareRenameRequest as PrepareRename, RangeFormatting, References,
+    Rename, ResolveCompletionItem as ResolveCompletion, WorkspaceSymbol,
 };
+pub use crate::lsp_data::BuildReportRequest;
 use crate::lsp_data::*;
 use crate::server;
-use crate::server::{Ack, Output, Request, RequestAction, ResponseError, ResponseWithMessage};
+use crate::server::{
+    Ack, Notification, Output, Request, RequestAction, ResponseError, ResponseWithMessage,
+    ShowMessage,
+};
 
 /// The result of a deglob action for a single wildcard import.
 ///
@@ -44,6 +60,16 @@ pub struct DeglobResult {
     pub new_text: String,
 }
 
+/// The argument of the `rls.fixAll` command.
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub struct FixAllParams {
+    /// The file whose suggestions to apply, or `None` for the whole workspace.
+    pub uri: Option<Url>,
+    /// The lints (or error codes) whose suggestions to apply, or `None` for
+    /// all of them.
+    pub lints: Option<Vec<String>>,
+}
+
 impl RequestAction for WorkspaceSymbol {
     type Response = Vec<SymbolInformation>;
 
@@ -127,6 +153,12 @@ impl RequestAction for Hover {
         ctx: InitActionContext,
         params: Self::Params,
     ) -> Result<Self::Response, ResponseError> {
+        let file_path = parse_file_path!(&params.text_document.uri, "hover")?;
+        if manifest::is_manifest(&file_path) {
+            return manifest::hover(&ctx, &file_path, params.position)
+                .map_or_else(Self::fallback_response, Ok);
+        }
+
         let tooltip = hover::tooltip(&ctx, &params)?;
 
         Ok(lsp_data::Hover {
@@ -180,9 +212,22 @@ impl RequestAction for Definition {
     ) -> Result<Self::Response, ResponseError> {
         // Save-analysis thread.
         let file_path = parse_file_path!(&params.text_document.uri, "goto_def")?;
+        if manifest::is_manifest(&file_path) {
+            return Ok(manifest::definition(&ctx, &file_path, params.position)
+                .into_iter()
+                .collect());
+        }
         let span = ctx.convert_pos_to_span(file_path.clone(), params.position);
 
         if let Ok(out) = ctx.analysis.goto_def(&span) {
+            // Jump to the impl of a trait method which is called, if any.
+            let impl_method = ctx
+                .analysis
+                .id(&span)
+                .and_then(|id| ctx.analysis.get_def(id))
+                .ok()
+                .and_then(|def| hover::impl_method(&ctx, &span, &def));
+            let out = impl_method.map_or(out, |def| def.span);
             let result = vec![ls_util::rls_to_location(&out)];
             trace!("goto_def (compiler): {:?}", result);
             Ok(result)
@@ -246,39 +291,154 @@ impl RequestAction for Completion {
         ctx: InitActionContext,
         params: Self::Params,
     ) -> Result<Self::Response, ResponseError> {
-        if !ctx.config.lock().unwrap().racer_completion {
+        let (native_completion, racer_completion) = {
+            let config = ctx.config.lock().unwrap();
+            (config.native_completion, config.racer_completion)
+        };
+        if !native_completion && !racer_completion {
             return Self::fallback_response();
         }
 
         let file_path =
             parse_file_path!(&params.text_document_position.text_document.uri, "complete")?;
+        let position = params.text_document_position.position;
 
-        let cache = ctx.racer_cache();
-        let session = ctx.racer_session(&cache);
-
-        let location = pos_to_racer_location(params.text_document_position.position);
-        let results = racer::complete_from_file(&file_path, location, &session);
-        let is_use_stmt = racer::is_use_stmt(&file_path, location, &session);
-
-        let code_completion_has_snippet_support =
-            ctx.client_capabilities.code_completion_has_snippet_support;
-
-        Ok(results
-            .map(|comp| {
-                let mut item = completion_item_from_racer_match(&comp);
-                if is_use_stmt && comp.mtype.is_function() {
-                    item.insert_text = Some(comp.matchstr);
-                } else if code_completion_has_snippet_support {
-                    let snippet = racer::snippet_for_match(&comp, &session);
-                    if !snippet.is_empty() {
-                        item.insert_text = Some(snippet);
-                        item.insert_text_format = Some(InsertTextFormat::Snippet);
-                    }
+        if manifest::is_manifest(&file_path) {
+            return Ok(manifest::complete(&ctx, &file_path, position));
+        }
+        if native_completion {
+            if let Some(items) = attributes::complete(&ctx, &file_path, position) {
+                let items = items.into_iter().map(|item| (item, Locality::SameFile)).collect();
+                return Ok(completion::rank(&ctx, &file_path, position, items));
+            }
+        }
+        let (mut items, mut is_use_stmt) = if native_completion {
+            completion::complete(&ctx, &file_path, position)
+        } else {
+            (vec![], false)
+        };
+        if racer_completion && (items.is_empty() || !ctx.build_results_current(&file_path)) {
+            // The analysis data doesn't know about code typed since the last
+            // build, so add what racer completes, besides the items which
+            // were already completed.
+            let (racer_items, racer_is_use_stmt) = racer_completions(&ctx, &file_path, position);
+            let native: HashSet<_> = items.iter().map(|(item, _)| item.label.clone()).collect();
+            items.extend(racer_items.into_iter().filter(|(item, _)| !native.contains(&item.label)));
+            is_use_stmt |= racer_is_use_stmt;
+        }
+
+        if !is_use_stmt {
+            let existing: Vec<_> = items.iter().map(|(item, _)| item.clone()).collect();
+            let import_items = auto_import_completions(&ctx, &file_path, position, &existing);
+            items.extend(import_items.into_iter().map(|item| (item, Locality::External)));
+        }
+        if ctx.client_capabilities.code_completion_has_snippet_support {
+            let snippet_items = snippets::complete(&ctx, &file_path, position);
+            items.extend(snippet_items.into_iter().map(|item| (item, Locality::SameFile)));
+        }
+        Ok(completion::rank(&ctx, &file_path, position, items))
+    }
+}
+
+/// Completes the code at `position` using racer, returning each item along
+/// with the locality of its definition. Also returns whether the position is in
+/// a `use` statement.
+fn racer_completions(
+    ctx: &InitActionContext,
+    file_path: &Path,
+    position: Position,
+) -> (Vec<(CompletionItem, Locality)>, bool) {
+    let cache = ctx.racer_cache();
+    let session = ctx.racer_session(&cache);
+
+    let location = pos_to_racer_location(position);
+    let results = racer::complete_from_file(file_path, location, &session);
+    let is_use_stmt = racer::is_use_stmt(file_path, location, &session);
+
+    let code_completion_has_snippet_support =
+        ctx.client_capabilities.code_completion_has_snippet_support;
+
+    let items = results
+        .map(|comp| {
+            let mut item = completion_item_from_racer_match(&comp);
+            if is_use_stmt && comp.mtype.is_function() {
+                item.insert_text = Some(comp.matchstr);
+            } else if code_completion_has_snippet_support {
+                let snippet = racer::snippet_for_match(&comp, &session);
+                if !snippet.is_empty() {
+                    item.insert_text = Some(snippet);
+                    item.insert_text_format = Some(InsertTextFormat::Snippet);
                 }
-                item
-            })
-            .collect())
+            }
+            (item, Locality::of(ctx, file_path, &comp.filepath))
+        })
+        .collect();
+    (items, is_use_stmt)
+}
+
+/// Minimum length of the completed name before items which aren't in scope
+/// yet are suggested.
+const AUTO_IMPORT_MIN_PREFIX: usize = 3;
+/// Maximum number of items which aren't in scope yet to suggest.
+const AUTO_IMPORT_COMPLETION_LIMIT: usize = 50;
+/// Definition kinds which are suggested by the auto-import completion.
+const AUTO_IMPORT_COMPLETION_KINDS: &[DefKind] = &[
+    DefKind::Struct,
+    DefKind::Tuple,
+    DefKind::Enum,
+    DefKind::Union,
+    DefKind::Trait,
+    DefKind::Type,
+    DefKind::Function,
+    DefKind::Const,
+    DefKind::Static,
+];
+
+/// Completes names which aren't in scope yet, adding the necessary import as
+/// a side effect of accepting the completion.
+fn auto_import_completions(
+    ctx: &InitActionContext,
+    file_path: &Path,
+    position: Position,
+    existing: &[CompletionItem],
+) -> Vec<CompletionItem> {
+    let text = match ctx.vfs.load_file(file_path) {
+        Ok(FileContents::Text(text)) => text,
+        _ => return vec![],
+    };
+    let offset = match codegen::position_to_offset(&text, position) {
+        Some(offset) => offset,
+        None => return vec![],
+    };
+    let start = completion::identifier_start(&text, offset);
+    let prefix = &text[start..offset];
+    // Only plain names can be imported, not paths, fields or methods.
+    if prefix.len() < AUTO_IMPORT_MIN_PREFIX
+        || text[..start].ends_with("::")
+        || text[..start].ends_with('.')
+    {
+        return vec![];
     }
+
+    let defs = ctx.analysis.matching_defs(prefix).unwrap_or_else(|_| vec![]);
+    let module = completion::module_at(ctx, &text, file_path, offset)
+        .and_then(|id| ctx.analysis.get_def(id).ok())
+        .map(|def| def.qualname);
+    import_candidates(ctx, file_path, module.as_deref(), defs, AUTO_IMPORT_COMPLETION_KINDS)
+        .into_iter()
+        .filter(|(_, def)| !existing.iter().any(|item| item.label == def.name))
+        .filter_map(|(path, def)| {
+            let edit = imports::add_import(&text, &path)?;
+            Some(CompletionItem {
+                label: def.name.clone(),
+                kind: Some(completion_kind_from_def_kind(def.kind)),
+                detail: Some(path.to_string()),
+                additional_text_edits: Some(vec![edit]),
+                ..CompletionItem::default()
+            })
+        })
+        .take(AUTO_IMPORT_COMPLETION_LIMIT)
+        .collect()
 }
 
 impl RequestAction for DocumentHighlight {
@@ -313,6 +473,60 @@ impl RequestAction for DocumentHighlight {
     }
 }
 
+impl RequestAction for PrepareRename {
+    type Response = Option<PrepareRenameResponse>;
+
+    fn fallback_response() -> Result<Self::Response, ResponseError> {
+        Ok(None)
+    }
+
+    fn handle(
+        ctx: InitActionContext,
+        params: Self::Params,
+    ) -> Result<Self::Response, ResponseError> {
+        let file_path = parse_file_path!(&params.text_document.uri, "prepare_rename")?;
+        let text = match ctx.vfs.load_file(&file_path) {
+            Ok(FileContents::Text(text)) => text,
+            _ => return Ok(None),
+        };
+        let (start, end) = match codegen::position_to_offset(&text, params.position)
+            .and_then(|offset| codegen::identifier_at(&text, offset))
+        {
+            Some(ident) => ident,
+            None => return Ok(None),
+        };
+        let range = Range::new(
+            codegen::offset_to_position(&text, start),
+            codegen::offset_to_position(&text, end),
+        );
+        let span = span::Span::from_range(ls_util::range_to_rls(range), file_path);
+
+        let name = &text[start..end];
+        if codegen::is_keyword(name) {
+            return match name {
+                "self" | "Self" | "super" | "crate" => Err(ResponseError::Message(
+                    ErrorCode::InvalidRequest,
+                    format!("cannot rename `{}`", name),
+                )),
+                _ => Ok(None),
+            };
+        }
+
+        // The analysis may be out of date, in which case we leave it to the
+        // rename itself to find out whether the symbol can be renamed.
+        if let Ok(def) = ctx.analysis.id(&span).and_then(|id| ctx.analysis.get_def(id)) {
+            if let Some(reason) = rename_blocker(&ctx, &def, name) {
+                return Err(ResponseError::Message(ErrorCode::InvalidRequest, reason));
+            }
+        }
+
+        Ok(Some(PrepareRenameResponse::RangeWithPlaceholder {
+            range,
+            placeholder: name.to_owned(),
+        }))
+    }
+}
+
 impl RequestAction for Rename {
     type Response = ResponseWithMessage<WorkspaceEdit>;
 
@@ -332,8 +546,9 @@ impl RequestAction for Rename {
         let file_path =
             parse_file_path!(&params.text_document_position.text_document.uri, "rename")?;
         let span = ctx.convert_pos_to_span(file_path, params.text_document_position.position);
+        let new_name = &params.new_name;
 
-        let analysis = ctx.analysis;
+        let analysis = &ctx.analysis;
 
         macro_rules! unwrap_or_fallback {
             ($e: expr, $msg: expr) => {
@@ -346,20 +561,22 @@ impl RequestAction for Rename {
             };
         }
 
+        if !codegen::is_valid_identifier(new_name) {
+            return Ok(ResponseWithMessage::Warn(if codegen::is_keyword(new_name) {
+                format!("Rename failed: `{}` is a keyword", new_name)
+            } else {
+                format!("Rename failed: `{}` is not a valid identifier", new_name)
+            }));
+        }
+
         let id = unwrap_or_fallback!(
             analysis.crate_local_id(&span),
             "Rename failed: no information for symbol"
         );
         let def =
             unwrap_or_fallback!(analysis.get_def(id), "Rename failed: no definition for symbol");
-        if def.name == "self" || def.name == "Self"
-            // FIXME(#578)
-            || def.kind == data::DefKind::Mod
-        {
-            return Ok(ResponseWithMessage::Warn(format!(
-                "Rename failed: cannot rename {}",
-                if def.kind == data::DefKind::Mod { "modules" } else { &def.name }
-            )));
+        if let Some(reason) = rename_blocker(&ctx, &def, &def.name) {
+            return Ok(ResponseWithMessage::Warn(format!("Rename failed: {}", reason)));
         }
 
         let result = unwrap_or_fallback!(
@@ -374,6 +591,22 @@ impl RequestAction for Rename {
             ));
         }
 
+        if let Some(conflict) = rename_conflict(&ctx, id, &def, new_name, &result) {
+            return Ok(ResponseWithMessage::Warn(format!("Rename failed: {}", conflict)));
+        }
+
+        let file_renames = if def.kind == DefKind::Mod {
+            let renames = module_file_renames(&ctx, &def, new_name);
+            if !renames.is_empty() && !ctx.client_capabilities.resource_rename_support {
+                return Ok(ResponseWithMessage::Warn(
+                    "Rename failed: the client does not support renaming module files".to_owned(),
+                ));
+            }
+            renames
+        } else {
+            vec![]
+        };
+
         let mut edits: HashMap<Url, Vec<TextEdit>> = HashMap::new();
 
         for item in &result {
@@ -381,7 +614,7 @@ impl RequestAction for Rename {
             edits
                 .entry(loc.uri)
                 .or_insert_with(Vec::new)
-                .push(TextEdit { range: loc.range, new_text: params.new_name.clone() });
+                .push(TextEdit { range: loc.range, new_text: new_name.clone() });
         }
 
         if !ctx.quiescent.load(Ordering::SeqCst) {
@@ -390,17 +623,232 @@ impl RequestAction for Rename {
             ));
         }
 
+        if file_renames.is_empty() {
+            return Ok(ResponseWithMessage::Response(WorkspaceEdit {
+                changes: Some(edits),
+                document_changes: None,
+            }));
+        }
+
+        // Edit the files before moving them, as the edits refer to the old paths.
+        let operations = edits
+            .into_iter()
+            .map(|(uri, edits)| {
+                DocumentChangeOperation::Edit(TextDocumentEdit {
+                    text_document: VersionedTextDocumentIdentifier { uri, version: None },
+                    edits,
+                })
+            })
+            .chain(file_renames.into_iter().map(|(old_uri, new_uri)| {
+                DocumentChangeOperation::Op(ResourceOp::Rename(RenameFile {
+                    old_uri,
+                    new_uri,
+                    options: None,
+                }))
+            }))
+            .collect();
         Ok(ResponseWithMessage::Response(WorkspaceEdit {
-            changes: Some(edits),
-            document_changes: None,
+            changes: None,
+            document_changes: Some(DocumentChanges::Operations(operations)),
         }))
     }
 }
 
+/// Returns why the definition `def`, referred to as `name`, can't be renamed,
+/// if it can't.
+fn rename_blocker(ctx: &InitActionContext, def: &Def, name: &str) -> Option<String> {
+    if ["self", "Self", "super", "crate"].contains(&name) || def.name == "self" {
+        Some(format!("cannot rename `{}`", name))
+    } else if def.kind == DefKind::Mod && def.name.is_empty() {
+        Some("cannot rename the crate root".to_owned())
+    } else if !def.span.file.starts_with(&ctx.current_project) {
+        Some(format!("`{}` is defined outside of the workspace", def.name))
+    } else {
+        None
+    }
+}
+
+/// Returns why renaming `def` (with the id `id` and the references `refs`) to
+/// `new_name` would change the meaning of the program, if it would.
+fn rename_conflict(
+    ctx: &InitActionContext,
+    id: rls_analysis::Id,
+    def: &Def,
+    new_name: &str,
+    refs: &[span::Span],
+) -> Option<String> {
+    let new_name = new_name.trim_start_matches("r#");
+    let name_space = rls_analysis::name_space_for_def_kind(def.kind);
+
+    // Another definition with the same name in the same scope.
+    if let Some(parent) = def.parent {
+        let clashes = ctx
+            .analysis
+            .for_each_child_def(parent, |child_id, child| {
+                child_id != id
+                    && child.name == new_name
+                    && rls_analysis::name_space_for_def_kind(child.kind) == name_space
+            })
+            .unwrap_or_default();
+        if clashes.into_iter().any(|clash| clash) {
+            let scope = ctx.analysis.get_def(parent).map(|parent| parent.name).unwrap_or_default();
+            return Some(format!("`{}` is already defined in `{}`", new_name, scope));
+        }
+    }
+
+    // Fields, methods, variants and modules are always qualified, so only
+    // variables and free-standing values can be shadowed.
+    match def.kind {
+        DefKind::Local
+        | DefKind::Function
+        | DefKind::Const
+        | DefKind::Static
+        | DefKind::ForeignFunction
+        | DefKind::ForeignStatic => {}
+        _ => return None,
+    }
+
+    let mut refs: Vec<_> = refs.iter().filter(|span| **span != def.span).collect();
+    refs.sort_by(|a, b| a.file.cmp(&b.file));
+    for (file, spans) in &refs.into_iter().group_by(|span| &span.file) {
+        let text = match ctx.vfs.load_file(file) {
+            Ok(FileContents::Text(text)) => text,
+            _ => continue,
+        };
+        let locals = locals_named(ctx, file, &text, new_name);
+        if locals.is_empty() {
+            continue;
+        }
+
+        // A variable with the new name in scope at a reference would shadow
+        // the renamed definition there.
+        for span in spans {
+            let offset =
+                match codegen::position_to_offset(&text, ls_util::rls_to_range(span.range).start) {
+                    Some(offset) => offset,
+                    None => continue,
+                };
+            let in_scope = locals.iter().find(|(local, _)| {
+                *local < offset
+                    && local_scope(&text, *local)
+                        .map_or(false, |(open, close)| open < offset && offset < close)
+            });
+            if let Some((_, local)) = in_scope {
+                return Some(format!(
+                    "`{}` would conflict with the variable `{}` on line {}",
+                    def.name,
+                    new_name,
+                    local.range.row_start.one_indexed().0
+                ));
+            }
+        }
+    }
+
+    // A renamed variable would shadow whatever the new name refers to in its
+    // scope.
+    if def.kind == DefKind::Local {
+        if let Ok(FileContents::Text(text)) = ctx.vfs.load_file(&def.span.file) {
+            let offset =
+                codegen::position_to_offset(&text, ls_util::rls_to_range(def.span.range).start);
+            let scope = offset.and_then(|offset| Some((offset, local_scope(&text, offset)?.1)));
+            if let Some((offset, close)) = scope {
+                if codegen::refers_to(&text[offset + def.name.len()..close], new_name) {
+                    return Some(format!(
+                        "`{}` would shadow `{}` in its scope",
+                        def.name, new_name
+                    ));
+                }
+            }
+        }
+    }
+    None
+}
+
+/// Returns the offsets and spans of the definitions of the locals named `name`
+/// in the file at `file_path`.
+fn locals_named(
+    ctx: &InitActionContext,
+    file_path: &Path,
+    text: &str,
+    name: &str,
+) -> Vec<(usize, span::Span)> {
+    let symbols = ctx.analysis.symbols(file_path).unwrap_or_else(|_| vec![]);
+    symbols
+        .into_iter()
+        .filter(|symbol| symbol.kind == DefKind::Local && symbol.name == name)
+        .filter_map(|symbol| {
+            let range = ls_util::rls_to_range(symbol.span.range);
+            Some((codegen::position_to_offset(text, range.start)?, symbol.span))
+        })
+        .collect()
+}
+
+/// Returns the offsets of the braces delimiting the scope of the local defined
+/// at `def`: the function body for parameters, otherwise the block containing
+/// the definition.
+fn local_scope(text: &str, def: usize) -> Option<(usize, usize)> {
+    if let Some(open) = text[def..].find('{').map(|i| def + i) {
+        let header_start = text[..open].rfind(&[';', '{', '}'][..]).map_or(0, |i| i + 1);
+        if header_start <= def && codegen::is_fn_header(&text[header_start..open]) {
+            return Some((open, codegen::matching_delimiter(text, open)?));
+        }
+    }
+    let open = *codegen::enclosing_blocks(text, def).first()?;
+    Some((open, codegen::matching_delimiter(text, open)?))
+}
+
+/// Returns the files or directories (as pairs of old and new URIs) to move
+/// when renaming the module `def` to `new_name`. Inline modules and modules
+/// with a `#[path]` attribute don't need any.
+fn module_file_renames(ctx: &InitActionContext, def: &Def, new_name: &str) -> Vec<(Url, Url)> {
+    let value = Path::new(def.value.trim());
+    let file = [Some(ctx.current_project.as_path()), ctx.current_project.parent()]
+        .iter()
+        .flatten()
+        .map(|dir| dir.join(value))
+        .chain(Some(value.to_owned()).filter(|value| value.is_absolute()))
+        .find(|file| file.is_file());
+    let file = match file {
+        Some(ref file) if *file != def.span.file => file,
+        _ => return vec![],
+    };
+    let new_name = new_name.trim_start_matches("r#");
+
+    let mut renames = vec![];
+    let mut rename = |old: &Path| {
+        let new = old.with_file_name(format!(
+            "{}{}",
+            new_name,
+            old.extension().map(|ext| format!(".{}", ext.to_string_lossy())).unwrap_or_default()
+        ));
+        if let (Ok(old), Ok(new)) = (Url::from_file_path(old), Url::from_file_path(new)) {
+            renames.push((old, new));
+        }
+    };
+    let dir = file.parent().unwrap_or_else(|| Path::new(""));
+    if file.file_name() == Some("mod.rs".as_ref()) {
+        if dir.file_name() == Some(def.name.as_ref()) {
+            rename(dir);
+        }
+    } else if file.file_stem() == Some(def.name.as_ref()) {
+        rename(file);
+        // Any submodules of `foo.rs` live in `foo/`.
+        let sub_dir = dir.join(&def.name);
+        if sub_dir.is_dir() {
+            rename(&sub_dir);
+        }
+    }
+    renames
+}
+
 #[derive(Debug)]
 pub enum ExecuteCommandResponse {
     /// Response/client request containing workspace edits.
     ApplyEdit(ApplyWorkspaceEditParams),
+    /// Notification of the outcome of the command, shown to the user.
+    ShowMessage(ShowMessageParams),
+    /// The command only affected the server, so there's nothing to send.
+    Done,
 }
 
 impl server::Response for ExecuteCommandResponse {
@@ -414,6 +862,10 @@ impl server::Response for ExecuteCommandResponse {
                 let request = Request::<ApplyWorkspaceEdit>::new(id, params);
                 out.request(request);
             }
+            ExecuteCommandResponse::ShowMessage(params) => {
+                out.notify(Notification::<ShowMessage>::new(params));
+            }
+            ExecuteCommandResponse::Done => {}
         }
 
         // The formal request response is a simple ACK, though the objective
@@ -429,7 +881,9 @@ impl RequestAction for ExecuteCommand {
         Err(ResponseError::Empty)
     }
 
-    /// Currently supports "rls.applySuggestion", "rls.deglobImports".
+    /// Currently supports "rls.applySuggestion", "rls.deglobImports", "rls.deglobWorkspace",
+    /// "rls.organizeImports", "rls.extract", "rls.inline", "rls.generate", "rls.fixAll",
+    /// "rls.createDiagnosticsBaseline", "rls.acceptCompletion".
     fn handle(
         ctx: InitActionContext,
         params: ExecuteCommandParams,
@@ -438,6 +892,22 @@ impl RequestAction for ExecuteCommand {
             apply_suggestion(&params.arguments).map(ExecuteCommandResponse::ApplyEdit)
         } else if params.command.starts_with("rls.deglobImports") {
             apply_deglobs(params.arguments, &ctx).map(ExecuteCommandResponse::ApplyEdit)
+        } else if params.command.starts_with("rls.deglobWorkspace") {
+            apply_deglob_workspace(&ctx).map(ExecuteCommandResponse::ApplyEdit)
+        } else if params.command.starts_with("rls.organizeImports") {
+            apply_organize_imports(&params.arguments, &ctx).map(ExecuteCommandResponse::ApplyEdit)
+        } else if params.command.starts_with("rls.extract") {
+            apply_extract(&params.arguments, &ctx).map(ExecuteCommandResponse::ApplyEdit)
+        } else if params.command.starts_with("rls.inline") {
+            apply_inline(&params.arguments, &ctx).map(ExecuteCommandResponse::ApplyEdit)
+        } else if params.command.starts_with("rls.generate") {
+            apply_generate(&params.arguments, &ctx).map(ExecuteCommandResponse::ApplyEdit)
+        } else if params.command.starts_with("rls.fixAll") {
+            apply_fix_all(&params.arguments, &ctx).map(ExecuteCommandResponse::ApplyEdit)
+        } else if params.command.starts_with("rls.createDiagnosticsBaseline") {
+            create_diagnostics_baseline(&ctx).map(ExecuteCommandResponse::ShowMessage)
+        } else if params.command.starts_with("rls.acceptCompletion") {
+            accept_completion(&params.arguments, &ctx).map(|()| ExecuteCommandResponse::Done)
         } else {
             debug!("Unknown command: {}", params.command);
             Err(ResponseError::Message(ErrorCode::MethodNotFound, "Unknown command".to_owned()))
@@ -445,6 +915,19 @@ impl RequestAction for ExecuteCommand {
     }
 }
 
+/// Records the accepted completion item whose key (see `completion::item_key`)
+/// is in `args`, to rank it higher in the future.
+fn accept_completion(
+    args: &[serde_json::Value],
+    ctx: &InitActionContext,
+) -> Result<(), ResponseError> {
+    let key = args.first().and_then(|arg| arg.as_str()).ok_or_else(|| {
+        ResponseError::Message(ErrorCode::InvalidParams, "Missing completion key".to_owned())
+    })?;
+    ctx.recent_completions.lock().unwrap().record(key);
+    Ok(())
+}
+
 fn apply_suggestion(args: &[serde_json::Value]) -> Result<ApplyWorkspaceEditParams, ResponseError> {
     let location = serde_json::from_value(args[0].clone()).expect("Bad argument");
     let new_text = serde_json::from_value(args[1].clone()).expect("Bad argument");
@@ -481,6 +964,369 @@ fn apply_deglobs(
     Ok(ApplyWorkspaceEditParams { edit })
 }
 
+/// Replaces every glob import in the workspace with the names it actually
+/// imports, except in crates and modules listed in `deglob_exclude`.
+fn apply_deglob_workspace(
+    ctx: &InitActionContext,
+) -> Result<ApplyWorkspaceEditParams, ResponseError> {
+    ctx.quiescent.store(true, Ordering::SeqCst);
+    let globs = ctx.analysis.globs().map_err(|_| ResponseError::Empty)?;
+    let excluded = ctx.config.lock().unwrap().deglob_exclude.clone();
+
+    let mut changes: HashMap<Url, Vec<TextEdit>> = HashMap::new();
+    for (span, module, names) in globs {
+        if !span.file.starts_with(&ctx.current_project) {
+            continue;
+        }
+        if excluded.iter().any(|e| module == *e || module.starts_with(&format!("{}::", e))) {
+            trace!("apply_deglob_workspace: skipping glob in {}", module);
+            continue;
+        }
+
+        let new_text = match names.len() {
+            1 => names[0].clone(),
+            _ => format!("{{{}}}", sort_deglob_str(&names.join(", "))),
+        };
+        let location = ls_util::rls_to_location(&span);
+        changes
+            .entry(location.uri)
+            .or_insert_with(Vec::new)
+            .push(TextEdit { range: location.range, new_text });
+    }
+    trace!("apply_deglob_workspace: {} files changed", changes.len());
+
+    let edit = WorkspaceEdit {
+        changes: if changes.is_empty() { None } else { Some(changes) },
+        document_changes: None,
+    };
+
+    if !ctx.quiescent.load(Ordering::SeqCst) {
+        return Err(ResponseError::Empty);
+    }
+    Ok(ApplyWorkspaceEditParams { edit })
+}
+
+fn apply_organize_imports(
+    args: &[serde_json::Value],
+    ctx: &InitActionContext,
+) -> Result<ApplyWorkspaceEditParams, ResponseError> {
+    let uri: Url = match args.first().map(|arg| serde_json::from_value(arg.clone())) {
+        Some(Ok(uri)) => uri,
+        _ => {
+            return Err(ResponseError::Message(
+                ErrorCode::InvalidParams,
+                "Expected the URI of the file to organize the imports of".to_owned(),
+            ))
+        }
+    };
+    let file_path = parse_file_path!(&uri, "organize_imports")?;
+
+    trace!("apply_organize_imports {:?}", file_path);
+    let edit = match organize_imports_edit(ctx, &file_path) {
+        Some(edit) => make_workspace_edit(Location::new(uri, edit.range), edit.new_text),
+        None => WorkspaceEdit { changes: None, document_changes: None },
+    };
+    Ok(ApplyWorkspaceEditParams { edit })
+}
+
+/// Computes the edit organizing the imports of `file_path`, or `None` if they
+/// are already organized.
+fn organize_imports_edit(ctx: &InitActionContext, file_path: &Path) -> Option<TextEdit> {
+    let text = match ctx.vfs.load_file(file_path) {
+        Ok(FileContents::Text(text)) => text,
+        _ => return None,
+    };
+    // The ranges of the last build only apply to the text it was for.
+    let current = ctx.build_results_current(file_path);
+    let unused: Vec<_> = ctx
+        .previous_build_results
+        .lock()
+        .unwrap()
+        .get(file_path)
+        .filter(|_| current)
+        .map(|results| {
+            results
+                .iter()
+                .filter(|(diag, _)| match &diag.code {
+                    Some(NumberOrString::String(code)) => code == "unused_imports",
+                    _ => false,
+                })
+                .map(|(diag, _)| diag.range)
+                .collect()
+        })
+        .unwrap_or_default();
+
+    let mut config = ctx.fmt_config().get_rustfmt_config().clone();
+    let granularity = ImportGranularity::from_rustfmt(&config.imports_granularity().to_string());
+    let mut edit = imports::organize_imports(&text, &unused, granularity)?;
+
+    // Let rustfmt take care of wrapping long declarations.
+    config.set().newline_style(NewlineStyle::Unix);
+    match ctx.formatter().format(edit.new_text.clone(), config) {
+        Ok(formatted) => edit.new_text = formatted.trim_end().to_owned(),
+        Err(e) => debug!("organize_imports: couldn't format imports: {:?}", e),
+    }
+
+    let (start, end) = (edit.range.start.line as usize, edit.range.end.line as usize);
+    let current = text.lines().skip(start).take(end - start + 1).join("\n");
+    if current == edit.new_text {
+        None
+    } else {
+        Some(edit)
+    }
+}
+
+/// Creates a `CodeAction` organizing the imports of the file if the requested
+/// range touches them. The edit is only computed once the command is executed.
+/// The result is appended to `code_actions_result`.
+fn make_organize_imports_action(
+    params: &<CodeAction as lsp_data::request::Request>::Params,
+    file_path: &Path,
+    ctx: &InitActionContext,
+    code_actions_result: &mut <CodeAction as RequestAction>::Response,
+) {
+    let text = match ctx.vfs.load_file(file_path) {
+        Ok(FileContents::Text(text)) => text,
+        _ => return,
+    };
+    match imports::import_block_range(&text) {
+        Some(range) if range.overlaps(&params.range) => {
+            code_actions_result.push(Command {
+                title: "Organize imports".to_owned(),
+                command: format!("rls.organizeImports-{}", ctx.pid),
+                arguments: Some(vec![serde_json::to_value(&params.text_document.uri).unwrap()]),
+            });
+        }
+        _ => {}
+    }
+}
+
+/// Applies every machine-applicable compiler suggestion of the last build, in
+/// one file or in the whole workspace, optionally only for some lints.
+fn apply_fix_all(
+    args: &[serde_json::Value],
+    ctx: &InitActionContext,
+) -> Result<ApplyWorkspaceEditParams, ResponseError> {
+    let params: FixAllParams = match args.first().map(|arg| serde_json::from_value(arg.clone())) {
+        Some(Ok(params)) => params,
+        Some(Err(_)) => {
+            return Err(ResponseError::Message(
+                ErrorCode::InvalidParams,
+                "Expected the file and lints to fix".into(),
+            ))
+        }
+        None => FixAllParams::default(),
+    };
+    let file_path = match &params.uri {
+        Some(uri) => Some(parse_file_path!(uri, "fix_all")?),
+        None => None,
+    };
+
+    trace!("apply_fix_all {:?}", params);
+    let changes = fix_all_edits(
+        ctx,
+        file_path.as_ref().map(PathBuf::as_path),
+        params.lints.as_ref().map(Vec::as_slice),
+    );
+    let edit = WorkspaceEdit {
+        changes: if changes.is_empty() { None } else { Some(changes) },
+        document_changes: None,
+    };
+    Ok(ApplyWorkspaceEditParams { edit })
+}
+
+/// Records the warnings of the last build in the diagnostics baseline file of
+/// the project.
+fn create_diagnostics_baseline(
+    ctx: &InitActionContext,
+) -> Result<ShowMessageParams, ResponseError> {
+    let path = ctx.current_project.join(&ctx.config.lock().unwrap().diagnostics_baseline_file);
+    let baseline = {
+        let results = ctx.previous_build_results.lock().unwrap();
+        Baseline::from_results(&results, &ctx.current_project)
+    };
+    trace!("create_diagnostics_baseline: {} warnings", baseline.warnings.len());
+
+    baseline.save(&path).map_err(|e| {
+        ResponseError::Message(
+            ErrorCode::InternalError,
+            format!("Couldn't write the diagnostics baseline to {}: {}", path.display(), e),
+        )
+    })?;
+    Ok(ShowMessageParams {
+        typ: MessageType::Info,
+        message: format!(
+            "Recorded {} warnings in the diagnostics baseline {}",
+            baseline.warnings.len(),
+            path.display()
+        ),
+    })
+}
+
+/// Returns the code of `diagnostic`, or an empty string if it has none.
+fn diagnostic_code(diagnostic: &Diagnostic) -> &str {
+    match &diagnostic.code {
+        Some(NumberOrString::String(code)) => code.as_str(),
+        _ => "",
+    }
+}
+
+/// Returns whether applying both `a` and `b` would be ambiguous, i.e., they
+/// replace overlapping text or insert at the same position.
+fn edits_overlap(a: &TextEdit, b: &TextEdit) -> bool {
+    (a.range.start < b.range.end && b.range.start < a.range.end)
+        || (a.range == b.range && a.range.start == a.range.end)
+}
+
+/// Collects the machine-applicable suggestions of the last build for
+/// `file_path` (or for every file of the workspace) and diagnostics with one
+/// of the codes in `lints` (or any code). Files changed since the build are
+/// skipped. The edits of a diagnostic's suggestions are kept or dropped as a
+/// whole: duplicates of earlier ones, and ones overlapping earlier ones, are
+/// dropped.
+fn fix_all_edits(
+    ctx: &InitActionContext,
+    file_path: Option<&Path>,
+    lints: Option<&[String]>,
+) -> HashMap<Url, Vec<TextEdit>> {
+    let results = ctx.previous_build_results.lock().unwrap();
+    let mut changes = HashMap::new();
+    for (path, results) in results.iter() {
+        if file_path.map_or(!path.starts_with(&ctx.current_project), |file_path| path != file_path)
+            || !ctx.build_results_current(path)
+        {
+            continue;
+        }
+        let mut fixes: Vec<Vec<TextEdit>> = results
+            .iter()
+            .filter(|(diag, _)| {
+                let code = diagnostic_code(diag);
+                lints.map_or(true, |lints| lints.iter().any(|lint| lint == code))
+            })
+            .map(|(_, suggestions)| {
+                let mut edits: Vec<_> = suggestions
+                    .iter()
+                    .filter(|suggestion| suggestion.machine_applicable)
+                    .map(|suggestion| TextEdit {
+                        range: suggestion.range,
+                        new_text: suggestion.new_text.clone(),
+                    })
+                    .collect();
+                edits.sort_by_key(|edit| (edit.range.start, edit.range.end));
+                edits.dedup();
+                edits
+            })
+            .filter(|edits| !edits.is_empty())
+            .collect();
+        let edits = merge_fixes(fixes);
+        if edits.is_empty() {
+            continue;
+        }
+        if let Ok(uri) = Url::from_file_path(path) {
+            changes.insert(uri, edits);
+        }
+    }
+    changes
+}
+
+/// Merges the edits of `fixes`, each of which is kept or dropped as a whole.
+/// Fixes are dropped if they overlap earlier ones (by position).
+fn merge_fixes(mut fixes: Vec<Vec<TextEdit>>) -> Vec<TextEdit> {
+    fixes.sort_by_key(|edits| (edits[0].range.start, edits[0].range.end));
+
+    let mut edits: Vec<TextEdit> = vec![];
+    for fix in fixes {
+        // Edits equal to ones already kept (e.g., of a diagnostic reported for
+        // several spans) are fine, overlapping ones are not.
+        let new: Vec<_> = fix.into_iter().filter(|edit| !edits.contains(edit)).collect();
+        let conflicts = new
+            .iter()
+            .enumerate()
+            .any(|(i, edit)| edits.iter().chain(&new[..i]).any(|other| edits_overlap(edit, other)));
+        if !conflicts {
+            edits.extend(new);
+        }
+    }
+    edits.sort_by_key(|edit| (edit.range.start, edit.range.end));
+    edits
+}
+
+/// Creates `CodeAction`s applying all the machine-applicable compiler
+/// suggestions in the file (for the lint at the cursor, or for all of them)
+/// or in the workspace. The edits are only collected once the command is
+/// executed, so the actions are offered based on the number of fixable
+/// diagnostics. The results are appended to `code_actions_result`.
+fn make_fix_all_actions(
+    params: &<CodeAction as lsp_data::request::Request>::Params,
+    file_path: &Path,
+    ctx: &InitActionContext,
+    code_actions_result: &mut <CodeAction as RequestAction>::Response,
+) {
+    if !ctx.build_results_current(file_path) {
+        return;
+    }
+    let mut command = |title: String, args: FixAllParams| {
+        code_actions_result.push(Command {
+            title,
+            command: format!("rls.fixAll-{}", ctx.pid),
+            arguments: Some(vec![serde_json::to_value(&args).unwrap()]),
+        });
+    };
+    let uri = &params.text_document.uri;
+    let fixable = |(_, suggestions): &&(RichDiagnostic, Vec<Suggestion>)| {
+        suggestions.iter().any(|suggestion| suggestion.machine_applicable)
+    };
+
+    let results = ctx.previous_build_results.lock().unwrap();
+    let file_results = match results.get(file_path) {
+        Some(file_results) => file_results,
+        None => return,
+    };
+    // The lints of the fixable diagnostics at the cursor.
+    let lints: Vec<&str> = file_results
+        .iter()
+        .filter(fixable)
+        .filter(|(diag, _)| diag.range.overlaps(&params.range))
+        .map(|(diag, _)| diagnostic_code(diag))
+        .filter(|code| !code.is_empty())
+        .unique()
+        .collect();
+    for lint in lints {
+        let count = file_results
+            .iter()
+            .filter(fixable)
+            .filter(|(diag, _)| diagnostic_code(diag) == lint)
+            .count();
+        if count > 1 {
+            command(
+                format!("Fix all `{}` issues in this file", lint),
+                FixAllParams { uri: Some(uri.clone()), lints: Some(vec![lint.to_owned()]) },
+            );
+        }
+    }
+
+    let file_fixes = file_results.iter().filter(fixable).count();
+    if file_fixes > 1 {
+        command(
+            "Fix all auto-fixable issues in this file".to_owned(),
+            FixAllParams { uri: Some(uri.clone()), lints: None },
+        );
+    }
+    // Finding out whether there's more to fix elsewhere is enough.
+    let elsewhere = results
+        .iter()
+        .filter(|(path, _)| *path != file_path && path.starts_with(&ctx.current_project))
+        .flat_map(|(_, path_results)| path_results.iter().filter(fixable))
+        .take(2)
+        .count();
+    if elsewhere > 0 && file_fixes + elsewhere > 1 {
+        command(
+            "Fix all auto-fixable issues in the workspace".to_owned(),
+            FixAllParams { uri: None, lints: None },
+        );
+    }
+}
+
 /// Creates `CodeAction`s for fixes suggested by the compiler.
 /// The results are appended to `code_actions_result`.
 fn make_suggestion_fix_actions(
@@ -509,30 +1355,458 @@ fn make_suggestion_fix_actions(
     }
 }
 
-/// Creates `CodeAction`s for performing deglobbing when a wildcard import is found.
-/// The results are appended to `code_actions_result`.
-fn make_deglob_actions(
-    params: &<CodeAction as lsp_data::request::Request>::Params,
-    file_path: &Path,
-    ctx: &InitActionContext,
-    code_actions_result: &mut <CodeAction as RequestAction>::Response,
-) {
-    // Search for a glob in the line.
-    if let Ok(line) = ctx.vfs.load_line(file_path, ls_util::range_to_rls(params.range).row_start) {
-        let span = Location::new(params.text_document.uri.clone(), params.range);
+/// Maximum number of import candidates offered for a single unresolved name.
+const AUTO_IMPORT_ACTION_LIMIT: usize = 10;
+
+/// Returns the name reported as unresolved by `diagnostic`, if it's one of the
+/// errors which can be fixed by importing the name, along with the kinds of
+/// definitions the name may refer to.
+fn unresolved_name(diagnostic: &Diagnostic) -> Option<(&str, &'static [DefKind])> {
+    const TYPES: &[DefKind] = &[
+        DefKind::Struct,
+        DefKind::Tuple,
+        DefKind::Enum,
+        DefKind::Union,
+        DefKind::Trait,
+        DefKind::Type,
+    ];
+    const VALUES: &[DefKind] =
+        &[DefKind::Function, DefKind::Const, DefKind::Static, DefKind::Struct, DefKind::Tuple];
+    const PATH_ROOTS: &[DefKind] = &[
+        DefKind::Struct,
+        DefKind::Tuple,
+        DefKind::Enum,
+        DefKind::Union,
+        DefKind::Trait,
+        DefKind::Type,
+        DefKind::Mod,
+    ];
+
+    let kinds = match &diagnostic.code {
+        // cannot find type `{}` in this scope
+        Some(NumberOrString::String(code)) if code == "E0412" => TYPES,
+        // cannot find value `{}` in this scope
+        Some(NumberOrString::String(code)) if code == "E0425" => VALUES,
+        // failed to resolve: use of undeclared type or module `{}`
+        Some(NumberOrString::String(code)) if code == "E0433" => PATH_ROOTS,
+        _ => return None,
+    };
+    let name = diagnostic.message.lines().next()?.split('`').nth(1)?;
+    Some((name, kinds))
+}
 
-        // For all indices that are a `*`, check if we can deglob them.
-        // This handles badly-formatted text containing multiple `use`s in one line.
-        let deglob_results: Vec<_> = line
-            .char_indices()
-            .filter(|&(_, chr)| chr == '*')
-            .filter_map(|(index, _)| {
-                // Map the indices to `Span`s.
-                let mut span = ls_util::location_to_rls(&span).unwrap();
-                span.range.col_start = span::Column::new_zero_indexed(index as u32);
-                span.range.col_end = span::Column::new_zero_indexed(index as u32 + 1);
+/// Returns the path under which `qualname` can be imported from a file of one
+/// of `crate_names`, of the given `edition`, if it's importable at all (e.g.
+/// not an associated item or an item local to a function body).
+fn import_path(qualname: &str, crate_names: &[String], edition: Edition) -> Option<ImportPath> {
+    let mut path = ImportPath::new(qualname);
+    let is_ident = |s: &String| !s.is_empty() && s.chars().all(|c| c.is_alphanumeric() || c == '_');
+    if path.segments.len() < 2 || !path.segments.iter().all(is_ident) {
+        return None;
+    }
+    if crate_names.contains(&path.segments[0]) {
+        // Paths in `use` declarations are relative to the crate root in 2015.
+        if edition == Edition::Edition2015 {
+            path.segments.remove(0);
+        } else {
+            path.segments[0] = "crate".to_owned();
+        }
+    }
+    Some(path)
+}
 
-                // Load the deglob type information.
+/// Filters `defs` down to those of `kinds` which can be imported into the
+/// module `module` (the qualified name of the module at the import, if known)
+/// of `file_path`, ranked by crate locality (the current crate, then other
+/// workspace crates, then dependencies).
+fn import_candidates(
+    ctx: &InitActionContext,
+    file_path: &Path,
+    module: Option<&str>,
+    defs: Vec<Def>,
+    kinds: &[DefKind],
+) -> Vec<(ImportPath, Def)> {
+    let (crate_names, edition) = match ctx.file_to_crates.lock().unwrap().get(file_path) {
+        Some(crates) => (
+            crates.iter().map(|krate| krate.name.clone()).collect(),
+            crates.iter().map(|krate| krate.edition).next().unwrap_or(Edition::Edition2018),
+        ),
+        None => (vec![], Edition::Edition2018),
+    };
+
+    // Save-analysis data doesn't record visibilities, so read them from the
+    // declaration in front of the name of the item.
+    let visibility = |def: &Def| {
+        ctx.vfs
+            .load_line(&def.span.file, def.span.range.row_start)
+            .map(|line| {
+                let header: String =
+                    line.chars().take(def.span.range.col_start.0 as usize).collect();
+                imports::declared_visibility(&header)
+            })
+            .unwrap_or(Visibility::Private)
+    };
+    // Whether the items of the module `scope` and its descendants include the
+    // importing module.
+    let module = module.map(|module| module.trim_end_matches("::"));
+    let in_scope = |scope: Option<&str>| match (module, scope) {
+        (Some(module), Some(scope)) => {
+            module == scope || module.starts_with(&format!("{}::", scope))
+        }
+        _ => false,
+    };
+
+    let mut candidates: Vec<_> = defs
+        .into_iter()
+        .filter(|def| kinds.contains(&def.kind))
+        // The qualified names of std items point to where they're defined rather
+        // than where they're re-exported (e.g. `std::collections::hash::map`),
+        // and rustc already suggests the right paths for those anyway.
+        .filter(|def| !def.distro_crate)
+        .filter_map(|def| {
+            let path = import_path(&def.qualname, &crate_names, edition)?;
+            let krate = def.qualname.split("::").next().unwrap_or_default();
+            let locality = if crate_names.iter().any(|name| name == krate) {
+                let item_module = def.qualname.rsplitn(2, "::").nth(1);
+                let visible = match visibility(&def) {
+                    Visibility::Public | Visibility::Crate => true,
+                    // Usually `pub(super)`.
+                    Visibility::Restricted => {
+                        in_scope(item_module.and_then(|m| m.rsplitn(2, "::").nth(1)))
+                    }
+                    Visibility::Private => in_scope(item_module),
+                };
+                if !visible {
+                    return None;
+                }
+                0
+            } else if def.span.file.starts_with(&ctx.current_project) {
+                if visibility(&def) != Visibility::Public {
+                    return None;
+                }
+                1
+            } else {
+                // Dependencies are only analyzed for their public items.
+                2
+            };
+            Some((locality, path, def))
+        })
+        .collect();
+    candidates.sort_by(|(a_rank, a_path, _), (b_rank, b_path, _)| {
+        a_rank.cmp(b_rank).then_with(|| imports::compare_paths(a_path, b_path))
+    });
+    candidates.dedup_by(|(_, a, _), (_, b, _)| a == b);
+    candidates.into_iter().map(|(_, path, def)| (path, def)).collect()
+}
+
+/// Creates `CodeAction`s importing names reported as unresolved by the compiler.
+/// The results are appended to `code_actions_result`.
+fn make_auto_import_actions(
+    params: &<CodeAction as lsp_data::request::Request>::Params,
+    file_path: &Path,
+    ctx: &InitActionContext,
+    code_actions_result: &mut <CodeAction as RequestAction>::Response,
+) {
+    let unresolved: Vec<(String, &[DefKind])> =
+        match ctx.previous_build_results.lock().unwrap().get(file_path) {
+            Some(results) => results
+                .iter()
+                .filter(|(diag, _)| diag.range.overlaps(&params.range))
+                .filter_map(|(diag, _)| unresolved_name(diag))
+                .map(|(name, kinds)| (name.to_owned(), kinds))
+                .unique_by(|(name, _)| name.clone())
+                .collect(),
+            None => return,
+        };
+    if unresolved.is_empty() {
+        return;
+    }
+
+    let text = match ctx.vfs.load_file(file_path) {
+        Ok(FileContents::Text(text)) => text,
+        _ => return,
+    };
+
+    let module = codegen::position_to_offset(&text, params.range.start)
+        .and_then(|offset| completion::module_at(ctx, &text, file_path, offset))
+        .and_then(|id| ctx.analysis.get_def(id).ok())
+        .map(|def| def.qualname);
+
+    for (name, kinds) in unresolved {
+        let defs = ctx.analysis.matching_defs(&name).unwrap_or_else(|_| vec![]);
+        let defs = defs.into_iter().filter(|def| def.name == name).collect();
+        let candidates = import_candidates(ctx, file_path, module.as_deref(), defs, kinds);
+        for (path, _) in candidates.into_iter().take(AUTO_IMPORT_ACTION_LIMIT) {
+            let edit = match imports::add_import(&text, &path) {
+                Some(edit) => edit,
+                None => continue,
+            };
+            let location = Location::new(params.text_document.uri.clone(), edit.range);
+            code_actions_result.push(Command {
+                title: format!("Import `{}`", path),
+                command: format!("rls.applySuggestion-{}", ctx.pid),
+                arguments: Some(vec![
+                    serde_json::to_value(&location).unwrap(),
+                    serde_json::to_value(&edit.new_text).unwrap(),
+                ]),
+            });
+        }
+    }
+}
+
+/// Returns the names of the items an E0046 diagnostic ("not all trait items
+/// implemented") reports as missing.
+fn missing_trait_items(diagnostic: &Diagnostic) -> Option<Vec<String>> {
+    match &diagnostic.code {
+        Some(NumberOrString::String(code)) if code == "E0046" => {}
+        _ => return None,
+    }
+    let first_line = diagnostic.message.lines().next()?;
+    let missing = &first_line[first_line.find("missing: ")? + "missing: ".len()..];
+    Some(missing.split(", ").map(|name| name.trim_matches('`').to_owned()).collect())
+}
+
+/// Returns the declaration of `def`, preferring the save-analysis signature
+/// and falling back to its source.
+fn def_declaration(ctx: &InitActionContext, def: &Def) -> Option<String> {
+    if let Some(sig) = &def.sig {
+        return Some(sig.text.clone());
+    }
+    let text = match ctx.vfs.load_file(&def.span.file) {
+        Ok(FileContents::Text(text)) => text,
+        _ => return None,
+    };
+    let start = ls_util::rls_to_range(def.span.range).start;
+    codegen::item_declaration(&text, codegen::position_to_offset(&text, start)?)
+}
+
+/// Creates a `CodeAction` adding stubs for the items missing from a trait
+/// impl. The result is appended to `code_actions_result`.
+fn make_missing_trait_items_actions(
+    params: &<CodeAction as lsp_data::request::Request>::Params,
+    file_path: &Path,
+    ctx: &InitActionContext,
+    code_actions_result: &mut <CodeAction as RequestAction>::Response,
+) {
+    let missing: Vec<(Range, Vec<String>)> =
+        match ctx.previous_build_results.lock().unwrap().get(file_path) {
+            Some(results) => results
+                .iter()
+                .filter(|(diag, _)| diag.range.overlaps(&params.range))
+                .filter_map(|(diag, _)| missing_trait_items(diag).map(|names| (diag.range, names)))
+                .collect(),
+            None => return,
+        };
+    if missing.is_empty() {
+        return;
+    }
+
+    let text = match ctx.vfs.load_file(file_path) {
+        Ok(FileContents::Text(text)) => text,
+        _ => return,
+    };
+
+    for (range, names) in missing {
+        let block = match codegen::position_to_offset(&text, range.start)
+            .and_then(|start| codegen::parse_impl_block(&text, start))
+        {
+            Some(block) => block,
+            None => continue,
+        };
+
+        // Find the trait through the reference to it in the impl header.
+        let start = codegen::offset_to_position(&text, block.trait_name_offset);
+        let end =
+            codegen::offset_to_position(&text, block.trait_name_offset + block.trait_name.len());
+        let span = span::Span::from_range(ls_util::range_to_rls(Range::new(start, end)), file_path);
+        let trait_id = match ctx.analysis.id(&span) {
+            Ok(id) => id,
+            Err(_) => continue,
+        };
+        let generic_params: Vec<GenericParam> = ctx
+            .analysis
+            .get_def(trait_id)
+            .ok()
+            .and_then(|def| def_declaration(ctx, &def))
+            .and_then(|decl| {
+                codegen::generic_list_after(&decl, &block.trait_name)
+                    .map(codegen::parse_generic_params)
+            })
+            .unwrap_or_default();
+
+        let mut items = ctx
+            .analysis
+            .for_each_child_def(trait_id, |_, def| def.clone())
+            .unwrap_or_else(|_| vec![]);
+        items.retain(|def| names.contains(&def.name));
+        items.sort_by(|a, b| a.span.range.cmp(&b.span.range));
+
+        let stubs: Vec<_> = items
+            .iter()
+            .filter_map(|def| {
+                let decl = def_declaration(ctx, def)?;
+                let decl = codegen::substitute_generics(&decl, &generic_params, &block.trait_args);
+                codegen::trait_item_stub(def.kind, &def.name, &decl)
+            })
+            .collect();
+        if stubs.is_empty() {
+            continue;
+        }
+
+        let edit = codegen::add_items_edit(&text, (block.open, block.close), &stubs);
+        let location = Location::new(params.text_document.uri.clone(), edit.range);
+        code_actions_result.push(Command {
+            title: format!("Implement missing items of `{}`", block.trait_name),
+            command: format!("rls.applySuggestion-{}", ctx.pid),
+            arguments: Some(vec![
+                serde_json::to_value(&location).unwrap(),
+                serde_json::to_value(&edit.new_text).unwrap(),
+            ]),
+        });
+    }
+}
+
+/// Returns the first pattern an E0004 diagnostic ("non-exhaustive patterns")
+/// reports as not covered.
+fn first_uncovered_pattern(diagnostic: &Diagnostic) -> Option<&str> {
+    match &diagnostic.code {
+        Some(NumberOrString::String(code)) if code == "E0004" => {}
+        _ => return None,
+    }
+    let first_line = diagnostic.message.lines().next()?;
+    let start = first_line.find('`')? + 1;
+    let end = start + first_line[start..].find('`')?;
+    Some(&first_line[start..end])
+}
+
+/// Returns the id and definition of the enum one of whose variants is matched
+/// by `pattern`, e.g., `Foo::Bar(_)`.
+fn enum_of_pattern(ctx: &InitActionContext, pattern: &str) -> Option<(rls_analysis::Id, Def)> {
+    let path =
+        pattern.trim_start_matches('&').split(|c| c == '(' || c == '{' || c == ' ').next()?;
+    let segments: Vec<_> = path.split("::").collect();
+    let (variant, enum_name) = match segments.as_slice() {
+        [.., enum_name, variant] => (*variant, Some(*enum_name)),
+        [variant] => (*variant, None),
+        [] => return None,
+    };
+    let ids = ctx.analysis.search_for_id(variant).ok()?;
+    ids.into_iter()
+        .filter_map(|id| ctx.analysis.get_def(id).ok())
+        .filter(|def| def.kind == DefKind::TupleVariant || def.kind == DefKind::StructVariant)
+        .filter_map(|def| {
+            let id = def.parent?;
+            let def = ctx.analysis.get_def(id).ok()?;
+            Some((id, def))
+        })
+        .find(|(_, def)| {
+            def.kind == DefKind::Enum && enum_name.map_or(true, |name| def.name == name)
+        })
+}
+
+/// Creates `CodeAction`s adding arms for the enum variants a `match` doesn't
+/// cover. The results are appended to `code_actions_result`.
+fn make_missing_match_arms_actions(
+    params: &<CodeAction as lsp_data::request::Request>::Params,
+    file_path: &Path,
+    ctx: &InitActionContext,
+    code_actions_result: &mut <CodeAction as RequestAction>::Response,
+) {
+    // The diagnostics' ranges only apply to the text they were reported for.
+    if !ctx.build_results_current(file_path) {
+        return;
+    }
+    let uncovered: Vec<(Range, String)> =
+        match ctx.previous_build_results.lock().unwrap().get(file_path) {
+            Some(results) => results
+                .iter()
+                .filter(|(diag, _)| diag.range.overlaps(&params.range))
+                .filter_map(|(diag, _)| {
+                    first_uncovered_pattern(diag).map(|pattern| (diag.range, pattern.to_owned()))
+                })
+                .collect(),
+            None => return,
+        };
+    if uncovered.is_empty() {
+        return;
+    }
+
+    let text = match ctx.vfs.load_file(file_path) {
+        Ok(FileContents::Text(text)) => text,
+        _ => return,
+    };
+
+    for (range, pattern) in uncovered {
+        let block = match codegen::position_to_offset(&text, range.start)
+            .and_then(|start| codegen::parse_match_block(&text, start))
+        {
+            Some(block) => block,
+            None => continue,
+        };
+        let (enum_id, enum_def) = match enum_of_pattern(ctx, &pattern) {
+            Some(found) => found,
+            None => continue,
+        };
+
+        let mut variants = ctx
+            .analysis
+            .for_each_child_def(enum_id, |_, def| def.clone())
+            .unwrap_or_else(|_| vec![]);
+        variants
+            .retain(|def| def.kind == DefKind::TupleVariant || def.kind == DefKind::StructVariant);
+        variants.sort_by(|a, b| a.span.range.cmp(&b.span.range));
+        let names: Vec<_> = variants.iter().map(|def| def.name.clone()).collect();
+
+        // The variants of `Option` and `Result` are in the prelude.
+        let prefix = if enum_def.distro_crate && ["Option", "Result"].contains(&&*enum_def.name) {
+            String::new()
+        } else {
+            codegen::variant_prefix(&text, &enum_def.name, &names, &block.patterns)
+        };
+        let missing: Vec<_> = variants
+            .iter()
+            .filter(|def| !codegen::covers_variant(&block.patterns, &def.name))
+            .map(|def| codegen::variant_pattern(def.kind, &prefix, &def.name, &def.value))
+            .collect();
+        if missing.is_empty() {
+            continue;
+        }
+
+        let edit = codegen::add_match_arms_edit(&text, &block, &missing);
+        let location = Location::new(params.text_document.uri.clone(), edit.range);
+        code_actions_result.push(extract_command(
+            ctx,
+            "Add missing match arms",
+            location,
+            edit.new_text,
+        ));
+    }
+}
+
+/// Creates `CodeAction`s for performing deglobbing when a wildcard import is found.
+/// The results are appended to `code_actions_result`.
+fn make_deglob_actions(
+    params: &<CodeAction as lsp_data::request::Request>::Params,
+    file_path: &Path,
+    ctx: &InitActionContext,
+    code_actions_result: &mut <CodeAction as RequestAction>::Response,
+) {
+    // Search for a glob in the line.
+    if let Ok(line) = ctx.vfs.load_line(file_path, ls_util::range_to_rls(params.range).row_start) {
+        let span = Location::new(params.text_document.uri.clone(), params.range);
+
+        // For all indices that are a `*`, check if we can deglob them.
+        // This handles badly-formatted text containing multiple `use`s in one line.
+        let deglob_results: Vec<_> = line
+            .char_indices()
+            .filter(|&(_, chr)| chr == '*')
+            .filter_map(|(index, _)| {
+                // Map the indices to `Span`s.
+                let mut span = ls_util::location_to_rls(&span).unwrap();
+                span.range.col_start = span::Column::new_zero_indexed(index as u32);
+                span.range.col_end = span::Column::new_zero_indexed(index as u32 + 1);
+
+                // Load the deglob type information.
                 ctx.analysis.show_type(&span).ok().map(|ty| (ty, span))
             })
             .map(|(mut deglob_str, span)| {
@@ -564,32 +1838,907 @@ fn make_deglob_actions(
     };
 }
 
-// Ideally we'd use Rustfmt for this, but reparsing is a bit of a pain.
-fn sort_deglob_str(s: &str) -> String {
-    let mut substrings = s.split(',').map(str::trim).collect::<Vec<_>>();
-    substrings.sort_by(|a, b| {
-        use std::cmp::Ordering;
+/// Formats `snippet` with rustfmt, wrapped in a block introduced by `wrapper`
+/// (e.g., `fn f()` for statements) unless it's an item in its own right.
+/// Returns the snippet unchanged if it can't be formatted.
+fn format_snippet(ctx: &InitActionContext, snippet: &str, wrapper: Option<&str>) -> String {
+    let mut config = ctx.fmt_config().get_rustfmt_config().clone();
+    config.set().newline_style(NewlineStyle::Unix);
+    let indent = if config.hard_tabs() { "\t".to_owned() } else { " ".repeat(config.tab_spaces()) };
+
+    let input = match wrapper {
+        Some(wrapper) => format!("{} {{\n{}\n}}\n", wrapper, snippet),
+        None => snippet.to_owned(),
+    };
+    let formatted = match ctx.formatter().format(input, config) {
+        Ok(formatted) => formatted,
+        Err(e) => {
+            debug!("format_snippet: couldn't format {:?}: {:?}", snippet, e);
+            return snippet.to_owned();
+        }
+    };
+    if wrapper.is_none() {
+        return formatted.trim_end().to_owned();
+    }
+    let lines: Vec<_> = formatted.trim_end().lines().collect();
+    if lines.len() < 3 {
+        return snippet.to_owned();
+    }
+    lines[1..lines.len() - 1]
+        .iter()
+        .map(|&line| line.strip_prefix(indent.as_str()).unwrap_or(line))
+        .join("\n")
+}
+
+/// A local variable of a file, with the offsets of its definition and of its
+/// references in the file.
+struct LocalUses {
+    name: String,
+    span: span::Span<span::ZeroIndexed>,
+    def: usize,
+    refs: Vec<usize>,
+    mutable: bool,
+}
+
+fn file_locals(ctx: &InitActionContext, file_path: &Path, text: &str) -> Vec<LocalUses> {
+    let offset = |span: &span::Span<span::ZeroIndexed>| {
+        codegen::position_to_offset(text, ls_util::rls_to_range(span.range).start)
+    };
+    let symbols = ctx.analysis.symbols(file_path).unwrap_or_else(|_| vec![]);
+    symbols
+        .into_iter()
+        .filter(|symbol| symbol.kind == DefKind::Local)
+        .filter_map(|symbol| {
+            let def = offset(&symbol.span)?;
+            let refs = ctx
+                .analysis
+                .find_all_refs_by_id(symbol.id)
+                .unwrap_or_else(|_| vec![])
+                .iter()
+                .filter(|span| span.file == file_path)
+                .filter_map(offset)
+                .filter(|&r| r != def)
+                .collect();
+            let mutable = text[..def].trim_end().ends_with("mut");
+            Some(LocalUses { name: symbol.name, span: symbol.span, def, refs, mutable })
+        })
+        .collect()
+}
+
+/// Returns `name`, or `name` followed by a number if it's already `taken`.
+fn unique_name(name: String, taken: &[&str]) -> String {
+    if !taken.contains(&name.as_str()) {
+        return name;
+    }
+    (2..).map(|i| format!("{}{}", name, i)).find(|n| !taken.contains(&n.as_str())).unwrap()
+}
+
+fn local_type(ctx: &InitActionContext, local: &LocalUses) -> String {
+    match ctx.analysis.show_type(&local.span) {
+        Ok(ty) if !ty.is_empty() => ty,
+        _ => "_".to_owned(),
+    }
+}
+
+fn extract_command(
+    ctx: &InitActionContext,
+    title: &str,
+    location: Location,
+    new_text: String,
+) -> Command {
+    Command {
+        title: title.to_owned(),
+        command: format!("rls.applySuggestion-{}", ctx.pid),
+        arguments: Some(vec![
+            serde_json::to_value(&location).unwrap(),
+            serde_json::to_value(&new_text).unwrap(),
+        ]),
+    }
+}
+
+/// A selection within a function body, without the whitespace around it.
+struct ExtractSelection {
+    start: usize,
+    end: usize,
+    /// The offsets of the braces opening the blocks enclosing the selection,
+    /// innermost first.
+    blocks: Vec<usize>,
+    /// The index in `blocks` of the body of the enclosing function.
+    fn_index: usize,
+}
+
+impl ExtractSelection {
+    fn new(text: &str, range: Range) -> Option<ExtractSelection> {
+        let (start, end) = match (
+            codegen::position_to_offset(text, range.start),
+            codegen::position_to_offset(text, range.end),
+        ) {
+            (Some(start), Some(end)) if start < end => (start, end),
+            _ => return None,
+        };
+
+        // Ignore any whitespace around the selection.
+        let selection = text[start..end].trim();
+        if selection.is_empty() || !codegen::is_balanced(selection) {
+            return None;
+        }
+        let start = start + (text[start..end].len() - text[start..end].trim_start().len());
+        let end = start + selection.len();
+
+        let blocks = codegen::enclosing_blocks(text, start);
+        if codegen::enclosing_blocks(text, end) != blocks {
+            return None;
+        }
+        let fn_index = blocks
+            .iter()
+            .position(|&open| codegen::is_fn_header(codegen::block_header(text, open)))?;
+        Some(ExtractSelection { start, end, blocks, fn_index })
+    }
+}
+
+/// What the selection is extracted into.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ExtractKind {
+    Variable,
+    Function,
+}
 
-        // Algorithm taken from rustfmt (`rustfmt/src/imports.rs`).
+/// The argument of the `rls.extract` command.
+#[derive(Debug, Deserialize, Serialize)]
+pub struct ExtractParams {
+    pub uri: Url,
+    /// The selection to extract.
+    pub range: Range,
+    pub kind: ExtractKind,
+}
+
+/// Returns whether the selected text is an expression which can be bound to a
+/// variable, as far as can be told without the locals it uses.
+fn is_extractable_expression(selection: &str) -> bool {
+    !selection.starts_with("let ") && codegen::find_top_level(selection, ';').is_none()
+}
+
+/// Where the function extracted from a sequence of statements goes.
+struct ExtractTarget {
+    /// Whether the function is a method of the enclosing inherent impl.
+    in_impl: bool,
+    receiver: Option<&'static str>,
+    /// The offset of the `}` closing the enclosing function.
+    fn_close: usize,
+    /// The offset of the `{` opening the item the function follows.
+    anchor: usize,
+}
 
-        let is_upper_snake_case =
-            |s: &str| s.chars().all(|c| c.is_uppercase() || c == '_' || c.is_numeric());
+/// Returns where the function extracted from the selection goes, or `None` if
+/// it isn't a sequence of statements which can be moved into a function.
+fn extract_target(text: &str, sel: &ExtractSelection) -> Option<ExtractTarget> {
+    lazy_static! {
+        static ref CONTROL_FLOW: Regex =
+            Regex::new(r"\b(?:return|break|continue)\b|\?(?:\s|[;.)])").unwrap();
+    }
+
+    let selection = &text[sel.start..sel.end];
+    if !(selection.ends_with(';') || selection.ends_with('}'))
+        || codegen::statement_start(text, sel.start) != sel.start
+        || CONTROL_FLOW.is_match(selection)
+    {
+        return None;
+    }
 
-        // snake_case < CamelCase < UPPER_SNAKE_CASE
-        if a.starts_with(char::is_uppercase) && b.starts_with(char::is_lowercase) {
-            return Ordering::Greater;
+    let fn_close = codegen::matching_delimiter(text, sel.blocks[sel.fn_index])?;
+    let parent_header =
+        sel.blocks.get(sel.fn_index + 1).map(|&open| codegen::block_header(text, open));
+    let in_impl = parent_header.map_or(false, codegen::is_inherent_impl_header);
+    let receiver = codegen::receiver_for(selection);
+    if receiver.is_some() && !in_impl {
+        return None;
+    }
+
+    // Methods of inherent impls get a sibling method, other functions a free
+    // function following the item they're part of.
+    let mut anchor = sel.blocks[sel.fn_index];
+    if !in_impl {
+        for &open in &sel.blocks[sel.fn_index + 1..] {
+            if codegen::is_mod_header(codegen::block_header(text, open)) {
+                break;
+            }
+            anchor = open;
         }
-        if a.starts_with(char::is_lowercase) && b.starts_with(char::is_uppercase) {
-            return Ordering::Less;
+    }
+    Some(ExtractTarget { in_impl, receiver, fn_close, anchor })
+}
+
+/// Returns the edit binding the selected expression to a new variable,
+/// declared just before the statement containing it.
+fn extract_variable(
+    ctx: &InitActionContext,
+    text: &str,
+    sel: &ExtractSelection,
+    locals: &[LocalUses],
+) -> Option<TextEdit> {
+    let (start, end) = (sel.start, sel.end);
+    let selection = &text[start..end];
+    if !is_extractable_expression(selection) {
+        return None;
+    }
+    let stmt = codegen::statement_start(text, start);
+    // Bindings introduced by the statement itself (e.g., closure parameters)
+    // aren't in scope before it.
+    let in_selection = |r: &usize| *r >= start && *r < end;
+    if locals.iter().any(|l| l.def >= stmt && l.def < start && l.refs.iter().any(in_selection)) {
+        return None;
+    }
+
+    let taken: Vec<_> = locals.iter().map(|l| l.name.as_str()).collect();
+    let name = unique_name(codegen::variable_name(selection), &taken);
+    let indent = codegen::indent_at(text, stmt);
+    let binding = format_snippet(ctx, &format!("let {} = {};", name, selection), Some("fn f()"));
+    let new_text = format!(
+        "{}\n{}{}{}",
+        codegen::reindent(&binding, "", indent).trim_start(),
+        indent,
+        &text[stmt..start],
+        name
+    );
+    let range =
+        Range::new(codegen::offset_to_position(text, stmt), codegen::offset_to_position(text, end));
+    Some(TextEdit { range, new_text })
+}
+
+/// Returns the edit moving the selected statements into a new function,
+/// taking the locals they use as parameters and returning the ones they
+/// define for the rest of the enclosing function.
+fn extract_function(
+    ctx: &InitActionContext,
+    text: &str,
+    sel: &ExtractSelection,
+    locals: &[LocalUses],
+) -> Option<TextEdit> {
+    let (start, end) = (sel.start, sel.end);
+    let selection = &text[start..end];
+    let ExtractTarget { in_impl, receiver, fn_close, anchor } = extract_target(text, sel)?;
+    let close = codegen::matching_delimiter(text, anchor)?;
+
+    let mut params = vec![];
+    let mut returns = vec![];
+    for local in locals {
+        let used_inside = local.refs.iter().any(|&r| r >= start && r < end);
+        let used_after = local.refs.iter().any(|&r| r >= end && r < fn_close);
+        if local.def < start && used_inside {
+            let mutated = local.mutable || codegen::mutates(selection, &local.name);
+            let pass_by = match (used_after, mutated) {
+                (true, true) => PassBy::RefMut,
+                (true, false) => PassBy::Ref,
+                (false, mutable) => PassBy::Value { mutable },
+            };
+            let ty = local_type(ctx, local);
+            params.push((local.def, ExtractedLocal { name: local.name.clone(), ty, pass_by }));
+        } else if local.def >= start && local.def < end && used_after {
+            let pass_by = PassBy::Value { mutable: local.mutable };
+            let ty = local_type(ctx, local);
+            returns.push((local.def, ExtractedLocal { name: local.name.clone(), ty, pass_by }));
         }
-        if is_upper_snake_case(a) && !is_upper_snake_case(b) {
-            return Ordering::Greater;
+    }
+    params.sort_by_key(|(def, _)| *def);
+    returns.sort_by_key(|(def, _)| *def);
+
+    // Keep the generics of the enclosing function the new one depends on.
+    let types = params.iter().chain(&returns).map(|(_, local)| local.ty.as_str());
+    let uses = types.chain(Some(selection)).join("\n");
+    let (generics, where_predicates) =
+        codegen::used_generics(codegen::block_header(text, sel.blocks[sel.fn_index]), &uses);
+
+    let function = ExtractedFunction {
+        name: "new_function".to_owned(),
+        receiver,
+        params: params.into_iter().map(|(_, param)| param).collect(),
+        returns: returns.into_iter().map(|(_, local)| local).collect(),
+        generics,
+        where_predicates,
+    };
+    let callee = match (receiver, in_impl) {
+        (Some(_), _) => format!("self.{}", function.name),
+        (None, true) => format!("Self::{}", function.name),
+        (None, false) => function.name.clone(),
+    };
+
+    let body = codegen::reindent(selection, codegen::indent_at(text, start), "");
+    let definition = function.definition(&body);
+    let definition = format_snippet(ctx, &definition, if in_impl { Some("impl X") } else { None });
+    let new_text = format!(
+        "{}{}\n\n{}",
+        function.call(&callee),
+        &text[end..=close],
+        codegen::reindent(&definition, "", codegen::indent_at(text, anchor))
+    );
+    let range = Range::new(
+        codegen::offset_to_position(text, start),
+        codegen::offset_to_position(text, close + 1),
+    );
+    Some(TextEdit { range, new_text })
+}
+
+/// Creates `CodeAction`s extracting the selection into a variable or a
+/// function, if it's within a function body. Finding the locals involved is
+/// left to the execution of the command. The results are appended to
+/// `code_actions_result`.
+fn make_extract_actions(
+    params: &<CodeAction as lsp_data::request::Request>::Params,
+    file_path: &Path,
+    ctx: &InitActionContext,
+    code_actions_result: &mut <CodeAction as RequestAction>::Response,
+) {
+    // The locals are found through the analysis, which only matches the text
+    // it was built from.
+    if params.range.start == params.range.end || !ctx.build_results_current(file_path) {
+        return;
+    }
+    let text = match ctx.vfs.load_file(file_path) {
+        Ok(FileContents::Text(text)) => text,
+        _ => return,
+    };
+    let sel = match ExtractSelection::new(&text, params.range) {
+        Some(sel) => sel,
+        None => return,
+    };
+
+    let mut command = |title: &str, kind| {
+        let args =
+            ExtractParams { uri: params.text_document.uri.clone(), range: params.range, kind };
+        code_actions_result.push(Command {
+            title: title.to_owned(),
+            command: format!("rls.extract-{}", ctx.pid),
+            arguments: Some(vec![serde_json::to_value(&args).unwrap()]),
+        });
+    };
+    if is_extractable_expression(&text[sel.start..sel.end]) {
+        command("Extract into variable", ExtractKind::Variable);
+    }
+    if extract_target(&text, &sel).is_some() {
+        command("Extract into function", ExtractKind::Function);
+    }
+}
+
+fn apply_extract(
+    args: &[serde_json::Value],
+    ctx: &InitActionContext,
+) -> Result<ApplyWorkspaceEditParams, ResponseError> {
+    let invalid = |message: &str| ResponseError::Message(ErrorCode::InvalidParams, message.into());
+    let params: ExtractParams = match args.first().map(|arg| serde_json::from_value(arg.clone())) {
+        Some(Ok(params)) => params,
+        _ => return Err(invalid("Expected the selection to extract")),
+    };
+    let file_path = parse_file_path!(&params.uri, "extract")?;
+    trace!("apply_extract {:?}", params);
+
+    if !ctx.build_results_current(&file_path) {
+        return Err(invalid("The file has changed since it was last built"));
+    }
+    let text = match ctx.vfs.load_file(&file_path) {
+        Ok(FileContents::Text(text)) => text,
+        _ => return Err(invalid("Couldn't read the file")),
+    };
+    let sel = ExtractSelection::new(&text, params.range)
+        .ok_or_else(|| invalid("The selection isn't within a function body"))?;
+
+    let locals = file_locals(ctx, &file_path, &text);
+    let edit = match params.kind {
+        ExtractKind::Variable => extract_variable(ctx, &text, &sel, &locals),
+        ExtractKind::Function => extract_function(ctx, &text, &sel, &locals),
+    }
+    .ok_or_else(|| invalid("The selection can't be extracted"))?;
+    let location = Location::new(params.uri, edit.range);
+    Ok(ApplyWorkspaceEditParams { edit: make_workspace_edit(location, edit.new_text) })
+}
+
+/// The argument of the `rls.inline` command.
+#[derive(Debug, Deserialize, Serialize)]
+pub struct InlineParams {
+    pub uri: Url,
+    /// The position of the local variable, or of the name of the called function.
+    pub position: Position,
+}
+
+/// The local variable or the called function which is inlined.
+struct InlineTarget {
+    id: rls_analysis::Id,
+    def: Def,
+    /// The range of the name at the position.
+    name: (usize, usize),
+}
+
+impl InlineTarget {
+    fn new(
+        ctx: &InitActionContext,
+        file_path: &Path,
+        text: &str,
+        position: Position,
+    ) -> Option<InlineTarget> {
+        let (start, end) = codegen::position_to_offset(text, position)
+            .and_then(|offset| codegen::identifier_at(text, offset))?;
+        let range = Range::new(
+            codegen::offset_to_position(text, start),
+            codegen::offset_to_position(text, end),
+        );
+        let span = span::Span::from_range(ls_util::range_to_rls(range), file_path);
+        let id = ctx.analysis.id(&span).ok()?;
+        let def = ctx.analysis.get_def(id).ok()?;
+        match def.kind {
+            DefKind::Local => {}
+            DefKind::Function | DefKind::Method if def.span != span => {}
+            _ => return None,
         }
-        if !is_upper_snake_case(a) && is_upper_snake_case(b) {
-            return Ordering::Less;
+        Some(InlineTarget { id, def, name: (start, end) })
+    }
+
+    fn title(&self) -> String {
+        match self.def.kind {
+            DefKind::Local => format!("Inline variable `{}`", self.def.name),
+            _ => format!("Inline function `{}`", self.def.name),
         }
-        a.cmp(b)
+    }
+}
+
+/// Creates a `CodeAction` inlining the local variable or function call at the
+/// cursor. Computing the edit is left to the execution of the command. The
+/// result is appended to `code_actions_result`.
+fn make_inline_actions(
+    params: &<CodeAction as lsp_data::request::Request>::Params,
+    file_path: &Path,
+    ctx: &InitActionContext,
+    code_actions_result: &mut <CodeAction as RequestAction>::Response,
+) {
+    // The references are found through the analysis, which only matches the
+    // text it was built from.
+    if !ctx.build_results_current(file_path) {
+        return;
+    }
+    let text = match ctx.vfs.load_file(file_path) {
+        Ok(FileContents::Text(text)) => text,
+        _ => return,
+    };
+    let target = match InlineTarget::new(ctx, file_path, &text, params.range.start) {
+        Some(target) => target,
+        None => return,
+    };
+
+    let args = InlineParams { uri: params.text_document.uri.clone(), position: params.range.start };
+    code_actions_result.push(Command {
+        title: target.title(),
+        command: format!("rls.inline-{}", ctx.pid),
+        arguments: Some(vec![serde_json::to_value(&args).unwrap()]),
     });
+}
+
+fn apply_inline(
+    args: &[serde_json::Value],
+    ctx: &InitActionContext,
+) -> Result<ApplyWorkspaceEditParams, ResponseError> {
+    let invalid = |message: &str| ResponseError::Message(ErrorCode::InvalidParams, message.into());
+    let params: InlineParams = match args.first().map(|arg| serde_json::from_value(arg.clone())) {
+        Some(Ok(params)) => params,
+        _ => return Err(invalid("Expected the position to inline at")),
+    };
+    let file_path = parse_file_path!(&params.uri, "inline")?;
+    trace!("apply_inline {:?}", params);
+
+    if !ctx.build_results_current(&file_path) {
+        return Err(invalid("The file has changed since it was last built"));
+    }
+    let text = match ctx.vfs.load_file(&file_path) {
+        Ok(FileContents::Text(text)) => text,
+        _ => return Err(invalid("Couldn't read the file")),
+    };
+    let target = InlineTarget::new(ctx, &file_path, &text, params.position)
+        .ok_or_else(|| invalid("There's nothing to inline at the position"))?;
+
+    let edit = match target.def.kind {
+        DefKind::Local => inline_local(ctx, &text, &target),
+        _ => inline_function(&file_path, &text, &target),
+    }
+    .ok_or_else(|| invalid("The definition can't be inlined"))?;
+    let location = Location::new(params.uri, edit.range);
+    Ok(ApplyWorkspaceEditParams { edit: make_workspace_edit(location, edit.new_text) })
+}
+
+/// Returns the edit replacing every reference to the local variable with its
+/// initializer and removing its `let` statement.
+fn inline_local(ctx: &InitActionContext, text: &str, target: &InlineTarget) -> Option<TextEdit> {
+    let def = &target.def;
+    let offset = |range: span::Range<span::ZeroIndexed>| {
+        let range = ls_util::rls_to_range(range);
+        Some((
+            codegen::position_to_offset(text, range.start)?,
+            codegen::position_to_offset(text, range.end)?,
+        ))
+    };
+    let (def_start, _) = offset(def.span.range)?;
+    let binding = codegen::parse_let(text, def_start)?;
+    // The annotation may determine the type of the initializer (e.g., of an
+    // integer literal), which the references wouldn't.
+    if binding.mutable || binding.ty.is_some() {
+        return None;
+    }
+    let refs: Vec<_> = ctx
+        .analysis
+        .find_all_refs_by_id(target.id)
+        .ok()?
+        .iter()
+        .filter(|span| span.file == def.span.file)
+        .filter_map(|span| offset(span.range))
+        .filter(|&(start, _)| start != def_start)
+        .collect();
+
+    // Evaluating the initializer another number of times than once, or
+    // moving its value more than once, would change the program.
+    if codegen::has_side_effects(&binding.init) && refs.len() != 1 {
+        return None;
+    }
+    if refs.len() > 1 && !codegen::is_copy_type(&ctx.analysis.show_type(&def.span).ok()?) {
+        return None;
+    }
+
+    let (start, end) = codegen::line_range(text, binding.start, binding.end);
+    let mut edits = vec![(start, end, String::new())];
+    for (start, end) in refs {
+        let replacement = if codegen::needs_parens(&binding.init)
+            && !codegen::is_standalone_position(text, start, end)
+        {
+            format!("({})", binding.init)
+        } else {
+            binding.init.clone()
+        };
+        edits.push((start, end, replacement));
+    }
+    let (start, end, new_text) = codegen::combine_edits(text, edits)?;
+    let range = Range::new(
+        codegen::offset_to_position(text, start),
+        codegen::offset_to_position(text, end),
+    );
+    Some(TextEdit { range, new_text })
+}
+
+/// Returns the edit replacing the call of the function, whose name is at the
+/// position, with the function's body.
+fn inline_function(file_path: &Path, text: &str, target: &InlineTarget) -> Option<TextEdit> {
+    lazy_static! {
+        static ref PATH: Regex = Regex::new(r"(?:[A-Za-z_]\w*\s*::\s*)*$").unwrap();
+        static ref SIMPLE: Regex = Regex::new(r"^[\w.]+$").unwrap();
+        static ref LET: Regex = Regex::new(r"\blet\s+(?:mut\s+)?(\w+)").unwrap();
+    }
+
+    let (def, (name_start, name_end)) = (&target.def, target.name);
+    // The names in the body only resolve to the same items at the call site
+    // if it's in the module of the function.
+    let module = |offset| {
+        codegen::enclosing_blocks(text, offset)
+            .into_iter()
+            .find(|&open| codegen::is_mod_header(codegen::block_header(text, open)))
+    };
+    let def_start = ls_util::rls_to_range(def.span.range).start;
+    let def_start = codegen::position_to_offset(text, def_start)?;
+    if def.span.file != file_path || module(def_start) != module(name_start) {
+        return None;
+    }
+
+    // Method calls would need their receiver substituted for `self`.
+    if text[..name_start].trim_end().ends_with('.') {
+        return None;
+    }
+    let mut open = name_end + (text[name_end..].len() - text[name_end..].trim_start().len());
+    if text[open..].starts_with("::<") {
+        open = codegen::matching_delimiter(text, open + 2)? + 1;
+    }
+    if !text[open..].starts_with('(') {
+        return None;
+    }
+    let close = codegen::matching_delimiter(text, open)?;
+    let args = codegen::split_top_level(&text[open + 1..close]);
+    let call_start = PATH.find(&text[..name_start])?.start();
+
+    let function = codegen::parse_fn(text, def_start)?;
+    if function.params.len() != args.len() || !function.is_inlinable() {
+        return None;
+    }
+
+    // Place expressions are substituted directly, anything else is bound to
+    // the parameter first (as are arguments which would be shadowed).
+    let body_locals: Vec<_> =
+        LET.captures_iter(&function.statements).map(|caps| caps[1].to_owned()).collect();
+    let mut bindings = vec![];
+    let mut substitutions = vec![];
+    for ((param, mutable), arg) in function.params.iter().zip(&args) {
+        let shadowed =
+            body_locals.iter().any(|local| arg.split('.').next() == Some(local.as_str()));
+        if SIMPLE.is_match(arg) && !mutable && !shadowed {
+            substitutions.push((param.as_str(), *arg));
+        } else {
+            let keyword = if *mutable { "let mut" } else { "let" };
+            bindings.push(format!("{} {} = {};", keyword, param, arg));
+        }
+    }
+    let statements = codegen::substitute_names(&function.statements, &substitutions);
+    let tail = function.tail.as_ref().map(|tail| codegen::substitute_names(tail, &substitutions));
+
+    let new_text = if bindings.is_empty() && statements.is_empty() {
+        let tail = tail.unwrap_or_else(|| "()".to_owned());
+        if codegen::needs_parens(&tail)
+            && !codegen::is_standalone_position(text, call_start, close + 1)
+        {
+            format!("({})", tail)
+        } else {
+            tail
+        }
+    } else {
+        let indent = codegen::indent_at(text, call_start);
+        let mut lines = bindings;
+        lines.extend(Some(statements).filter(|s| !s.is_empty()));
+        lines.extend(tail);
+        let body = codegen::reindent(&lines.join("\n"), "", &format!("{}    ", indent));
+        format!("{{\n{}\n{}}}", body, indent)
+    };
+
+    let range = Range::new(
+        codegen::offset_to_position(text, call_start),
+        codegen::offset_to_position(text, close + 1),
+    );
+    Some(TextEdit { range, new_text })
+}
+
+/// What the `rls.generate` command generates for a struct or enum.
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum GenerateKind {
+    /// A derive of the named trait.
+    Derive(String),
+    /// A `new` function taking every field.
+    Constructor,
+    /// A getter for the named field.
+    Getter(String),
+    /// A setter for the named field.
+    Setter(String),
+    /// An impl of `Default`.
+    Default,
+}
+
+impl GenerateKind {
+    fn title(&self) -> String {
+        match self {
+            GenerateKind::Derive(trait_name) => format!("Derive `{}`", trait_name),
+            GenerateKind::Constructor => "Generate `new`".to_owned(),
+            GenerateKind::Getter(field) => format!("Generate getter for `{}`", field),
+            GenerateKind::Setter(field) => format!("Generate setter for `{}`", field),
+            GenerateKind::Default => "Implement `Default`".to_owned(),
+        }
+    }
+}
+
+/// The argument of the `rls.generate` command.
+#[derive(Debug, Deserialize, Serialize)]
+pub struct GenerateParams {
+    pub uri: Url,
+    /// The position in the struct or enum definition.
+    pub position: Position,
+    pub kind: GenerateKind,
+}
+
+/// The struct or enum definition which boilerplate is generated for.
+struct GenerateTarget {
+    name: String,
+    is_enum: bool,
+    def: codegen::TypeDefinition,
+    /// The fields of a struct, in declaration order, with their types and the
+    /// row they're declared on.
+    fields: Vec<(String, String, span::Row<span::ZeroIndexed>)>,
+}
+
+impl GenerateTarget {
+    fn new(
+        ctx: &InitActionContext,
+        file_path: &Path,
+        text: &str,
+        position: Position,
+    ) -> Option<GenerateTarget> {
+        let cursor = codegen::position_to_offset(text, position)?;
+        let offset = |span: &span::Span<span::ZeroIndexed>| {
+            codegen::position_to_offset(text, ls_util::rls_to_range(span.range).start)
+        };
+
+        let symbols = ctx.analysis.symbols(file_path).unwrap_or_else(|_| vec![]);
+        let (symbol, def) = symbols
+            .into_iter()
+            .filter(|symbol| symbol.kind == DefKind::Struct || symbol.kind == DefKind::Enum)
+            .filter_map(|symbol| {
+                let def = codegen::parse_type_definition(text, offset(&symbol.span)?)?;
+                Some((symbol, def))
+            })
+            .find(|(_, def)| def.attrs_start <= cursor && cursor <= def.end)?;
+
+        let mut fields = vec![];
+        if symbol.kind == DefKind::Struct {
+            let mut field_defs = ctx
+                .analysis
+                .for_each_child_def(symbol.id, |_, def| def.clone())
+                .unwrap_or_else(|_| vec![]);
+            field_defs.retain(|field| field.kind == DefKind::Field);
+            field_defs.sort_by(|a, b| a.span.range.cmp(&b.span.range));
+            fields = field_defs
+                .into_iter()
+                .map(|field| {
+                    let ty = match ctx.analysis.show_type(&field.span) {
+                        Ok(ty) if !ty.is_empty() => ty,
+                        _ => field.value.clone(),
+                    };
+                    (field.name, ty, field.span.range.row_start)
+                })
+                .collect();
+        }
+        Some(GenerateTarget {
+            name: symbol.name,
+            is_enum: symbol.kind == DefKind::Enum,
+            def,
+            fields,
+        })
+    }
+
+    /// Returns the body of the first inherent impl, which methods go into.
+    fn impl_body(&self, text: &str) -> Option<(usize, usize)> {
+        codegen::inherent_impl_body(text, &self.name)
+    }
+
+    fn has_method(&self, text: &str, method: &str) -> bool {
+        let pattern = format!(r"\bfn\s+{}\b", regex::escape(method));
+        self.impl_body(text).map_or(false, |(open, close)| {
+            Regex::new(&pattern).map_or(false, |re| re.is_match(&text[open..close]))
+        })
+    }
+
+    /// Returns what can be generated, with accessors for the fields on the row
+    /// of `position`.
+    fn kinds(&self, text: &str, position: Position) -> Vec<GenerateKind> {
+        let mut kinds: Vec<_> = codegen::derivable_traits(&self.def.derives, self.is_enum)
+            .into_iter()
+            .map(|trait_name| GenerateKind::Derive(trait_name.to_owned()))
+            .collect();
+        if self.fields.is_empty() {
+            return kinds;
+        }
+
+        if !self.has_method(text, "new") {
+            kinds.push(GenerateKind::Constructor);
+        }
+        let cursor_row = ls_util::position_to_rls(position).row;
+        for (field, _, _) in self.fields.iter().filter(|(_, _, row)| *row == cursor_row) {
+            let getter = codegen::accessor_name(field);
+            let setter = format!("set_{}", getter.trim_start_matches("r#"));
+            if !self.has_method(text, &getter) {
+                kinds.push(GenerateKind::Getter(field.clone()));
+            }
+            if !self.has_method(text, &setter) {
+                kinds.push(GenerateKind::Setter(field.clone()));
+            }
+        }
+
+        let implements_default =
+            Regex::new(&format!(r"\bDefault\s+for\s+{}\b", regex::escape(&self.name)))
+                .map_or(false, |re| re.is_match(text));
+        if !implements_default && !self.def.derives.iter().any(|derive| derive == "Default") {
+            kinds.push(GenerateKind::Default);
+        }
+        kinds
+    }
+
+    /// Returns the edit generating `kind`, formatted with rustfmt.
+    fn edit(&self, ctx: &InitActionContext, text: &str, kind: &GenerateKind) -> Option<TextEdit> {
+        let def = &self.def;
+        let add_method = |method: String| match self.impl_body(text) {
+            Some(body) => {
+                codegen::add_items_edit(text, body, &[format_snippet(ctx, &method, Some("impl X"))])
+            }
+            None => {
+                let block = format!("{} {{\n{}\n}}", def.impl_header(&self.name, None), method);
+                codegen::add_item_after_edit(text, def, &format_snippet(ctx, &block, None))
+            }
+        };
+        let ty = |name: &str| {
+            self.fields.iter().find(|(field, _, _)| field == name).map(|(_, ty, _)| ty)
+        };
+
+        Some(match kind {
+            GenerateKind::Derive(trait_name) => codegen::derive_edit(text, def, trait_name),
+            GenerateKind::Constructor => {
+                let fields: Vec<_> =
+                    self.fields.iter().map(|(field, ty, _)| (field.clone(), ty.clone())).collect();
+                add_method(codegen::constructor(&def.visibility, &fields))
+            }
+            GenerateKind::Getter(field) => {
+                add_method(codegen::getter(&def.visibility, field, ty(field)?))
+            }
+            GenerateKind::Setter(field) => {
+                add_method(codegen::setter(&def.visibility, field, ty(field)?))
+            }
+            GenerateKind::Default => {
+                let names: Vec<_> = self.fields.iter().map(|(field, _, _)| field.clone()).collect();
+                let block =
+                    codegen::default_impl(&def.bounded_impl_header(&self.name, "Default"), &names);
+                codegen::add_item_after_edit(text, def, &format_snippet(ctx, &block, None))
+            }
+        })
+    }
+}
+
+/// Creates `CodeAction`s generating boilerplate for the struct or enum
+/// definition at the cursor: derives, a constructor, accessors for the field
+/// at the cursor and an impl of `Default`. Computing (and formatting) the
+/// edits is left to the execution of the commands. The results are appended
+/// to `code_actions_result`.
+fn make_generate_actions(
+    params: &<CodeAction as lsp_data::request::Request>::Params,
+    file_path: &Path,
+    ctx: &InitActionContext,
+    code_actions_result: &mut <CodeAction as RequestAction>::Response,
+) {
+    // The definition is found through the analysis, which only matches the
+    // text it was built from.
+    if !ctx.build_results_current(file_path) {
+        return;
+    }
+    let text = match ctx.vfs.load_file(file_path) {
+        Ok(FileContents::Text(text)) => text,
+        _ => return,
+    };
+    let position = params.range.start;
+    let target = match GenerateTarget::new(ctx, file_path, &text, position) {
+        Some(target) => target,
+        None => return,
+    };
+
+    for kind in target.kinds(&text, position) {
+        let title = kind.title();
+        let args = GenerateParams { uri: params.text_document.uri.clone(), position, kind };
+        code_actions_result.push(Command {
+            title,
+            command: format!("rls.generate-{}", ctx.pid),
+            arguments: Some(vec![serde_json::to_value(&args).unwrap()]),
+        });
+    }
+}
+
+fn apply_generate(
+    args: &[serde_json::Value],
+    ctx: &InitActionContext,
+) -> Result<ApplyWorkspaceEditParams, ResponseError> {
+    let invalid = |message: &str| ResponseError::Message(ErrorCode::InvalidParams, message.into());
+    let params: GenerateParams = match args.first().map(|arg| serde_json::from_value(arg.clone())) {
+        Some(Ok(params)) => params,
+        _ => return Err(invalid("Expected the position and what to generate")),
+    };
+    let file_path = parse_file_path!(&params.uri, "generate")?;
+    trace!("apply_generate {:?}", params);
+
+    if !ctx.build_results_current(&file_path) {
+        return Err(invalid("The file has changed since it was last built"));
+    }
+    let text = match ctx.vfs.load_file(&file_path) {
+        Ok(FileContents::Text(text)) => text,
+        _ => return Err(invalid("Couldn't read the file")),
+    };
+    let target = GenerateTarget::new(ctx, &file_path, &text, params.position)
+        .filter(|target| target.kinds(&text, params.position).contains(&params.kind))
+        .ok_or_else(|| invalid("There's nothing to generate at the position"))?;
+
+    let edit = target
+        .edit(ctx, &text, &params.kind)
+        .ok_or_else(|| invalid("The code can't be generated"))?;
+    let location = Location::new(params.uri, edit.range);
+    Ok(ApplyWorkspaceEditParams { edit: make_workspace_edit(location, edit.new_text) })
+}
+
+// Ideally we'd use Rustfmt for this, but reparsing is a bit of a pain.
+fn sort_deglob_str(s: &str) -> String {
+    let mut substrings = s.split(',').map(str::trim).collect::<Vec<_>>();
+    substrings.sort_by(|a, b| imports::compare_segments(a, b));
     substrings.join(", ")
 }
 
@@ -613,7 +2762,17 @@ impl RequestAction for CodeAction {
             make_suggestion_fix_actions(&params, &file_path, &ctx, &mut cmds);
         }
         if ctx.analysis_ready() {
+            make_auto_import_actions(&params, &file_path, &ctx, &mut cmds);
+            make_missing_trait_items_actions(&params, &file_path, &ctx, &mut cmds);
+            make_missing_match_arms_actions(&params, &file_path, &ctx, &mut cmds);
             make_deglob_actions(&params, &file_path, &ctx, &mut cmds);
+            make_extract_actions(&params, &file_path, &ctx, &mut cmds);
+            make_inline_actions(&params, &file_path, &ctx, &mut cmds);
+            make_generate_actions(&params, &file_path, &ctx, &mut cmds);
+        }
+        if ctx.build_ready() {
+            make_organize_imports_action(&params, &file_path, &ctx, &mut cmds);
+            make_fix_all_actions(&params, &file_path, &ctx, &mut cmds);
         }
         Ok(cmds)
     }
@@ -747,10 +2906,13 @@ impl RequestAction for ResolveCompletion {
         Err(ResponseError::Empty)
     }
 
-    fn handle(_: InitActionContext, params: Self::Params) -> Result<Self::Response, ResponseError> {
-        // Currently, we safely ignore this as a pass-through since we fully handle
-        // `textDocument/completion`. In the future, we may want to use this method as a
-        // way to more lazily fill out completion information.
+    fn handle(
+        _ctx: InitActionContext,
+        params: Self::Params,
+    ) -> Result<Self::Response, ResponseError> {
+        // We fully handle `textDocument/completion`, so there is nothing to fill
+        // out here. Accepted items are recorded by their command instead, as
+        // clients also resolve the items which are merely focused.
         Ok(params)
     }
 }
@@ -812,6 +2974,21 @@ impl RequestAction for CodeLensRequest {
     }
 }
 
+impl RequestAction for BuildReportRequest {
+    type Response = Option<BuildReport>;
+
+    fn fallback_response() -> Result<Self::Response, ResponseError> {
+        Ok(None)
+    }
+
+    fn handle(
+        ctx: InitActionContext,
+        _params: Self::Params,
+    ) -> Result<Self::Response, ResponseError> {
+        Ok(ctx.last_build_report.lock().unwrap().clone())
+    }
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
@@ -828,4 +3005,102 @@ mod test {
             "arc, bow, curve, Arc, Bow, Curve, ARC",
         );
     }
+
+    #[test]
+    fn test_unresolved_name() {
+        let diagnostic = |code: &str, message: &str| Diagnostic {
+            code: Some(NumberOrString::String(code.to_owned())),
+            message: message.to_owned(),
+            ..Diagnostic::default()
+        };
+
+        let (name, kinds) =
+            unresolved_name(&diagnostic("E0412", "cannot find type `Foo` in this scope\n\nlabel"))
+                .unwrap();
+        assert_eq!(name, "Foo");
+        assert!(kinds.contains(&DefKind::Struct) && !kinds.contains(&DefKind::Function));
+
+        let (name, _) = unresolved_name(&diagnostic(
+            "E0433",
+            "failed to resolve: use of undeclared type `HashMap`",
+        ))
+        .unwrap();
+        assert_eq!(name, "HashMap");
+
+        assert!(unresolved_name(&diagnostic("E0308", "mismatched types")).is_none());
+    }
+
+    #[test]
+    fn test_missing_trait_items() {
+        let diagnostic = Diagnostic {
+            code: Some(NumberOrString::String("E0046".to_owned())),
+            message: "not all trait items implemented, missing: `Output`, `add`\n\nlabel"
+                .to_owned(),
+            ..Diagnostic::default()
+        };
+        assert_eq!(
+            missing_trait_items(&diagnostic),
+            Some(vec!["Output".to_owned(), "add".to_owned()])
+        );
+    }
+
+    #[test]
+    fn test_first_uncovered_pattern() {
+        let diagnostic = Diagnostic {
+            code: Some(NumberOrString::String("E0004".to_owned())),
+            message: "non-exhaustive patterns: `Foo::B(_)`, `Foo::C` and 2 more not covered"
+                .to_owned(),
+            ..Diagnostic::default()
+        };
+        assert_eq!(first_uncovered_pattern(&diagnostic), Some("Foo::B(_)"));
+    }
+
+    #[test]
+    fn test_local_scope() {
+        let text =
+            "fn f(a: u32) -> u32 {\n    let b = a;\n    {\n        let c = b;\n    }\n    b\n}\n";
+        let scope = |name| {
+            let (open, close) = local_scope(text, text.find(name).unwrap()).unwrap();
+            (text[..open].matches('\n').count(), text[..close].matches('\n').count())
+        };
+        assert_eq!(scope("a:"), (0, 6));
+        assert_eq!(scope("b ="), (0, 6));
+        assert_eq!(scope("c ="), (2, 4));
+    }
+
+    #[test]
+    fn test_merge_fixes() {
+        let edit = |line, start, end, new_text: &str| TextEdit {
+            range: Range::new(Position::new(line, start), Position::new(line, end)),
+            new_text: new_text.to_owned(),
+        };
+        let fixes = vec![
+            vec![edit(2, 0, 0, "&"), edit(2, 8, 10, "")],
+            // Overlaps the second edit of the first fix, so its other edit is
+            // dropped along with it.
+            vec![edit(2, 9, 12, "x"), edit(4, 0, 4, "let")],
+            vec![edit(2, 0, 0, "&"), edit(3, 0, 0, "mut ")],
+        ];
+        assert_eq!(
+            merge_fixes(fixes),
+            vec![edit(2, 0, 0, "&"), edit(2, 8, 10, ""), edit(3, 0, 0, "mut ")]
+        );
+    }
+
+    #[test]
+    fn test_import_path() {
+        let crates = vec!["my_crate".to_owned()];
+        let path =
+            |qualname| import_path(qualname, &crates, Edition::Edition2018).map(|p| p.to_string());
+
+        assert_eq!(path("my_crate::foo::Bar"), Some("crate::foo::Bar".to_owned()));
+        assert_eq!(path("serde::Serialize"), Some("serde::Serialize".to_owned()));
+        assert_eq!(path("my_crate::<impl Foo>::new"), None);
+        assert_eq!(path("my_crate::main::{{closure}}"), None);
+
+        let path =
+            |qualname| import_path(qualname, &crates, Edition::Edition2015).map(|p| p.to_string());
+        assert_eq!(path("my_crate::foo::Bar"), Some("foo::Bar".to_owned()));
+        assert_eq!(path("serde::Serialize"), Some("serde::Serialize".to_owned()));
+    }
 }
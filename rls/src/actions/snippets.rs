@@ -0,0 +1,236 @@
+//! Snippet completions: the built-in postfix templates, which wrap the
+//! expression in front of them (e.g., `foo.bar().if`), and the snippets
+//! defined in the config.
+
+use std::path::Path;
+
+use rls_vfs::FileContents;
+
+use crate::actions::codegen;
+use crate::actions::completion::{self, CompletionContext};
+use crate::actions::InitActionContext;
+use crate::config::{Snippet, SnippetScope};
+use crate::lsp_data::*;
+
+/// A postfix template, expanded in place of the expression before it.
+struct PostfixTemplate {
+    label: &'static str,
+    /// Shown as the detail, with `expr` standing for the expression.
+    detail: &'static str,
+    /// The snippet, with `{}` standing for the expression. Lines after the
+    /// first are indented like the line of the expression.
+    body: &'static str,
+    /// Whether the template is a statement, so it can only replace an
+    /// expression which starts one.
+    statement: bool,
+}
+
+const POSTFIX_TEMPLATES: &[PostfixTemplate] = &[
+    PostfixTemplate {
+        label: "if",
+        detail: "if expr {}",
+        body: "if {} {\n    $0\n}",
+        statement: false,
+    },
+    PostfixTemplate {
+        label: "match",
+        detail: "match expr {}",
+        body: "match {} {\n    ${1:_} => $0,\n}",
+        statement: false,
+    },
+    PostfixTemplate { label: "ok", detail: "Ok(expr)", body: "Ok({})", statement: false },
+    PostfixTemplate { label: "err", detail: "Err(expr)", body: "Err({})", statement: false },
+    PostfixTemplate { label: "some", detail: "Some(expr)", body: "Some({})", statement: false },
+    PostfixTemplate { label: "dbg", detail: "dbg!(expr)", body: "dbg!({})", statement: false },
+    PostfixTemplate { label: "ref", detail: "&expr", body: "&{}", statement: false },
+    PostfixTemplate { label: "refm", detail: "&mut expr", body: "&mut {}", statement: false },
+    PostfixTemplate {
+        label: "box",
+        detail: "Box::new(expr)",
+        body: "Box::new({})",
+        statement: false,
+    },
+    PostfixTemplate { label: "not", detail: "!expr", body: "!{}", statement: false },
+    PostfixTemplate {
+        label: "let",
+        detail: "let name = expr;",
+        body: "let ${1:name} = {};$0",
+        statement: true,
+    },
+    PostfixTemplate {
+        label: "letm",
+        detail: "let mut name = expr;",
+        body: "let mut ${1:name} = {};$0",
+        statement: true,
+    },
+];
+
+/// Returns the postfix and user-defined snippets which can be completed at
+/// `position`. The caller is expected to check that the client supports
+/// snippets.
+pub fn complete(
+    ctx: &InitActionContext,
+    file_path: &Path,
+    position: Position,
+) -> Vec<CompletionItem> {
+    let text = match ctx.vfs.load_file(file_path) {
+        Ok(FileContents::Text(text)) => text,
+        _ => return vec![],
+    };
+    let offset = match codegen::position_to_offset(&text, position) {
+        Some(offset) => offset,
+        None => return vec![],
+    };
+    if completion::in_use_statement(&text, offset) {
+        return vec![];
+    }
+    let mut items = postfix_items(&text, offset);
+    items.extend(user_items(&text, offset, &ctx.config.lock().unwrap().snippets));
+    items
+}
+
+/// Returns whether `offset` is in a function body.
+fn in_fn_body(text: &str, offset: usize) -> bool {
+    codegen::enclosing_blocks(text, offset)
+        .into_iter()
+        .any(|open| codegen::is_fn_header(codegen::block_header(text, open)))
+}
+
+/// Escapes the characters with a meaning in the snippet syntax.
+pub fn escape_snippet(text: &str) -> String {
+    text.replace('\\', "\\\\").replace('$', "\\$").replace('}', "\\}")
+}
+
+/// Returns the postfix templates for the expression before the `.` in front
+/// of the name being typed at `offset`, each replacing both the expression and
+/// the typed name.
+fn postfix_items(text: &str, offset: usize) -> Vec<CompletionItem> {
+    let start = completion::identifier_start(text, offset);
+    let before = text[..start].trim_end();
+    if !before.ends_with('.') || before.ends_with("..") || !in_fn_body(text, start) {
+        return vec![];
+    }
+    let dot = before.len() - 1;
+    let expr_start = match codegen::postfix_expression_start(text, dot) {
+        Some(expr_start) => expr_start,
+        None => return vec![],
+    };
+    let expr = &text[expr_start..dot];
+    let starts_statement = codegen::statement_start(text, expr_start) == expr_start;
+    let indent = codegen::indent_at(text, expr_start);
+    let range = Range::new(
+        codegen::offset_to_position(text, expr_start),
+        codegen::offset_to_position(text, offset),
+    );
+
+    POSTFIX_TEMPLATES
+        .iter()
+        .filter(|template| starts_statement || !template.statement)
+        .map(|template| {
+            let body = template
+                .body
+                .replacen("{}", &escape_snippet(expr), 1)
+                .replace('\n', &format!("\n{}", indent));
+            CompletionItem {
+                label: template.label.to_owned(),
+                kind: Some(CompletionItemKind::Snippet),
+                detail: Some(template.detail.to_owned()),
+                // Clients filter by the text in the range of the edit.
+                filter_text: Some(format!("{}.{}", &text[expr_start..dot], template.label)),
+                insert_text_format: Some(InsertTextFormat::Snippet),
+                text_edit: Some(TextEdit::new(range, body)),
+                ..CompletionItem::default()
+            }
+        })
+        .collect()
+}
+
+/// Returns the user-defined `snippets` whose scope allows them at `offset`.
+fn user_items(text: &str, offset: usize, snippets: &[Snippet]) -> Vec<CompletionItem> {
+    let start = match completion::completion_context(text, offset) {
+        Some((CompletionContext::Scope, start)) => start,
+        _ => return vec![],
+    };
+    let in_fn = in_fn_body(text, start);
+    let starts_statement = codegen::statement_start(text, start) == start;
+
+    snippets
+        .iter()
+        .filter(|snippet| match snippet.scope {
+            SnippetScope::Item => !in_fn && starts_statement,
+            SnippetScope::Stmt => in_fn && starts_statement,
+            SnippetScope::Expr => in_fn,
+        })
+        .map(|snippet| CompletionItem {
+            label: snippet.trigger.clone(),
+            kind: Some(CompletionItemKind::Snippet),
+            detail: snippet.description.clone(),
+            insert_text: Some(snippet.body.clone()),
+            insert_text_format: Some(InsertTextFormat::Snippet),
+            ..CompletionItem::default()
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn labels(items: &[CompletionItem]) -> Vec<&str> {
+        items.iter().map(|item| item.label.as_str()).collect()
+    }
+
+    #[test]
+    fn expands_postfix_templates() {
+        let text = "fn main() {\n    let x = foo.bar(1).o\n}";
+        let items = postfix_items(text, text.len() - 2);
+        let ok = items.iter().find(|item| item.label == "ok").unwrap();
+        assert_eq!(ok.filter_text.as_ref().unwrap(), "foo.bar(1).ok");
+        let edit = ok.text_edit.as_ref().unwrap();
+        assert_eq!(edit.new_text, "Ok(foo.bar(1))");
+        assert_eq!(edit.range, Range::new(Position::new(1, 12), Position::new(1, 24)));
+        // Not at the start of a statement.
+        assert!(!labels(&items).contains(&"let"));
+
+        let text = "fn main() {\n    if a {\n        x.len().\n    }\n}";
+        let offset = text.find("len().").unwrap() + 6;
+        let items = postfix_items(text, offset);
+        let edit = items.iter().find(|item| item.label == "if").unwrap().text_edit.clone();
+        assert_eq!(edit.unwrap().new_text, "if x.len() {\n            $0\n        }");
+        assert!(labels(&items).contains(&"letm"));
+
+        let text = "fn main() {\n    format!(\"${}\", a).dbg\n}";
+        let items = postfix_items(text, text.len() - 2);
+        let dbg = items.iter().find(|item| item.label == "dbg").unwrap();
+        assert_eq!(dbg.text_edit.as_ref().unwrap().new_text, "dbg!(format!(\"\\${\\}\", a))");
+
+        // Outside of function bodies and not after a `.`.
+        assert!(postfix_items("struct S {\n    x: a.\n}", 20).is_empty());
+        assert!(postfix_items("fn main() {\n    x\n}", 17).is_empty());
+        assert!(postfix_items("fn main() {\n    0..\n}", 19).is_empty());
+    }
+
+    #[test]
+    fn filters_user_snippets_by_scope() {
+        let snippet = |trigger: &str, scope| Snippet {
+            trigger: trigger.to_owned(),
+            body: format!("{}$0", trigger),
+            description: None,
+            scope,
+        };
+        let snippets = [
+            snippet("item", SnippetScope::Item),
+            snippet("stmt", SnippetScope::Stmt),
+            snippet("expr", SnippetScope::Expr),
+        ];
+
+        let text = "mod m {\n    i\n}";
+        assert_eq!(labels(&user_items(text, 13, &snippets)), ["item"]);
+        let text = "fn main() {\n    s\n}";
+        assert_eq!(labels(&user_items(text, 17, &snippets)), ["stmt", "expr"]);
+        let text = "fn main() {\n    let x = e\n}";
+        assert_eq!(labels(&user_items(text, 25, &snippets)), ["expr"]);
+        let text = "fn main() {\n    x.e\n}";
+        assert!(user_items(text, 19, &snippets).is_empty());
+    }
+}
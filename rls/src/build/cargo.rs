@@ -8,6 +8,7 @@ use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::mpsc::Sender;
 use std::sync::{Arc, Mutex};
 use std::thread;
+use std::time::{Duration, Instant};
 
 use cargo::core::compiler::{BuildConfig, CompileMode, Context, Executor, Unit};
 use cargo::core::resolver::{CliFeatures, ResolveError};
@@ -19,7 +20,9 @@ use cargo::util::{
     ConfigValue,
 };
 use cargo_util::ProcessBuilder;
+use lazy_static::lazy_static;
 use log::{debug, trace, warn};
+use regex::Regex;
 use rls_data::Analysis;
 use rls_vfs::Vfs;
 
@@ -27,8 +30,9 @@ use crate::actions::progress::ProgressUpdate;
 use crate::build::cargo_plan::CargoPlan;
 use crate::build::environment::{self, Environment, EnvironmentLock};
 use crate::build::plan::{BuildPlan, Crate};
+use crate::build::timings::{self, UnitTiming};
 use crate::build::{BufWriter, BuildResult, CompilationContext, Internals, PackageArg};
-use crate::config::Config;
+use crate::config::{Config, ResolveMode};
 use crate::lsp_data::{Position, Range};
 
 // Runs an in-process instance of Cargo.
@@ -122,13 +126,21 @@ fn run_cargo(
     let mut shell = Shell::from_write(Box::new(BufWriter(Arc::clone(&out))));
     shell.set_verbosity(Verbosity::Quiet);
 
-    let mut config = {
+    let (config, resolve_mode) = {
         let rls_config = rls_config.lock().unwrap();
 
+        let resolve_mode = rls_config.resolve_mode();
         let target_dir = rls_config.target_dir.as_ref().as_ref().map(|p| p as &Path);
-        make_cargo_config(manifest_dir, target_dir, restore_env.get_old_cwd(), shell)
+        let config = make_cargo_config(
+            manifest_dir,
+            target_dir,
+            restore_env.get_old_cwd(),
+            shell,
+            resolve_mode,
+            build_std_crates(&rls_config).as_deref(),
+        )?;
+        (config, resolve_mode)
     };
-    config.nightly_features_allowed = true;
 
     let ws = Workspace::new(&manifest_path, &config)
         .map_err(|err| ManifestAwareError::new(err, &manifest_path, None))?;
@@ -148,6 +160,7 @@ fn run_cargo(
         &config,
         &ws,
     )
+    .map_err(|err| explain_resolve_error(err, resolve_mode))
     .map_err(|err| ManifestAwareError::new(err, &manifest_path, Some(&ws)).into())
 }
 
@@ -177,7 +190,8 @@ fn run_cargo_ws(
         // We mustn't lock configuration for the whole build process
         let rls_config = rls_config.lock().unwrap();
 
-        let opts = CargoOptions::new(&rls_config);
+        let mut opts = CargoOptions::new(&rls_config);
+        opts.target = opts.target.map(|target| resolve_compile_target(target, ws.root()));
         trace!("Cargo compilation options:\n{:?}", opts);
         let rustflags = prepare_cargo_rustflags(&rls_config);
 
@@ -361,6 +375,27 @@ impl RlsExecutor {
     fn is_primary_package(&self, id: PackageId) -> bool {
         id.source_id().is_path() || self.member_packages.lock().unwrap().contains(&id)
     }
+
+    fn record_unit_timing(
+        &self,
+        id: PackageId,
+        target: &Target,
+        mode: CompileMode,
+        rustc: Duration,
+        save_analysis: Option<Duration>,
+        disambiguator: Option<(u64, u64)>,
+    ) {
+        let mut cx = self.compilation_cx.lock().unwrap();
+        timings::record_unit(
+            &mut cx.unit_timings,
+            &id.name(),
+            target.name(),
+            mode.is_any_test(),
+            rustc,
+            save_analysis,
+            disambiguator,
+        );
+    }
 }
 
 impl Executor for RlsExecutor {
@@ -378,6 +413,16 @@ impl Executor for RlsExecutor {
         let only_primary = |unit: &Unit| self.is_primary_package(unit.pkg.package_id());
 
         plan.emplace_dep_with_filter(unit, cx, &only_primary);
+
+        // Assume that every unit is fresh until it's actually compiled in `exec`.
+        // Running build scripts doesn't involve the compiler, so skip these.
+        if !unit.mode.is_run_custom_build() {
+            compilation_cx.unit_timings.push(UnitTiming::new(
+                &unit.pkg.name(),
+                unit.target.name(),
+                unit.mode.is_any_test(),
+            ));
+        }
     }
 
     fn force_rebuild(&self, unit: &Unit) -> bool {
@@ -515,11 +560,14 @@ impl Executor for RlsExecutor {
                 let config = self.config.lock().unwrap();
                 (config.crate_blacklist.clone(), *config.full_docs.clone().as_ref())
             };
+            let start = Instant::now();
             if crate_blacklist.as_ref().0.contains(&crate_name) {
                 // By running the original command (rather than using our shim), we
                 // avoid producing save-analysis data.
                 trace!("crate is blacklisted");
-                return cargo_cmd.exec();
+                let result = cargo_cmd.exec();
+                self.record_unit_timing(id, target, mode, start.elapsed(), None, None);
+                return result;
             }
             // Only include public symbols in externally compiled deps data
             let save_config = serde_json::to_string(&rls_data::config::Config {
@@ -530,7 +578,9 @@ impl Executor for RlsExecutor {
             })?;
             cmd.env("RUST_SAVE_ANALYSIS_CONFIG", &OsString::from(save_config));
 
-            return cmd.exec();
+            let result = cmd.exec();
+            self.record_unit_timing(id, target, mode, start.elapsed(), None, None);
+            return result;
         }
 
         trace!("rustc intercepted - args: {:?} envs: {:?}", args, envs,);
@@ -560,17 +610,30 @@ impl Executor for RlsExecutor {
             cx.build_dir.clone().unwrap()
         };
 
-        if let BuildResult::Success(_, mut messages, mut analysis, input_files, success) =
-            super::rustc::rustc(
-                &self.vfs,
-                &args,
-                &envs,
-                cargo_cmd.get_cwd(),
-                &build_dir,
-                Arc::clone(&self.config),
-                &self.env_lock.as_facade(),
-            )
-        {
+        let start = Instant::now();
+        let (result, save_analysis_time) = super::rustc::rustc(
+            &self.vfs,
+            &args,
+            &envs,
+            cargo_cmd.get_cwd(),
+            &build_dir,
+            Arc::clone(&self.config),
+            &self.env_lock.as_facade(),
+        );
+        if let BuildResult::Success(_, mut messages, mut analysis, input_files, success) = result {
+            let disambiguator = analysis
+                .first()
+                .and_then(|a| a.prelude.as_ref())
+                .map(|prelude| prelude.crate_id.disambiguator);
+            self.record_unit_timing(
+                id,
+                target,
+                mode,
+                start.elapsed(),
+                save_analysis_time,
+                disambiguator,
+            );
+
             self.compiler_messages.lock().unwrap().append(&mut messages);
             self.analysis.lock().unwrap().append(&mut analysis);
 
@@ -639,6 +702,41 @@ impl CargoOptions {
     }
 }
 
+/// Returns the standard library crates to build from source: the configured
+/// ones or, when compiling for a target whose sysroot has no `rust-analysis`
+/// component, the ones the target supports, so that std analysis data is
+/// produced locally.
+pub(crate) fn build_std_crates(config: &Config) -> Option<Vec<String>> {
+    if config.build_std.is_some() {
+        return config.build_std.clone();
+    }
+    let target = config.target.as_deref()?;
+    if !rls_analysis::needs_local_std_analysis(target) {
+        return None;
+    }
+
+    // Bare-metal targets, like most custom ones, only support `core` and `alloc`.
+    let name = rls_analysis::compile_target_name(target);
+    let crates: &[&str] = if target.ends_with(".json") || name.contains("-none") {
+        &["core", "alloc"]
+    } else {
+        &["std"]
+    };
+    debug!("building {:?} from source for std analysis of target {}", crates, target);
+    Some(crates.iter().map(|&c| c.to_owned()).collect())
+}
+
+/// Custom target specification files are resolved by Cargo relative to its
+/// working directory, which isn't necessarily the workspace root for the RLS.
+fn resolve_compile_target(target: String, ws_root: &Path) -> String {
+    let path = Path::new(&target);
+    if target.ends_with(".json") && path.is_relative() {
+        ws_root.join(path).to_string_lossy().into_owned()
+    } else {
+        target
+    }
+}
+
 fn prepare_cargo_rustflags(config: &Config) -> String {
     let mut flags = env::var("RUSTFLAGS").unwrap_or_else(|_| String::new());
 
@@ -653,15 +751,20 @@ fn prepare_cargo_rustflags(config: &Config) -> String {
     dedup_flags(&flags)
 }
 
-/// Constructs a cargo configuration for the given build and target directories
-/// and shell.
+/// Constructs a cargo configuration for the given build and target directories,
+/// shell and network/lock file restrictions. If `build_std` is specified, the
+/// given standard library crates are built from source, as if `-Zbuild-std`
+/// was passed.
 pub fn make_cargo_config(
     build_dir: &Path,
     target_dir: Option<&Path>,
     cwd: &Path,
     shell: Shell,
-) -> cargo_config::Config {
-    let config = cargo_config::Config::new(shell, cwd.to_path_buf(), homedir(build_dir).unwrap());
+    resolve_mode: ResolveMode,
+    build_std: Option<&[String]>,
+) -> CargoResult<cargo_config::Config> {
+    let mut config =
+        cargo_config::Config::new(shell, cwd.to_path_buf(), homedir(build_dir).unwrap());
 
     // Cargo is expecting the config to come from a config file and keeps
     // track of the path to that file. We'll make one up, it shouldn't be
@@ -708,7 +811,53 @@ pub fn make_cargo_config(
     }
 
     config.set_values(config_value_map).unwrap();
-    config
+
+    // Enable nightly flag for cargo(see #1043)
+    config.nightly_features_allowed = true;
+    let ResolveMode { frozen, locked, offline } = resolve_mode;
+    let unstable_flags: Vec<_> =
+        build_std.map(|crates| format!("build-std={}", crates.join(","))).into_iter().collect();
+    config.configure(0, true, None, frozen, locked, offline, &None, &unstable_flags, &[])?;
+
+    Ok(config)
+}
+
+/// Cargo reports a dependency that can't be fetched in offline mode with a
+/// fairly generic error, so try to point at the missing dependency instead.
+pub(crate) fn explain_resolve_error(error: anyhow::Error, mode: ResolveMode) -> anyhow::Error {
+    let option = match mode.offline_option() {
+        Some(option) => option,
+        None => return error,
+    };
+
+    match unavailable_dependency(&error) {
+        Some(name) => error.context(format!(
+            "dependency `{}` is not available in the local registry cache or vendor \
+             directory and can't be downloaded, since the `{}` option is enabled",
+            name, option
+        )),
+        None => error,
+    }
+}
+
+/// Finds the name of a dependency that Cargo failed to resolve or fetch.
+fn unavailable_dependency(error: &anyhow::Error) -> Option<String> {
+    lazy_static! {
+        static ref UNAVAILABLE_DEP_RES: Vec<Regex> = [
+            r"failed to get `([^`]+)` as a dependency",
+            r"no matching package named `([^`]+)` found",
+            r"failed to select a version for the requirement `([^`= ]+)",
+            r"failed to load source for dependency `([^`]+)`",
+            r"failed to download `([^` ]+)",
+        ]
+        .iter()
+        .map(|re| Regex::new(re).unwrap())
+        .collect();
+    }
+
+    error.chain().map(ToString::to_string).find_map(|msg| {
+        UNAVAILABLE_DEP_RES.iter().find_map(|re| re.captures(&msg)).map(|caps| caps[1].to_owned())
+    })
 }
 
 fn parse_arg(args: &[OsString], arg: &str) -> Option<String> {
@@ -881,7 +1030,8 @@ impl std::error::Error for ManifestAwareError {}
 
 #[cfg(test)]
 mod test {
-    use super::{dedup_flags, filter_arg};
+    use super::{build_std_crates, dedup_flags, filter_arg, unavailable_dependency};
+    use crate::config::Config;
 
     #[test]
     fn test_dedup_flags() {
@@ -915,6 +1065,16 @@ mod test {
         );
     }
 
+    #[test]
+    fn test_build_std_crates() {
+        let mut config = Config::default();
+        assert_eq!(build_std_crates(&config), None);
+
+        config.target = Some("thumbv7em-none-eabihf".to_owned());
+        config.build_std = Some(vec!["core".to_owned()]);
+        assert_eq!(build_std_crates(&config), Some(vec!["core".to_owned()]));
+    }
+
     #[test]
     fn test_filter_arg() {
         use std::ffi::OsString;
@@ -931,4 +1091,26 @@ mod test {
         assert_eq!(filter_arg(&args("-a --error-format json -b"), "--error-format"), ["-a", "-b"]);
         assert_eq!(filter_arg(&args("-a -b -x"), "--error-format"), ["-a", "-b", "-x"]);
     }
+
+    #[test]
+    fn test_unavailable_dependency() {
+        let error = anyhow::format_err!(
+            "no matching package named `derive-new` found\n\
+             location searched: registry `https://github.com/rust-lang/crates.io-index`"
+        );
+        assert_eq!(unavailable_dependency(&error).as_deref(), Some("derive-new"));
+
+        let error = anyhow::format_err!("can't checkout: you are in the offline mode (--offline)")
+            .context("failed to load source for dependency `cargo`")
+            .context("failed to get `cargo` as a dependency of package `rls v1.41.0`");
+        assert_eq!(unavailable_dependency(&error).as_deref(), Some("cargo"));
+
+        let error = anyhow::format_err!(
+            "failed to select a version for the requirement `serde = \"^9.0\"`"
+        );
+        assert_eq!(unavailable_dependency(&error).as_deref(), Some("serde"));
+
+        let error = anyhow::format_err!("the lock file needs to be updated");
+        assert_eq!(unavailable_dependency(&error), None);
+    }
 }
@@ -14,9 +14,11 @@ use log::{debug, info, trace};
 use rls_data::Analysis;
 use rls_vfs::Vfs;
 
+pub(crate) use self::cargo::{build_std_crates, explain_resolve_error};
 use self::environment::EnvironmentLock;
 use self::plan::{BuildGraph, BuildPlan, WorkStatus};
 pub use self::plan::{Crate, Edition};
+pub use self::timings::{BuildReport, UnitTiming};
 use crate::actions::post_build::PostBuildHandler;
 use crate::actions::progress::{ProgressNotifier, ProgressUpdate};
 use crate::config::Config;
@@ -30,6 +32,7 @@ mod external;
 mod ipc;
 mod plan;
 mod rustc;
+mod timings;
 
 /// Manages builds.
 ///
@@ -143,6 +146,8 @@ struct CompilationContext {
     /// Build plan, which should know all the inter-package/target dependencies
     /// along with args/envs.
     build_plan: BuildPlan,
+    /// Timings of the units processed during the current build.
+    unit_timings: Vec<UnitTiming>,
 }
 
 impl CompilationContext {
@@ -152,6 +157,7 @@ impl CompilationContext {
             build_dir: None,
             needs_rebuild: true,
             build_plan: BuildPlan::new(),
+            unit_timings: vec![],
         }
     }
 }
@@ -396,6 +402,7 @@ impl BuildQueue {
                 .expect("Failed to start progress-notifier thread");
 
             // Run the build.
+            let start = Instant::now();
             let result = internals.run_build(
                 &build.build_dir,
                 build.priority,
@@ -408,6 +415,11 @@ impl BuildQueue {
             }
 
             let mut pbh = build.pbh;
+            if let BuildResult::Success(..) = result {
+                let unit_timings =
+                    mem::take(&mut internals.compilation_cx.lock().unwrap().unit_timings);
+                pbh.build_report = Some(BuildReport::new(start.elapsed(), unit_timings));
+            }
             {
                 let mut blocked = internals.blocked.lock().unwrap();
                 pbh.blocked_threads.extend(blocked.drain(..));
@@ -519,6 +531,7 @@ impl Internals {
             let mut cx = self.compilation_cx.lock().unwrap();
             let build_dir = cx.build_dir.clone().unwrap();
             let needs_rebuild = cx.needs_rebuild;
+            cx.unit_timings.clear();
 
             // Check if an external build command was provided and execute that, instead.
             if let Some(cmd) = self.config.lock().unwrap().build_command.clone() {
@@ -11,6 +11,7 @@ use std::hash::Hash;
 use std::path::{Path, PathBuf};
 use std::sync::mpsc::Sender;
 use std::sync::Arc;
+use std::time::Instant;
 
 use cargo_util::ProcessBuilder;
 use log::trace;
@@ -19,6 +20,7 @@ use serde::{Deserialize, Serialize};
 use crate::actions::progress::ProgressUpdate;
 use crate::build::cargo_plan::CargoPlan;
 use crate::build::external::ExternalPlan;
+use crate::build::timings;
 use crate::build::{BuildResult, Internals, PackageArg};
 
 pub(crate) trait BuildKey {
@@ -172,7 +174,8 @@ impl JobQueue {
                 progress_sender.send(update).expect("Failed to send progress update");
             }
 
-            match super::rustc::rustc(
+            let start = Instant::now();
+            let (result, save_analysis_time) = super::rustc::rustc(
                 &internals.vfs,
                 &args,
                 job.get_envs(),
@@ -180,8 +183,28 @@ impl JobQueue {
                 &build_dir,
                 Arc::clone(&internals.config),
                 &internals.env_lock.as_facade(),
-            ) {
+            );
+            match result {
                 BuildResult::Success(c, mut messages, mut analysis, files, success) => {
+                    let crate_name = proc_argument_value(&job, "--crate-name")
+                        .and_then(OsStr::to_str)
+                        .unwrap_or_default();
+                    let package = job.get_env("CARGO_PKG_NAME");
+                    let package = package.as_ref().and_then(|p| p.to_str()).unwrap_or(crate_name);
+                    let disambiguator = analysis
+                        .first()
+                        .and_then(|a| a.prelude.as_ref())
+                        .map(|prelude| prelude.crate_id.disambiguator);
+                    timings::record_unit(
+                        &mut internals.compilation_cx.lock().unwrap().unit_timings,
+                        package,
+                        crate_name,
+                        job.get_args().iter().any(|arg| arg == "--test"),
+                        start.elapsed(),
+                        save_analysis_time,
+                        disambiguator,
+                    );
+
                     compiler_messages.append(&mut messages);
                     analyses.append(&mut analysis);
                     for (file, inputs) in files {
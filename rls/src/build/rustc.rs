@@ -14,6 +14,7 @@ use std::mem;
 use std::path::{Path, PathBuf};
 use std::process::Command;
 use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 
 use log::trace;
 use rls_data::Analysis;
@@ -33,7 +34,8 @@ use crate::build::plan::{Crate, Edition};
 use crate::build::{BufWriter, BuildResult};
 use crate::config::{ClippyPreference, Config};
 
-// Runs a single instance of Rustc.
+// Runs a single instance of Rustc. Also returns the time spent dumping the
+// save-analysis data, if it's known (i.e., when compiling in-process).
 pub(crate) fn rustc(
     vfs: &Vfs,
     args: &[String],
@@ -42,7 +44,7 @@ pub(crate) fn rustc(
     build_dir: &Path,
     rls_config: Arc<Mutex<Config>>,
     env_lock: &EnvironmentLockFacade,
-) -> BuildResult {
+) -> (BuildResult, Option<Duration>) {
     trace!(
         "rustc - args: `{:?}`, envs: {:?}, cwd: {:?}, build dir: {:?}",
         args,
@@ -55,13 +57,31 @@ pub(crate) fn rustc(
 
     let mut envs = envs.clone();
 
-    let clippy_preference = {
+    let (clippy_preference, out_of_process, limits) = {
         let config = rls_config.lock().unwrap();
         if config.clear_env_rust_log {
             envs.insert(String::from("RUST_LOG"), None);
         }
+        // Item signatures are needed to generate code for missing trait items.
+        let save_config = rls_data::config::Config {
+            full_docs: *config.full_docs.as_ref(),
+            signatures: true,
+            ..Default::default()
+        };
+        if let Ok(save_config) = serde_json::to_string(&save_config) {
+            envs.insert(String::from("RUST_SAVE_ANALYSIS_CONFIG"), Some(save_config.into()));
+        }
+
+        let out_of_process = env::var("RLS_OUT_OF_PROCESS").is_ok()
+            || (config.isolate_proc_macros && uses_proc_macros(args));
+        let limits = ProcessLimits {
+            timeout: Some(config.out_of_process_timeout)
+                .filter(|&secs| secs > 0)
+                .map(Duration::from_secs),
+            memory_limit: config.out_of_process_memory_limit.map(|mib| mib * 1024 * 1024),
+        };
 
-        config.clippy_preference
+        (config.clippy_preference, out_of_process, limits)
     };
 
     let lock_environment = |envs, cwd| {
@@ -69,22 +89,26 @@ pub(crate) fn rustc(
         Environment::push_with_lock(envs, cwd, guard)
     };
 
-    let CompilationResult { result, stderr, analysis, input_files } = match std::env::var(
-        "RLS_OUT_OF_PROCESS",
-    ) {
-        #[cfg(feature = "ipc")]
-        Ok(..) => run_out_of_process(changed.clone(), &args, &envs, clippy_preference)
-            .unwrap_or_else(|_| {
-                run_in_process(changed, &args, clippy_preference, lock_environment(&envs, cwd))
-            }),
-        #[cfg(not(feature = "ipc"))]
-        Ok(..) => {
+    #[cfg(feature = "ipc")]
+    let out_of_process = if out_of_process {
+        run_out_of_process(changed.clone(), &args, &envs, clippy_preference, limits).ok()
+    } else {
+        None
+    };
+    #[cfg(not(feature = "ipc"))]
+    let out_of_process = {
+        if env::var("RLS_OUT_OF_PROCESS").is_ok() {
             log::warn!("Support for out-of-process compilation was not compiled. Rebuild with 'ipc' feature enabled");
-            run_in_process(changed, &args, clippy_preference, lock_environment(&envs, cwd))
         }
-        Err(..) => run_in_process(changed, &args, clippy_preference, lock_environment(&envs, cwd)),
+        let _ = (out_of_process, limits);
+        None
     };
 
+    let CompilationResult { result, stderr, analysis, input_files, save_analysis_time } =
+        out_of_process.unwrap_or_else(|| {
+            run_in_process(changed, &args, clippy_preference, lock_environment(&envs, cwd))
+        });
+
     let stderr = String::from_utf8(stderr).unwrap();
     log::debug!("rustc - stderr: {}", &stderr);
     let stderr_json_msgs: Vec<_> = stderr.lines().map(String::from).collect();
@@ -94,7 +118,10 @@ pub(crate) fn rustc(
 
     let cwd = cwd.unwrap_or_else(|| Path::new(".")).to_path_buf();
 
-    BuildResult::Success(cwd, stderr_json_msgs, analysis, input_files, result.is_ok())
+    (
+        BuildResult::Success(cwd, stderr_json_msgs, analysis, input_files, result.is_ok()),
+        save_analysis_time,
+    )
 }
 
 /// Resulting data from compiling a crate (in the rustc sense)
@@ -105,6 +132,27 @@ pub struct CompilationResult {
     analysis: Option<Analysis>,
     // TODO: Move to Vec<PathBuf>
     input_files: HashMap<PathBuf, HashSet<Crate>>,
+    /// Time spent dumping save-analysis data, if known
+    save_analysis_time: Option<Duration>,
+}
+
+/// Resource limits applied to out-of-process compilations.
+#[derive(Clone, Copy, Debug)]
+#[cfg_attr(not(feature = "ipc"), allow(dead_code))]
+struct ProcessLimits {
+    timeout: Option<Duration>,
+    /// In bytes.
+    memory_limit: Option<u64>,
+}
+
+/// Returns whether the compiled crate links to any procedural macro crates,
+/// meaning that arbitrary user code will be run as part of the compilation.
+fn uses_proc_macros(args: &[String]) -> bool {
+    args.iter()
+        .zip(args.iter().skip(1))
+        .filter(|(flag, _)| *flag == "--extern")
+        .filter_map(|(_, value)| value.splitn(2, '=').nth(1))
+        .any(|path| Path::new(path).extension() == Some(env::consts::DLL_EXTENSION.as_ref()))
 }
 
 #[cfg(feature = "ipc")]
@@ -113,7 +161,11 @@ fn run_out_of_process(
     args: &[String],
     envs: &BTreeMap<String, Option<OsString>>,
     clippy_preference: ClippyPreference,
+    limits: ProcessLimits,
 ) -> Result<CompilationResult, ()> {
+    use std::io::Read;
+    use std::process::Stdio;
+
     let analysis = Arc::default();
     let input_files = Arc::default();
 
@@ -126,21 +178,56 @@ fn run_out_of_process(
         .and_then(|x| x.to_str().map(String::from))
         .expect("Couldn't set executable for RLS rustc shim");
 
-    let output = Command::new(rustc_shim)
+    let mut command = Command::new(rustc_shim);
+    command
         .env(crate::RUSTC_SHIM_ENV_VAR_NAME, "1")
         .env("RLS_IPC_ENDPOINT", ipc_server.endpoint())
         .env("RLS_CLIPPY_PREFERENCE", clippy_preference.to_string())
         .args(args.iter().skip(1))
         .envs(envs.iter().filter_map(|(k, v)| v.as_ref().map(|v| (k, v))))
-        .output()
-        .map_err(|_| ());
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped());
+    #[cfg(unix)]
+    {
+        if let Some(limit) = limits.memory_limit {
+            set_memory_limit(&mut command, limit);
+        }
+    }
 
-    let result = match &output {
-        Ok(output) if output.status.code() == Some(0) => Ok(()),
-        _ => Err(()),
+    let mut child = match command.spawn() {
+        Ok(child) => child,
+        Err(e) => {
+            log::warn!("Couldn't spawn the RLS rustc shim: {}", e);
+            ipc_server.close();
+            return Err(());
+        }
     };
+
+    // Drain stderr on a separate thread, so that a chatty compiler can't block
+    // on a full pipe while we wait for it to finish.
+    let stderr_reader = child.stderr.take().map(|mut pipe| {
+        std::thread::spawn(move || {
+            let mut stderr = vec![];
+            let _ = pipe.read_to_end(&mut stderr);
+            stderr
+        })
+    });
+
+    let status = wait_with_timeout(&mut child, limits.timeout);
     // NOTE: Make sure that we pass JSON error format
-    let stderr = output.map(|out| out.stderr).unwrap_or_default();
+    let mut stderr = stderr_reader.and_then(|reader| reader.join().ok()).unwrap_or_default();
+
+    let result = match status {
+        Ok(Some(status)) if status.success() => Ok(()),
+        _ => Err(()),
+    };
+    if let Some(reason) = crash_reason(&status, limits) {
+        log::warn!("Out-of-process compilation {}: {:?}", reason, args);
+        if let Some(diagnostic) = crash_diagnostic(args, &reason, &stderr) {
+            stderr.extend_from_slice(diagnostic.as_bytes());
+            stderr.push(b'\n');
+        }
+    }
 
     ipc_server.close();
 
@@ -148,7 +235,139 @@ fn run_out_of_process(
     let analysis = unwrap_shared(analysis, "Other ref dropped by closed IPC server");
     // FIXME(#25): given that we are running the compiler directly, there is no need
     // to serialize the error messages -- we should pass them in memory.
-    Ok(CompilationResult { result, stderr, analysis, input_files })
+    Ok(CompilationResult { result, stderr, analysis, input_files, save_analysis_time: None })
+}
+
+/// Waits for the child to exit, killing it if it runs for longer than
+/// `timeout`. Returns `None` if the child had to be killed.
+#[cfg(feature = "ipc")]
+fn wait_with_timeout(
+    child: &mut std::process::Child,
+    timeout: Option<Duration>,
+) -> io::Result<Option<std::process::ExitStatus>> {
+    let start = Instant::now();
+    loop {
+        if let Some(status) = child.try_wait()? {
+            return Ok(Some(status));
+        }
+        if timeout.map_or(false, |timeout| start.elapsed() >= timeout) {
+            child.kill()?;
+            child.wait()?;
+            return Ok(None);
+        }
+        std::thread::sleep(Duration::from_millis(50));
+    }
+}
+
+#[cfg(all(feature = "ipc", unix))]
+fn set_memory_limit(command: &mut Command, limit: u64) {
+    use std::os::unix::process::CommandExt;
+
+    let limit = limit as libc::rlim_t;
+    // SAFETY: `setrlimit` is async-signal-safe and we don't allocate here.
+    unsafe {
+        command.pre_exec(move || {
+            let rlimit = libc::rlimit { rlim_cur: limit, rlim_max: limit };
+            if libc::setrlimit(libc::RLIMIT_AS, &rlimit) != 0 {
+                return Err(io::Error::last_os_error());
+            }
+            Ok(())
+        });
+    }
+}
+
+/// Describes why the out-of-process compilation didn't finish normally, if it
+/// didn't. Regular compilation errors (exit code 1) are not considered a crash.
+#[cfg(feature = "ipc")]
+fn crash_reason(
+    status: &io::Result<Option<std::process::ExitStatus>>,
+    limits: ProcessLimits,
+) -> Option<String> {
+    let status = match status {
+        Ok(Some(status)) => status,
+        Ok(None) => {
+            let timeout = limits.timeout.unwrap_or_default();
+            return Some(format!("timed out after {} seconds", timeout.as_secs()));
+        }
+        Err(e) => return Some(format!("couldn't be waited on: {}", e)),
+    };
+
+    let memory_hint = if limits.memory_limit.is_some() {
+        " (it may have exceeded the configured memory limit)"
+    } else {
+        ""
+    };
+    match status.code() {
+        Some(0) | Some(1) => None,
+        Some(code) => Some(format!("crashed with exit code {}{}", code, memory_hint)),
+        None => {
+            #[cfg(unix)]
+            {
+                use std::os::unix::process::ExitStatusExt;
+                if let Some(signal) = status.signal() {
+                    return Some(format!("was killed by signal {}{}", signal, memory_hint));
+                }
+            }
+            Some(format!("was terminated{}", memory_hint))
+        }
+    }
+}
+
+/// Creates a JSON compiler message reporting a crashed compilation on the root
+/// file of the compiled crate, so that it's visible to the user rather than
+/// just silently producing no analysis.
+#[cfg(feature = "ipc")]
+fn crash_diagnostic(args: &[String], reason: &str, stderr: &[u8]) -> Option<String> {
+    let crate_root = args.iter().skip(1).find(|arg| arg.ends_with(".rs"))?;
+    let crate_name = args
+        .iter()
+        .zip(args.iter().skip(1))
+        .find(|(flag, _)| *flag == "--crate-name")
+        .map(|(_, name)| name.as_str())
+        .unwrap_or("<unknown>");
+
+    // Anything that's not a JSON message is likely the panic message or the
+    // ICE report, which is the most useful bit to show.
+    let stderr = String::from_utf8_lossy(stderr);
+    let output: Vec<_> = stderr.lines().filter(|line| !line.starts_with('{')).collect();
+    let children = if output.is_empty() {
+        vec![]
+    } else {
+        let output = output[output.len().saturating_sub(20)..].join("\n");
+        vec![serde_json::json!({
+            "message": format!("compiler output:\n{}", output),
+            "code": null,
+            "level": "note",
+            "spans": [],
+            "children": [],
+            "rendered": null,
+        })]
+    };
+
+    let message = format!("compilation of crate `{}` {}", crate_name, reason);
+    let diagnostic = serde_json::json!({
+        "message": message,
+        "code": null,
+        "level": "error",
+        "spans": [{
+            "file_name": crate_root,
+            "byte_start": 0,
+            "byte_end": 0,
+            "line_start": 1,
+            "line_end": 1,
+            "column_start": 1,
+            "column_end": 1,
+            "is_primary": true,
+            "text": [],
+            "label": null,
+            "suggested_replacement": null,
+            "suggestion_applicability": null,
+            "expansion": null,
+        }],
+        "children": children,
+        "rendered": format!("error: {}\n", message),
+    });
+    Some(diagnostic.to_string())
 }
 
 fn run_in_process(
@@ -160,6 +379,7 @@ fn run_in_process(
     let mut callbacks = RlsRustcCalls { clippy_preference, ..Default::default() };
     let input_files = Arc::clone(&callbacks.input_files);
     let analysis = Arc::clone(&callbacks.analysis);
+    let save_analysis_time = Arc::clone(&callbacks.save_analysis_time);
 
     let args: Vec<_> = if cfg!(feature = "clippy") && clippy_preference != ClippyPreference::Off {
         // Allow feature gating in the same way as `cargo clippy`
@@ -202,8 +422,10 @@ fn run_in_process(
     let stderr = unwrap_shared(stderr, "Other ref dropped by scoped compilation");
     let input_files = unwrap_shared(input_files, "Other ref dropped by scoped compilation");
     let analysis = unwrap_shared(analysis, "Other ref dropped by scoped compilation");
+    let save_analysis_time =
+        unwrap_shared(save_analysis_time, "Other ref dropped by scoped compilation");
 
-    CompilationResult { result, stderr, analysis, input_files }
+    CompilationResult { result, stderr, analysis, input_files, save_analysis_time }
 }
 
 // Our compiler controller. We mostly delegate to the default rustc
@@ -212,6 +434,7 @@ fn run_in_process(
 struct RlsRustcCalls {
     analysis: Arc<Mutex<Option<Analysis>>>,
     input_files: Arc<Mutex<HashMap<PathBuf, HashSet<Crate>>>>,
+    save_analysis_time: Arc<Mutex<Option<Duration>>>,
     clippy_preference: ClippyPreference,
 }
 
@@ -290,6 +513,7 @@ impl rustc_driver::Callbacks for RlsRustcCalls {
             //                     save::DumpHandler::new(state.out_dir,
             //                                            state.crate_name.unwrap()));
             // This version passes directly, it is more efficient.
+            let start = Instant::now();
             save::process_crate(
                 tcx,
                 &crate_name,
@@ -303,6 +527,7 @@ impl rustc_driver::Callbacks for RlsRustcCalls {
                     },
                 },
             );
+            *self.save_analysis_time.lock().unwrap() = Some(start.elapsed());
         });
 
         Compilation::Continue
@@ -406,3 +631,50 @@ pub fn src_path(cwd: Option<&Path>, path: impl AsRef<Path>) -> Option<PathBuf> {
 fn unwrap_shared<T: std::fmt::Debug>(shared: Arc<Mutex<T>>, msg: &'static str) -> T {
     Arc::try_unwrap(shared).expect(msg).into_inner().unwrap()
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(args: &[&str]) -> Vec<String> {
+        args.iter().map(|arg| arg.to_string()).collect()
+    }
+
+    #[test]
+    fn detects_proc_macro_dependencies() {
+        let dylib = format!(
+            "serde_derive=/target/debug/deps/libserde_derive.{}",
+            env::consts::DLL_EXTENSION
+        );
+        let with_macros = args(&[
+            "rustc",
+            "src/lib.rs",
+            "--extern",
+            "serde=/deps/libserde.rlib",
+            "--extern",
+            &dylib,
+        ]);
+        let without_macros =
+            args(&["rustc", "src/lib.rs", "--extern", "serde=/deps/libserde.rlib"]);
+
+        assert!(uses_proc_macros(&with_macros));
+        assert!(!uses_proc_macros(&without_macros));
+    }
+
+    #[cfg(feature = "ipc")]
+    #[test]
+    fn crash_is_reported_on_crate_root() {
+        use crate::actions::diagnostics::parse_diagnostics;
+
+        let args = args(&["rustc", "--crate-name", "foo", "src/lib.rs", "--edition=2018"]);
+        let stderr = b"thread 'rustc' panicked at 'boom', src/lib.rs:1:1\n";
+        let message = crash_diagnostic(&args, "crashed with exit code 101", stderr).unwrap();
+
+        let cwd = Path::new("/project");
+        let parsed = parse_diagnostics(&message, cwd, true).unwrap();
+        let (diagnostic, _) = &parsed.diagnostics[&cwd.join("src/lib.rs")][0];
+        assert!(diagnostic.message.starts_with("compilation of crate `foo` crashed"));
+        assert!(diagnostic.message.contains("panicked at 'boom'"));
+        assert_eq!(diagnostic.range.start.line, 0);
+    }
+}
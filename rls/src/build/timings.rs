@@ -0,0 +1,237 @@
+//! Per-unit build timings, similar to what `cargo build --timings` reports.
+//!
+//! Timings are gathered while executing the build (both when running Cargo and
+//! when executing the cached build plan) and are later completed with the time
+//! it took to lower the resulting save-analysis data.
+
+use std::fmt::Write as _;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::time::Duration;
+
+use rls_analysis::CrateId;
+use serde_derive::{Deserialize, Serialize};
+
+/// Name of the JSON report written to the target directory.
+pub const JSON_REPORT_FILE: &str = "rls-timings.json";
+/// Name of the HTML report written to the target directory.
+pub const HTML_REPORT_FILE: &str = "rls-timings.html";
+
+/// Timings of a single compilation unit. All durations are in seconds.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct UnitTiming {
+    /// Name of the package the unit belongs to.
+    pub package: String,
+    /// Name of the compiled target.
+    pub target: String,
+    /// Whether the unit was compiled under `cfg(test)`.
+    pub test: bool,
+    /// `true` if the unit was up-to-date and was not recompiled.
+    pub cached: bool,
+    /// Time spent running the compiler, including save-analysis.
+    pub rustc: Option<f64>,
+    /// Time spent dumping save-analysis data. Only known for crates which
+    /// were compiled in-process.
+    pub save_analysis: Option<f64>,
+    /// Time it took to lower the save-analysis data of the crate, if it was
+    /// lowered again after this build.
+    pub lowering: Option<f64>,
+    /// Crate disambiguator reported by the compiler.
+    #[serde(skip)]
+    pub(crate) disambiguator: Option<(u64, u64)>,
+}
+
+impl UnitTiming {
+    pub(crate) fn new(package: &str, target: &str, test: bool) -> UnitTiming {
+        UnitTiming {
+            package: package.to_owned(),
+            target: target.to_owned(),
+            test,
+            cached: true,
+            ..UnitTiming::default()
+        }
+    }
+
+    /// Marks the unit as rebuilt, taking `rustc` to compile.
+    pub(crate) fn rebuilt(&mut self, rustc: Duration, save_analysis: Option<Duration>) {
+        self.cached = false;
+        self.rustc = Some(rustc.as_secs_f64());
+        self.save_analysis = save_analysis.map(|d| d.as_secs_f64());
+    }
+
+    fn crate_name(&self) -> String {
+        self.target.replace('-', "_")
+    }
+}
+
+/// Records the compilation of `target` from `package`, replacing a matching
+/// unit which was previously assumed to be cached.
+pub(crate) fn record_unit(
+    timings: &mut Vec<UnitTiming>,
+    package: &str,
+    target: &str,
+    test: bool,
+    rustc: Duration,
+    save_analysis: Option<Duration>,
+    disambiguator: Option<(u64, u64)>,
+) {
+    let index = timings
+        .iter()
+        .position(|t| t.cached && t.package == package && t.target == target && t.test == test);
+    let unit = match index {
+        Some(index) => &mut timings[index],
+        None => {
+            timings.push(UnitTiming::new(package, target, test));
+            timings.last_mut().unwrap()
+        }
+    };
+    unit.rebuilt(rustc, save_analysis);
+    unit.disambiguator = disambiguator;
+}
+
+/// Timings of a whole build, as returned by the `rls/buildReport` request.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BuildReport {
+    /// Total wall-clock duration of the build, in seconds.
+    pub duration: f64,
+    pub units: Vec<UnitTiming>,
+}
+
+impl BuildReport {
+    pub fn new(duration: Duration, units: Vec<UnitTiming>) -> BuildReport {
+        BuildReport { duration: duration.as_secs_f64(), units }
+    }
+
+    /// Fills in the lowering times of the crates loaded by the analysis host.
+    ///
+    /// Units compiled in-process are matched by their crate disambiguator,
+    /// while the remaining ones fall back to matching the crate name.
+    pub fn add_lowering_times(&mut self, lowering_times: &[(CrateId, Duration)]) {
+        for unit in &mut self.units {
+            let crate_name = unit.crate_name();
+            let lowering = lowering_times.iter().find(|(id, _)| match unit.disambiguator {
+                Some(disambiguator) => id.disambiguator == disambiguator,
+                None => id.name == crate_name,
+            });
+            unit.lowering = lowering.map(|(_, time)| time.as_secs_f64());
+        }
+    }
+
+    /// Writes the report as both JSON and HTML into `dir`.
+    pub fn write_to(&self, dir: &Path) -> io::Result<()> {
+        fs::create_dir_all(dir)?;
+        let json = serde_json::to_string_pretty(self)?;
+        fs::write(dir.join(JSON_REPORT_FILE), json)?;
+        fs::write(dir.join(HTML_REPORT_FILE), self.to_html())
+    }
+
+    fn to_html(&self) -> String {
+        let mut units: Vec<_> = self.units.iter().collect();
+        // Slowest units first, since these are the ones worth looking at.
+        units.sort_by(|a, b| {
+            let total = |t: &UnitTiming| t.rustc.unwrap_or(0.0) + t.lowering.unwrap_or(0.0);
+            total(b).partial_cmp(&total(a)).unwrap_or(std::cmp::Ordering::Equal)
+        });
+
+        let rebuilt = units.iter().filter(|u| !u.cached).count();
+        let secs = |t: Option<f64>| t.map(|t| format!("{:.2}s", t)).unwrap_or_default();
+
+        let mut html = String::new();
+        html.push_str(
+            "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n\
+             <title>RLS Build Timings</title>\n<style>\n\
+             body { font-family: sans-serif; }\n\
+             table { border-collapse: collapse; }\n\
+             th, td { border: 1px solid #ccc; padding: 4px 8px; text-align: left; }\n\
+             td.num { text-align: right; }\n\
+             tr.cached { color: #888; }\n\
+             </style>\n</head>\n<body>\n<h1>RLS Build Timings</h1>\n",
+        );
+        writeln!(
+            html,
+            "<p>Total time: {:.2}s, {} units, {} rebuilt, {} cached</p>",
+            self.duration,
+            units.len(),
+            rebuilt,
+            units.len() - rebuilt
+        )
+        .unwrap();
+        html.push_str(
+            "<table>\n<tr><th>Unit</th><th>Package</th><th>Status</th><th>rustc</th>\
+             <th>save-analysis</th><th>Lowering</th></tr>\n",
+        );
+        for unit in units {
+            writeln!(
+                html,
+                "<tr class=\"{}\"><td>{}{}</td><td>{}</td><td>{}</td>\
+                 <td class=\"num\">{}</td><td class=\"num\">{}</td><td class=\"num\">{}</td></tr>",
+                if unit.cached { "cached" } else { "rebuilt" },
+                unit.target,
+                if unit.test { " (test)" } else { "" },
+                unit.package,
+                if unit.cached { "cached" } else { "rebuilt" },
+                secs(unit.rustc),
+                secs(unit.save_analysis),
+                secs(unit.lowering),
+            )
+            .unwrap();
+        }
+        html.push_str("</table>\n</body>\n</html>\n");
+        html
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn record_replaces_cached_unit() {
+        let mut timings = vec![
+            UnitTiming::new("foo", "foo", false),
+            UnitTiming::new("foo", "foo", true),
+            UnitTiming::new("bar", "bar", false),
+        ];
+
+        record_unit(&mut timings, "foo", "foo", true, Duration::from_secs(2), None, None);
+        record_unit(&mut timings, "baz", "baz", false, Duration::from_secs(1), None, None);
+
+        assert_eq!(timings.len(), 4);
+        assert!(timings[0].cached);
+        assert!(!timings[1].cached);
+        assert_eq!(timings[1].rustc, Some(2.0));
+        assert!(timings[2].cached);
+        assert_eq!(timings[3].target, "baz");
+        assert!(!timings[3].cached);
+    }
+
+    #[test]
+    fn lowering_times_are_matched() {
+        let crate_id = |name: &str, disambiguator| CrateId { name: name.to_owned(), disambiguator };
+
+        let mut primary = UnitTiming::new("my-crate", "my-crate", false);
+        primary.disambiguator = Some((1, 2));
+        let mut report = BuildReport::new(
+            Duration::from_secs(3),
+            vec![
+                primary,
+                UnitTiming::new("dep", "dep", false),
+                UnitTiming::new("unchanged", "unchanged", false),
+            ],
+        );
+
+        report.add_lowering_times(&[
+            (crate_id("my_crate", (3, 4)), Duration::from_secs(5)),
+            (crate_id("my_crate", (1, 2)), Duration::from_secs(1)),
+            (crate_id("dep", (5, 6)), Duration::from_secs(2)),
+        ]);
+
+        assert_eq!(report.units[0].lowering, Some(1.0));
+        assert_eq!(report.units[1].lowering, Some(2.0));
+        // The crate wasn't lowered again, so it has no lowering time.
+        assert_eq!(report.units[2].lowering, None);
+    }
+}
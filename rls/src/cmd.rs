@@ -3,26 +3,30 @@
 //! the RLS as usual and prints the JSON result back on the command line.
 
 use crate::actions::requests;
-use crate::config::Config;
+use crate::config::{BaselineMode, Config};
+use crate::lsp_data::{ChangeConfigSettings, LSPNotification};
 use crate::server::{self, LsService, Notification, Request, RequestId};
 use rls_analysis::{AnalysisHost, Target};
 use rls_vfs::Vfs;
-use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
 
+use lsp_types::notification::{Progress, PublishDiagnostics, ShowMessage};
 use lsp_types::{
-    ClientCapabilities, CodeActionContext, CodeActionParams, CompletionItem,
-    DocumentFormattingParams, DocumentRangeFormattingParams, DocumentSymbolParams,
-    FormattingOptions, InitializeParams, Position, Range, RenameParams, TextDocumentIdentifier,
-    TextDocumentPositionParams, TraceOption, WindowClientCapabilities, WorkspaceSymbolParams,
+    ClientCapabilities, CodeActionContext, CodeActionParams, CompletionItem, Diagnostic,
+    DiagnosticSeverity, DocumentFormattingParams, DocumentRangeFormattingParams,
+    DocumentSymbolParams, FormattingOptions, InitializeParams, MessageType, NumberOrString,
+    Position, ProgressParams, PublishDiagnosticsParams, Range, RenameParams, ShowMessageParams,
+    TextDocumentIdentifier, TextDocumentPositionParams, TraceOption, WindowClientCapabilities,
+    WorkspaceSymbolParams,
 };
 
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 use std::fmt;
 use std::io::{stdin, stdout, Write};
 use std::marker::PhantomData;
 use std::path::Path;
 use std::str::FromStr;
-use std::sync::mpsc::{channel, Receiver, Sender};
+use std::sync::mpsc::{channel, Receiver, RecvTimeoutError, Sender};
 use std::sync::{Arc, Mutex};
 use std::thread;
 use std::time::{Duration, Instant};
@@ -172,6 +176,181 @@ pub fn run() {
     }
 }
 
+/// How long to wait for the diagnostics of the build before giving up.
+const BUILD_TIMEOUT: Duration = Duration::from_secs(30 * 60);
+
+/// Builds the project in the current directory and reports the warnings which
+/// aren't in its diagnostics baseline, along with any errors. `args` are
+/// `[--config <settings file>] [--timeout <seconds>] [baseline file]`, where
+/// the settings file holds the `rust` settings of the client, like the
+/// `settings` of `workspace/didChangeConfiguration`. Returns the exit code of
+/// the RLS: `1` if there are any, `0` otherwise.
+pub fn check_baseline(args: Vec<String>) -> i32 {
+    let mut config_file = None;
+    let mut timeout = BUILD_TIMEOUT;
+    let mut baseline_file = None;
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--config" => match args.next() {
+                Some(file) => config_file = Some(file),
+                None => {
+                    println!("Expected a settings file after `--config`");
+                    return 101;
+                }
+            },
+            "--timeout" => match args.next().and_then(|secs| secs.parse().ok()) {
+                Some(secs) => timeout = Duration::from_secs(secs),
+                None => {
+                    println!("Expected a number of seconds after `--timeout`");
+                    return 101;
+                }
+            },
+            _ => baseline_file = Some(arg),
+        }
+    }
+
+    let mut config = match config_file {
+        Some(file) => match load_config(Path::new(&file)) {
+            Ok(config) => config,
+            Err(e) => {
+                println!("Could not load the settings in {}: {}", file, e);
+                return 101;
+            }
+        },
+        None => Config::default(),
+    };
+    configure_baseline_check(&mut config);
+    if let Some(baseline_file) = baseline_file {
+        config.diagnostics_baseline_file = baseline_file;
+    }
+
+    let current_dir = ::std::env::current_dir().unwrap();
+    let baseline_path = current_dir.join(&config.diagnostics_baseline_file);
+    if !baseline_path.is_file() {
+        println!(
+            "No diagnostics baseline at {}, create one with the `rls.createDiagnosticsBaseline` \
+             command",
+            baseline_path.display()
+        );
+        return 1;
+    }
+
+    let analysis = Arc::new(AnalysisHost::new(Target::Debug));
+    let vfs = Arc::new(Vfs::new());
+    let (sender, receiver) = channel();
+    let (done_sender, done) = channel();
+    let output = DiagnosticsOutput {
+        diagnostics: Arc::default(),
+        failed: Arc::default(),
+        done: Arc::new(Mutex::new(done_sender)),
+    };
+
+    let service = LsService::new(
+        analysis,
+        vfs,
+        Arc::new(Mutex::new(config)),
+        Box::new(ChannelMsgReader::new(receiver)),
+        output.clone(),
+    );
+    thread::spawn(|| LsService::run(service));
+
+    sender
+        .send(initialize(current_dir.to_str().unwrap().to_owned()).to_string())
+        .expect("Error sending init");
+    println!("Building...");
+    let mut failed = match done.recv_timeout(timeout) {
+        Ok(()) => output.failed.load(Ordering::SeqCst),
+        Err(RecvTimeoutError::Timeout) => {
+            println!("error: the build didn't finish within {} seconds", timeout.as_secs());
+            true
+        }
+        Err(RecvTimeoutError::Disconnected) => {
+            println!("error: the RLS stopped before the build finished");
+            true
+        }
+    };
+    for (uri, diagnostics) in output.diagnostics.lock().unwrap().iter() {
+        let path = uri.to_file_path().unwrap_or_else(|_| uri.path().into());
+        let path = path.strip_prefix(&current_dir).unwrap_or(&path);
+        for diagnostic in diagnostics {
+            let level = match diagnostic.severity {
+                Some(DiagnosticSeverity::Error) => "error",
+                Some(DiagnosticSeverity::Warning) => "warning",
+                _ => continue,
+            };
+            let code = match &diagnostic.code {
+                Some(NumberOrString::String(code)) if !code.is_empty() => {
+                    format!("[{}]", code)
+                }
+                _ => String::new(),
+            };
+            println!(
+                "{}:{}:{}: {}{}: {}",
+                path.display(),
+                diagnostic.range.start.line + 1,
+                diagnostic.range.start.character + 1,
+                level,
+                code,
+                diagnostic.message.lines().next().unwrap_or_default(),
+            );
+            failed = true;
+        }
+    }
+    if !failed {
+        println!("No new warnings");
+    }
+
+    sender.send(shutdown().to_string()).expect("Error sending on channel");
+    sender.send(exit().to_string()).expect("Error sending on channel");
+    // Sometimes we don't quite exit in time and we get an error on the channel. Hack it.
+    thread::sleep(Duration::from_millis(100));
+
+    if failed {
+        1
+    } else {
+        0
+    }
+}
+
+/// Overrides the settings which would keep warnings from being checked
+/// against the baseline.
+fn configure_baseline_check(config: &mut Config) {
+    // Don't clear `RUST_LOG` in CLI mode since it's intended for debugging purposes.
+    config.clear_env_rust_log = false;
+    config.diagnostics_baseline = BaselineMode::Hide;
+    // There are no open files, and the warnings are what is checked.
+    config.diagnostics_open_files_only = false;
+    config.show_warnings = true;
+}
+
+/// Reads the `rust` settings in the JSON `file`, reporting any unknown,
+/// deprecated or duplicated keys.
+fn load_config(file: &Path) -> Result<Config, String> {
+    let settings = std::fs::read_to_string(file).map_err(|e| e.to_string())?;
+    let settings: serde_json::Value = serde_json::from_str(&settings).map_err(|e| e.to_string())?;
+
+    let mut dups = HashMap::new();
+    let mut unknowns = vec![];
+    let mut deprecated = vec![];
+    let settings =
+        ChangeConfigSettings::try_deserialize(&settings, &mut dups, &mut unknowns, &mut deprecated)
+            .map_err(|()| "expected an object with the `rust` settings".to_owned())?;
+    for key in unknowns {
+        println!("warning: unknown RLS configuration `{}`", key);
+    }
+    for key in deprecated {
+        println!("warning: RLS configuration option `{}` is deprecated", key);
+    }
+    for (key, dups) in dups {
+        println!("warning: duplicated RLS configuration `{}`: {}", key, dups.join(", "));
+    }
+
+    let mut config = settings.rust;
+    config.normalise();
+    Ok(config)
+}
+
 fn def(file_name: &str, row: &str, col: &str) -> Request<requests::Definition> {
     let params = TextDocumentPositionParams {
         text_document: TextDocumentIdentifier::new(url(file_name)),
@@ -338,6 +517,56 @@ impl server::Output for PrintlnOutput {
     }
 }
 
+/// Output for the RLS server collecting the diagnostics it publishes, until
+/// those of the first build are done.
+#[derive(Clone)]
+struct DiagnosticsOutput {
+    diagnostics: Arc<Mutex<BTreeMap<Url, Vec<Diagnostic>>>>,
+    /// Whether the build failed before producing diagnostics.
+    failed: Arc<AtomicBool>,
+    done: Arc<Mutex<Sender<()>>>,
+}
+
+impl server::Output for DiagnosticsOutput {
+    fn response(&self, output: String) {
+        print_verb!("response: {}", output);
+        let message: serde_json::Value = match serde_json::from_str(&output) {
+            Ok(message) => message,
+            Err(_) => return,
+        };
+        let params = message["params"].clone();
+
+        match message["method"].as_str() {
+            Some(PublishDiagnostics::METHOD) => {
+                if let Ok(params) = serde_json::from_value::<PublishDiagnosticsParams>(params) {
+                    self.diagnostics.lock().unwrap().insert(params.uri, params.diagnostics);
+                }
+            }
+            Some(ShowMessage::METHOD) => {
+                if let Ok(params) = serde_json::from_value::<ShowMessageParams>(params) {
+                    if params.typ == MessageType::Error {
+                        println!("error: {}", params.message);
+                        self.failed.store(true, Ordering::SeqCst);
+                    }
+                }
+            }
+            Some(Progress::METHOD) => {
+                // The diagnostics of a build are done once its indexing is.
+                if let Ok(params) = serde_json::from_value::<ProgressParams>(params) {
+                    if params.title == "Indexing" && params.done == Some(true) {
+                        let _ = self.done.lock().unwrap().send(());
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn provide_id(&self) -> RequestId {
+        RequestId::Num(0)
+    }
+}
+
 struct ChannelMsgReader {
     channel: Mutex<Receiver<String>>,
 }
@@ -425,7 +654,12 @@ Supported commands:
                   textDocument/codeAction
 
     resolve       label detail
-                  completionItem/resolve"
+                  completionItem/resolve
+
+Run `rls --check-baseline [--config settings_file] [--timeout seconds] [baseline_file]`
+to build the project in the current directory and fail if it has any warnings
+that aren't in its diagnostics baseline. The settings file holds the `rust`
+settings of the editor, e.g. `{ "rust": { "all_targets": false } }`."
     );
 }
 
@@ -439,3 +673,42 @@ fn url_workaround_unc_canonicals() {
     let url_str = format!("{}", url);
     assert!(!url_str.starts_with(r"file:////?\"), "Unexpected UNC url {}", url);
 }
+
+#[test]
+fn check_baseline_loads_config() {
+    let dir = tempfile::tempdir().unwrap();
+    let file = dir.path().join("settings.json");
+    std::fs::write(
+        &file,
+        r#"{ "rust": { "all_targets": false, "diagnosticsBaselineFile": "base.json" } }"#,
+    )
+    .unwrap();
+
+    let config = load_config(&file).unwrap();
+    assert!(!config.all_targets);
+    assert_eq!(config.diagnostics_baseline_file, "base.json");
+
+    std::fs::write(&file, r#"{ "all_targets": false }"#).unwrap();
+    assert!(load_config(&file).is_err());
+}
+
+#[test]
+fn check_baseline_shows_all_warnings() {
+    let dir = tempfile::tempdir().unwrap();
+    let file = dir.path().join("settings.json");
+    std::fs::write(
+        &file,
+        r#"{ "rust": { "show_warnings": false, "diagnosticsOpenFilesOnly": true } }"#,
+    )
+    .unwrap();
+
+    let mut config = load_config(&file).unwrap();
+    assert!(!config.show_warnings);
+    assert!(config.diagnostics_open_files_only);
+
+    configure_baseline_check(&mut config);
+    assert!(config.show_warnings);
+    assert!(!config.diagnostics_open_files_only);
+    assert_eq!(config.diagnostics_baseline, BaselineMode::Hide);
+    assert!(!config.clear_env_rust_log);
+}
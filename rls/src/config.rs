@@ -123,7 +123,17 @@ pub fn unstable_features_allowed() -> bool {
 #[serde(default)]
 pub struct Config {
     pub sysroot: Option<String>,
+    /// Target triple or path to a custom target specification JSON file (relative
+    /// paths are resolved against the workspace root) to compile for.
+    /// Default: `None` (the host).
     pub target: Option<String>,
+    /// Standard library crates to build from source for the target, as if
+    /// `-Zbuild-std=<crates>` was passed to Cargo. Analysis data for them is then
+    /// produced locally in the target directory, which is useful for targets
+    /// without a `rust-analysis` component in the sysroot. For such a `target`,
+    /// the crates it supports are built from source if this isn't set.
+    /// Default: `None`.
+    pub build_std: Option<Vec<String>>,
     pub rustflags: Option<String>,
     pub build_lib: Inferrable<bool>,
     pub build_bin: Inferrable<Option<String>>,
@@ -131,6 +141,29 @@ pub struct Config {
     pub unstable_features: bool,
     pub wait_to_build: Option<u64>,
     pub show_warnings: bool,
+    /// Lints (e.g., `dead_code` or `clippy::needless_return`) and error codes
+    /// (e.g., `E0308`) whose diagnostics are hidden.
+    /// Default: `[]`.
+    pub diagnostics_ignore: Vec<String>,
+    /// Glob patterns (e.g., `src/generated/**`) of files whose diagnostics are
+    /// hidden. Relative patterns are matched against paths relative to the
+    /// workspace root.
+    /// Default: `[]`.
+    pub diagnostics_exclude: Vec<String>,
+    /// The severity of the diagnostics of the given lints or error codes,
+    /// overriding the one reported by the compiler.
+    /// Default: `{}`.
+    pub diagnostics_severity: HashMap<String, DiagnosticLevel>,
+    /// Only show the diagnostics of the files open in the editor.
+    /// Default: `false`.
+    pub diagnostics_open_files_only: bool,
+    /// What happens to the warnings recorded in the diagnostics baseline,
+    /// created with the `rls.createDiagnosticsBaseline` command.
+    /// Default: `"off"`.
+    pub diagnostics_baseline: BaselineMode,
+    /// The file of the diagnostics baseline, relative to the workspace root.
+    /// Default: `"rls-baseline.json"`.
+    pub diagnostics_baseline_file: String,
     /// `true` to clear the `RUST_LOG` env variable before calling rustc/cargo.
     /// Default: `true`.
     pub clear_env_rust_log: bool,
@@ -147,11 +180,48 @@ pub struct Config {
     pub no_default_features: bool,
     pub jobs: Option<u32>,
     pub all_targets: bool,
+    /// Run Cargo without accessing the network, as if `--offline` was passed.
+    /// Dependencies have to be available in the local registry cache or a
+    /// vendor directory.
+    /// Default: `false`.
+    pub offline: bool,
+    /// Require `Cargo.lock` to be up-to-date, as if `--locked` was passed.
+    /// Default: `false`.
+    pub locked: bool,
+    /// Equivalent to both `offline` and `locked`, as if `--frozen` was passed.
+    /// Default: `false`.
+    pub frozen: bool,
+    /// Write a per-crate timing report of every build, similar to the one
+    /// produced by `cargo build --timings`, as `rls-timings.html` and
+    /// `rls-timings.json` to the target directory.
+    /// Default: `false`.
+    pub build_timings_report: bool,
+    /// Compile crates which depend on procedural macros in a separate `rustc`
+    /// process, so that a misbehaving macro can't take the RLS down with it.
+    /// Has no effect if the RLS was built without the `ipc` feature.
+    /// Default: `true`.
+    pub isolate_proc_macros: bool,
+    /// Time in seconds after which an out-of-process compilation is killed.
+    /// `0` disables the timeout.
+    /// Default: `300`.
+    pub out_of_process_timeout: u64,
+    /// Maximum amount of memory, in MiB, an out-of-process compilation may
+    /// allocate (only enforced on Unix).
+    /// Default: `None` (no limit).
+    pub out_of_process_memory_limit: Option<u64>,
     /// Enables use of Racer for `textDocument/completion` requests.
     ///
     /// Enabled also enables racer fallbacks for hover and go-to-definition functionality
     /// if rustc analysis should fail.
     pub racer_completion: bool,
+    /// Enables completion from the save-analysis data for `textDocument/completion`
+    /// requests. Racer (if enabled) is used as a fallback for code which hasn't
+    /// been analyzed yet.
+    pub native_completion: bool,
+    /// Snippets offered as completions (if the client supports snippets), in
+    /// addition to the built-in postfix templates (e.g., `expr.if`).
+    /// Default: `[]`.
+    pub snippets: Vec<Snippet>,
     #[serde(deserialize_with = "deserialize_clippy_preference")]
     pub clippy_preference: ClippyPreference,
     /// Instructs cargo to enable full documentation extraction during save-analysis
@@ -165,6 +235,11 @@ pub struct Config {
     /// local variable declaration. When set to false, the content is only available when
     /// holding the `Ctrl` key in some editors.
     pub show_hover_context: bool,
+    /// Crates (e.g., `my_crate`) and modules (e.g., `my_crate::prelude`) whose
+    /// glob imports are left alone by the `rls.deglobWorkspace` command.
+    /// Excluding a module also excludes its submodules.
+    /// Default: `[]`.
+    pub deglob_exclude: Vec<String>,
     /// Use provided rustfmt binary instead of the statically linked one.
     /// (requires unstable features).
     pub rustfmt_path: Option<String>,
@@ -182,6 +257,7 @@ impl Default for Config {
         let mut result = Config {
             sysroot: None,
             target: None,
+            build_std: None,
             rustflags: None,
             build_lib: Inferrable::Inferred(false),
             build_bin: Inferrable::Inferred(None),
@@ -189,6 +265,12 @@ impl Default for Config {
             unstable_features: false,
             wait_to_build: None,
             show_warnings: true,
+            diagnostics_ignore: vec![],
+            diagnostics_exclude: vec![],
+            diagnostics_severity: HashMap::new(),
+            diagnostics_open_files_only: false,
+            diagnostics_baseline: BaselineMode::Off,
+            diagnostics_baseline_file: "rls-baseline.json".to_owned(),
             clear_env_rust_log: true,
             build_on_save: false,
             crate_blacklist: Inferrable::Inferred(CrateBlacklist::default()),
@@ -198,10 +280,20 @@ impl Default for Config {
             no_default_features: false,
             jobs: None,
             all_targets: true,
+            offline: false,
+            locked: false,
+            frozen: false,
+            build_timings_report: false,
+            isolate_proc_macros: true,
+            out_of_process_timeout: 300,
+            out_of_process_memory_limit: None,
             racer_completion: true,
+            native_completion: true,
+            snippets: vec![],
             clippy_preference: ClippyPreference::default(),
             full_docs: Inferrable::Inferred(false),
             show_hover_context: true,
+            deglob_exclude: vec![],
             rustfmt_path: None,
             build_command: None,
             use_crate_blacklist: None,
@@ -308,6 +400,12 @@ impl Config {
         }
     }
 
+    /// Returns the network and lock file restrictions that Cargo should respect
+    /// when resolving dependencies.
+    pub fn resolve_mode(&self) -> ResolveMode {
+        ResolveMode { frozen: self.frozen, locked: self.locked, offline: self.offline }
+    }
+
     /// Checks if this config is incomplete, and needs additional values to be inferred.
     pub fn needs_inference(&self) -> bool {
         self.build_bin.is_none() || self.build_lib.is_none() || self.target_dir.is_none()
@@ -353,6 +451,82 @@ impl Config {
     }
 }
 
+/// Mirrors Cargo's `--frozen`, `--locked` and `--offline` flags, used both
+/// when loading the project model and when running Cargo builds.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct ResolveMode {
+    pub frozen: bool,
+    pub locked: bool,
+    pub offline: bool,
+}
+
+impl ResolveMode {
+    /// The name of the option responsible for disallowing network access, if any.
+    pub fn offline_option(self) -> Option<&'static str> {
+        if self.frozen {
+            Some("frozen")
+        } else if self.offline {
+            Some("offline")
+        } else {
+            None
+        }
+    }
+}
+
+/// A severity of diagnostics, which can be set per lint or error code.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DiagnosticLevel {
+    Error,
+    Warning,
+    Information,
+    Hint,
+}
+
+/// What happens to the warnings recorded in the diagnostics baseline.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum BaselineMode {
+    /// The baseline is not used.
+    Off,
+    /// Baselined warnings are shown as hints.
+    Hint,
+    /// Baselined warnings are hidden.
+    Hide,
+}
+
+/// A user-defined snippet, offered as a completion item.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Snippet {
+    /// The text which is completed into the snippet.
+    pub trigger: String,
+    /// The inserted text, in the LSP snippet syntax (e.g., `$0` or `${1:name}`).
+    pub body: String,
+    /// Shown next to the trigger in the completion list.
+    #[serde(default)]
+    pub description: Option<String>,
+    /// Where the snippet may be used.
+    #[serde(default)]
+    pub scope: SnippetScope,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SnippetScope {
+    /// Outside of function bodies, e.g., at the module level or in an impl.
+    Item,
+    /// Anywhere in a function body.
+    Expr,
+    /// At the start of a statement in a function body.
+    Stmt,
+}
+
+impl Default for SnippetScope {
+    fn default() -> Self {
+        SnippetScope::Expr
+    }
+}
+
 #[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
 pub enum ClippyPreference {
     /// Disable clippy.
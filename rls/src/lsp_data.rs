@@ -13,6 +13,7 @@ use serde_derive::{Deserialize, Serialize};
 use url::Url;
 
 use crate::actions::hover;
+use crate::build::BuildReport;
 use crate::config;
 
 /// An error that can occur when parsing a file URI.
@@ -155,6 +156,25 @@ pub fn source_kind_from_def_kind(k: DefKind) -> SymbolKind {
     }
 }
 
+/// Converts an RLS def-kind to a language server protocol completion-kind.
+pub fn completion_kind_from_def_kind(k: DefKind) -> CompletionItemKind {
+    match k {
+        DefKind::Enum | DefKind::Union => CompletionItemKind::Enum,
+        DefKind::Static | DefKind::ForeignStatic => CompletionItemKind::Variable,
+        DefKind::Const => CompletionItemKind::Constant,
+        DefKind::Tuple | DefKind::Struct => CompletionItemKind::Struct,
+        DefKind::Function | DefKind::ForeignFunction => CompletionItemKind::Function,
+        DefKind::Macro => CompletionItemKind::Snippet,
+        DefKind::Method => CompletionItemKind::Method,
+        DefKind::Mod => CompletionItemKind::Module,
+        DefKind::Trait => CompletionItemKind::Interface,
+        DefKind::Type | DefKind::ExternType => CompletionItemKind::TypeParameter,
+        DefKind::Local => CompletionItemKind::Variable,
+        DefKind::Field => CompletionItemKind::Field,
+        DefKind::TupleVariant | DefKind::StructVariant => CompletionItemKind::EnumMember,
+    }
+}
+
 /// Indicates the kind of completion for this racer match type.
 pub fn completion_kind_from_match_type(m: racer::MatchType) -> CompletionItemKind {
     match m {
@@ -204,6 +224,113 @@ pub fn completion_item_from_racer_match(m: &racer::Match) -> CompletionItem {
 
 /* ------  Extension methods for JSON-RPC protocol types ------ */
 
+/// Custom RLS request returning per-crate timings of the most recent build.
+#[derive(Debug)]
+pub enum BuildReportRequest {}
+
+impl LSPRequest for BuildReportRequest {
+    type Params = ();
+    type Result = Option<BuildReport>;
+    const METHOD: &'static str = "rls/buildReport";
+}
+
+/// The tags of a diagnostic, added to the protocol after the version
+/// supported by `lsp_types`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DiagnosticTag {
+    /// Unused or unnecessary code, which clients may fade out.
+    Unnecessary = 1,
+    /// Deprecated code, which clients may strike through.
+    Deprecated = 2,
+}
+
+impl serde::Serialize for DiagnosticTag {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_u8(*self as u8)
+    }
+}
+
+impl<'de> serde::Deserialize<'de> for DiagnosticTag {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        match u8::deserialize(deserializer)? {
+            1 => Ok(DiagnosticTag::Unnecessary),
+            2 => Ok(DiagnosticTag::Deprecated),
+            tag => Err(serde::de::Error::custom(format!("unknown diagnostic tag: {}", tag))),
+        }
+    }
+}
+
+/// A link to the documentation of the code of a diagnostic.
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct CodeDescription {
+    pub href: Url,
+}
+
+/// The data attached to the diagnostics of the compiler, which clients send
+/// back as they received it.
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct DiagnosticData {
+    /// The diagnostic as rendered by rustc in a terminal, with the snippets of
+    /// the source it points at.
+    pub rendered: String,
+}
+
+/// A `Diagnostic` with the fields added to the protocol after the version
+/// supported by `lsp_types`. Dereferences to the inner `Diagnostic`.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RichDiagnostic {
+    #[serde(flatten)]
+    pub diagnostic: Diagnostic,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tags: Vec<DiagnosticTag>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub code_description: Option<CodeDescription>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub data: Option<DiagnosticData>,
+    /// The source the diagnostic points at, with whitespace normalized, which
+    /// isn't sent to the client.
+    #[serde(skip)]
+    pub snippet: Option<String>,
+}
+
+impl From<Diagnostic> for RichDiagnostic {
+    fn from(diagnostic: Diagnostic) -> Self {
+        RichDiagnostic { diagnostic, ..RichDiagnostic::default() }
+    }
+}
+
+impl std::ops::Deref for RichDiagnostic {
+    type Target = Diagnostic;
+
+    fn deref(&self) -> &Diagnostic {
+        &self.diagnostic
+    }
+}
+
+impl std::ops::DerefMut for RichDiagnostic {
+    fn deref_mut(&mut self) -> &mut Diagnostic {
+        &mut self.diagnostic
+    }
+}
+
+/// `PublishDiagnosticsParams` with `RichDiagnostic`s.
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct RichPublishDiagnosticsParams {
+    pub uri: Url,
+    pub diagnostics: Vec<RichDiagnostic>,
+}
+
+/// The `textDocument/publishDiagnostics` notification, publishing
+/// `RichDiagnostic`s.
+#[derive(Debug)]
+pub enum RichPublishDiagnostics {}
+
+impl LSPNotification for RichPublishDiagnostics {
+    type Params = RichPublishDiagnosticsParams;
+    const METHOD: &'static str = "textDocument/publishDiagnostics";
+}
+
 /// Provides additional methods for the remote `Range` type.
 pub trait RangeExt {
     /// `true` if both `Range`s overlap.
@@ -300,6 +427,8 @@ impl Default for InitializationOptions {
 pub struct ClientCapabilities {
     pub code_completion_has_snippet_support: bool,
     pub related_information_support: bool,
+    pub rename_prepare_support: bool,
+    pub resource_rename_support: bool,
 }
 
 impl ClientCapabilities {
@@ -328,6 +457,33 @@ impl ClientCapabilities {
             .copied()
             .unwrap_or(false);
 
-        ClientCapabilities { code_completion_has_snippet_support, related_information_support }
+        let rename_prepare_support = params
+            .capabilities
+            .text_document
+            .as_ref()
+            .and_then(|doc| doc.rename.as_ref())
+            .and_then(|rename| rename.prepare_support.as_ref())
+            .copied()
+            .unwrap_or(false);
+
+        let resource_rename_support = params
+            .capabilities
+            .workspace
+            .as_ref()
+            .and_then(|workspace| workspace.workspace_edit.as_ref())
+            .map(|edit| {
+                edit.document_changes == Some(true)
+                    && edit.resource_operations.as_ref().map_or(false, |ops| {
+                        ops.contains(&lsp_types::ResourceOperationKind::Rename)
+                    })
+            })
+            .unwrap_or(false);
+
+        ClientCapabilities {
+            code_completion_has_snippet_support,
+            related_information_support,
+            rename_prepare_support,
+            resource_rename_support,
+        }
     }
 }
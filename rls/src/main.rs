@@ -39,9 +39,11 @@ fn main_inner() -> i32 {
     }
 
     if env::var(rls::RUSTC_SHIM_ENV_VAR_NAME).ok().map_or(false, |v| v != "0") {
+        // Mirror rustc: regular compilation errors exit with 1, while 101 is
+        // left to panics, so that the RLS can tell when the shim crashed.
         match rustc_shim::run() {
             Ok(..) => return 0,
-            Err(..) => return 101,
+            Err(..) => return 1,
         }
     }
 
@@ -59,6 +61,7 @@ fn main_inner() -> i32 {
                 rls::cmd::run();
                 0
             }
+            "--check-baseline" => rls::cmd::check_baseline(env::args().skip(2).collect()),
             unknown => {
                 println!("Unknown argument '{}'. Supported arguments:\n{}", unknown, help());
                 101
@@ -77,6 +80,11 @@ fn help() -> &'static str {
     --version or -V to print the version and commit info
     --help or -h for this message
     --cli starts the RLS in command line mode
+    --check-baseline [--config settings] [--timeout seconds] [file] builds the project
+        in the current directory and fails if it has warnings which aren't in its
+        diagnostics baseline (`rls-baseline.json` by default), using the `rust` settings
+        in the JSON settings file, if any, and giving up after the timeout (30 minutes
+        by default)
     No input starts the RLS as a language server
     "#
 }
@@ -12,6 +12,9 @@ use cargo::{
 };
 use log::warn;
 use rls_vfs::{FileContents, Vfs};
+
+use crate::build::explain_resolve_error;
+use crate::config::ResolveMode;
 use std::{
     collections::HashMap,
     path::{Path, PathBuf},
@@ -32,6 +35,7 @@ struct PackageData {
     lib: Option<(PathBuf, String)>,
     deps: Vec<Dep>,
     edition: racer::Edition,
+    features: Vec<String>,
 }
 
 #[derive(Debug)]
@@ -41,13 +45,26 @@ pub struct Dep {
 }
 
 impl ProjectModel {
-    pub fn load(ws_manifest: &Path, vfs: &Vfs) -> Result<ProjectModel, anyhow::Error> {
+    pub fn load(
+        ws_manifest: &Path,
+        vfs: &Vfs,
+        resolve_mode: ResolveMode,
+    ) -> Result<ProjectModel, anyhow::Error> {
+        ProjectModel::load_with_mode(ws_manifest, vfs, resolve_mode)
+            .map_err(|err| explain_resolve_error(err, resolve_mode))
+    }
+
+    fn load_with_mode(
+        ws_manifest: &Path,
+        vfs: &Vfs,
+        resolve_mode: ResolveMode,
+    ) -> Result<ProjectModel, anyhow::Error> {
         assert!(ws_manifest.ends_with("Cargo.toml"));
         let mut config = Config::default()?;
         // Enable nightly flag for cargo(see #1043)
         config.nightly_features_allowed = true;
-        // frozen = false, locked = false, offline = false
-        config.configure(0, true, None, false, false, false, &None, &[], &[])?;
+        let ResolveMode { frozen, locked, offline } = resolve_mode;
+        config.configure(0, true, None, frozen, locked, offline, &None, &[], &[])?;
         let ws = Workspace::new(&ws_manifest, &config)?;
         // get resolve from lock file
         let prev = {
@@ -95,6 +112,7 @@ impl ProjectModel {
                     // https://github.com/racer-rust/racer/pull/1152 is published.
                     cargo::core::Edition::Edition2021 => racer::Edition::Ed2018,
                 },
+                features: cargo_pkg.summary().features().keys().map(|f| f.to_string()).collect(),
             });
             manifest_to_id.insert(manifest, pkg);
         }
@@ -117,6 +135,12 @@ impl ProjectModel {
         self.manifest_to_id.get(manifest_path).cloned()
     }
 
+    /// Returns the package whose manifest is the closest to `path`, i.e., the
+    /// package a source file belongs to.
+    pub fn package_for_path(&self, path: &Path) -> Option<Package> {
+        path.ancestors().find_map(|dir| self.package_for_manifest(&dir.join("Cargo.toml")))
+    }
+
     fn get(&self, pkg: Package) -> &PackageData {
         &self.packages[pkg.0]
     }
@@ -133,6 +157,11 @@ impl Package {
     pub fn lib_root(self, project: &ProjectModel) -> Option<&Path> {
         project.get(self).lib.as_ref().map(|p| p.0.as_path())
     }
+    /// The features declared by the package, including the implicit features
+    /// of its optional dependencies.
+    pub fn features(self, project: &ProjectModel) -> &[String] {
+        &project.get(self).features
+    }
 }
 
 // We use the following wrappers to teach Racer about the structure
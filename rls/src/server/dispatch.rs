@@ -94,12 +94,14 @@ define_dispatch_request_enum!(
     Implementation,
     DocumentHighlight,
     Rename,
+    PrepareRename,
     CodeAction,
     ResolveCompletion,
     Formatting,
     RangeFormatting,
     ExecuteCommand,
     CodeLensRequest,
+    BuildReportRequest,
 );
 
 /// Provides ability to dispatch requests to a worker thread that will
@@ -25,8 +25,8 @@ pub use lsp_types::request::Initialize as InitializeRequest;
 pub use lsp_types::request::Shutdown as ShutdownRequest;
 use lsp_types::{
     CodeActionProviderCapability, CodeLensOptions, CompletionOptions, ExecuteCommandOptions,
-    ImplementationProviderCapability, InitializeParams, InitializeResult, RenameProviderCapability,
-    ServerCapabilities, TextDocumentSyncCapability, TextDocumentSyncKind,
+    ImplementationProviderCapability, InitializeParams, InitializeResult, RenameOptions,
+    RenameProviderCapability, ServerCapabilities, TextDocumentSyncCapability, TextDocumentSyncKind,
 };
 use rls_analysis::AnalysisHost;
 use rls_vfs::Vfs;
@@ -176,13 +176,13 @@ impl BlockingRequestAction for InitializeRequest {
         maybe_notify_deprecated_configs(&out, &deprecated);
         maybe_notify_duplicated_configs(&out, &dups);
 
-        let result = InitializeResult { capabilities: server_caps(ctx) };
+        let capabilities = lsp_data::ClientCapabilities::new(&params);
+        let result = InitializeResult { capabilities: server_caps(ctx, &capabilities) };
 
         // Send response early before `ctx.init` to enforce
         // initialize-response-before-all-other-messages constraint.
         result.send(id, &out);
 
-        let capabilities = lsp_data::ClientCapabilities::new(&params);
         ctx.init(get_root_path(&params), init_options, capabilities, &out).unwrap();
 
         Ok(NoResponse)
@@ -318,6 +318,7 @@ impl<O: Output> LsService<O> {
             notifications:
                 notifications::Initialized,
                 notifications::DidOpenTextDocument,
+                notifications::DidCloseTextDocument,
                 notifications::DidChangeTextDocument,
                 notifications::DidSaveTextDocument,
                 notifications::DidChangeConfiguration,
@@ -332,6 +333,7 @@ impl<O: Output> LsService<O> {
                 requests::RangeFormatting,
                 requests::ResolveCompletion,
                 requests::Rename,
+                requests::PrepareRename,
                 requests::CodeAction,
                 requests::DocumentHighlight,
                 requests::Implementation,
@@ -341,7 +343,8 @@ impl<O: Output> LsService<O> {
                 requests::Definition,
                 requests::References,
                 requests::Completion,
-                requests::CodeLensRequest;
+                requests::CodeLensRequest,
+                requests::BuildReportRequest;
         );
         Ok(())
     }
@@ -417,7 +420,10 @@ pub enum ServerStateChange {
     Break { exit_code: i32 },
 }
 
-fn server_caps(ctx: &ActionContext) -> ServerCapabilities {
+fn server_caps(
+    ctx: &ActionContext,
+    client_capabilities: &lsp_data::ClientCapabilities,
+) -> ServerCapabilities {
     ServerCapabilities {
         text_document_sync: Some(TextDocumentSyncCapability::Kind(
             TextDocumentSyncKind::Incremental,
@@ -443,9 +449,22 @@ fn server_caps(ctx: &ActionContext) -> ServerCapabilities {
             commands: vec![
                 format!("rls.applySuggestion-{}", ctx.pid()),
                 format!("rls.deglobImports-{}", ctx.pid()),
+                format!("rls.organizeImports-{}", ctx.pid()),
+                format!("rls.deglobWorkspace-{}", ctx.pid()),
+                format!("rls.extract-{}", ctx.pid()),
+                format!("rls.inline-{}", ctx.pid()),
+                format!("rls.generate-{}", ctx.pid()),
+                format!("rls.fixAll-{}", ctx.pid()),
+                format!("rls.createDiagnosticsBaseline-{}", ctx.pid()),
+                format!("rls.acceptCompletion-{}", ctx.pid()),
             ],
         }),
-        rename_provider: Some(RenameProviderCapability::Simple(true)),
+        // Clients may only be sent rename options if they support `prepareRename`.
+        rename_provider: Some(if client_capabilities.rename_prepare_support {
+            RenameProviderCapability::Options(RenameOptions { prepare_provider: Some(true) })
+        } else {
+            RenameProviderCapability::Simple(true)
+        }),
         color_provider: None,
 
         // These are supported if the `unstable_features` option is set.
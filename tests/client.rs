@@ -1274,6 +1274,49 @@ fn client_deglob() {
     );
 }
 
+#[test]
+fn client_deglob_workspace() {
+    let p = project("deglob_workspace")
+        .file("Cargo.toml", &basic_bin_manifest("deglob_workspace"))
+        .file(
+            "src/main.rs",
+            "mod excluded;\nuse std::io::*;\n\nfn main() {\n    let _ = stdin();\n    excluded::f();\n}\n",
+        )
+        .file("src/excluded.rs", "use std::mem::*;\n\npub fn f() -> usize {\n    size_of::<u8>()\n}\n")
+        .build();
+    let root_path = p.root();
+    let mut rls = p.spawn_rls_async();
+
+    let opts = json!({"settings": {"rust": {"deglob_exclude": ["deglob_workspace::excluded"] } } });
+    rls.request::<Initialize>(0, initialize_params_with_opts(root_path, opts));
+
+    rls.wait_for_indexing();
+
+    rls.request::<ExecuteCommand>(
+        100,
+        ExecuteCommandParams { command: "rls.deglobWorkspace".to_owned(), arguments: vec![] },
+    );
+    let result = rls
+        .messages()
+        .iter()
+        .rfind(|msg| msg["method"] == ApplyWorkspaceEdit::METHOD)
+        .unwrap()
+        .clone();
+    let params = <ApplyWorkspaceEdit as Request>::Params::deserialize(&result["params"])
+        .expect("Couldn't deserialize params");
+
+    // The glob in the excluded module is kept.
+    let changes = params.edit.changes.unwrap();
+    assert_eq!(changes.len(), 1);
+    assert_eq!(
+        changes[&Url::from_file_path(p.root().join("src/main.rs")).unwrap()],
+        vec![TextEdit {
+            range: Range { start: Position::new(1, 13), end: Position::new(1, 14) },
+            new_text: "stdin".to_string(),
+        }]
+    );
+}
+
 fn is_notification_for_unknown_config(msg: &serde_json::Value) -> bool {
     msg["method"] == ShowMessage::METHOD
         && msg["params"]["message"].as_str().unwrap().contains("Unknown")
@@ -1418,6 +1461,61 @@ fn client_goto_def() {
     assert!(ranges.iter().any(|r| r.start == Position { line: 11, character: 8 }));
 }
 
+#[test]
+fn client_goto_def_impl_method() {
+    const SRC: &str = r#"
+        trait Greet {
+            fn greet(&self) {}
+        }
+
+        struct Foo;
+
+        impl Greet for Foo {
+            fn greet(&self) {}
+        }
+
+        fn main() {
+            let foo = Foo;
+            foo.greet();
+        }
+    "#;
+
+    let p = project("impl_method")
+        .file("Cargo.toml", &basic_bin_manifest("bar"))
+        .file("src/main.rs", SRC)
+        .build();
+    let root_path = p.root();
+    let mut rls = p.spawn_rls_async();
+
+    rls.request::<Initialize>(0, initialize_params(root_path));
+
+    rls.wait_for_indexing();
+
+    // The trait method is called, but the method of the impl runs.
+    let result = rls.request::<GotoDefinition>(
+        11,
+        TextDocumentPositionParams {
+            position: Position { line: 13, character: 17 },
+            text_document: TextDocumentIdentifier {
+                uri: Url::from_file_path(p.root().join("src/main.rs")).unwrap(),
+            },
+        },
+    );
+
+    let ranges: Vec<_> = result
+        .into_iter()
+        .flat_map(|x| match x {
+            GotoDefinitionResponse::Scalar(loc) => vec![loc].into_iter(),
+            GotoDefinitionResponse::Array(locs) => locs.into_iter(),
+            _ => unreachable!(),
+        })
+        .map(|x| x.range)
+        .collect();
+
+    assert_eq!(ranges.len(), 1);
+    assert_eq!(ranges[0].start, Position { line: 8, character: 15 });
+}
+
 #[test]
 fn client_hover() {
     let p = ProjectBuilder::try_from_fixture(fixtures_dir().join("common")).unwrap().build();
@@ -1767,6 +1865,76 @@ fn client_rename() {
     assert_eq!(result.changes, Some(changes));
 }
 
+#[test]
+fn client_rename_module() {
+    let p = project("rename_module")
+        .file("Cargo.toml", &basic_bin_manifest("rename_module"))
+        .file(
+            "src/main.rs",
+            "mod foo;\nuse foo::bar;\n\nfn main() {\n    bar();\n    foo::bar();\n}\n",
+        )
+        .file("src/foo.rs", "pub fn bar() {}\n")
+        .build();
+    let root_path = p.root();
+    let mut rls = p.spawn_rls_async();
+
+    let mut params = initialize_params(root_path);
+    params.capabilities.workspace = Some(WorkspaceClientCapabilities {
+        workspace_edit: Some(WorkspaceEditCapability {
+            document_changes: Some(true),
+            resource_operations: Some(vec![ResourceOperationKind::Rename]),
+            ..Default::default()
+        }),
+        ..Default::default()
+    });
+    rls.request::<Initialize>(0, params);
+
+    rls.wait_for_indexing();
+
+    let main_uri = Url::from_file_path(p.root().join("src/main.rs")).unwrap();
+    let result = rls
+        .request::<Rename>(
+            42,
+            RenameParams {
+                text_document_position: TextDocumentPositionParams {
+                    position: Position { line: 5, character: 5 },
+                    text_document: TextDocumentIdentifier { uri: main_uri.clone() },
+                },
+                new_name: "baz".to_owned(),
+            },
+        )
+        .unwrap();
+
+    // The declaration and both paths are renamed, and so is the module's file.
+    let operations = match result.document_changes {
+        Some(DocumentChanges::Operations(operations)) => operations,
+        changes => panic!("Expected document change operations, got {:?}", changes),
+    };
+    assert_eq!(operations.len(), 2);
+    match &operations[0] {
+        DocumentChangeOperation::Edit(edit) => {
+            assert_eq!(edit.text_document.uri, main_uri);
+            let mut ranges: Vec<_> = edit.edits.iter().map(|edit| edit.range).collect();
+            ranges.sort_by_key(|range| (range.start.line, range.start.character));
+            let expected: Vec<_> = [0, 1, 5]
+                .iter()
+                .map(|&line| Range::new(Position::new(line, 4), Position::new(line, 7)))
+                .collect();
+            assert_eq!(ranges, expected);
+            assert!(edit.edits.iter().all(|edit| edit.new_text == "baz"));
+        }
+        operation => panic!("Expected an edit, got {:?}", operation),
+    }
+    assert_eq!(
+        operations[1],
+        DocumentChangeOperation::Op(ResourceOp::Rename(RenameFile {
+            old_uri: Url::from_file_path(p.root().join("src/foo.rs")).unwrap(),
+            new_uri: Url::from_file_path(p.root().join("src/baz.rs")).unwrap(),
+            options: None,
+        }))
+    );
+}
+
 #[test]
 fn client_reformat() {
     let p = ProjectBuilder::try_from_fixture(fixtures_dir().join("reformat")).unwrap().build();
@@ -182,6 +182,8 @@ impl TooltipTestHarness {
         let client_caps = ClientCapabilities {
             code_completion_has_snippet_support: true,
             related_information_support: true,
+            rename_prepare_support: true,
+            resource_rename_support: true,
         };
 
         let _working_dir = tempfile::tempdir().expect("Couldn't create tempdir");